@@ -17,6 +17,9 @@ struct BindTarget {
 
 #[derive(Default, Serialize, Deserialize)]
 struct Parameters {
+    #[serde(default)]
+    metal_lang_version: Option<(u8, u8)>,
+    #[serde(default)]
     metal_bindings: naga::FastHashMap<BindSource, BindTarget>,
 }
 
@@ -41,7 +44,15 @@ fn main() {
         #[cfg(feature = "spirv")]
         "spv" => {
             let input = fs::read(&args[1]).unwrap();
-            naga::front::spv::parse_u8_slice(&input).unwrap()
+            naga::front::spv::parse_u8_slice(&input, &Default::default()).unwrap()
+        }
+        #[cfg(feature = "spirv")]
+        "spvasm" => {
+            let input = fs::read_to_string(&args[1]).unwrap();
+            let words = naga::back::spv::assemble(&input).unwrap();
+            naga::front::spv::Parser::new(words.into_iter(), &Default::default())
+                .parse()
+                .unwrap()
         }
         "wgsl" => {
             let input = fs::read_to_string(&args[1]).unwrap();
@@ -156,17 +167,20 @@ fn main() {
                         texture: value.texture,
                         sampler: value.sampler,
                         mutable: value.mutable,
+                        inline_sampler: None,
                     },
                 );
             }
             let options = msl::Options {
-                binding_map: &binding_map,
+                lang_version: params.metal_lang_version.unwrap_or((1, 2)),
+                allocate_bindings: true,
+                ..msl::Options::new(&binding_map)
             };
-            let msl = msl::write_string(&module, options).unwrap();
+            let (msl, _) = msl::write_string(&module, options).unwrap();
             fs::write(&args[2], msl).unwrap();
         }
         #[cfg(feature = "spirv")]
-        "spv" => {
+        extension @ "spv" | extension @ "spvasm" => {
             use naga::back::spv;
 
             let debug_flag = args.get(3).map_or(spv::WriterFlags::DEBUG, |arg| {
@@ -177,7 +191,19 @@ fn main() {
                 }
             });
 
-            let spv = spv::Writer::new(&module.header, debug_flag).write(&module);
+            let options = spv::Options {
+                flags: debug_flag,
+                ..Default::default()
+            };
+            let spv = spv::Writer::new(&module.header, &options)
+                .write(&module)
+                .unwrap();
+
+            if extension == "spvasm" {
+                let text = spv::disassemble(&spv).unwrap();
+                fs::write(&args[2], text).unwrap();
+                return;
+            }
 
             let bytes = spv
                 .iter()
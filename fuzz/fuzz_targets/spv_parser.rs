@@ -4,5 +4,5 @@ use naga::front::spv::Parser;
 
 fuzz_target!(|data: Vec<u32>| {
     // Ensure the parser can handle potentially malformed data without crashing.
-    let _result = Parser::new(data.into_iter()).parse();
+    let _result = Parser::new(data.into_iter(), &Default::default()).parse();
 });
@@ -84,7 +84,7 @@ impl<T> Handle<T> {
 /// Adding new items to the arena produces a strongly-typed [`Handle`].
 /// The arena can be indexed using the given handle to obtain
 /// a reference to the stored item.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "serialize", derive(crate::Serialize))]
 #[cfg_attr(feature = "deserialize", derive(crate::Deserialize))]
 #[cfg_attr(
@@ -710,6 +710,92 @@ fn write_expression<'a>(
                 ty,
             )
         }
+        Expression::Select {
+            condition,
+            accept,
+            reject,
+        } => {
+            let (condition_expr, condition_ty) =
+                write_expression(&builder.expressions[*condition], module, builder)?;
+            let (accept_expr, ty) =
+                write_expression(&builder.expressions[*accept], module, builder)?;
+            let (reject_expr, _) =
+                write_expression(&builder.expressions[*reject], module, builder)?;
+
+            let expr = match condition_ty.as_ref() {
+                TypeInner::Scalar { .. } => {
+                    format!("({} ? {} : {})", condition_expr, accept_expr, reject_expr)
+                }
+                _ => format!("mix({},{},{})", reject_expr, accept_expr, condition_expr),
+            };
+
+            (expr, ty)
+        }
+        Expression::As {
+            expr,
+            kind,
+            convert,
+        } => {
+            let (value_expr, value_ty) =
+                write_expression(&builder.expressions[*expr], module, builder)?;
+
+            let (source_kind, size, width) = match *value_ty.as_ref() {
+                TypeInner::Scalar { kind, width } => (kind, None, width),
+                TypeInner::Vector { size, kind, width } => (kind, Some(size), width),
+                ref other => return Err(Error::Custom(format!("Cannot cast {:?}", other))),
+            };
+
+            let constructor = match size {
+                Some(size) => format!(
+                    "{}vec{}",
+                    match kind {
+                        ScalarKind::Sint => "i",
+                        ScalarKind::Uint => "u",
+                        ScalarKind::Float => "",
+                        ScalarKind::Bool => "b",
+                    },
+                    size as u8
+                ),
+                None => String::from(match kind {
+                    ScalarKind::Sint => "int",
+                    ScalarKind::Uint => "uint",
+                    ScalarKind::Float => "float",
+                    ScalarKind::Bool => "bool",
+                }),
+            };
+
+            let fun = if *convert {
+                constructor.as_str()
+            } else {
+                match (source_kind, *kind) {
+                    (ScalarKind::Float, ScalarKind::Sint) => "floatBitsToInt",
+                    (ScalarKind::Float, ScalarKind::Uint) => "floatBitsToUint",
+                    (ScalarKind::Sint, ScalarKind::Float) => "intBitsToFloat",
+                    (ScalarKind::Uint, ScalarKind::Float) => "uintBitsToFloat",
+                    (ScalarKind::Sint, ScalarKind::Uint) | (ScalarKind::Uint, ScalarKind::Sint) => {
+                        constructor.as_str()
+                    }
+                    (from, to) if from == to => "",
+                    (from, to) => {
+                        return Err(Error::Custom(format!(
+                            "Cannot bitcast {:?} to {:?}",
+                            from, to
+                        )))
+                    }
+                }
+            };
+
+            let ty = match size {
+                Some(size) => TypeInner::Vector {
+                    size,
+                    kind: *kind,
+                    width,
+                },
+                None => TypeInner::Scalar { kind: *kind, width },
+            };
+
+            (format!("{}({})", fun, value_expr), Cow::Owned(ty))
+        }
         Expression::Call { origin, arguments } => {
             let ty = match origin {
                 FunctionOrigin::Local(function) => module.functions[*function]
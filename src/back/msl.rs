@@ -5,7 +5,46 @@
 Metal's bindings are flat per resource. Since there isn't an obvious mapping
 from SPIR-V's descriptor sets, we require a separate mapping provided in the options.
 This mapping may have one or more resource end points for each descriptor set + index
-pair.
+pair. With `Options::allocate_bindings`, the resources missing from the mapping
+get the next free indices of each stage instead, and the chosen mapping is returned.
+
+With `Options::argument_buffers`, the resources of each descriptor set are instead
+gathered into an argument buffer struct bound at `[[buffer(set)]]`, where the mapping
+gives the `[[id(n)]]` of each resource. The resulting layouts are returned in
+`TranslationInfo`, so that the host can encode the argument buffers.
+
+## Entry points
+
+Every function is written as a regular Metal function, which takes the global
+variables it uses, directly or through its callees, as reference parameters.
+Each entry point then gets its own function named after `EntryPoint::name`,
+which gathers the stage inputs and resources, calls the function, and returns
+the outputs. This way a function can be shared by entry points of different stages.
+
+## Vertex pulling
+
+When `Options::vertex_buffers` is not empty, the location inputs of vertex entry points
+are fetched from these buffers, indexed by `[[vertex_id]]` or `[[instance_id]]`
+following the step mode of each buffer, instead of being declared as `[[attribute(n)]]`.
+
+## Runtime-sized arrays
+
+Metal doesn't know the length of the buffers. With `Options::sizes_buffer`, the host
+passes their sizes in a hidden buffer, from which the lengths of runtime-sized arrays
+are computed for `arrayLength` and for clamping the indices.
+
+## Images
+
+Metal takes the array layer of an image access as a separate argument, so it is split
+from the last component of the coordinate, and rounded to the nearest layer.
+Multisampled textures can't be sampled, so sampling one is an error.
+
+## Source maps
+
+With `Options::source_map`, the ranges of output lines written for each function,
+and for each statement made of an expression, are returned in `TranslationInfo`
+along with their locations in the original source, from the span table of the module.
+With `Options::annotate`, these lines are preceded by comments naming their origin.
 
 ## Outputs
 
@@ -14,16 +53,141 @@ the output struct. If there is a structure in the outputs, and it contains any b
 we move them up to the root output structure that we define ourselves.
 !*/
 
-use std::fmt::{Display, Error as FmtError, Formatter, Write};
+use std::{
+    convert::TryFrom,
+    fmt::{Display, Error as FmtError, Formatter, Write},
+};
 
-use crate::{arena::Handle, FastHashMap};
+use crate::{
+    arena::{Arena, Handle},
+    proc::{ResolveError, Typifier},
+    FastHashMap,
+};
 
+/// Slot a resource is bound to.
+///
+/// When the resources are gathered into argument buffers,
+/// this is the `[[id(n)]]` of the resource within the argument buffer of its set.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct BindTarget {
     pub buffer: Option<u8>,
     pub texture: Option<u8>,
     pub sampler: Option<u8>,
     pub mutable: bool,
+    /// Declare the sampler as a `constexpr sampler` in the entry points,
+    /// instead of taking it as an argument.
+    pub inline_sampler: Option<InlineSampler>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(missing_docs)] // The names are self evident
+pub enum SamplerFilter {
+    Nearest,
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(missing_docs)] // The names are self evident
+pub enum SamplerAddress {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToZero,
+    ClampToBorder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(missing_docs)] // The names are self evident
+pub enum SamplerBorderColor {
+    TransparentBlack,
+    OpaqueBlack,
+    OpaqueWhite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(missing_docs)] // The names are self evident
+pub enum SamplerCompareFunction {
+    Never,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    Always,
+}
+
+/// State of a sampler known when writing the shaders.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InlineSampler {
+    pub mag_filter: SamplerFilter,
+    pub min_filter: SamplerFilter,
+    /// Filter between the mipmap levels, or none to only use the base level.
+    pub mip_filter: Option<SamplerFilter>,
+    /// Address modes for the S, T and R coordinates.
+    pub address: [SamplerAddress; 3],
+    /// Color used with `SamplerAddress::ClampToBorder`.
+    pub border_color: SamplerBorderColor,
+    /// Comparison against the reference of depth samples, if any.
+    pub compare_func: Option<SamplerCompareFunction>,
+    /// Range of the mipmap levels to sample from.
+    pub lod_clamp: Option<std::ops::Range<f32>>,
+}
+
+impl InlineSampler {
+    /// Writes the arguments of the `constexpr sampler` declaration.
+    fn arguments(&self, options: Options) -> Result<String, Error> {
+        fn filter(filter: SamplerFilter) -> &'static str {
+            match filter {
+                SamplerFilter::Nearest => "nearest",
+                SamplerFilter::Linear => "linear",
+            }
+        }
+        let mut arguments = vec![
+            format!("mag_filter::{}", filter(self.mag_filter)),
+            format!("min_filter::{}", filter(self.min_filter)),
+        ];
+        if let Some(mip_filter) = self.mip_filter {
+            arguments.push(format!("mip_filter::{}", filter(mip_filter)));
+        }
+        for (coordinate, &address) in ["s", "t", "r"].iter().zip(self.address.iter()) {
+            let mode = match address {
+                SamplerAddress::Repeat => "repeat",
+                SamplerAddress::MirroredRepeat => "mirrored_repeat",
+                SamplerAddress::ClampToEdge => "clamp_to_edge",
+                SamplerAddress::ClampToZero => "clamp_to_zero",
+                SamplerAddress::ClampToBorder => "clamp_to_border",
+            };
+            arguments.push(format!("{}_address::{}", coordinate, mode));
+        }
+        // only macOS has border colors, don't ask for them without a need
+        if self.address.contains(&SamplerAddress::ClampToBorder) {
+            options.require(Feature::SamplerBorderColor)?;
+            let color = match self.border_color {
+                SamplerBorderColor::TransparentBlack => "transparent_black",
+                SamplerBorderColor::OpaqueBlack => "opaque_black",
+                SamplerBorderColor::OpaqueWhite => "opaque_white",
+            };
+            arguments.push(format!("border_color::{}", color));
+        }
+        if let Some(compare_func) = self.compare_func {
+            let func = match compare_func {
+                SamplerCompareFunction::Never => "never",
+                SamplerCompareFunction::Less => "less",
+                SamplerCompareFunction::LessEqual => "less_equal",
+                SamplerCompareFunction::Greater => "greater",
+                SamplerCompareFunction::GreaterEqual => "greater_equal",
+                SamplerCompareFunction::Equal => "equal",
+                SamplerCompareFunction::NotEqual => "not_equal",
+                SamplerCompareFunction::Always => "always",
+            };
+            arguments.push(format!("compare_func::{}", func));
+        }
+        if let Some(ref range) = self.lod_clamp {
+            arguments.push(format!("lod_clamp({:?}, {:?})", range.start, range.end));
+        }
+        Ok(arguments.join(", "))
+    }
 }
 
 #[derive(Clone, Debug, Hash, Eq, Ord, PartialEq, PartialOrd)]
@@ -60,7 +224,6 @@ impl Display for Level {
 pub enum Error {
     Format(FmtError),
     UnexpectedLocation,
-    MixedExecutionModels(crate::Handle<crate::Function>),
     MissingBinding(crate::Handle<crate::GlobalVariable>),
     MissingBindTarget(BindSource),
     InvalidImageFlags(crate::ImageFlags),
@@ -70,16 +233,19 @@ pub enum Error {
     UnimplementedBindTarget(BindTarget),
     UnexpectedIndexing(crate::TypeInner),
     UnsupportedCompose(crate::Handle<crate::Type>),
-    UnexpectedLoadPointer(crate::TypeInner),
-    UnsupportedBinaryOp(crate::BinaryOperator),
-    UnableToInferBinaryOpOutput(crate::TypeInner, crate::BinaryOperator, crate::TypeInner),
     UnexpectedImageType(crate::TypeInner),
-    UnexpectedDistanceArgument(crate::TypeInner),
+    UnexpectedImageCoordinate(crate::TypeInner),
+    UnexpectedCast(crate::TypeInner),
     UnsupportedCall(String),
-    UnsupportedExpression(crate::Expression),
-    UnableToReturnValue(crate::Handle<crate::Expression>),
-    UnsupportedStatement(crate::Statement),
     AccessIndexExceedsStaticLength(u32, u32),
+    UnexpectedEntryPointParameters(crate::Handle<crate::Function>),
+    UnsupportedLanguageVersion(u8, u8),
+    UnsupportedDescriptorSet(u32),
+    InvalidVertexAttribute(u32),
+    WritableTextureInArgumentBuffer(crate::Handle<crate::GlobalVariable>),
+    /// The module needs a feature the target language version or platform lacks.
+    UnsupportedFeature(Feature),
+    Resolve(Handle<crate::Function>, ResolveError),
 }
 
 impl From<FmtError> for Error {
@@ -101,16 +267,352 @@ fn dim_str(dim: crate::ImageDimension) -> &'static str {
         crate::ImageDimension::D1 => "1d",
         crate::ImageDimension::D2 => "2d",
         crate::ImageDimension::D3 => "3d",
-        crate::ImageDimension::Cube => "Cube",
+        crate::ImageDimension::Cube => "cube",
+    }
+}
+
+/// Platform the shaders are compiled for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Platform {
+    MacOs,
+    Ios,
+}
+
+/// Language feature whose availability depends on the target.
+///
+/// The `fast::` and `precise::` math functions are available in every
+/// supported language version, so they don't need to be checked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Feature {
+    ArgumentBuffers,
+    CubeArrayTextures,
+    RasterOrderGroups,
+    ReadWriteTextures,
+    SamplerBorderColor,
+    SimdGroupFunctions,
+    TextureAtomics,
+}
+
+impl Feature {
+    /// The first language version providing this feature on the platform,
+    /// or `None` if no supported version does.
+    pub fn required_version(self, platform: Platform) -> Option<(u8, u8)> {
+        match (self, platform) {
+            (Feature::ArgumentBuffers, _)
+            | (Feature::RasterOrderGroups, _)
+            | (Feature::ReadWriteTextures, _) => Some((2, 0)),
+            (Feature::CubeArrayTextures, Platform::MacOs) => Some((1, 2)),
+            (Feature::CubeArrayTextures, Platform::Ios) => Some((2, 0)),
+            (Feature::SamplerBorderColor, Platform::MacOs) => Some((1, 2)),
+            (Feature::SamplerBorderColor, Platform::Ios) => None,
+            (Feature::SimdGroupFunctions, Platform::MacOs) => Some((2, 0)),
+            (Feature::SimdGroupFunctions, Platform::Ios) => Some((2, 2)),
+            (Feature::TextureAtomics, _) => None,
+        }
+    }
+}
+
+/// Layout of the argument buffers, following the tier supported by the device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgumentBufferTier {
+    /// Argument buffers can't hold writable textures.
+    Tier1,
+    Tier2,
+}
+
+/// Resource placed in an argument buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArgumentBufferEntry {
+    /// Binding of the resource within the descriptor set.
+    pub binding: u32,
+    /// Index of the resource within the argument buffer, written as `[[id(n)]]`.
+    pub id: u8,
+    /// Variable of the resource, whose type gives the kind of the resource.
+    pub variable: crate::Handle<crate::GlobalVariable>,
+    /// The shaders write into the resource.
+    pub mutable: bool,
+}
+
+/// Argument buffer holding the resources of a descriptor set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArgumentBufferLayout {
+    pub set: u32,
+    /// Index of the buffer the argument buffer is bound at, equal to the set.
+    pub buffer: u8,
+    /// Resources in the argument buffer, by increasing `id`.
+    pub entries: Vec<ArgumentBufferEntry>,
+}
+
+/// Information about the written shaders, needed by the host to use them.
+#[derive(Clone, Debug, Default)]
+pub struct TranslationInfo {
+    /// Argument buffers, by increasing descriptor set.
+    pub argument_buffers: Vec<ArgumentBufferLayout>,
+    /// Bindings of the resources used by the entry points of each stage,
+    /// including the allocated ones.
+    pub binding_maps: FastHashMap<crate::ShaderStage, BindingMap>,
+    /// Global variables whose sizes go into the `buffer_sizes` buffer, in this order.
+    pub sized_buffers: Vec<Handle<crate::GlobalVariable>>,
+    /// Names of the written entry point functions, indexed like `Module::entry_points`.
+    /// They differ from the entry point names when these are reserved or shared by several stages.
+    pub entry_point_names: Vec<String>,
+    /// Origins of the written lines, by increasing first line, if `Options::source_map` is set.
+    /// The mapping of a statement follows the ones of the statements containing it.
+    pub source_map: Vec<SourceMapping>,
+}
+
+/// Origin of a range of lines in the written shaders.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceMapping {
+    /// Lines of the output, starting from 1, with the end excluded.
+    pub lines: std::ops::Range<u32>,
+    /// Function written on these lines.
+    pub function: Handle<crate::Function>,
+    /// Expression of the statement written on these lines, if any.
+    /// `None` for the whole function.
+    pub expression: Option<Handle<crate::Expression>>,
+    /// Location of the function or expression in the original source,
+    /// from the span table of the module.
+    pub span: Option<crate::Span>,
+}
+
+/// Rate at which a vertex buffer advances.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VertexStepMode {
+    /// Indexed by `[[vertex_id]]`.
+    Vertex,
+    /// Indexed by `[[instance_id]]`.
+    Instance,
+}
+
+/// Format of a vertex attribute in its buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(missing_docs)] // The names are self evident
+pub enum VertexFormat {
+    Uint8x4,
+    Sint8x4,
+    Unorm8x4,
+    Snorm8x4,
+    Unorm16x2,
+    Snorm16x2,
+    Float16x2,
+    Float16x4,
+    Float32,
+    Float32x2,
+    Float32x3,
+    Float32x4,
+    Uint32,
+    Uint32x2,
+    Uint32x3,
+    Uint32x4,
+    Sint32,
+    Sint32x2,
+    Sint32x3,
+    Sint32x4,
+}
+
+impl VertexFormat {
+    fn components(self) -> usize {
+        use VertexFormat as Vf;
+        match self {
+            Vf::Float32 | Vf::Uint32 | Vf::Sint32 => 1,
+            Vf::Unorm16x2
+            | Vf::Snorm16x2
+            | Vf::Float16x2
+            | Vf::Float32x2
+            | Vf::Uint32x2
+            | Vf::Sint32x2 => 2,
+            Vf::Float32x3 | Vf::Uint32x3 | Vf::Sint32x3 => 3,
+            Vf::Uint8x4
+            | Vf::Sint8x4
+            | Vf::Unorm8x4
+            | Vf::Snorm8x4
+            | Vf::Float16x4
+            | Vf::Float32x4
+            | Vf::Uint32x4
+            | Vf::Sint32x4 => 4,
+        }
+    }
+
+    /// Writes the expression loading the attribute from a byte pointer.
+    fn load(self, pointer: &str) -> String {
+        use VertexFormat as Vf;
+        let (function, ty) = match self {
+            Vf::Uint8x4 => ("uint4", "packed_uchar4"),
+            Vf::Sint8x4 => ("int4", "packed_char4"),
+            Vf::Unorm8x4 => ("unpack_unorm4x8_to_float", "uint"),
+            Vf::Snorm8x4 => ("unpack_snorm4x8_to_float", "uint"),
+            Vf::Unorm16x2 => ("unpack_unorm2x16_to_float", "uint"),
+            Vf::Snorm16x2 => ("unpack_snorm2x16_to_float", "uint"),
+            Vf::Float16x2 => ("float2", "packed_half2"),
+            Vf::Float16x4 => ("float4", "packed_half4"),
+            Vf::Float32 => ("", "float"),
+            Vf::Float32x2 => ("float2", "packed_float2"),
+            Vf::Float32x3 => ("float3", "packed_float3"),
+            Vf::Float32x4 => ("float4", "packed_float4"),
+            Vf::Uint32 => ("", "uint"),
+            Vf::Uint32x2 => ("uint2", "packed_uint2"),
+            Vf::Uint32x3 => ("uint3", "packed_uint3"),
+            Vf::Uint32x4 => ("uint4", "packed_uint4"),
+            Vf::Sint32 => ("", "int"),
+            Vf::Sint32x2 => ("int2", "packed_int2"),
+            Vf::Sint32x3 => ("int3", "packed_int3"),
+            Vf::Sint32x4 => ("int4", "packed_int4"),
+        };
+        format!("{}(*(const device {}*)({}))", function, ty, pointer)
     }
 }
 
+/// Vertex attribute fetched from a vertex buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexAttribute {
+    /// Location of the input variable receiving the attribute.
+    pub location: u32,
+    pub format: VertexFormat,
+    /// Offset of the attribute within an element of the buffer, in bytes.
+    pub offset: u32,
+}
+
+/// Layout of a vertex buffer, from which the vertex attributes are fetched.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexBufferLayout {
+    /// Index of the buffer the vertex buffer is bound at.
+    pub buffer: u8,
+    /// Distance between the elements of the buffer, in bytes.
+    pub stride: u32,
+    pub step_mode: VertexStepMode,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+/// Language versions the writer can target, as `(major, minor)`.
+const LANGUAGE_VERSIONS: &[(u8, u8)] = &[(1, 2), (2, 0), (2, 1), (2, 2), (2, 3)];
+
 #[derive(Debug, Clone, Copy)]
 pub struct Options<'a> {
+    /// Target language version, as `(major, minor)`, from 1.2 to 2.3.
+    pub lang_version: (u8, u8),
+    /// Platform the shaders are compiled for.
+    pub platform: Platform,
     pub binding_map: &'a BindingMap,
+    /// Gather the resources of each descriptor set into an argument buffer,
+    /// bound at the buffer index equal to the set, instead of binding them separately.
+    pub argument_buffers: Option<ArgumentBufferTier>,
+    /// Assign the resources missing from `binding_map` to the next free
+    /// indices of each stage, or of their argument buffer.
+    pub allocate_bindings: bool,
+    /// Fetch the inputs of vertex entry points from these vertex buffers,
+    /// instead of receiving them as `[[attribute(n)]]`. No vertex pulling if empty.
+    pub vertex_buffers: &'a [VertexBufferLayout],
+    /// Index of the hidden `buffer_sizes` buffer, holding the sizes in bytes of the buffers
+    /// with runtime-sized arrays, listed by `TranslationInfo::sized_buffers`.
+    /// The lengths of these arrays are computed from it, and their accesses are clamped.
+    pub sizes_buffer: Option<u8>,
+    /// Return the origin of the written lines in `TranslationInfo::source_map`.
+    pub source_map: bool,
+    /// Precede the functions and statements with comments naming their origin.
+    pub annotate: bool,
 }
 
-impl Options<'_> {
+impl<'a> Options<'a> {
+    /// Targets MSL 1.2 on macOS, with the given bindings and none of the optional features.
+    pub fn new(binding_map: &'a BindingMap) -> Self {
+        Options {
+            lang_version: (1, 2),
+            platform: Platform::MacOs,
+            binding_map,
+            argument_buffers: None,
+            allocate_bindings: false,
+            vertex_buffers: &[],
+            sizes_buffer: None,
+            source_map: false,
+            annotate: false,
+        }
+    }
+
+    /// Checks if the target has the given feature.
+    pub fn supports(&self, feature: Feature) -> bool {
+        match feature.required_version(self.platform) {
+            Some(version) => self.lang_version >= version,
+            None => false,
+        }
+    }
+
+    fn require(&self, feature: Feature) -> Result<(), Error> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedFeature(feature))
+        }
+    }
+
+    /// Finds the bind target of a resource used by a stage, looking into the bindings
+    /// already chosen for the stage before allocating a new one.
+    fn resolve_resource(
+        &self,
+        stage: crate::ShaderStage,
+        source: BindSource,
+        inner: &crate::TypeInner,
+        mutable: bool,
+        chosen: &mut BindingMap,
+    ) -> Result<BindTarget, Error> {
+        if let Some(target) = chosen.get(&source) {
+            return Ok(target.clone());
+        }
+        let target = match self.binding_map.get(&source) {
+            Some(target) => target.clone(),
+            None if self.allocate_bindings => {
+                let slot = |target: &BindTarget| match *inner {
+                    crate::TypeInner::Image { .. } | crate::TypeInner::DepthImage { .. } => {
+                        target.texture
+                    }
+                    crate::TypeInner::Sampler { .. } => target.sampler,
+                    _ => target.buffer,
+                };
+                let vertex_buffers = match *inner {
+                    crate::TypeInner::Image { .. }
+                    | crate::TypeInner::DepthImage { .. }
+                    | crate::TypeInner::Sampler { .. } => &[][..],
+                    _ if stage == crate::ShaderStage::Vertex => self.vertex_buffers,
+                    _ => &[][..],
+                };
+                let last = self
+                    .binding_map
+                    .values()
+                    .chain(chosen.values())
+                    .filter_map(slot)
+                    .chain(vertex_buffers.iter().map(|layout| layout.buffer))
+                    .chain(match *inner {
+                        crate::TypeInner::Image { .. }
+                        | crate::TypeInner::DepthImage { .. }
+                        | crate::TypeInner::Sampler { .. } => None,
+                        _ => self.sizes_buffer,
+                    })
+                    .max();
+                let index = match last {
+                    Some(index) => index
+                        .checked_add(1)
+                        .ok_or_else(|| Error::MissingBindTarget(source.clone()))?,
+                    None => 0,
+                };
+                let mut target = BindTarget {
+                    mutable,
+                    ..BindTarget::default()
+                };
+                match *inner {
+                    crate::TypeInner::Image { .. } | crate::TypeInner::DepthImage { .. } => {
+                        target.texture = Some(index)
+                    }
+                    crate::TypeInner::Sampler { .. } => target.sampler = Some(index),
+                    _ => target.buffer = Some(index),
+                }
+                target
+            }
+            None => return Err(Error::MissingBindTarget(source)),
+        };
+        chosen.insert(source, target.clone());
+        Ok(target)
+    }
+
     fn resolve_binding(
         &self,
         binding: &crate::Binding,
@@ -170,6 +672,14 @@ impl Indexed for crate::Handle<crate::Function> {
     }
 }
 
+struct ArgumentBufferIndex(u32);
+impl Indexed for ArgumentBufferIndex {
+    const CLASS: &'static str = "ArgumentBuffer";
+    fn id(&self) -> usize {
+        self.0 as usize
+    }
+}
+
 struct MemberIndex(usize);
 impl Indexed for MemberIndex {
     const CLASS: &'static str = "field";
@@ -184,20 +694,20 @@ impl Indexed for ParameterIndex {
         self.0
     }
 }
-struct InputStructIndex(crate::Handle<crate::Function>);
+struct InputStructIndex(usize);
 impl Indexed for InputStructIndex {
     const CLASS: &'static str = "Input";
     const PREFIX: bool = true;
     fn id(&self) -> usize {
-        self.0.index()
+        self.0
     }
 }
-struct OutputStructIndex(crate::Handle<crate::Function>);
+struct OutputStructIndex(usize);
 impl Indexed for OutputStructIndex {
     const CLASS: &'static str = "Output";
     const PREFIX: bool = true;
     fn id(&self) -> usize {
-        self.0.index()
+        self.0
     }
 }
 
@@ -206,7 +716,188 @@ enum NameSource<'a> {
     Index(usize),
 }
 
-const RESERVED_NAMES: &[&str] = &["main"];
+/// Names that can't be used as identifiers in the generated code:
+/// C++ keywords, Metal keywords and types, and the library functions we call.
+const RESERVED_NAMES: &[&str] = &[
+    // C++
+    "alignas",
+    "alignof",
+    "and",
+    "and_eq",
+    "asm",
+    "auto",
+    "bitand",
+    "bitor",
+    "bool",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "compl",
+    "const",
+    "const_cast",
+    "constexpr",
+    "continue",
+    "decltype",
+    "default",
+    "delete",
+    "do",
+    "double",
+    "dynamic_cast",
+    "else",
+    "enum",
+    "explicit",
+    "export",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "friend",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "mutable",
+    "namespace",
+    "new",
+    "noexcept",
+    "not",
+    "not_eq",
+    "nullptr",
+    "operator",
+    "or",
+    "or_eq",
+    "private",
+    "protected",
+    "public",
+    "register",
+    "reinterpret_cast",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "static_assert",
+    "static_cast",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "thread_local",
+    "throw",
+    "true",
+    "try",
+    "typedef",
+    "typeid",
+    "typename",
+    "union",
+    "unsigned",
+    "using",
+    "virtual",
+    "void",
+    "volatile",
+    "wchar_t",
+    "while",
+    "xor",
+    "xor_eq",
+    // Metal
+    "access",
+    "array",
+    "constant",
+    "depth2d",
+    "depth2d_array",
+    "depthcube",
+    "depthcube_array",
+    "device",
+    "fragment",
+    "half",
+    "kernel",
+    "main",
+    "metal",
+    "sampler",
+    "size_t",
+    "texture1d",
+    "texture1d_array",
+    "texture2d",
+    "texture2d_array",
+    "texture2d_ms",
+    "texture3d",
+    "texturecube",
+    "texturecube_array",
+    "thread",
+    "threadgroup",
+    "uchar",
+    "uint",
+    "ulong",
+    "ushort",
+    "vertex",
+    // library functions
+    "abs",
+    "atan2",
+    "clamp",
+    "cos",
+    "cross",
+    "dfdx",
+    "dfdy",
+    "discard_fragment",
+    "distance",
+    "dot",
+    "fwidth",
+    "isinf",
+    "isnan",
+    "length",
+    "max",
+    "min",
+    "mix",
+    "normalize",
+    "select",
+    "sin",
+    // names given by the writer
+    LOCATION_INPUT_STRUCT_NAME,
+    OUTPUT_STRUCT_NAME,
+    VERTEX_ID_NAME,
+    INSTANCE_ID_NAME,
+    BUFFER_SIZES_NAME,
+];
+
+/// Checks if the name is reserved, including the vector and matrix types.
+fn is_reserved(name: &str) -> bool {
+    const SCALARS: &[&str] = &[
+        "bool", "char", "uchar", "short", "ushort", "int", "uint", "long", "ulong", "half",
+        "float", "double",
+    ];
+    if RESERVED_NAMES.contains(&name) {
+        return true;
+    }
+    SCALARS
+        .iter()
+        .any(|scalar| match name.strip_prefix(scalar) {
+            Some(dims) => {
+                let dims = dims.as_bytes();
+                let is_size = |c: u8| (b'2'..=b'4').contains(&c);
+                match *dims {
+                    [size] => is_size(size),
+                    [columns, b'x', rows] => is_size(columns) && is_size(rows),
+                    _ => false,
+                }
+            }
+            None => false,
+        })
+}
+
+/// Replaces the characters that aren't allowed in identifiers.
+fn write_sanitized(formatter: &mut Formatter<'_>, name: &str) -> Result<(), FmtError> {
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            formatter.write_char(c)?;
+        } else {
+            formatter.write_char('_')?;
+        }
+    }
+    Ok(())
+}
 
 struct Name<'a> {
     class: &'static str,
@@ -218,14 +909,29 @@ impl Display for Name<'_> {
             NameSource::Custom {
                 name,
                 prefix: false,
-            } if RESERVED_NAMES.contains(&name) => write!(formatter, "{}_", name),
-            NameSource::Custom {
-                name,
-                prefix: false,
-            } => formatter.write_str(name),
+            } => {
+                if name.starts_with(|c: char| c.is_ascii_digit()) {
+                    formatter.write_str("_")?;
+                }
+                write_sanitized(formatter, name)?;
+                if is_reserved(name) {
+                    formatter.write_str("_")?;
+                }
+                Ok(())
+            }
             NameSource::Custom { name, prefix: true } => {
-                let (head, tail) = name.split_at(1);
-                write!(formatter, "{}{}{}", self.class, head.to_uppercase(), tail)
+                formatter.write_str(self.class)?;
+                let mut chars = name.chars();
+                if let Some(head) = chars.next() {
+                    let head = head.to_ascii_uppercase();
+                    let head = if head.is_ascii_alphanumeric() {
+                        head
+                    } else {
+                        '_'
+                    };
+                    formatter.write_char(head)?;
+                }
+                write_sanitized(formatter, chars.as_str())
             }
             NameSource::Index(index) => write!(formatter, "{}{}", self.class, index),
         }
@@ -262,24 +968,35 @@ struct TypedGlobalVariable<'a> {
     module: &'a crate::Module,
     handle: crate::Handle<crate::GlobalVariable>,
     usage: crate::GlobalUse,
+    /// Declare a reference to the variable, as a function parameter.
+    reference: bool,
 }
 
 impl<'a> TypedGlobalVariable<'a> {
     fn try_fmt<W: Write>(&self, formatter: &mut W) -> Result<(), Error> {
         let var = &self.module.global_variables[self.handle];
         let name = var.name.or_index(self.handle);
-        let (space_qualifier, reference) = match var.class {
-            crate::StorageClass::Constant
-            | crate::StorageClass::Uniform
-            | crate::StorageClass::StorageBuffer => {
-                let space = if self.usage.contains(crate::GlobalUse::STORE) {
-                    "device "
-                } else {
-                    "constant "
-                };
-                (space, "&")
-            }
-            _ => ("", ""),
+        let (space_qualifier, reference) = match self.module.types[var.ty].inner {
+            // textures and samplers are always passed by value
+            crate::TypeInner::Image { .. }
+            | crate::TypeInner::DepthImage { .. }
+            | crate::TypeInner::Sampler { .. } => ("", ""),
+            _ => match var.class {
+                crate::StorageClass::Constant
+                | crate::StorageClass::Uniform
+                | crate::StorageClass::StorageBuffer => {
+                    let space = if self.usage.contains(crate::GlobalUse::STORE) {
+                        "device "
+                    } else {
+                        "constant "
+                    };
+                    (space, "&")
+                }
+                crate::StorageClass::WorkGroup if self.reference => ("threadgroup ", "&"),
+                crate::StorageClass::WorkGroup => ("threadgroup ", ""),
+                _ if self.reference => ("thread ", "&"),
+                _ => ("", ""),
+            },
         };
         let ty_name = self.module.types[var.ty].name.or_index(var.ty);
         Ok(write!(
@@ -350,7 +1067,38 @@ impl ResolvedBinding {
 }
 
 pub struct Writer<W> {
-    out: W,
+    out: LineCounter<W>,
+    /// Usage of the global variables by each function, including its callees.
+    global_usage: FastHashMap<Handle<crate::Function>, Vec<crate::GlobalUse>>,
+    /// Usage of the global variables by the whole module.
+    module_usage: Vec<crate::GlobalUse>,
+    function_names: FastHashMap<Handle<crate::Function>, String>,
+    entry_point_names: Vec<String>,
+    binding_maps: FastHashMap<crate::ShaderStage, BindingMap>,
+    /// Global variables with runtime-sized arrays, whose sizes are passed to the shaders.
+    sized_buffers: Vec<Handle<crate::GlobalVariable>>,
+    /// Types of the module, with the ones of the expressions added by the typifier.
+    types: Arena<crate::Type>,
+    typifier: Typifier,
+    /// Number of loops written, for naming their variables.
+    loop_count: usize,
+    /// Origins of the written lines, if requested.
+    source_map: Option<Vec<SourceMapping>>,
+    annotate: bool,
+}
+
+/// Output of the writer, counting the lines for the source map.
+struct LineCounter<W> {
+    inner: W,
+    /// Number of complete lines written.
+    lines: u32,
+}
+
+impl<W: Write> Write for LineCounter<W> {
+    fn write_str(&mut self, s: &str) -> Result<(), FmtError> {
+        self.lines += s.matches('\n').count() as u32;
+        self.inner.write_str(s)
+    }
 }
 
 fn scalar_kind_string(kind: crate::ScalarKind) -> &'static str {
@@ -372,6 +1120,12 @@ fn vector_size_string(size: crate::VectorSize) -> &'static str {
 
 const OUTPUT_STRUCT_NAME: &str = "output";
 const LOCATION_INPUT_STRUCT_NAME: &str = "input";
+const ARGUMENT_BUFFER_NAME: &str = "argument_buffer";
+const VERTEX_BUFFER_NAME: &str = "vertex_buffer";
+const VERTEX_ID_NAME: &str = "vertex_id";
+const INSTANCE_ID_NAME: &str = "instance_id";
+const BUFFER_SIZES_NAME: &str = "buffer_sizes";
+const LOOP_INIT_NAME: &str = "loop_init";
 const COMPONENTS: &[char] = &['x', 'y', 'z', 'w'];
 
 fn separate(is_last: bool) -> &'static str {
@@ -414,11 +1168,22 @@ impl<W: Write> Writer<W> {
         log::trace!("expression {:?} = {:?}", expr_handle, expression);
         match *expression {
             crate::Expression::Access { base, index } => {
+                let length = self.array_length(base, function, module);
                 match *self.put_expression(base, function, module)?.borrow() {
                     crate::TypeInner::Array { base, .. } => {
-                        //TODO: add size check
                         self.out.write_str("[")?;
-                        self.put_expression(index, function, module)?;
+                        match length {
+                            // clamp the index to the runtime length of the array,
+                            // which is empty if the bound buffer is too small
+                            Some(length) => {
+                                self.out.write_str("min(uint(")?;
+                                self.put_expression(index, function, module)?;
+                                write!(self.out, "), max({}, 1u) - 1)", length)?;
+                            }
+                            None => {
+                                self.put_expression(index, function, module)?;
+                            }
+                        }
                         self.out.write_str("]")?;
                         Ok(module.borrow_type(base))
                     }
@@ -485,30 +1250,30 @@ impl<W: Write> Writer<W> {
                         self.put_expression(components[0], function, module)?;
                         write!(self.out, ")")?;
                     }
+                    crate::TypeInner::Matrix { .. } => {
+                        write!(self.out, "{}(", module.types[ty].name.or_index(ty))?;
+                        self.put_expression_list(components, function, module)?;
+                        write!(self.out, ")")?;
+                    }
+                    // aggregates are initialized with braces
+                    crate::TypeInner::Array { .. } | crate::TypeInner::Struct { .. } => {
+                        write!(self.out, "{}{{", module.types[ty].name.or_index(ty))?;
+                        self.put_expression_list(components, function, module)?;
+                        write!(self.out, "}}")?;
+                    }
                     _ => return Err(Error::UnsupportedCompose(ty)),
                 }
                 Ok(MaybeOwned::Borrowed(inner))
             }
+            crate::Expression::FunctionParameter(index) => {
+                write!(self.out, "{}", Name::from(ParameterIndex(index as usize)))?;
+                Ok(module.borrow_type(function.parameter_types[index as usize]))
+            }
             crate::Expression::GlobalVariable(handle) => {
                 let var = &module.global_variables[handle];
-                let inner = &module.types[var.ty].inner;
-                match var.class {
-                    crate::StorageClass::Output => {
-                        if let crate::TypeInner::Struct { .. } = *inner {
-                            return Ok(MaybeOwned::Borrowed(inner));
-                        }
-                        write!(self.out, "{}.", OUTPUT_STRUCT_NAME)?;
-                    }
-                    crate::StorageClass::Input => {
-                        if let Some(crate::Binding::Location(_)) = var.binding {
-                            write!(self.out, "{}.", LOCATION_INPUT_STRUCT_NAME)?;
-                        }
-                    }
-                    _ => {}
-                }
                 let name = var.name.or_index(handle);
                 write!(self.out, "{}", name)?;
-                Ok(MaybeOwned::Borrowed(inner))
+                Ok(module.borrow_type(var.ty))
             }
             crate::Expression::LocalVariable(handle) => {
                 let var = &function.local_variables[handle];
@@ -518,208 +1283,473 @@ impl<W: Write> Writer<W> {
                 Ok(MaybeOwned::Borrowed(inner))
             }
             crate::Expression::Load { pointer } => {
-                //write!(self.out, "*")?;
-                match *self.put_expression(pointer, function, module)?.borrow() {
+                // variables are referenced by value, so loading them is a no-op
+                let result = self.put_expression(pointer, function, module)?;
+                match *result.borrow() {
                     crate::TypeInner::Pointer { base, .. } => Ok(module.borrow_type(base)),
-                    ref other => Err(Error::UnexpectedLoadPointer(other.clone())),
+                    _ => Ok(result),
                 }
             }
             crate::Expression::Unary { op, expr } => {
-                let op_str = match op {
-                    crate::UnaryOperator::Negate => "-",
-                    crate::UnaryOperator::Not => "!",
+                let op_str = match (op, self.resolved_type(expr)) {
+                    (crate::UnaryOperator::Negate, _) => "-",
+                    (crate::UnaryOperator::Not, &crate::TypeInner::Scalar { kind, .. })
+                    | (crate::UnaryOperator::Not, &crate::TypeInner::Vector { kind, .. })
+                        if kind != crate::ScalarKind::Bool =>
+                    {
+                        "~"
+                    }
+                    (crate::UnaryOperator::Not, _) => "!",
                 };
                 write!(self.out, "{}", op_str)?;
                 self.put_expression(expr, function, module)
             }
             crate::Expression::Binary { op, left, right } => {
+                let is_float = match *self.resolved_type(left) {
+                    crate::TypeInner::Scalar { kind, .. }
+                    | crate::TypeInner::Vector { kind, .. } => kind == crate::ScalarKind::Float,
+                    _ => false,
+                };
                 let op_str = match op {
                     crate::BinaryOperator::Add => "+",
                     crate::BinaryOperator::Subtract => "-",
                     crate::BinaryOperator::Multiply => "*",
                     crate::BinaryOperator::Divide => "/",
+                    crate::BinaryOperator::Modulo if is_float => {
+                        write!(self.out, "fmod(")?;
+                        self.put_expression(left, function, module)?;
+                        write!(self.out, ", ")?;
+                        self.put_expression(right, function, module)?;
+                        write!(self.out, ")")?;
+                        return Ok(self.resolved_type_owned(expr_handle));
+                    }
                     crate::BinaryOperator::Modulo => "%",
                     crate::BinaryOperator::Equal => "==",
                     crate::BinaryOperator::NotEqual => "!=",
                     crate::BinaryOperator::Less => "<",
                     crate::BinaryOperator::LessEqual => "<=",
-                    crate::BinaryOperator::Greater => "==",
+                    crate::BinaryOperator::Greater => ">",
                     crate::BinaryOperator::GreaterEqual => ">=",
                     crate::BinaryOperator::And => "&",
-                    other => return Err(Error::UnsupportedBinaryOp(other)),
-                };
-                //write!(self.out, "(")?;
-                let ty_left = self.put_expression(left, function, module)?;
-                write!(self.out, " {} ", op_str)?;
-                let ty_right = self.put_expression(right, function, module)?;
-                //write!(self.out, ")")?;
-
-                Ok(if op_str.len() == 1 {
-                    match (ty_left.borrow(), ty_right.borrow()) {
-                        (
-                            &crate::TypeInner::Scalar { kind, width },
-                            &crate::TypeInner::Scalar { .. },
-                        ) => MaybeOwned::Owned(crate::TypeInner::Scalar { kind, width }),
-                        (
-                            &crate::TypeInner::Scalar { .. },
-                            &crate::TypeInner::Vector { size, kind, width },
-                        )
-                        | (
-                            &crate::TypeInner::Vector { size, kind, width },
-                            &crate::TypeInner::Scalar { .. },
-                        )
-                        | (
-                            &crate::TypeInner::Vector { size, kind, width },
-                            &crate::TypeInner::Vector { .. },
-                        ) => MaybeOwned::Owned(crate::TypeInner::Vector { size, kind, width }),
-                        (other_left, other_right) => {
-                            return Err(Error::UnableToInferBinaryOpOutput(
-                                other_left.clone(),
-                                op,
-                                other_right.clone(),
-                            ))
+                    crate::BinaryOperator::ExclusiveOr => "^",
+                    crate::BinaryOperator::InclusiveOr => "|",
+                    crate::BinaryOperator::LogicalAnd => "&&",
+                    crate::BinaryOperator::LogicalOr => "||",
+                    crate::BinaryOperator::ShiftLeftLogical => "<<",
+                    crate::BinaryOperator::ShiftRightLogical
+                    | crate::BinaryOperator::ShiftRightArithmetic => {
+                        // the kind of the left operand picks the logical or arithmetic shift,
+                        // so the operand is reinterpreted when it doesn't match the operator
+                        let (kind, size) = match *self.resolved_type(left) {
+                            crate::TypeInner::Scalar { kind, .. } => (kind, None),
+                            crate::TypeInner::Vector { kind, size, .. } => (kind, Some(size)),
+                            _ => (crate::ScalarKind::Bool, None),
+                        };
+                        let shift_kind = match (op, kind) {
+                            (crate::BinaryOperator::ShiftRightLogical, crate::ScalarKind::Sint) => {
+                                Some(crate::ScalarKind::Uint)
+                            }
+                            (
+                                crate::BinaryOperator::ShiftRightArithmetic,
+                                crate::ScalarKind::Uint,
+                            ) => Some(crate::ScalarKind::Sint),
+                            _ => None,
+                        };
+                        if let Some(shift_kind) = shift_kind {
+                            let size_str = size.map_or("", vector_size_string);
+                            write!(
+                                self.out,
+                                "as_type<{}{}>(as_type<{}{}>(",
+                                scalar_kind_string(kind),
+                                size_str,
+                                scalar_kind_string(shift_kind),
+                                size_str
+                            )?;
+                            self.put_expression(left, function, module)?;
+                            write!(self.out, ") >> ")?;
+                            self.put_expression(right, function, module)?;
+                            write!(self.out, ")")?;
+                            return Ok(self.resolved_type_owned(expr_handle));
                         }
+                        ">>"
                     }
-                } else {
-                    MaybeOwned::Owned(crate::TypeInner::Scalar {
-                        kind: crate::ScalarKind::Bool,
-                        width: 1,
-                    })
-                })
+                };
+                write!(self.out, "(")?;
+                self.put_expression(left, function, module)?;
+                write!(self.out, " {} ", op_str)?;
+                self.put_expression(right, function, module)?;
+                write!(self.out, ")")?;
+                Ok(self.resolved_type_owned(expr_handle))
             }
             crate::Expression::ImageSample {
                 image,
                 sampler,
                 coordinate,
-                depth_ref: None,
+                depth_ref,
             } => {
                 let ty_image = self.put_expression(image, function, module)?;
-                write!(self.out, ".sample(")?;
-                self.put_expression(sampler, function, module)?;
-                write!(self.out, ", ")?;
-                self.put_expression(coordinate, function, module)?;
-                write!(self.out, ")")?;
-                match *ty_image.borrow() {
-                    crate::TypeInner::Image { base, .. } => Ok(module.borrow_type(base)),
-                    ref other => Err(Error::UnexpectedImageType(other.clone())),
+                let (dim, arrayed, flags, depth) = match *ty_image.borrow() {
+                    crate::TypeInner::Image { dim, flags, .. } => (
+                        dim,
+                        flags.contains(crate::ImageFlags::ARRAYED),
+                        flags,
+                        false,
+                    ),
+                    crate::TypeInner::DepthImage { dim, arrayed } => {
+                        (dim, arrayed, crate::ImageFlags::empty(), true)
+                    }
+                    ref other => return Err(Error::UnexpectedImageType(other.clone())),
+                };
+                match depth_ref {
+                    // multisampled textures can only be read by texel, not sampled
+                    None if flags.contains(crate::ImageFlags::MULTISAMPLED) => {
+                        return Err(Error::InvalidImageFlags(flags));
+                    }
+                    None => {
+                        write!(self.out, ".sample(")?;
+                        self.put_expression(sampler, function, module)?;
+                        write!(self.out, ", ")?;
+                        self.put_image_coordinate(coordinate, dim, arrayed, function, module)?;
+                    }
+                    Some(dref) if depth => {
+                        write!(self.out, ".sample_compare(")?;
+                        self.put_expression(sampler, function, module)?;
+                        write!(self.out, ", ")?;
+                        self.put_image_coordinate(coordinate, dim, arrayed, function, module)?;
+                        write!(self.out, ", ")?;
+                        self.put_expression(dref, function, module)?;
+                    }
+                    Some(_) => return Err(Error::UnexpectedImageType(ty_image.borrow().clone())),
                 }
-            }
-            crate::Expression::ImageSample {
-                image,
-                sampler,
-                coordinate,
-                depth_ref: Some(dref),
-            } => {
-                self.put_expression(image, function, module)?;
-                write!(self.out, ".sample_compare(")?;
-                self.put_expression(sampler, function, module)?;
-                write!(self.out, ", ")?;
-                self.put_expression(coordinate, function, module)?;
-                write!(self.out, ", ")?;
-                self.put_expression(dref, function, module)?;
                 write!(self.out, ")")?;
-                Ok(MaybeOwned::Owned(crate::TypeInner::Scalar {
-                    kind: crate::ScalarKind::Float,
-                    width: 4,
-                }))
+                Ok(self.resolved_type_owned(expr_handle))
             }
             crate::Expression::Call {
                 origin: crate::FunctionOrigin::External(ref name),
                 ref arguments,
             } => match name.as_str() {
-                "cos" | "normalize" | "sin" => {
-                    write!(self.out, "{}(", name)?;
-                    let result = self.put_expression(arguments[0], function, module)?;
-                    write!(self.out, ")")?;
-                    Ok(result)
-                }
-                "fclamp" => {
-                    write!(self.out, "clamp(")?;
-                    let result = self.put_expression(arguments[0], function, module)?;
-                    write!(self.out, ", ")?;
-                    self.put_expression(arguments[1], function, module)?;
-                    write!(self.out, ", ")?;
-                    self.put_expression(arguments[2], function, module)?;
+                "arrayLength" => match self.array_length(arguments[0], function, module) {
+                    Some(length) => {
+                        self.out.write_str(&length)?;
+                        Ok(MaybeOwned::Owned(crate::TypeInner::Scalar {
+                            kind: crate::ScalarKind::Uint,
+                            width: 4,
+                        }))
+                    }
+                    None => Err(Error::UnsupportedCall(name.clone())),
+                },
+                _ => {
+                    let msl_name = match name.as_str() {
+                        "fclamp" => "clamp",
+                        "inversesqrt" => "rsqrt",
+                        "abs" | "acos" | "asin" | "atan" | "atan2" | "ceil" | "clamp" | "cos"
+                        | "cross" | "distance" | "dot" | "exp" | "exp2" | "faceforward"
+                        | "floor" | "fract" | "length" | "log" | "log2" | "max" | "min" | "mix"
+                        | "normalize" | "pow" | "reflect" | "sign" | "sin" | "smoothstep"
+                        | "sqrt" | "step" | "tan" | "transpose" => name.as_str(),
+                        other => return Err(Error::UnsupportedCall(other.to_owned())),
+                    };
+                    write!(self.out, "{}(", msl_name)?;
+                    self.put_expression_list(arguments, function, module)?;
                     write!(self.out, ")")?;
-                    Ok(result)
+                    Ok(self.resolved_type_owned(expr_handle))
                 }
-                "atan2" => {
-                    write!(self.out, "{}(", name)?;
-                    let result = self.put_expression(arguments[0], function, module)?;
-                    write!(self.out, ", ")?;
-                    self.put_expression(arguments[1], function, module)?;
-                    write!(self.out, ")")?;
-                    Ok(result)
+            },
+            crate::Expression::Call {
+                origin: crate::FunctionOrigin::Local(callee),
+                ref arguments,
+            } => {
+                write!(self.out, "{}(", self.function_names[&callee])?;
+                self.put_expression_list(arguments, function, module)?;
+                let mut separator = if arguments.is_empty() { "" } else { ", " };
+                // the callee gets the global variables it uses as parameters
+                for (handle, var) in module.global_variables.iter() {
+                    if !self.global_usage[&callee][handle.index()].is_empty() {
+                        write!(self.out, "{}{}", separator, var.name.or_index(handle))?;
+                        separator = ", ";
+                    }
                 }
-                "distance" => {
-                    write!(self.out, "distance(")?;
-                    let result = match *self
-                        .put_expression(arguments[0], function, module)?
-                        .borrow()
-                    {
-                        crate::TypeInner::Vector { kind, width, .. } => {
-                            crate::TypeInner::Scalar { kind, width }
-                        }
-                        ref other => return Err(Error::UnexpectedDistanceArgument(other.clone())),
-                    };
-                    write!(self.out, ", ")?;
-                    self.put_expression(arguments[1], function, module)?;
-                    write!(self.out, ")")?;
-                    Ok(MaybeOwned::Owned(result))
+                if self.uses_buffer_sizes(callee) {
+                    write!(self.out, "{}{}", separator, BUFFER_SIZES_NAME)?;
                 }
-                "length" => {
-                    write!(self.out, "length(")?;
-                    let result = match *self
-                        .put_expression(arguments[0], function, module)?
-                        .borrow()
-                    {
-                        crate::TypeInner::Vector { kind, width, .. } => {
-                            crate::TypeInner::Scalar { kind, width }
-                        }
-                        ref other => return Err(Error::UnexpectedDistanceArgument(other.clone())),
-                    };
-                    write!(self.out, ")")?;
-                    Ok(MaybeOwned::Owned(result))
+                self.out.write_str(")")?;
+                match module.functions[callee].return_type {
+                    Some(ty) => Ok(module.borrow_type(ty)),
+                    None => Err(Error::UnsupportedCall(self.function_names[&callee].clone())),
                 }
-
-                other => Err(Error::UnsupportedCall(other.to_owned())),
-            },
-            ref other => Err(Error::UnsupportedExpression(other.clone())),
-        }
-    }
-
-    fn put_constant<'a>(
-        &mut self,
-        handle: Handle<crate::Constant>,
-        module: &'a crate::Module,
-    ) -> Result<MaybeOwned<'a, crate::TypeInner>, Error> {
-        let constant = &module.constants[handle];
-        let ty = &module.types[constant.ty];
-
-        match constant.inner {
-            crate::ConstantInner::Sint(value) => {
-                write!(self.out, "{}", value)?;
             }
-            crate::ConstantInner::Uint(value) => {
-                write!(self.out, "{}", value)?;
+            crate::Expression::Intrinsic { fun, argument } => {
+                let fun_name = match fun {
+                    crate::IntrinsicFunction::Any => "any",
+                    crate::IntrinsicFunction::All => "all",
+                    crate::IntrinsicFunction::IsNan => "isnan",
+                    crate::IntrinsicFunction::IsInf => "isinf",
+                    crate::IntrinsicFunction::IsFinite => "isfinite",
+                    crate::IntrinsicFunction::IsNormal => "isnormal",
+                };
+                write!(self.out, "{}(", fun_name)?;
+                self.put_expression(argument, function, module)?;
+                write!(self.out, ")")?;
+                Ok(self.resolved_type_owned(expr_handle))
             }
-            crate::ConstantInner::Float(value) => {
-                write!(self.out, "{}", value)?;
-                if value.fract() == 0.0 {
-                    self.out.write_str(".0")?;
-                }
+            crate::Expression::DotProduct(left, right) => {
+                write!(self.out, "dot(")?;
+                self.put_expression(left, function, module)?;
+                write!(self.out, ", ")?;
+                self.put_expression(right, function, module)?;
+                write!(self.out, ")")?;
+                Ok(self.resolved_type_owned(expr_handle))
             }
-            crate::ConstantInner::Bool(value) => {
-                write!(self.out, "{}", value)?;
+            crate::Expression::CrossProduct(left, right) => {
+                write!(self.out, "cross(")?;
+                self.put_expression(left, function, module)?;
+                write!(self.out, ", ")?;
+                let result = self.put_expression(right, function, module)?;
+                write!(self.out, ")")?;
+                Ok(result)
             }
-            crate::ConstantInner::Composite(ref constituents) => {
-                let ty_name = ty.name.or_index(constant.ty);
-                write!(self.out, "{}(", ty_name)?;
-                for (i, handle) in constituents.iter().enumerate() {
-                    if i != 0 {
-                        write!(self.out, ", ")?;
-                    }
-                    self.put_constant(*handle, module)?;
+            crate::Expression::Derivative { axis, expr } => {
+                let fun_name = match axis {
+                    crate::DerivativeAxis::X => "dfdx",
+                    crate::DerivativeAxis::Y => "dfdy",
+                    crate::DerivativeAxis::Width => "fwidth",
+                };
+                write!(self.out, "{}(", fun_name)?;
+                let result = self.put_expression(expr, function, module)?;
+                write!(self.out, ")")?;
+                Ok(result)
+            }
+            crate::Expression::Select {
+                condition,
+                accept,
+                reject,
+            } => {
+                // `select(a, b, c)` is `c ? b : a`, per component
+                write!(self.out, "select(")?;
+                self.put_expression(reject, function, module)?;
+                write!(self.out, ", ")?;
+                let result = self.put_expression(accept, function, module)?;
+                write!(self.out, ", ")?;
+                self.put_expression(condition, function, module)?;
+                write!(self.out, ")")?;
+                Ok(result)
+            }
+            crate::Expression::As {
+                expr,
+                kind,
+                convert,
+            } => {
+                let (target, inner) = match *self.resolved_type(expr) {
+                    crate::TypeInner::Scalar { width, .. } => (
+                        scalar_kind_string(kind).to_string(),
+                        crate::TypeInner::Scalar { kind, width },
+                    ),
+                    crate::TypeInner::Vector { size, width, .. } => (
+                        format!("{}{}", scalar_kind_string(kind), vector_size_string(size)),
+                        crate::TypeInner::Vector { size, kind, width },
+                    ),
+                    ref other => return Err(Error::UnexpectedCast(other.clone())),
+                };
+                if convert {
+                    write!(self.out, "{}(", target)?;
+                } else {
+                    write!(self.out, "as_type<{}>(", target)?;
+                }
+                self.put_expression(expr, function, module)?;
+                write!(self.out, ")")?;
+                Ok(MaybeOwned::Owned(inner))
+            }
+        }
+    }
+
+    fn put_expression_list(
+        &mut self,
+        expressions: &[Handle<crate::Expression>],
+        function: &crate::Function,
+        module: &crate::Module,
+    ) -> Result<(), Error> {
+        for (index, &handle) in expressions.iter().enumerate() {
+            if index != 0 {
+                write!(self.out, ", ")?;
+            }
+            self.put_expression(handle, function, module)?;
+        }
+        Ok(())
+    }
+
+    /// Gets the type of an expression of the current function, from the typifier.
+    fn resolved_type(&self, expr_handle: Handle<crate::Expression>) -> &crate::TypeInner {
+        &self.types[self.typifier.get(expr_handle)].inner
+    }
+
+    fn resolved_type_owned<'a>(
+        &self,
+        expr_handle: Handle<crate::Expression>,
+    ) -> MaybeOwned<'a, crate::TypeInner> {
+        MaybeOwned::Owned(self.resolved_type(expr_handle).clone())
+    }
+
+    /// Writes the coordinate of an image access, followed by the array layer
+    /// for arrayed images, as expected by the texture methods.
+    fn put_image_coordinate(
+        &mut self,
+        coordinate: Handle<crate::Expression>,
+        dim: crate::ImageDimension,
+        arrayed: bool,
+        function: &crate::Function,
+        module: &crate::Module,
+    ) -> Result<(), Error> {
+        let size = match *self.resolved_type(coordinate) {
+            crate::TypeInner::Scalar { .. } => 1,
+            crate::TypeInner::Vector { size, .. } => size as usize,
+            ref other => return Err(Error::UnexpectedImageCoordinate(other.clone())),
+        };
+        let count = match dim {
+            crate::ImageDimension::D1 => 1,
+            crate::ImageDimension::D2 => 2,
+            crate::ImageDimension::D3 | crate::ImageDimension::Cube => 3,
+        };
+        if size < count + arrayed as usize {
+            return Err(Error::UnexpectedImageCoordinate(
+                self.resolved_type(coordinate).clone(),
+            ));
+        }
+        self.put_expression(coordinate, function, module)?;
+        if size != count {
+            let swizzle: String = COMPONENTS[..count].iter().collect();
+            write!(self.out, ".{}", swizzle)?;
+        }
+        if arrayed {
+            write!(self.out, ", ")?;
+            self.put_array_layer(coordinate, count, function, module)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the array layer of an image coordinate, from the given component.
+    ///
+    /// Like in SPIR-V, a floating-point layer is rounded to the nearest integer.
+    fn put_array_layer(
+        &mut self,
+        coordinate: Handle<crate::Expression>,
+        index: usize,
+        function: &crate::Function,
+        module: &crate::Module,
+    ) -> Result<(), Error> {
+        let (size, kind) = match *self.resolved_type(coordinate) {
+            crate::TypeInner::Scalar { kind, .. } => (1, kind),
+            crate::TypeInner::Vector { size, kind, .. } => (size as usize, kind),
+            ref other => return Err(Error::UnexpectedImageCoordinate(other.clone())),
+        };
+        let float = kind == crate::ScalarKind::Float;
+        write!(self.out, "uint(")?;
+        if float {
+            write!(self.out, "rint(")?;
+        }
+        self.put_expression(coordinate, function, module)?;
+        if size > 1 {
+            write!(self.out, ".{}", COMPONENTS[index])?;
+        }
+        if float {
+            write!(self.out, ")")?;
+        }
+        write!(self.out, ")")?;
+        Ok(())
+    }
+
+    /// Writes the expression computing the length of a runtime-sized array
+    /// in one of the sized buffers.
+    fn array_length(
+        &self,
+        expr_handle: Handle<crate::Expression>,
+        function: &crate::Function,
+        module: &crate::Module,
+    ) -> Option<String> {
+        let (handle, offset, ty) = match function.expressions[expr_handle] {
+            crate::Expression::GlobalVariable(handle) => {
+                (handle, 0, module.global_variables[handle].ty)
+            }
+            crate::Expression::AccessIndex { base, index } => {
+                let handle = match function.expressions[base] {
+                    crate::Expression::GlobalVariable(handle) => handle,
+                    _ => return None,
+                };
+                match module.types[module.global_variables[handle].ty].inner {
+                    crate::TypeInner::Struct { ref members } => {
+                        let member = members.get(index as usize)?;
+                        match member.origin {
+                            crate::MemberOrigin::Offset(offset) => (handle, offset, member.ty),
+                            _ => return None,
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        let index = self
+            .sized_buffers
+            .iter()
+            .position(|&sized| sized == handle)?;
+        match module.types[ty].inner {
+            crate::TypeInner::Array {
+                size: crate::ArraySize::Dynamic,
+                stride: Some(stride),
+                ..
+            } if offset == 0 => Some(format!("({}[{}] / {})", BUFFER_SIZES_NAME, index, stride)),
+            // the buffer may be bound with fewer bytes than the members before the array
+            crate::TypeInner::Array {
+                size: crate::ArraySize::Dynamic,
+                stride: Some(stride),
+                ..
+            } => Some(format!(
+                "((max({0}[{1}], {2}u) - {2}u) / {3})",
+                BUFFER_SIZES_NAME, index, offset, stride
+            )),
+            _ => None,
+        }
+    }
+
+    /// Checks if the function needs the `buffer_sizes` buffer.
+    fn uses_buffer_sizes(&self, fun_handle: Handle<crate::Function>) -> bool {
+        let usage = &self.global_usage[&fun_handle];
+        self.sized_buffers
+            .iter()
+            .any(|handle| !usage[handle.index()].is_empty())
+    }
+
+    fn put_constant<'a>(
+        &mut self,
+        handle: Handle<crate::Constant>,
+        module: &'a crate::Module,
+    ) -> Result<MaybeOwned<'a, crate::TypeInner>, Error> {
+        let constant = &module.constants[handle];
+        let ty = &module.types[constant.ty];
+
+        match constant.inner {
+            crate::ConstantInner::Sint(value) => {
+                write!(self.out, "{}", value)?;
+            }
+            crate::ConstantInner::Uint(value) => {
+                write!(self.out, "{}", value)?;
+            }
+            crate::ConstantInner::Float(value) => {
+                write!(self.out, "{}", value)?;
+                if value.fract() == 0.0 {
+                    self.out.write_str(".0")?;
+                }
+            }
+            crate::ConstantInner::Bool(value) => {
+                write!(self.out, "{}", value)?;
+            }
+            crate::ConstantInner::Composite(ref constituents) => {
+                let ty_name = ty.name.or_index(constant.ty);
+                write!(self.out, "{}(", ty_name)?;
+                for (i, handle) in constituents.iter().enumerate() {
+                    if i != 0 {
+                        write!(self.out, ", ")?;
+                    }
+                    self.put_constant(*handle, module)?;
                 }
                 write!(self.out, ")")?;
             }
@@ -728,17 +1758,86 @@ impl<W: Write> Writer<W> {
         Ok(MaybeOwned::Borrowed(&ty.inner))
     }
 
+    /// Starts mapping the lines written next to a function, or one of its expressions,
+    /// after annotating them. Returns the index of the mapping to end.
+    fn begin_mapping(
+        &mut self,
+        level: &Level,
+        function: Handle<crate::Function>,
+        expression: Option<Handle<crate::Expression>>,
+        module: &crate::Module,
+    ) -> Result<Option<usize>, Error> {
+        let span = match expression {
+            Some(expr_handle) => module.spans.expressions.get(&(function, expr_handle)),
+            None => module.spans.functions.get(&function),
+        }
+        .cloned();
+        if self.annotate {
+            match expression {
+                Some(expr_handle) => {
+                    write!(self.out, "{}// expression {}", level, expr_handle.index())?
+                }
+                None => write!(self.out, "{}// function {}", level, function.index())?,
+            }
+            if let Some(ref span) = span {
+                match module.spans.files[span.file].name {
+                    Some(ref name) => write!(self.out, " ({}", name)?,
+                    None => write!(self.out, " (file {}", span.file.index())?,
+                }
+                write!(self.out, ":{}:{})", span.line, span.column)?;
+            }
+            writeln!(self.out)?;
+        }
+        let line = self.out.lines + 1;
+        Ok(self.source_map.as_mut().map(|source_map| {
+            source_map.push(SourceMapping {
+                lines: line..line,
+                function,
+                expression,
+                span,
+            });
+            source_map.len() - 1
+        }))
+    }
+
+    fn end_mapping(&mut self, index: Option<usize>) {
+        if let (Some(source_map), Some(index)) = (self.source_map.as_mut(), index) {
+            source_map[index].lines.end = self.out.lines + 1;
+        }
+    }
+
     fn put_statement<'a>(
         &mut self,
         level: Level,
         statement: &crate::Statement,
-        function: &crate::Function,
-        has_output: bool,
+        fun_handle: Handle<crate::Function>,
         module: &'a crate::Module,
     ) -> Result<(), Error> {
         log::trace!("statement[{}] {:?}", level.0, statement);
+        let function = &module.functions[fun_handle];
+        // statements are mapped to the expressions they are made of
+        let expression = match *statement {
+            crate::Statement::If { condition, .. } => Some(condition),
+            crate::Statement::Switch { selector, .. } => Some(selector),
+            crate::Statement::Store { value, .. } => Some(value),
+            crate::Statement::Return { value } => value,
+            _ => None,
+        };
+        let mapping = match expression {
+            Some(expr_handle) => {
+                self.begin_mapping(&level, fun_handle, Some(expr_handle), module)?
+            }
+            None => None,
+        };
         match *statement {
             crate::Statement::Empty => {}
+            crate::Statement::Block(ref block) => {
+                writeln!(self.out, "{}{{", level)?;
+                for s in block {
+                    self.put_statement(level.next(), s, fun_handle, module)?;
+                }
+                writeln!(self.out, "{}}}", level)?;
+            }
             crate::Statement::If {
                 condition,
                 ref accept,
@@ -748,26 +1847,68 @@ impl<W: Write> Writer<W> {
                 self.put_expression(condition, function, module)?;
                 writeln!(self.out, ") {{")?;
                 for s in accept {
-                    self.put_statement(level.next(), s, function, has_output, module)?;
+                    self.put_statement(level.next(), s, fun_handle, module)?;
                 }
                 if !reject.is_empty() {
                     writeln!(self.out, "{}}} else {{", level)?;
                     for s in reject {
-                        self.put_statement(level.next(), s, function, has_output, module)?;
+                        self.put_statement(level.next(), s, fun_handle, module)?;
                     }
                 }
                 writeln!(self.out, "{}}}", level)?;
             }
+            crate::Statement::Switch {
+                selector,
+                ref cases,
+                ref default,
+            } => {
+                write!(self.out, "{}switch(", level)?;
+                self.put_expression(selector, function, module)?;
+                writeln!(self.out, ") {{")?;
+                let mut values: Vec<_> = cases.keys().cloned().collect();
+                values.sort();
+                let case_level = level.next();
+                for value in values {
+                    let (ref block, ref fall_through) = cases[&value];
+                    writeln!(self.out, "{}case {}: {{", case_level, value)?;
+                    for s in block {
+                        self.put_statement(case_level.next(), s, fun_handle, module)?;
+                    }
+                    if fall_through.is_none() {
+                        writeln!(self.out, "{}break;", case_level.next())?;
+                    }
+                    writeln!(self.out, "{}}}", case_level)?;
+                }
+                writeln!(self.out, "{}default: {{", case_level)?;
+                for s in default {
+                    self.put_statement(case_level.next(), s, fun_handle, module)?;
+                }
+                writeln!(self.out, "{}}}", case_level)?;
+                writeln!(self.out, "{}}}", level)?;
+            }
             crate::Statement::Loop {
                 ref body,
                 ref continuing,
             } => {
-                writeln!(self.out, "{}while(true) {{", level)?;
-                for s in body {
-                    self.put_statement(level.next(), s, function, has_output, module)?;
+                if continuing.is_empty() {
+                    writeln!(self.out, "{}while(true) {{", level)?;
+                } else {
+                    // the continuing block runs before every iteration but the first,
+                    // so that `continue` goes through it as well
+                    let init_name = format!("{}{}", LOOP_INIT_NAME, self.loop_count);
+                    self.loop_count += 1;
+                    writeln!(self.out, "{}bool {} = true;", level, init_name)?;
+                    writeln!(self.out, "{}while(true) {{", level)?;
+                    let lev2 = level.next();
+                    writeln!(self.out, "{}if (!{}) {{", lev2, init_name)?;
+                    for s in continuing {
+                        self.put_statement(lev2.next(), s, fun_handle, module)?;
+                    }
+                    writeln!(self.out, "{}}}", lev2)?;
+                    writeln!(self.out, "{}{} = false;", lev2, init_name)?;
                 }
-                if !continuing.is_empty() {
-                    //TODO
+                for s in body {
+                    self.put_statement(level.next(), s, fun_handle, module)?;
                 }
                 writeln!(self.out, "{}}}", level)?;
             }
@@ -786,39 +1927,174 @@ impl<W: Write> Writer<W> {
                 writeln!(self.out, "{}continue;", level)?;
             }
             crate::Statement::Return { value } => {
-                write!(self.out, "{}return ", level)?;
-                match value {
-                    None if has_output => self.out.write_str(OUTPUT_STRUCT_NAME)?,
-                    None => {}
-                    Some(expr_handle) if has_output => {
-                        return Err(Error::UnableToReturnValue(expr_handle));
-                    }
-                    Some(expr_handle) => {
-                        self.put_expression(expr_handle, function, module)?;
-                    }
+                write!(self.out, "{}return", level)?;
+                if let Some(expr_handle) = value {
+                    self.out.write_str(" ")?;
+                    self.put_expression(expr_handle, function, module)?;
                 }
                 writeln!(self.out, ";")?;
             }
-            ref other => return Err(Error::UnsupportedStatement(other.clone())),
+            crate::Statement::Kill => {
+                writeln!(self.out, "{}discard_fragment();", level)?;
+            }
         };
+        self.end_mapping(mapping);
         Ok(())
     }
 
-    pub fn write(&mut self, module: &crate::Module, options: Options) -> Result<(), Error> {
+    pub fn write(
+        &mut self,
+        module: &crate::Module,
+        options: Options,
+    ) -> Result<TranslationInfo, Error> {
+        if !LANGUAGE_VERSIONS.contains(&options.lang_version) {
+            let (major, minor) = options.lang_version;
+            return Err(Error::UnsupportedLanguageVersion(major, minor));
+        }
+
+        self.source_map = if options.source_map {
+            Some(Vec::new())
+        } else {
+            None
+        };
+        self.annotate = options.annotate;
+
         writeln!(self.out, "#include <metal_stdlib>")?;
         writeln!(self.out, "#include <simd/simd.h>")?;
         writeln!(self.out, "using namespace metal;")?;
 
         writeln!(self.out)?;
-        self.write_type_defs(module)?;
+        self.write_type_defs(module, options)?;
+
+        let function_order = self.analyze_functions(module);
+        self.types = module.types.clone();
+        self.sized_buffers.clear();
+        if options.sizes_buffer.is_some() {
+            for (handle, var) in module.global_variables.iter() {
+                let ty = match module.types[var.ty].inner {
+                    crate::TypeInner::Struct { ref members } => match members.last() {
+                        Some(member) => member.ty,
+                        None => continue,
+                    },
+                    _ => var.ty,
+                };
+                if let crate::TypeInner::Array {
+                    size: crate::ArraySize::Dynamic,
+                    ..
+                } = module.types[ty].inner
+                {
+                    self.sized_buffers.push(handle);
+                }
+            }
+        }
+        let argument_buffers = self.write_argument_buffers(module, options)?;
 
         writeln!(self.out)?;
-        self.write_functions(module, options)?;
+        self.write_functions(module, options, function_order)?;
 
-        Ok(())
+        Ok(TranslationInfo {
+            argument_buffers,
+            binding_maps: std::mem::take(&mut self.binding_maps),
+            sized_buffers: std::mem::take(&mut self.sized_buffers),
+            entry_point_names: std::mem::take(&mut self.entry_point_names),
+            source_map: self.source_map.take().unwrap_or_default(),
+        })
+    }
+
+    fn write_argument_buffers(
+        &mut self,
+        module: &crate::Module,
+        options: Options,
+    ) -> Result<Vec<ArgumentBufferLayout>, Error> {
+        let tier = match options.argument_buffers {
+            Some(tier) => tier,
+            None => return Ok(Vec::new()),
+        };
+        options.require(Feature::ArgumentBuffers)?;
+
+        let mut layouts = Vec::<ArgumentBufferLayout>::new();
+        let mut missing = Vec::new();
+        for (handle, var) in module.global_variables.iter() {
+            let source = match var.binding {
+                Some(crate::Binding::Descriptor { set, binding }) => BindSource { set, binding },
+                _ => continue,
+            };
+            // inline samplers are declared in the entry points
+            if inline_sampler(options, &source).is_some() {
+                continue;
+            }
+            if let crate::TypeInner::Image { flags, .. } = module.types[var.ty].inner {
+                if tier == ArgumentBufferTier::Tier1 && flags.contains(crate::ImageFlags::CAN_STORE)
+                {
+                    return Err(Error::WritableTextureInArgumentBuffer(handle));
+                }
+            }
+            let mut entry = ArgumentBufferEntry {
+                binding: source.binding,
+                id: 0,
+                variable: handle,
+                mutable: self.module_usage[handle.index()].contains(crate::GlobalUse::STORE),
+            };
+            match options.binding_map.get(&source) {
+                Some(target) => {
+                    entry.id = target
+                        .buffer
+                        .or(target.texture)
+                        .or(target.sampler)
+                        .ok_or_else(|| Error::UnimplementedBindTarget(target.clone()))?;
+                    find_layout(&mut layouts, source.set)?.entries.push(entry);
+                }
+                None if options.allocate_bindings => missing.push((source, entry)),
+                None => return Err(Error::MissingBindTarget(source)),
+            }
+        }
+        // the missing resources go after the mapped ones of their set
+        for (source, mut entry) in missing {
+            let layout = find_layout(&mut layouts, source.set)?;
+            entry.id = match layout.entries.iter().map(|other| other.id).max() {
+                Some(id) => id
+                    .checked_add(1)
+                    .ok_or_else(|| Error::MissingBindTarget(source.clone()))?,
+                None => 0,
+            };
+            layout.entries.push(entry);
+        }
+        layouts.sort_by_key(|layout| layout.set);
+
+        for layout in layouts.iter_mut() {
+            layout.entries.sort_by_key(|entry| entry.id);
+            writeln!(self.out)?;
+            writeln!(
+                self.out,
+                "struct {} {{",
+                Name::from(ArgumentBufferIndex(layout.set))
+            )?;
+            for entry in layout.entries.iter() {
+                let var = &module.global_variables[entry.variable];
+                let name = var.name.or_index(entry.variable);
+                let ty_name = module.types[var.ty].name.or_index(var.ty);
+                match module.types[var.ty].inner {
+                    crate::TypeInner::Image { .. }
+                    | crate::TypeInner::DepthImage { .. }
+                    | crate::TypeInner::Sampler { .. } => {
+                        writeln!(self.out, "\t{} {} [[id({})]];", ty_name, name, entry.id)?;
+                    }
+                    _ => {
+                        let space = if entry.mutable { "device" } else { "constant" };
+                        writeln!(
+                            self.out,
+                            "\t{} {}* {} [[id({})]];",
+                            space, ty_name, name, entry.id
+                        )?;
+                    }
+                }
+            }
+            writeln!(self.out, "}};")?;
+        }
+        Ok(layouts)
     }
 
-    fn write_type_defs(&mut self, module: &crate::Module) -> Result<(), Error> {
+    fn write_type_defs(&mut self, module: &crate::Module, options: Options) -> Result<(), Error> {
         for (handle, ty) in module.types.iter() {
             let name = ty.name.or_index(handle);
             match ty.inner {
@@ -881,34 +2157,38 @@ impl<W: Write> Writer<W> {
                     for (index, member) in members.iter().enumerate() {
                         let name = member.name.or_index(MemberIndex(index));
                         let base_name = module.types[member.ty].name.or_index(member.ty);
-                        write!(self.out, "\t{} {}", base_name, name)?;
-                        match member.origin {
-                            crate::MemberOrigin::BuiltIn(built_in) => {
-                                ResolvedBinding::BuiltIn(built_in)
-                                    .try_fmt_decorated(&mut self.out, "")?;
-                            }
-                            crate::MemberOrigin::Offset(_) => {
-                                //TODO
-                            }
-                        }
-                        writeln!(self.out, ";")?;
+                        // built-in members are decorated in the entry point outputs
+                        writeln!(self.out, "\t{} {};", base_name, name)?;
                     }
                     write!(self.out, "}}")?;
                 }
                 crate::TypeInner::Image { base, dim, flags } => {
                     let base_name = module.types[base].name.or_index(base);
                     let dim_str = dim_str(dim);
-                    let msaa_str = if flags.contains(crate::ImageFlags::MULTISAMPLED) {
-                        "_ms"
-                    } else {
-                        ""
-                    };
-                    let array_str = if flags.contains(crate::ImageFlags::ARRAYED) {
-                        "_array"
-                    } else {
-                        ""
-                    };
-                    let access = if flags.contains(crate::ImageFlags::SAMPLED) {
+                    let multisampled = flags.contains(crate::ImageFlags::MULTISAMPLED);
+                    let arrayed = flags.contains(crate::ImageFlags::ARRAYED);
+                    // only 2D images have samples, and 3D ones have no layers
+                    if (multisampled && dim != crate::ImageDimension::D2)
+                        || (arrayed && dim == crate::ImageDimension::D3)
+                    {
+                        return Err(Error::InvalidImageFlags(flags));
+                    }
+                    if arrayed && dim == crate::ImageDimension::Cube {
+                        options.require(Feature::CubeArrayTextures)?;
+                    }
+                    let msaa_str = if multisampled { "_ms" } else { "" };
+                    let array_str = if arrayed { "_array" } else { "" };
+                    let access = if multisampled {
+                        // multisampled textures can only be read, even if bound for sampling
+                        if flags.contains(crate::ImageFlags::CAN_STORE)
+                            || !flags.intersects(
+                                crate::ImageFlags::SAMPLED | crate::ImageFlags::CAN_LOAD,
+                            )
+                        {
+                            return Err(Error::InvalidImageFlags(flags));
+                        }
+                        "read"
+                    } else if flags.contains(crate::ImageFlags::SAMPLED) {
                         if flags.intersects(crate::ImageFlags::CAN_STORE) {
                             return Err(Error::InvalidImageFlags(flags));
                         }
@@ -916,6 +2196,7 @@ impl<W: Write> Writer<W> {
                     } else if flags
                         .contains(crate::ImageFlags::CAN_LOAD | crate::ImageFlags::CAN_STORE)
                     {
+                        options.require(Feature::ReadWriteTextures)?;
                         "read_write"
                     } else if flags.contains(crate::ImageFlags::CAN_STORE) {
                         "write"
@@ -931,6 +2212,15 @@ impl<W: Write> Writer<W> {
                     )?;
                 }
                 crate::TypeInner::DepthImage { dim, arrayed } => {
+                    match dim {
+                        crate::ImageDimension::D2 => {}
+                        crate::ImageDimension::Cube if arrayed => {
+                            options.require(Feature::CubeArrayTextures)?;
+                        }
+                        crate::ImageDimension::Cube => {}
+                        // there are no 1D or 3D depth textures
+                        _ => return Err(Error::UnexpectedImageType(ty.inner.clone())),
+                    }
                     let dim_str = dim_str(dim);
                     let array_str = if arrayed { "_array" } else { "" };
                     write!(
@@ -948,247 +2238,639 @@ impl<W: Write> Writer<W> {
         Ok(())
     }
 
-    fn write_functions(&mut self, module: &crate::Module, options: Options) -> Result<(), Error> {
-        for (fun_handle, fun) in module.functions.iter() {
-            let fun_name = fun.name.or_index(fun_handle);
-            // find the entry point(s) and inputs/outputs
-            let mut shader_stage = None;
-            let mut last_used_global = None;
-            for ((handle, var), &usage) in module.global_variables.iter().zip(&fun.global_usage) {
-                match var.class {
-                    crate::StorageClass::Input => {
-                        if let Some(crate::Binding::Location(_)) = var.binding {
-                            continue;
-                        }
-                    }
-                    crate::StorageClass::Output => continue,
-                    _ => {}
-                }
-                if !usage.is_empty() {
-                    last_used_global = Some(handle);
+    /// Collects the usage of the global variables and names the functions,
+    /// returning the functions ordered so that callees come before their callers.
+    fn analyze_functions(&mut self, module: &crate::Module) -> Vec<Handle<crate::Function>> {
+        fn visit(
+            module: &crate::Module,
+            handle: Handle<crate::Function>,
+            order: &mut Vec<Handle<crate::Function>>,
+            visited: &mut Vec<bool>,
+        ) {
+            if visited[handle.index()] {
+                return;
+            }
+            visited[handle.index()] = true;
+            for (_, expr) in module.functions[handle].expressions.iter() {
+                if let crate::Expression::Call {
+                    origin: crate::FunctionOrigin::Local(callee),
+                    ..
+                } = *expr
+                {
+                    visit(module, callee, order, visited);
                 }
             }
-            for ep in module.entry_points.iter() {
-                if ep.function == fun_handle {
-                    if shader_stage.is_some() {
-                        if shader_stage != Some(ep.stage) {
-                            return Err(Error::MixedExecutionModels(fun_handle));
-                        }
-                    } else {
-                        shader_stage = Some(ep.stage);
+            order.push(handle);
+        }
+
+        let mut order = Vec::new();
+        let mut visited = vec![false; module.functions.len()];
+        for (handle, _) in module.functions.iter() {
+            visit(module, handle, &mut order, &mut visited);
+        }
+
+        self.module_usage = vec![crate::GlobalUse::empty(); module.global_variables.len()];
+        for &handle in order.iter() {
+            let fun = &module.functions[handle];
+            let mut usage = fun.global_usage.clone();
+            usage.resize(module.global_variables.len(), crate::GlobalUse::empty());
+            for (_, expr) in fun.expressions.iter() {
+                if let crate::Expression::Call {
+                    origin: crate::FunctionOrigin::Local(callee),
+                    ..
+                } = *expr
+                {
+                    for (total, &callee_usage) in usage.iter_mut().zip(&self.global_usage[&callee])
+                    {
+                        *total |= callee_usage;
                     }
                 }
             }
-            let output_name = fun.name.or_index(OutputStructIndex(fun_handle));
+            for (total, &fun_usage) in self.module_usage.iter_mut().zip(&usage) {
+                *total |= fun_usage;
+            }
+            self.global_usage.insert(handle, usage);
+        }
 
-            // make dedicated input/output structs
-            if let Some(stage) = shader_stage {
-                assert_eq!(fun.return_type, None);
-                let (em_str, in_mode, out_mode) = match stage {
-                    crate::ShaderStage::Vertex => (
-                        "vertex",
-                        LocationMode::VertexInput,
-                        LocationMode::Intermediate,
-                    ),
-                    crate::ShaderStage::Fragment => (
-                        "fragment",
-                        LocationMode::Intermediate,
-                        LocationMode::FragmentOutput,
-                    ),
-                    crate::ShaderStage::Compute => {
-                        ("kernel", LocationMode::Uniform, LocationMode::Uniform)
-                    }
-                };
-                let location_input_name = fun.name.or_index(InputStructIndex(fun_handle));
+        // entry points are named after the `EntryPoint::name`, which has to be unique
+        self.entry_point_names.clear();
+        for (index, ep) in module.entry_points.iter().enumerate() {
+            let (stage_str, _, _) = stage_info(ep.stage);
+            let base = if ep.name.is_empty() {
+                "entry"
+            } else {
+                ep.name.as_str()
+            };
+            let mut name = Name {
+                class: "",
+                source: NameSource::Custom {
+                    name: base,
+                    prefix: false,
+                },
+            }
+            .to_string();
+            if is_reserved(base) || self.entry_point_names.contains(&name) {
+                name = format!("{}_{}", name.trim_end_matches('_'), stage_str);
+            }
+            if self.entry_point_names.contains(&name) {
+                name = format!("{}{}", name, index);
+            }
+            self.entry_point_names.push(name);
+        }
+        self.function_names.clear();
+        for (handle, fun) in module.functions.iter() {
+            let mut name = fun.name.or_index(handle).to_string();
+            if self.entry_point_names.contains(&name) {
+                name = Name::from(handle).to_string();
+            }
+            self.function_names.insert(handle, name);
+        }
 
-                if stage != crate::ShaderStage::Compute {
-                    writeln!(self.out, "struct {} {{", location_input_name)?;
-                    for ((handle, var), &usage) in
-                        module.global_variables.iter().zip(&fun.global_usage)
-                    {
-                        if var.class != crate::StorageClass::Input
-                            || !usage.contains(crate::GlobalUse::LOAD)
-                        {
-                            continue;
-                        }
-                        // if it's a struct, lift all the built-in contents up to the root
-                        let ty_handle = var.ty;
-                        if let crate::TypeInner::Struct { ref members } =
-                            module.types[ty_handle].inner
-                        {
-                            for (index, member) in members.iter().enumerate() {
-                                if let crate::MemberOrigin::BuiltIn(built_in) = member.origin {
-                                    let name = member.name.or_index(MemberIndex(index));
-                                    let ty_name = module.types[member.ty].name.or_index(member.ty);
-                                    write!(self.out, "\t{} {}", ty_name, name)?;
-                                    ResolvedBinding::BuiltIn(built_in)
-                                        .try_fmt_decorated(&mut self.out, ";\n")?;
-                                }
+        order
+    }
+
+    fn write_functions(
+        &mut self,
+        module: &crate::Module,
+        options: Options,
+        order: Vec<Handle<crate::Function>>,
+    ) -> Result<(), Error> {
+        for handle in order {
+            self.write_function(module, handle)?;
+        }
+        for index in 0..module.entry_points.len() {
+            self.write_entry_point(module, options, index)?;
+        }
+        Ok(())
+    }
+
+    fn write_function(
+        &mut self,
+        module: &crate::Module,
+        fun_handle: Handle<crate::Function>,
+    ) -> Result<(), Error> {
+        let fun = &module.functions[fun_handle];
+        self.typifier = Typifier::new();
+        if let Some((last, _)) = fun.expressions.iter().last() {
+            self.typifier
+                .resolve(
+                    last,
+                    &fun.expressions,
+                    &mut self.types,
+                    &module.constants,
+                    &module.global_variables,
+                    &fun.local_variables,
+                    &module.functions,
+                    &fun.parameter_types,
+                )
+                .map_err(|e| Error::Resolve(fun_handle, e))?;
+        }
+        let result_type_name = match fun.return_type {
+            Some(type_id) => module.types[type_id].name.or_index(type_id).to_string(),
+            None => "void".to_owned(),
+        };
+        let fun_name = self.function_names[&fun_handle].clone();
+        let mut parameters = Vec::new();
+        for (index, &ty) in fun.parameter_types.iter().enumerate() {
+            let name = Name::from(ParameterIndex(index));
+            let type_name = module.types[ty].name.or_index(ty);
+            parameters.push(format!("{} {}", type_name, name));
+        }
+        // global variables are passed by reference
+        for (handle, _) in module.global_variables.iter() {
+            if self.global_usage[&fun_handle][handle.index()].is_empty() {
+                continue;
+            }
+            let tyvar = TypedGlobalVariable {
+                module,
+                handle,
+                usage: self.module_usage[handle.index()],
+                reference: true,
+            };
+            let mut parameter = String::new();
+            tyvar.try_fmt(&mut parameter)?;
+            parameters.push(parameter);
+        }
+        if self.uses_buffer_sizes(fun_handle) {
+            parameters.push(format!("constant uint* {}", BUFFER_SIZES_NAME));
+        }
+        let mapping = self.begin_mapping(&Level(0), fun_handle, None, module)?;
+        writeln!(self.out, "{} {}(", result_type_name, fun_name)?;
+        for (index, parameter) in parameters.iter().enumerate() {
+            let separator = separate(index + 1 == parameters.len());
+            writeln!(self.out, "\t{}{}", parameter, separator)?;
+        }
+        writeln!(self.out, ") {{")?;
+
+        for (local_handle, local) in fun.local_variables.iter() {
+            // locals may be typed by the pointer to their value
+            let ty = match module.types[local.ty].inner {
+                crate::TypeInner::Pointer { base, .. } => base,
+                _ => local.ty,
+            };
+            let ty_name = module.types[ty].name.or_index(ty);
+            write!(
+                self.out,
+                "\t{} {}",
+                ty_name,
+                local.name.or_index(local_handle)
+            )?;
+            if let Some(value) = local.init {
+                write!(self.out, " = ")?;
+                self.put_expression(value, fun, module)?;
+            }
+            writeln!(self.out, ";")?;
+        }
+        for statement in fun.body.iter() {
+            self.put_statement(Level(1), statement, fun_handle, module)?;
+        }
+        writeln!(self.out, "}}")?;
+        self.end_mapping(mapping);
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    /// Writes the function called by the pipeline for an entry point,
+    /// which gathers the stage inputs and outputs around a call to the entry point function.
+    fn write_entry_point(
+        &mut self,
+        module: &crate::Module,
+        options: Options,
+        index: usize,
+    ) -> Result<(), Error> {
+        let ep = &module.entry_points[index];
+        if !module.functions[ep.function].parameter_types.is_empty() {
+            return Err(Error::UnexpectedEntryPointParameters(ep.function));
+        }
+        let usage = &self.global_usage[&ep.function];
+        let entry_name = self.entry_point_names[index].clone();
+        let (stage_str, in_mode, out_mode) = stage_info(ep.stage);
+        let input_name = Name {
+            class: InputStructIndex::CLASS,
+            source: NameSource::Custom {
+                name: &entry_name,
+                prefix: true,
+            },
+        };
+        let output_name = Name {
+            class: OutputStructIndex::CLASS,
+            source: NameSource::Custom {
+                name: &entry_name,
+                prefix: true,
+            },
+        };
+
+        let mut input_fields = Vec::new();
+        let mut output_fields = Vec::new();
+        let mut parameters = Vec::new();
+        // statements copying the inputs before the call, and the outputs after it
+        let mut prologue = Vec::new();
+        let mut epilogue = Vec::new();
+        let mut arguments = Vec::new();
+        let mut used_sets = Vec::new();
+        let mut used_vertex_buffers = Vec::new();
+        let mut step_modes = Vec::new();
+        let vertex_pulling =
+            ep.stage == crate::ShaderStage::Vertex && !options.vertex_buffers.is_empty();
+
+        for ((handle, var), &var_usage) in module.global_variables.iter().zip(usage) {
+            if var_usage.is_empty() {
+                continue;
+            }
+            let name = var.name.or_index(handle);
+            let argument_index = arguments.len();
+            arguments.push(name.to_string());
+            let ty_name = module.types[var.ty].name.or_index(var.ty);
+            let tyvar = TypedGlobalVariable {
+                module,
+                handle,
+                usage: self.module_usage[handle.index()],
+                reference: false,
+            };
+            match var.class {
+                crate::StorageClass::Input => {
+                    if let crate::TypeInner::Struct { ref members } = module.types[var.ty].inner {
+                        // built-in members are separate arguments of the entry point
+                        prologue.push(format!("{} {};", ty_name, name));
+                        for (member_index, member) in members.iter().enumerate() {
+                            if let crate::MemberOrigin::BuiltIn(built_in) = member.origin {
+                                let member_name = member.name.or_index(MemberIndex(member_index));
+                                let member_ty_name =
+                                    module.types[member.ty].name.or_index(member.ty);
+                                let mut parameter =
+                                    format!("{} {}_{}", member_ty_name, name, member_name);
+                                ResolvedBinding::BuiltIn(built_in)
+                                    .try_fmt_decorated(&mut parameter, "")?;
+                                parameters.push(parameter);
+                                prologue.push(format!(
+                                    "{}.{} = {}_{};",
+                                    name, member_name, name, member_name
+                                ));
                             }
-                        } else if let Some(ref binding @ crate::Binding::Location(_)) = var.binding
-                        {
-                            let tyvar = TypedGlobalVariable {
-                                module,
-                                handle,
-                                usage: crate::GlobalUse::empty(),
-                            };
-                            let resolved = options.resolve_binding(binding, in_mode)?;
-
-                            write!(self.out, "\t")?;
-                            tyvar.try_fmt(&mut self.out)?;
-                            resolved.try_fmt_decorated(&mut self.out, ";\n")?;
                         }
+                        continue;
                     }
-                    writeln!(self.out, "}};")?;
-                    writeln!(self.out, "struct {} {{", output_name)?;
-                    for ((handle, var), &usage) in
-                        module.global_variables.iter().zip(&fun.global_usage)
-                    {
-                        if var.class != crate::StorageClass::Output
-                            || !usage.contains(crate::GlobalUse::STORE)
-                        {
-                            continue;
+                    let binding = var.binding.as_ref().ok_or(Error::MissingBinding(handle))?;
+                    if let (true, &crate::Binding::Location(location)) = (vertex_pulling, binding) {
+                        let load = vertex_load(module, options.vertex_buffers, var.ty, location)?;
+                        prologue.push(format!("{} {} = {};", ty_name, name, load.value));
+                        if !used_vertex_buffers.contains(&load.buffer) {
+                            used_vertex_buffers.push(load.buffer);
                         }
-                        // if it's a struct, lift all the built-in contents up to the root
-                        let ty_handle = var.ty;
-                        if let crate::TypeInner::Struct { ref members } =
-                            module.types[ty_handle].inner
-                        {
-                            for (index, member) in members.iter().enumerate() {
-                                let name = member.name.or_index(MemberIndex(index));
-                                let ty_name = module.types[member.ty].name.or_index(member.ty);
-                                match member.origin {
-                                    crate::MemberOrigin::BuiltIn(built_in) => {
-                                        write!(self.out, "\t{} {}", ty_name, name)?;
-                                        ResolvedBinding::BuiltIn(built_in)
-                                            .try_fmt_decorated(&mut self.out, ";\n")?;
-                                    }
-                                    crate::MemberOrigin::Offset(_) => {
-                                        //TODO
-                                    }
-                                }
-                            }
-                        } else {
-                            let tyvar = TypedGlobalVariable {
-                                module,
-                                handle,
-                                usage: crate::GlobalUse::empty(),
-                            };
-                            write!(self.out, "\t")?;
-                            tyvar.try_fmt(&mut self.out)?;
-                            if let Some(ref binding) = var.binding {
-                                let resolved = options.resolve_binding(binding, out_mode)?;
-                                resolved.try_fmt_decorated(&mut self.out, "")?;
+                        continue;
+                    }
+                    // the vertex buffers need these indices as well
+                    let step_mode = match *binding {
+                        crate::Binding::BuiltIn(crate::BuiltIn::VertexIndex) => {
+                            Some((VertexStepMode::Vertex, VERTEX_ID_NAME))
+                        }
+                        crate::Binding::BuiltIn(crate::BuiltIn::InstanceIndex) => {
+                            Some((VertexStepMode::Instance, INSTANCE_ID_NAME))
+                        }
+                        _ => None,
+                    };
+                    if let (true, Some((step_mode, index))) = (vertex_pulling, step_mode) {
+                        prologue.push(format!("{} {} = {};", ty_name, name, index));
+                        if !step_modes.contains(&step_mode) {
+                            step_modes.push(step_mode);
+                        }
+                        continue;
+                    }
+                    let resolved = options.resolve_binding(binding, in_mode)?;
+                    let mut declaration = String::new();
+                    tyvar.try_fmt(&mut declaration)?;
+                    resolved.try_fmt_decorated(&mut declaration, "")?;
+                    match *binding {
+                        crate::Binding::Location(_) => {
+                            input_fields.push(declaration);
+                            prologue.push(format!(
+                                "{} {} = {}.{};",
+                                ty_name, name, LOCATION_INPUT_STRUCT_NAME, name
+                            ));
+                        }
+                        _ => parameters.push(declaration),
+                    }
+                }
+                crate::StorageClass::Output => {
+                    prologue.push(format!("{} {};", ty_name, name));
+                    if let crate::TypeInner::Struct { ref members } = module.types[var.ty].inner {
+                        // lift all the built-in members up to the root
+                        for (member_index, member) in members.iter().enumerate() {
+                            if let crate::MemberOrigin::BuiltIn(built_in) = member.origin {
+                                let member_name = member.name.or_index(MemberIndex(member_index));
+                                let member_ty_name =
+                                    module.types[member.ty].name.or_index(member.ty);
+                                let mut field = format!("{} {}", member_ty_name, member_name);
+                                ResolvedBinding::BuiltIn(built_in)
+                                    .try_fmt_decorated(&mut field, "")?;
+                                output_fields.push(field);
+                                epilogue.push(format!(
+                                    "{}.{} = {}.{};",
+                                    OUTPUT_STRUCT_NAME, member_name, name, member_name
+                                ));
                             }
-                            writeln!(self.out, ";")?;
                         }
+                        continue;
                     }
-                    writeln!(self.out, "}};")?;
-                    writeln!(self.out, "{} {} {}(", em_str, output_name, fun_name)?;
-                    let separator = separate(last_used_global.is_none());
-                    writeln!(
-                        self.out,
-                        "\t{} {} [[stage_in]]{}",
-                        location_input_name, LOCATION_INPUT_STRUCT_NAME, separator
-                    )?;
-                } else {
-                    writeln!(self.out, "{} void {}(", em_str, fun_name)?;
+                    let mut field = String::new();
+                    tyvar.try_fmt(&mut field)?;
+                    if let Some(ref binding) = var.binding {
+                        let resolved = options.resolve_binding(binding, out_mode)?;
+                        resolved.try_fmt_decorated(&mut field, "")?;
+                    }
+                    output_fields.push(field);
+                    epilogue.push(format!("{}.{} = {};", OUTPUT_STRUCT_NAME, name, name));
                 }
-
-                for ((handle, var), &usage) in module.global_variables.iter().zip(&fun.global_usage)
-                {
-                    if usage.is_empty() || var.class == crate::StorageClass::Output {
+                crate::StorageClass::Private | crate::StorageClass::Function => {
+                    prologue.push(format!("{} {} = {{}};", ty_name, name));
+                }
+                crate::StorageClass::WorkGroup => {
+                    let mut declaration = String::new();
+                    tyvar.try_fmt(&mut declaration)?;
+                    prologue.push(format!("{};", declaration));
+                }
+                crate::StorageClass::Constant
+                | crate::StorageClass::Uniform
+                | crate::StorageClass::StorageBuffer => {
+                    let binding = var.binding.as_ref().ok_or(Error::MissingBinding(handle))?;
+                    let source = match *binding {
+                        crate::Binding::Descriptor { set, binding } => BindSource { set, binding },
+                        _ => return Err(Error::UnexpectedLocation),
+                    };
+                    if let Some(sampler) = inline_sampler(options, &source) {
+                        prologue.push(format!(
+                            "constexpr sampler {}({});",
+                            name,
+                            sampler.arguments(options)?
+                        ));
                         continue;
                     }
-                    if var.class == crate::StorageClass::Input {
-                        if let Some(crate::Binding::Location(_)) = var.binding {
-                            // location inputs are put into a separate struct
-                            continue;
+                    if options.argument_buffers.is_some() {
+                        let set = source.set;
+                        // buffers are pointers in the argument buffer
+                        let dereference = match module.types[var.ty].inner {
+                            crate::TypeInner::Image { .. }
+                            | crate::TypeInner::DepthImage { .. }
+                            | crate::TypeInner::Sampler { .. } => "",
+                            _ => "*",
+                        };
+                        arguments[argument_index] =
+                            format!("{}{}{}.{}", dereference, ARGUMENT_BUFFER_NAME, set, name);
+                        if !used_sets.contains(&set) {
+                            used_sets.push(set);
                         }
+                        continue;
                     }
-                    let loc_mode = match (stage, var.class) {
-                        (crate::ShaderStage::Vertex, crate::StorageClass::Input) => {
-                            LocationMode::VertexInput
-                        }
-                        (crate::ShaderStage::Vertex, crate::StorageClass::Output)
-                        | (crate::ShaderStage::Fragment, crate::StorageClass::Input) => {
-                            LocationMode::Intermediate
-                        }
-                        (crate::ShaderStage::Fragment, crate::StorageClass::Output) => {
-                            LocationMode::FragmentOutput
-                        }
-                        _ => LocationMode::Uniform,
-                    };
-                    let resolved =
-                        options.resolve_binding(var.binding.as_ref().unwrap(), loc_mode)?;
+                    let target = options.resolve_resource(
+                        ep.stage,
+                        source,
+                        &module.types[var.ty].inner,
+                        self.module_usage[handle.index()].contains(crate::GlobalUse::STORE),
+                        self.binding_maps.entry(ep.stage).or_default(),
+                    )?;
+                    let resolved = ResolvedBinding::Resource(target);
                     let tyvar = TypedGlobalVariable {
-                        module,
-                        handle,
-                        usage,
+                        reference: true,
+                        ..tyvar
                     };
-                    let separator = separate(last_used_global == Some(handle));
-                    write!(self.out, "\t")?;
-                    tyvar.try_fmt(&mut self.out)?;
-                    resolved.try_fmt_decorated(&mut self.out, separator)?;
-                    writeln!(self.out)?;
-                }
-            } else {
-                let result_type_name = match fun.return_type {
-                    Some(type_id) => module.types[type_id].name.or_index(type_id),
-                    None => Name {
-                        class: "",
-                        source: NameSource::Custom {
-                            name: "void",
-                            prefix: false,
-                        },
-                    },
-                };
-                writeln!(self.out, "{} {}(", result_type_name, fun_name)?;
-                for (index, &ty) in fun.parameter_types.iter().enumerate() {
-                    let name = Name::from(ParameterIndex(index));
-                    let member_type_name = module.types[ty].name.or_index(ty);
-                    let separator = separate(
-                        index + 1 == fun.parameter_types.len() && last_used_global.is_none(),
-                    );
-                    writeln!(self.out, "\t{} {}{}", member_type_name, name, separator)?;
+                    let mut parameter = String::new();
+                    tyvar.try_fmt(&mut parameter)?;
+                    resolved.try_fmt_decorated(&mut parameter, "")?;
+                    parameters.push(parameter);
                 }
             }
-            writeln!(self.out, ") {{")?;
+        }
 
-            // write down function body
-            let has_output = match shader_stage {
-                Some(crate::ShaderStage::Vertex) | Some(crate::ShaderStage::Fragment) => {
-                    writeln!(self.out, "\t{} {};", output_name, OUTPUT_STRUCT_NAME)?;
-                    true
-                }
-                Some(crate::ShaderStage::Compute) | None => false,
-            };
-            for (local_handle, local) in fun.local_variables.iter() {
-                let ty_name = module.types[local.ty].name.or_index(local.ty);
-                write!(
-                    self.out,
-                    "\t{} {}",
-                    ty_name,
-                    local.name.or_index(local_handle)
-                )?;
-                if let Some(value) = local.init {
-                    write!(self.out, " = ")?;
-                    self.put_expression(value, fun, module)?;
+        if let (Some(buffer), true) = (options.sizes_buffer, self.uses_buffer_sizes(ep.function)) {
+            parameters.push(format!(
+                "constant uint* {} [[buffer({})]]",
+                BUFFER_SIZES_NAME, buffer
+            ));
+            arguments.push(BUFFER_SIZES_NAME.to_string());
+        }
+
+        used_vertex_buffers.sort();
+        for &buffer in used_vertex_buffers.iter() {
+            parameters.push(format!(
+                "const device uchar* {}{} [[buffer({})]]",
+                VERTEX_BUFFER_NAME, buffer, buffer
+            ));
+            for layout in options
+                .vertex_buffers
+                .iter()
+                .filter(|layout| layout.buffer == buffer)
+            {
+                if !step_modes.contains(&layout.step_mode) {
+                    step_modes.push(layout.step_mode);
                 }
-                writeln!(self.out, ";")?;
             }
-            for statement in fun.body.iter() {
-                self.put_statement(Level(1), statement, fun, has_output, module)?;
+        }
+        if step_modes.contains(&VertexStepMode::Vertex) {
+            parameters.push(format!("uint {} [[vertex_id]]", VERTEX_ID_NAME));
+        }
+        if step_modes.contains(&VertexStepMode::Instance) {
+            parameters.push(format!("uint {} [[instance_id]]", INSTANCE_ID_NAME));
+        }
+
+        used_sets.sort();
+        for set in used_sets {
+            parameters.push(format!(
+                "constant {}& {}{} [[buffer({})]]",
+                Name::from(ArgumentBufferIndex(set)),
+                ARGUMENT_BUFFER_NAME,
+                set,
+                set
+            ));
+        }
+
+        if !input_fields.is_empty() {
+            writeln!(self.out, "struct {} {{", input_name)?;
+            for field in input_fields.iter() {
+                writeln!(self.out, "\t{};", field)?;
             }
-            writeln!(self.out, "}}")?;
+            writeln!(self.out, "}};")?;
+            parameters.insert(
+                0,
+                format!("{} {} [[stage_in]]", input_name, LOCATION_INPUT_STRUCT_NAME),
+            );
         }
+        let result_type_name = if output_fields.is_empty() {
+            "void".to_owned()
+        } else {
+            writeln!(self.out, "struct {} {{", output_name)?;
+            for field in output_fields.iter() {
+                writeln!(self.out, "\t{};", field)?;
+            }
+            writeln!(self.out, "}};")?;
+            output_name.to_string()
+        };
 
+        let mapping = self.begin_mapping(&Level(0), ep.function, None, module)?;
+        writeln!(
+            self.out,
+            "{} {} {}(",
+            stage_str, result_type_name, entry_name
+        )?;
+        for (index, parameter) in parameters.iter().enumerate() {
+            let separator = separate(index + 1 == parameters.len());
+            writeln!(self.out, "\t{}{}", parameter, separator)?;
+        }
+        writeln!(self.out, ") {{")?;
+        for statement in prologue.iter() {
+            writeln!(self.out, "\t{}", statement)?;
+        }
+        writeln!(
+            self.out,
+            "\t{}({});",
+            self.function_names[&ep.function],
+            arguments.join(", ")
+        )?;
+        if !output_fields.is_empty() {
+            writeln!(self.out, "\t{} {};", output_name, OUTPUT_STRUCT_NAME)?;
+            for statement in epilogue.iter() {
+                writeln!(self.out, "\t{}", statement)?;
+            }
+            writeln!(self.out, "\treturn {};", OUTPUT_STRUCT_NAME)?;
+        }
+        writeln!(self.out, "}}")?;
+        self.end_mapping(mapping);
+        writeln!(self.out)?;
         Ok(())
     }
 }
 
-pub fn write_string(module: &crate::Module, options: Options) -> Result<String, Error> {
-    let mut w = Writer { out: String::new() };
-    w.write(module, options)?;
-    Ok(w.out)
+/// Function qualifier and location modes of the inputs and outputs of a stage.
+fn stage_info(stage: crate::ShaderStage) -> (&'static str, LocationMode, LocationMode) {
+    match stage {
+        crate::ShaderStage::Vertex => (
+            "vertex",
+            LocationMode::VertexInput,
+            LocationMode::Intermediate,
+        ),
+        crate::ShaderStage::Fragment => (
+            "fragment",
+            LocationMode::Intermediate,
+            LocationMode::FragmentOutput,
+        ),
+        crate::ShaderStage::Compute => ("kernel", LocationMode::Uniform, LocationMode::Uniform),
+    }
+}
+
+impl<W> Writer<W> {
+    pub fn new(out: W) -> Self {
+        Writer {
+            out: LineCounter {
+                inner: out,
+                lines: 0,
+            },
+            global_usage: FastHashMap::default(),
+            module_usage: Vec::new(),
+            function_names: FastHashMap::default(),
+            entry_point_names: Vec::new(),
+            binding_maps: FastHashMap::default(),
+            sized_buffers: Vec::new(),
+            types: Arena::new(),
+            typifier: Typifier::new(),
+            loop_count: 0,
+            source_map: None,
+            annotate: false,
+        }
+    }
+}
+
+/// Finds the state of a sampler to declare as a `constexpr sampler`.
+fn inline_sampler<'a>(options: Options<'a>, source: &BindSource) -> Option<&'a InlineSampler> {
+    options.binding_map.get(source)?.inline_sampler.as_ref()
+}
+
+struct VertexLoad {
+    buffer: u8,
+    value: String,
+}
+
+/// Writes the expression fetching a vertex input from its vertex buffer.
+fn vertex_load(
+    module: &crate::Module,
+    vertex_buffers: &[VertexBufferLayout],
+    ty: Handle<crate::Type>,
+    location: u32,
+) -> Result<VertexLoad, Error> {
+    let (layout, attribute) = vertex_buffers
+        .iter()
+        .flat_map(|layout| {
+            layout
+                .attributes
+                .iter()
+                .map(move |attribute| (layout, attribute))
+        })
+        .find(|&(_, attribute)| attribute.location == location)
+        .ok_or(Error::InvalidVertexAttribute(location))?;
+    let (kind, size) = match module.types[ty].inner {
+        crate::TypeInner::Scalar { kind, .. } => (kind, 1),
+        crate::TypeInner::Vector { size, kind, .. } => (kind, size as usize),
+        _ => return Err(Error::InvalidVertexAttribute(location)),
+    };
+    let index = match layout.step_mode {
+        VertexStepMode::Vertex => VERTEX_ID_NAME,
+        VertexStepMode::Instance => INSTANCE_ID_NAME,
+    };
+    let pointer = format!(
+        "{}{} + {} * {} + {}",
+        VERTEX_BUFFER_NAME, layout.buffer, index, layout.stride, attribute.offset
+    );
+    let loaded = attribute.format.load(&pointer);
+    let kind_str = scalar_kind_string(kind);
+    let ty_name = module.types[ty].name.or_index(ty);
+    let components = attribute.format.components();
+    // convert to the scalar kind of the input first, since the constructors
+    // of vectors don't mix kinds
+    let converted = match components {
+        1 => format!("{}({})", kind_str, loaded),
+        _ => format!("{}{}({})", kind_str, components, loaded),
+    };
+    let value = if size == components {
+        format!("{}({})", ty_name, converted)
+    } else if size < components {
+        let swizzle: String = COMPONENTS[..size].iter().collect();
+        format!("{}({}.{})", ty_name, converted, swizzle)
+    } else {
+        // the missing components default to (0, 0, 1)
+        let mut value = format!("{}({}", ty_name, converted);
+        for index in components..size {
+            value.push_str(if index == 3 { ", 1" } else { ", 0" });
+        }
+        value.push(')');
+        value
+    };
+    Ok(VertexLoad {
+        buffer: layout.buffer,
+        value,
+    })
+}
+
+fn find_layout(
+    layouts: &mut Vec<ArgumentBufferLayout>,
+    set: u32,
+) -> Result<&mut ArgumentBufferLayout, Error> {
+    let index = match layouts.iter().position(|layout| layout.set == set) {
+        Some(index) => index,
+        None => {
+            layouts.push(ArgumentBufferLayout {
+                set,
+                buffer: u8::try_from(set).map_err(|_| Error::UnsupportedDescriptorSet(set))?,
+                entries: Vec::new(),
+            });
+            layouts.len() - 1
+        }
+    };
+    Ok(&mut layouts[index])
+}
+
+pub fn write_string(
+    module: &crate::Module,
+    options: Options,
+) -> Result<(String, TranslationInfo), Error> {
+    let mut w = Writer::new(String::new());
+    let info = w.write(module, options)?;
+    Ok((w.out.inner, info))
 }
@@ -1,5 +1,6 @@
 mod helpers;
 mod layout;
+mod text;
 mod writer;
 
 #[cfg(test)]
@@ -8,9 +9,52 @@ mod test_framework;
 #[cfg(test)]
 mod layout_tests;
 
+pub use text::{assemble, disassemble, AssemblyError, AssemblyErrorKind, DisassemblyError};
 pub use writer::Writer;
 
 use spirv::*;
+use thiserror::Error;
+
+/// The reason an expression couldn't be written.
+#[derive(Clone, Debug, Error)]
+pub enum ExpressionError {
+    /// The expression has no pointer to take, e.g. when storing into it.
+    #[error("The expression is not a pointer")]
+    NotPointer,
+    #[error("Unexpected type {0:?}")]
+    UnexpectedType(crate::TypeInner),
+    #[error("The binary operator {0:?} is not supported for {1:?} operands")]
+    UnsupportedBinaryOp(crate::BinaryOperator, crate::ScalarKind),
+    #[error("The intrinsic {0:?} is not supported")]
+    UnsupportedIntrinsic(crate::IntrinsicFunction),
+    #[error("The conversion from {0:?} to {1:?} is not supported")]
+    UnsupportedConversion(crate::ScalarKind, crate::ScalarKind),
+    #[error("The function `{0}` is not supported")]
+    UnsupportedCall(String),
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum Error {
+    #[error("The constant {0:?} is not supported")]
+    UnsupportedConstant(crate::Handle<crate::Constant>),
+    #[error("The type of an expression in function {0:?} can not be resolved: {1}")]
+    Resolve(crate::Handle<crate::Function>, crate::proc::ResolveError),
+    #[error("The expression {1:?} of function {0:?} can not be written: {2}")]
+    Expression(
+        crate::Handle<crate::Function>,
+        crate::Handle<crate::Expression>,
+        ExpressionError,
+    ),
+    #[error("There is a `break` outside of a loop or switch in function {0:?}")]
+    MisplacedBreak(crate::Handle<crate::Function>),
+    #[error("There is a `continue` outside of a loop in function {0:?}")]
+    MisplacedContinue(crate::Handle<crate::Function>),
+    #[error("The target environment {0:?} is not supported")]
+    UnsupportedTarget(TargetEnvironment),
+    /// The module needs capabilities that aren't allowed by the options.
+    #[error("The capabilities {0:?} are needed but not allowed")]
+    MissingCapabilities(Vec<Capability>),
+}
 
 bitflags::bitflags! {
     pub struct WriterFlags: u32 {
@@ -19,6 +63,86 @@ bitflags::bitflags! {
     }
 }
 
+/// The environment a SPIR-V module is written for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetEnvironment {
+    /// SPIR-V of the given `(major, minor)` version, from 1.0 to 1.5.
+    Universal(u8, u8),
+    /// The newest SPIR-V accepted by the given `(major, minor)` Vulkan version,
+    /// from 1.0 to 1.2.
+    Vulkan(u8, u8),
+}
+
+impl TargetEnvironment {
+    /// The SPIR-V version to write, or `None` if the environment is unknown.
+    pub fn spirv_version(&self) -> Option<(u8, u8)> {
+        match *self {
+            TargetEnvironment::Universal(1, minor) if minor <= 5 => Some((1, minor)),
+            TargetEnvironment::Vulkan(1, 0) => Some((1, 0)),
+            TargetEnvironment::Vulkan(1, 1) => Some((1, 3)),
+            TargetEnvironment::Vulkan(1, 2) => Some((1, 5)),
+            _ => None,
+        }
+    }
+
+    /// The capabilities every implementation of the environment supports,
+    /// or `None` if it doesn't restrict them.
+    ///
+    /// Optional features, like 64-bit floats or cube map arrays in Vulkan,
+    /// have to be allowed with `Options::capabilities`.
+    pub fn capabilities(&self) -> Option<&'static [Capability]> {
+        const VULKAN_1_0: &[Capability] = &[
+            Capability::Matrix,
+            Capability::Shader,
+            Capability::InputAttachment,
+            Capability::Sampled1D,
+            Capability::Image1D,
+            Capability::SampledBuffer,
+            Capability::ImageBuffer,
+            Capability::ImageQuery,
+            Capability::DerivativeControl,
+        ];
+        const VULKAN_1_1: &[Capability] = &[
+            Capability::Matrix,
+            Capability::Shader,
+            Capability::InputAttachment,
+            Capability::Sampled1D,
+            Capability::Image1D,
+            Capability::SampledBuffer,
+            Capability::ImageBuffer,
+            Capability::ImageQuery,
+            Capability::DerivativeControl,
+            Capability::DeviceGroup,
+            Capability::MultiView,
+            Capability::GroupNonUniform,
+        ];
+        match *self {
+            TargetEnvironment::Universal(..) => None,
+            TargetEnvironment::Vulkan(1, 0) => Some(VULKAN_1_0),
+            TargetEnvironment::Vulkan(..) => Some(VULKAN_1_1),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub target_env: TargetEnvironment,
+    pub flags: WriterFlags,
+    /// Capabilities the module is allowed to declare, overriding the ones
+    /// of the target environment.
+    pub capabilities: Option<crate::FastHashSet<Capability>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            target_env: TargetEnvironment::Vulkan(1, 0),
+            flags: WriterFlags::NONE,
+            capabilities: None,
+        }
+    }
+}
+
 struct PhysicalLayout {
     magic_number: Word,
     version: Word,
@@ -0,0 +1,927 @@
+/*! Textual form of SPIR-V modules.
+
+The syntax follows the output of `spirv-dis`: one instruction per line,
+`%N = ` in front of instructions producing a result, and operands
+written as ids, literals, quoted strings or enumerant names.
+!*/
+use super::helpers;
+use crate::FastHashMap;
+use num_traits::FromPrimitive;
+use spirv::{Op, Word};
+use std::fmt::Debug;
+
+/// Enumerant values are looked up by name below this bound.
+const MAX_ENUM_VALUE: Word = 0x2000;
+/// Column at which the opcodes are aligned.
+const OPCODE_COLUMN: usize = 15;
+const GLSL_STD_450: &str = "GLSL.std.450";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisassemblyError {
+    InvalidHeader,
+    /// The instruction at the given word offset doesn't fit in the module.
+    InvalidWordCount(usize),
+    UnknownOpcode(u16),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AssemblyErrorKind {
+    UnknownInstruction(String),
+    UnknownOperand(String),
+    UnexpectedToken(String),
+    UnterminatedString,
+    MissingResult,
+    MissingOperand,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssemblyError {
+    /// Line of the text, starting from 1.
+    pub line: usize,
+    pub kind: AssemblyErrorKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum OperandKind {
+    Id,
+    LiteralInteger,
+    LiteralString,
+    /// Literal whose width and kind are given by the result type.
+    LiteralNumber,
+    /// Instruction number within an extended instruction set.
+    ExtInstruction,
+    /// Literal followed by a label, as used by `OpSwitch`.
+    PairLiteralId,
+    /// Value followed by a label, as used by `OpPhi`.
+    PairIdId,
+    SourceLanguage,
+    ExecutionModel,
+    AddressingModel,
+    MemoryModel,
+    ExecutionMode,
+    StorageClass,
+    Dim,
+    ImageFormat,
+    AccessQualifier,
+    Decoration,
+    BuiltIn,
+    Capability,
+    FunctionControl,
+    SelectionControl,
+    LoopControl,
+    MemoryAccess,
+    ImageOperands,
+}
+
+struct Grammar {
+    result_type: bool,
+    result: bool,
+    operands: &'static [OperandKind],
+    /// Trailing operands, where the last kind repeats until the end.
+    rest: &'static [OperandKind],
+}
+
+fn grammar(op: Op) -> Grammar {
+    use OperandKind as K;
+
+    let (result_type, result, operands, rest): (_, _, &[_], &[_]) = match op {
+        Op::Nop | Op::NoLine | Op::FunctionEnd | Op::Kill | Op::Return | Op::Unreachable => {
+            (false, false, &[], &[])
+        }
+        Op::EmitVertex | Op::EndPrimitive => (false, false, &[], &[]),
+        Op::SourceContinued | Op::SourceExtension | Op::Extension => {
+            (false, false, &[K::LiteralString], &[])
+        }
+        Op::Source => (
+            false,
+            false,
+            &[K::SourceLanguage, K::LiteralInteger],
+            &[K::Id, K::LiteralString],
+        ),
+        Op::Name => (false, false, &[K::Id, K::LiteralString], &[]),
+        Op::MemberName => (
+            false,
+            false,
+            &[K::Id, K::LiteralInteger, K::LiteralString],
+            &[],
+        ),
+        Op::Line => (
+            false,
+            false,
+            &[K::Id, K::LiteralInteger, K::LiteralInteger],
+            &[],
+        ),
+        Op::MemoryModel => (false, false, &[K::AddressingModel, K::MemoryModel], &[]),
+        Op::EntryPoint => (
+            false,
+            false,
+            &[K::ExecutionModel, K::Id, K::LiteralString],
+            &[K::Id],
+        ),
+        Op::ExecutionMode => (false, false, &[K::Id, K::ExecutionMode], &[]),
+        Op::Capability => (false, false, &[K::Capability], &[]),
+        Op::Decorate => (false, false, &[K::Id, K::Decoration], &[]),
+        Op::MemberDecorate => (
+            false,
+            false,
+            &[K::Id, K::LiteralInteger, K::Decoration],
+            &[],
+        ),
+        Op::Store | Op::CopyMemory => (
+            false,
+            false,
+            &[K::Id, K::Id],
+            &[K::MemoryAccess, K::LiteralInteger],
+        ),
+        Op::ReturnValue | Op::Branch => (false, false, &[K::Id], &[]),
+        Op::LoopMerge => (
+            false,
+            false,
+            &[K::Id, K::Id, K::LoopControl],
+            &[K::LiteralInteger],
+        ),
+        Op::SelectionMerge => (false, false, &[K::Id, K::SelectionControl], &[]),
+        Op::BranchConditional => (false, false, &[K::Id, K::Id, K::Id], &[K::LiteralInteger]),
+        Op::Switch => (false, false, &[K::Id, K::Id], &[K::PairLiteralId]),
+        Op::ControlBarrier => (false, false, &[K::Id, K::Id, K::Id], &[]),
+        Op::MemoryBarrier => (false, false, &[K::Id, K::Id], &[]),
+        Op::ImageWrite => (
+            false,
+            false,
+            &[K::Id, K::Id, K::Id],
+            &[K::ImageOperands, K::Id],
+        ),
+        Op::TypeForwardPointer => (false, false, &[K::Id, K::StorageClass], &[]),
+
+        Op::String | Op::ExtInstImport | Op::TypeOpaque => (false, true, &[K::LiteralString], &[]),
+        Op::TypeVoid | Op::TypeBool | Op::TypeSampler | Op::Label | Op::DecorationGroup => {
+            (false, true, &[], &[])
+        }
+        Op::TypeInt => (false, true, &[K::LiteralInteger, K::LiteralInteger], &[]),
+        Op::TypeFloat => (false, true, &[K::LiteralInteger], &[]),
+        Op::TypeVector | Op::TypeMatrix => (false, true, &[K::Id, K::LiteralInteger], &[]),
+        Op::TypeImage => (
+            false,
+            true,
+            &[
+                K::Id,
+                K::Dim,
+                K::LiteralInteger,
+                K::LiteralInteger,
+                K::LiteralInteger,
+                K::LiteralInteger,
+                K::ImageFormat,
+            ],
+            &[K::AccessQualifier],
+        ),
+        Op::TypeSampledImage | Op::TypeRuntimeArray => (false, true, &[K::Id], &[]),
+        Op::TypeArray => (false, true, &[K::Id, K::Id], &[]),
+        Op::TypeStruct => (false, true, &[], &[K::Id]),
+        Op::TypePointer => (false, true, &[K::StorageClass, K::Id], &[]),
+        Op::TypeFunction => (false, true, &[K::Id], &[K::Id]),
+
+        Op::Undef
+        | Op::ConstantTrue
+        | Op::ConstantFalse
+        | Op::ConstantNull
+        | Op::SpecConstantTrue
+        | Op::SpecConstantFalse
+        | Op::FunctionParameter => (true, true, &[], &[]),
+        Op::Constant | Op::SpecConstant => (true, true, &[], &[K::LiteralNumber]),
+        Op::ExtInst => (true, true, &[K::Id, K::ExtInstruction], &[K::Id]),
+        Op::Function => (true, true, &[K::FunctionControl, K::Id], &[]),
+        Op::Variable => (true, true, &[K::StorageClass], &[K::Id]),
+        Op::Load => (true, true, &[K::Id], &[K::MemoryAccess, K::LiteralInteger]),
+        Op::ArrayLength => (true, true, &[K::Id, K::LiteralInteger], &[]),
+        Op::VectorShuffle => (true, true, &[K::Id, K::Id], &[K::LiteralInteger]),
+        Op::CompositeExtract => (true, true, &[K::Id], &[K::LiteralInteger]),
+        Op::CompositeInsert => (true, true, &[K::Id, K::Id], &[K::LiteralInteger]),
+        Op::ImageSampleImplicitLod
+        | Op::ImageSampleExplicitLod
+        | Op::ImageSampleProjImplicitLod
+        | Op::ImageSampleProjExplicitLod
+        | Op::ImageFetch
+        | Op::ImageRead => (true, true, &[K::Id, K::Id], &[K::ImageOperands, K::Id]),
+        Op::ImageSampleDrefImplicitLod
+        | Op::ImageSampleDrefExplicitLod
+        | Op::ImageSampleProjDrefImplicitLod
+        | Op::ImageSampleProjDrefExplicitLod
+        | Op::ImageGather
+        | Op::ImageDrefGather => (
+            true,
+            true,
+            &[K::Id, K::Id, K::Id],
+            &[K::ImageOperands, K::Id],
+        ),
+        Op::Phi => (true, true, &[], &[K::PairIdId]),
+        // the remaining instructions, e.g. arithmetic, only take ids
+        _ => (true, true, &[], &[K::Id]),
+    };
+    Grammar {
+        result_type,
+        result,
+        operands,
+        rest,
+    }
+}
+
+impl Grammar {
+    fn kind(&self, index: usize, rest: &[OperandKind]) -> OperandKind {
+        match self.operands.get(index) {
+            Some(&kind) => kind,
+            // extra words of unknown meaning are kept as plain literals
+            None if rest.is_empty() => OperandKind::LiteralInteger,
+            None => rest[(index - self.operands.len()).min(rest.len() - 1)],
+        }
+    }
+}
+
+/// Kinds of the operands following an enumerant, which depend on its value.
+fn enumerant_parameters(kind: OperandKind, value: Word) -> &'static [OperandKind] {
+    match kind {
+        OperandKind::Decoration if value == spirv::Decoration::BuiltIn as Word => {
+            &[OperandKind::BuiltIn]
+        }
+        OperandKind::Decoration | OperandKind::ExecutionMode => &[OperandKind::LiteralInteger],
+        _ => &[],
+    }
+}
+
+fn enumerant_name<T: FromPrimitive + Debug>(value: Word) -> Option<String> {
+    T::from_u32(value).map(|e| format!("{:?}", e))
+}
+
+impl OperandKind {
+    fn enumerant(self, value: Word) -> Option<String> {
+        match self {
+            OperandKind::SourceLanguage => enumerant_name::<spirv::SourceLanguage>(value),
+            OperandKind::ExecutionModel => enumerant_name::<spirv::ExecutionModel>(value),
+            OperandKind::AddressingModel => enumerant_name::<spirv::AddressingModel>(value),
+            OperandKind::MemoryModel => enumerant_name::<spirv::MemoryModel>(value),
+            OperandKind::ExecutionMode => enumerant_name::<spirv::ExecutionMode>(value),
+            OperandKind::StorageClass => enumerant_name::<spirv::StorageClass>(value),
+            OperandKind::Dim => enumerant_name::<spirv::Dim>(value)
+                .map(|name| name.trim_start_matches("Dim").to_string()),
+            OperandKind::ImageFormat => enumerant_name::<spirv::ImageFormat>(value),
+            OperandKind::AccessQualifier => enumerant_name::<spirv::AccessQualifier>(value),
+            OperandKind::Decoration => enumerant_name::<spirv::Decoration>(value),
+            OperandKind::BuiltIn => enumerant_name::<spirv::BuiltIn>(value),
+            OperandKind::Capability => enumerant_name::<spirv::Capability>(value),
+            OperandKind::ExtInstruction => enumerant_name::<spirv::GLOp>(value),
+            _ => None,
+        }
+    }
+
+    fn mask_bits(self) -> Option<&'static [(&'static str, Word)]> {
+        Some(match self {
+            OperandKind::FunctionControl => {
+                &[("Inline", 1), ("DontInline", 2), ("Pure", 4), ("Const", 8)]
+            }
+            OperandKind::SelectionControl => &[("Flatten", 1), ("DontFlatten", 2)],
+            OperandKind::LoopControl => &[
+                ("Unroll", 1),
+                ("DontUnroll", 2),
+                ("DependencyInfinite", 4),
+                ("DependencyLength", 8),
+            ],
+            OperandKind::MemoryAccess => &[("Volatile", 1), ("Aligned", 2), ("Nontemporal", 4)],
+            OperandKind::ImageOperands => &[
+                ("Bias", 0x1),
+                ("Lod", 0x2),
+                ("Grad", 0x4),
+                ("ConstOffset", 0x8),
+                ("Offset", 0x10),
+                ("ConstOffsets", 0x20),
+                ("Sample", 0x40),
+                ("MinLod", 0x80),
+            ],
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum NumberType {
+    Int { signed: bool, width: Word },
+    Float { width: Word },
+}
+
+/// Records the number types, needed to read and write constant literals.
+fn register_number_type(types: &mut FastHashMap<Word, NumberType>, op: Op, words: &[Word]) {
+    match (op, words) {
+        (Op::TypeInt, &[id, width, signedness]) => {
+            types.insert(
+                id,
+                NumberType::Int {
+                    signed: signedness != 0,
+                    width,
+                },
+            );
+        }
+        (Op::TypeFloat, &[id, width]) => {
+            types.insert(id, NumberType::Float { width });
+        }
+        _ => {}
+    }
+}
+
+fn escape_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped.push('"');
+    escaped
+}
+
+struct Disassembler {
+    number_types: FastHashMap<Word, NumberType>,
+    glsl_imports: Vec<Word>,
+}
+
+impl Disassembler {
+    /// Formats the operand at the start of `words`, returning it with the number of words read.
+    fn operand(
+        &self,
+        kind: OperandKind,
+        words: &[Word],
+        type_id: Option<Word>,
+        previous: Option<Word>,
+    ) -> (String, usize) {
+        let word = words[0];
+        match kind {
+            OperandKind::Id => (format!("%{}", word), 1),
+            OperandKind::LiteralInteger => (word.to_string(), 1),
+            OperandKind::LiteralString => {
+                let count = words
+                    .iter()
+                    .position(|w| w.to_le_bytes().contains(&0))
+                    .map_or(words.len(), |position| position + 1);
+                let bytes: Vec<u8> = words[..count]
+                    .iter()
+                    .flat_map(|w| w.to_le_bytes().to_vec())
+                    .take_while(|&b| b != 0)
+                    .collect();
+                (escape_string(&String::from_utf8_lossy(&bytes)), count)
+            }
+            OperandKind::LiteralNumber => {
+                let number_type = type_id.and_then(|id| self.number_types.get(&id));
+                match (number_type, words) {
+                    (Some(&NumberType::Float { width: 64 }), &[low, high, ..]) => {
+                        let value = f64::from_bits((high as u64) << 32 | low as u64);
+                        if value.is_finite() {
+                            (format!("{:?}", value), 2)
+                        } else {
+                            (format!("0x{:x} 0x{:x}", low, high), 2)
+                        }
+                    }
+                    (Some(&NumberType::Float { .. }), _) => {
+                        let value = f32::from_bits(word);
+                        if value.is_finite() {
+                            (format!("{:?}", value), 1)
+                        } else {
+                            (format!("0x{:x}", word), 1)
+                        }
+                    }
+                    (Some(&NumberType::Int { signed, width: 64 }), &[low, high, ..]) => {
+                        let value = (high as u64) << 32 | low as u64;
+                        if signed {
+                            ((value as i64).to_string(), 2)
+                        } else {
+                            (value.to_string(), 2)
+                        }
+                    }
+                    (Some(&NumberType::Int { signed: true, .. }), _) => {
+                        ((word as i32).to_string(), 1)
+                    }
+                    _ => (word.to_string(), 1),
+                }
+            }
+            OperandKind::ExtInstruction => {
+                let name = match previous {
+                    Some(set) if self.glsl_imports.contains(&set) => kind.enumerant(word),
+                    _ => None,
+                };
+                (name.unwrap_or_else(|| word.to_string()), 1)
+            }
+            OperandKind::PairLiteralId | OperandKind::PairIdId if words.len() < 2 => {
+                (word.to_string(), 1)
+            }
+            OperandKind::PairLiteralId => (format!("{} %{}", word, words[1]), 2),
+            OperandKind::PairIdId => (format!("%{} %{}", word, words[1]), 2),
+            _ => {
+                if let Some(bits) = kind.mask_bits() {
+                    (format_mask(bits, word), 1)
+                } else {
+                    (kind.enumerant(word).unwrap_or_else(|| word.to_string()), 1)
+                }
+            }
+        }
+    }
+
+    fn instruction(&mut self, op: Op, words: &[Word]) -> String {
+        let grammar = grammar(op);
+        let mut offset = 0;
+        let mut tokens = Vec::new();
+
+        let type_id = if grammar.result_type && offset < words.len() {
+            tokens.push(format!("%{}", words[offset]));
+            offset += 1;
+            Some(words[offset - 1])
+        } else {
+            None
+        };
+        let result = if grammar.result && offset < words.len() {
+            offset += 1;
+            Some(words[offset - 1])
+        } else {
+            None
+        };
+
+        let mut rest = grammar.rest;
+        let mut index = 0;
+        let mut previous = None;
+        while offset < words.len() {
+            let kind = grammar.kind(index, rest);
+            let (token, count) = self.operand(kind, &words[offset..], type_id, previous);
+            if index + 1 == grammar.operands.len() && rest.is_empty() {
+                rest = enumerant_parameters(kind, words[offset]);
+            }
+            tokens.push(token);
+            previous = Some(words[offset]);
+            offset += count;
+            index += 1;
+        }
+
+        match op {
+            Op::TypeInt | Op::TypeFloat => {
+                register_number_type(&mut self.number_types, op, words);
+            }
+            Op::ExtInstImport if words[1..] == helpers::string_to_words(GLSL_STD_450)[..] => {
+                self.glsl_imports.extend(result);
+            }
+            _ => {}
+        }
+
+        let prefix = match result {
+            Some(id) => format!("%{} = ", id),
+            None => String::new(),
+        };
+        let mut line = format!("{:>width$}Op{:?}", prefix, op, width = OPCODE_COLUMN);
+        for token in tokens {
+            line.push(' ');
+            line.push_str(&token);
+        }
+        line.push('\n');
+        line
+    }
+}
+
+fn format_mask(bits: &[(&str, Word)], value: Word) -> String {
+    if value == 0 {
+        return "None".to_string();
+    }
+    let names: Vec<&str> = bits
+        .iter()
+        .filter(|&&(_, bit)| value & bit != 0)
+        .map(|&(name, _)| name)
+        .collect();
+    let known = bits.iter().fold(0, |mask, &(_, bit)| mask | bit);
+    if value & !known != 0 {
+        format!("0x{:x}", value)
+    } else {
+        names.join("|")
+    }
+}
+
+/// Converts a SPIR-V module into its textual form.
+pub fn disassemble(words: &[Word]) -> Result<String, DisassemblyError> {
+    if words.len() < 5 || words[0] != spirv::MAGIC_NUMBER {
+        return Err(DisassemblyError::InvalidHeader);
+    }
+    let mut output = format!(
+        "; SPIR-V\n; Version: {}.{}\n; Generator: 0x{:08x}\n; Bound: {}\n; Schema: {}\n",
+        (words[1] >> 16) & 0xFF,
+        (words[1] >> 8) & 0xFF,
+        words[2],
+        words[3],
+        words[4],
+    );
+
+    let mut disassembler = Disassembler {
+        number_types: FastHashMap::default(),
+        glsl_imports: Vec::new(),
+    };
+    let mut offset = 5;
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+        if word_count == 0 || offset + word_count > words.len() {
+            return Err(DisassemblyError::InvalidWordCount(offset));
+        }
+        let opcode = words[offset] as u16;
+        let op = Op::from_u16(opcode).ok_or(DisassemblyError::UnknownOpcode(opcode))?;
+        output += &disassembler.instruction(op, &words[offset + 1..offset + word_count]);
+        offset += word_count;
+    }
+    Ok(output)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token<'a> {
+    Id(&'a str),
+    Equals,
+    String(String),
+    Word(&'a str),
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token<'_>>, AssemblyErrorKind> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            ';' => break,
+            '=' => {
+                chars.next();
+                tokens.push(Token::Equals);
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => value.push(c),
+                            None => return Err(AssemblyErrorKind::UnterminatedString),
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => return Err(AssemblyErrorKind::UnterminatedString),
+                    }
+                }
+                tokens.push(Token::String(value));
+            }
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            _ => {
+                let mut end = line.len();
+                while let Some(&(position, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '=' || c == ';' || c == '"' {
+                        end = position;
+                        break;
+                    }
+                    chars.next();
+                }
+                let word = &line[start..end];
+                tokens.push(match word.strip_prefix('%') {
+                    Some(name) => Token::Id(name),
+                    None => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_integer(text: &str) -> Option<u64> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else if text.starts_with('-') {
+        text.parse::<i64>().ok().map(|value| value as u64)
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn parse_mask(bits: &[(&str, Word)], text: &str) -> Option<Word> {
+    if text == "None" {
+        return Some(0);
+    }
+    text.split('|').try_fold(0, |mask, name| {
+        bits.iter()
+            .find(|&&(bit_name, _)| bit_name == name)
+            .map(|&(_, bit)| mask | bit)
+    })
+}
+
+struct Assembler<'a> {
+    /// Ids given by name, numbered after the largest numeric id.
+    named_ids: FastHashMap<&'a str, Word>,
+    next_id: Word,
+    ops: FastHashMap<String, Op>,
+    enumerants: FastHashMap<OperandKind, FastHashMap<String, Word>>,
+    number_types: FastHashMap<Word, NumberType>,
+}
+
+impl<'a> Assembler<'a> {
+    fn id(&mut self, name: &'a str) -> Word {
+        if let Ok(id) = name.parse() {
+            return id;
+        }
+        let next_id = &mut self.next_id;
+        *self.named_ids.entry(name).or_insert_with(|| {
+            *next_id += 1;
+            *next_id - 1
+        })
+    }
+
+    fn enumerant(&mut self, kind: OperandKind, name: &str) -> Option<Word> {
+        let table = self.enumerants.entry(kind).or_insert_with(|| {
+            (0..MAX_ENUM_VALUE)
+                .filter_map(|value| kind.enumerant(value).map(|name| (name, value)))
+                .collect()
+        });
+        table.get(name).cloned()
+    }
+
+    /// Encodes the operands at the start of `tokens`, returning the number of tokens read.
+    fn operand(
+        &mut self,
+        kind: OperandKind,
+        tokens: &[Token<'a>],
+        type_id: Option<Word>,
+        output: &mut Vec<Word>,
+    ) -> Result<usize, AssemblyErrorKind> {
+        let unexpected = |token: &Token| AssemblyErrorKind::UnexpectedToken(format!("{:?}", token));
+        match (kind, &tokens[0]) {
+            (OperandKind::Id, &Token::Id(name)) => {
+                output.push(self.id(name));
+                Ok(1)
+            }
+            (OperandKind::LiteralString, Token::String(value)) => {
+                output.extend(helpers::string_to_words(value));
+                Ok(1)
+            }
+            (OperandKind::PairLiteralId, _) | (OperandKind::PairIdId, _) => {
+                let first = match kind {
+                    OperandKind::PairLiteralId => OperandKind::LiteralInteger,
+                    _ => OperandKind::Id,
+                };
+                let count = self.operand(first, tokens, type_id, output)?;
+                match tokens.get(count) {
+                    Some(&Token::Id(name)) => {
+                        output.push(self.id(name));
+                        Ok(count + 1)
+                    }
+                    Some(other) => Err(unexpected(other)),
+                    None => Err(AssemblyErrorKind::MissingOperand),
+                }
+            }
+            (OperandKind::LiteralNumber, &Token::Word(text)) => {
+                let number_type = type_id.and_then(|id| self.number_types.get(&id)).cloned();
+                let value = match number_type {
+                    _ if text.starts_with("0x") => parse_integer(text),
+                    Some(NumberType::Float { width: 64 }) => {
+                        text.parse::<f64>().ok().map(f64::to_bits)
+                    }
+                    Some(NumberType::Float { .. }) => {
+                        text.parse::<f32>().ok().map(|v| v.to_bits() as u64)
+                    }
+                    _ => parse_integer(text),
+                }
+                .ok_or_else(|| AssemblyErrorKind::UnknownOperand(text.to_string()))?;
+                output.push(value as Word);
+                match number_type {
+                    Some(NumberType::Float { width: 64 })
+                    | Some(NumberType::Int { width: 64, .. })
+                        if !text.starts_with("0x") =>
+                    {
+                        output.push((value >> 32) as Word)
+                    }
+                    _ => {}
+                }
+                Ok(1)
+            }
+            (_, &Token::Word(text))
+                if kind != OperandKind::Id && kind != OperandKind::LiteralString =>
+            {
+                let value = match parse_integer(text) {
+                    Some(value) => Some(value as Word),
+                    None => match kind.mask_bits() {
+                        Some(bits) => parse_mask(bits, text),
+                        None => self.enumerant(kind, text),
+                    },
+                };
+                output.push(
+                    value.ok_or_else(|| AssemblyErrorKind::UnknownOperand(text.to_string()))?,
+                );
+                Ok(1)
+            }
+            (_, other) => Err(unexpected(other)),
+        }
+    }
+
+    fn instruction(&mut self, tokens: &[Token<'a>]) -> Result<Vec<Word>, AssemblyErrorKind> {
+        let (result, tokens) = match *tokens {
+            [Token::Id(name), Token::Equals, ref rest @ ..] => (Some(self.id(name)), rest),
+            _ => (None, tokens),
+        };
+        let (op, mut tokens) = match tokens.split_first() {
+            Some((&Token::Word(name), rest)) => {
+                let op = name
+                    .strip_prefix("Op")
+                    .and_then(|name| self.ops.get(name).cloned());
+                (
+                    op.ok_or_else(|| AssemblyErrorKind::UnknownInstruction(name.to_string()))?,
+                    rest,
+                )
+            }
+            Some((other, _)) => {
+                return Err(AssemblyErrorKind::UnexpectedToken(format!("{:?}", other)))
+            }
+            None => return Err(AssemblyErrorKind::MissingOperand),
+        };
+
+        let grammar = grammar(op);
+        let mut words = vec![0];
+        let type_id = if grammar.result_type {
+            match tokens.split_first() {
+                Some((&Token::Id(name), rest)) => {
+                    tokens = rest;
+                    let id = self.id(name);
+                    words.push(id);
+                    Some(id)
+                }
+                Some((other, _)) => {
+                    return Err(AssemblyErrorKind::UnexpectedToken(format!("{:?}", other)))
+                }
+                None => return Err(AssemblyErrorKind::MissingOperand),
+            }
+        } else {
+            None
+        };
+        match (grammar.result, result) {
+            (true, Some(id)) => words.push(id),
+            (true, None) => return Err(AssemblyErrorKind::MissingResult),
+            (false, Some(_)) => return Err(AssemblyErrorKind::UnexpectedToken("=".to_string())),
+            (false, None) => {}
+        }
+
+        let mut rest = grammar.rest;
+        let mut index = 0;
+        while !tokens.is_empty() {
+            let kind = grammar.kind(index, rest);
+            let start = words.len();
+            let count = self.operand(kind, tokens, type_id, &mut words)?;
+            if index + 1 == grammar.operands.len() && rest.is_empty() {
+                rest = enumerant_parameters(kind, words[start]);
+            }
+            tokens = &tokens[count..];
+            index += 1;
+        }
+        if index < grammar.operands.len() {
+            return Err(AssemblyErrorKind::MissingOperand);
+        }
+
+        register_number_type(&mut self.number_types, op, &words[1..]);
+        words[0] = (words.len() as Word) << 16 | op as Word;
+        Ok(words)
+    }
+}
+
+/// Builds a SPIR-V module from its textual form.
+///
+/// Ids can be numbers, as produced by `disassemble`, or names, which get
+/// the ids following the largest numeric one.
+pub fn assemble(text: &str) -> Result<Vec<Word>, AssemblyError> {
+    let mut lines = Vec::new();
+    let mut version = (1, 0);
+    let mut generator = 0;
+    let mut bound = 1;
+    for (index, line) in text.lines().enumerate() {
+        let error = |kind| AssemblyError {
+            line: index + 1,
+            kind,
+        };
+        if let Some(comment) = line.trim_start().strip_prefix(';') {
+            let mut parts = comment.splitn(2, ':').map(str::trim);
+            match (parts.next(), parts.next()) {
+                (Some("Version"), Some(value)) => {
+                    let mut numbers = value.splitn(2, '.').map(str::parse::<u8>);
+                    if let (Some(Ok(major)), Some(Ok(minor))) = (numbers.next(), numbers.next()) {
+                        version = (major, minor);
+                    }
+                }
+                (Some("Generator"), Some(value)) => {
+                    generator = parse_integer(value).map_or(0, |value| value as Word);
+                }
+                (Some("Bound"), Some(value)) => {
+                    bound = parse_integer(value).map_or(1, |value| value as Word);
+                }
+                _ => {}
+            }
+            continue;
+        }
+        let tokens = tokenize(line).map_err(error)?;
+        for token in tokens.iter() {
+            if let Token::Id(name) = *token {
+                if let Ok(id) = name.parse::<Word>() {
+                    bound = bound.max(id + 1);
+                }
+            }
+        }
+        if !tokens.is_empty() {
+            lines.push((index + 1, tokens));
+        }
+    }
+
+    let mut assembler = Assembler {
+        named_ids: FastHashMap::default(),
+        next_id: bound,
+        ops: (0..MAX_ENUM_VALUE)
+            .filter_map(|value| Op::from_u32(value).map(|op| (format!("{:?}", op), op)))
+            .collect(),
+        enumerants: FastHashMap::default(),
+        number_types: FastHashMap::default(),
+    };
+    let mut instructions = Vec::new();
+    for (line, tokens) in lines.iter() {
+        let words = assembler
+            .instruction(tokens)
+            .map_err(|kind| AssemblyError { line: *line, kind })?;
+        instructions.extend(words);
+    }
+
+    let mut words = vec![
+        spirv::MAGIC_NUMBER,
+        (version.0 as Word) << 16 | (version.1 as Word) << 8,
+        generator,
+        assembler.next_id,
+        0,
+    ];
+    words.extend(instructions);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, disassemble, AssemblyError, AssemblyErrorKind};
+
+    const SOURCE: &str = r#"
+               OpCapability Shader
+          %1 = OpExtInstImport "GLSL.std.450"
+               OpMemoryModel Logical GLSL450
+               OpEntryPoint Fragment %main "main" %color
+               OpExecutionMode %main OriginUpperLeft
+               OpName %main "main"
+               OpDecorate %color Location 0
+               OpDecorate %coord BuiltIn FragCoord
+       %void = OpTypeVoid
+         %fn = OpTypeFunction %void
+      %float = OpTypeFloat 32
+      %v4f32 = OpTypeVector %float 4
+    %out_ptr = OpTypePointer Output %v4f32
+      %color = OpVariable %out_ptr Output
+     %in_ptr = OpTypePointer Input %v4f32
+      %coord = OpVariable %in_ptr Input
+       %half = OpConstant %float 0.5
+       %main = OpFunction %void None %fn
+      %entry = OpLabel
+          %2 = OpLoad %v4f32 %coord
+          %3 = OpCompositeConstruct %v4f32 %half %half %half %half
+          %4 = OpExtInst %v4f32 %1 FMix %2 %3 %3
+               OpStore %color %4
+               OpReturn
+               OpFunctionEnd
+"#;
+
+    #[test]
+    fn round_trip() {
+        let words = assemble(SOURCE).unwrap();
+        assert_eq!(words[0], spirv::MAGIC_NUMBER);
+        assert_eq!(words[1], 0x0001_0000);
+        // named ids come after the largest numeric one
+        assert_eq!(words[3], 16);
+
+        let text = disassemble(&words).unwrap();
+        assert!(text.contains("\n          %8 = OpTypeVoid\n"));
+        assert!(text.contains("OpDecorate %7 BuiltIn FragCoord\n"));
+        assert!(text.contains("%14 = OpConstant %10 0.5\n"));
+        assert!(text.contains("OpExtInst %11 %1 FMix %2 %3 %3\n"));
+        assert_eq!(assemble(&text).unwrap(), words);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            assemble("OpCapability Shader\nOpFoo %1"),
+            Err(AssemblyError {
+                line: 2,
+                kind: AssemblyErrorKind::UnknownInstruction("OpFoo".to_string()),
+            })
+        );
+        assert_eq!(
+            assemble("OpTypeVoid").unwrap_err().kind,
+            AssemblyErrorKind::MissingResult
+        );
+        assert_eq!(
+            assemble("OpName %1 \"name").unwrap_err().kind,
+            AssemblyErrorKind::UnterminatedString
+        );
+        assert!(disassemble(&[0, 0, 0, 0, 0]).is_err());
+    }
+}
@@ -1,6 +1,12 @@
 /*! Standard Portable Intermediate Representation (SPIR-V) backend !*/
-use super::{helpers, Instruction, LogicalLayout, PhysicalLayout, WriterFlags};
-use crate::{Bytes, FastHashMap, FastHashSet, ImageFlags, VectorSize};
+use super::{
+    helpers, Error, ExpressionError, Instruction, LogicalLayout, Options, PhysicalLayout,
+    TargetEnvironment, WriterFlags,
+};
+use crate::{
+    proc::{LayoutRule, Typifier},
+    Bytes, FastHashMap, FastHashSet, ImageFlags, VectorSize,
+};
 use spirv::{Op, Word};
 use std::collections::hash_map::Entry;
 
@@ -26,6 +32,9 @@ enum LocalType {
         base: crate::Handle<crate::Type>,
         class: spirv::StorageClass,
     },
+    SampledImage {
+        image_type: crate::Handle<crate::Type>,
+    },
 }
 
 #[derive(Debug, PartialEq, Hash, Eq, Copy, Clone)]
@@ -38,49 +47,262 @@ fn map_dim(dim: crate::ImageDimension) -> spirv::Dim {
     match dim {
         crate::ImageDimension::D1 => spirv::Dim::Dim1D,
         crate::ImageDimension::D2 => spirv::Dim::Dim2D,
-        crate::ImageDimension::D3 => spirv::Dim::Dim2D,
+        crate::ImageDimension::D3 => spirv::Dim::Dim3D,
         crate::ImageDimension::Cube => spirv::Dim::DimCube,
     }
 }
 
+fn map_binary_operator(op: crate::BinaryOperator, kind: crate::ScalarKind) -> Option<Op> {
+    use crate::BinaryOperator as Bo;
+    use crate::ScalarKind as Sk;
+
+    Some(match (op, kind) {
+        (Bo::Add, Sk::Float) => Op::FAdd,
+        (Bo::Add, _) => Op::IAdd,
+        (Bo::Subtract, Sk::Float) => Op::FSub,
+        (Bo::Subtract, _) => Op::ISub,
+        (Bo::Multiply, Sk::Float) => Op::FMul,
+        (Bo::Multiply, _) => Op::IMul,
+        (Bo::Divide, Sk::Float) => Op::FDiv,
+        (Bo::Divide, Sk::Sint) => Op::SDiv,
+        (Bo::Divide, Sk::Uint) => Op::UDiv,
+        (Bo::Modulo, Sk::Float) => Op::FRem,
+        (Bo::Modulo, Sk::Sint) => Op::SRem,
+        (Bo::Modulo, Sk::Uint) => Op::UMod,
+        (Bo::Equal, Sk::Float) => Op::FOrdEqual,
+        (Bo::Equal, Sk::Bool) => Op::LogicalEqual,
+        (Bo::Equal, _) => Op::IEqual,
+        (Bo::NotEqual, Sk::Float) => Op::FUnordNotEqual,
+        (Bo::NotEqual, Sk::Bool) => Op::LogicalNotEqual,
+        (Bo::NotEqual, _) => Op::INotEqual,
+        (Bo::Less, Sk::Float) => Op::FOrdLessThan,
+        (Bo::Less, Sk::Sint) => Op::SLessThan,
+        (Bo::Less, Sk::Uint) => Op::ULessThan,
+        (Bo::LessEqual, Sk::Float) => Op::FOrdLessThanEqual,
+        (Bo::LessEqual, Sk::Sint) => Op::SLessThanEqual,
+        (Bo::LessEqual, Sk::Uint) => Op::ULessThanEqual,
+        (Bo::Greater, Sk::Float) => Op::FOrdGreaterThan,
+        (Bo::Greater, Sk::Sint) => Op::SGreaterThan,
+        (Bo::Greater, Sk::Uint) => Op::UGreaterThan,
+        (Bo::GreaterEqual, Sk::Float) => Op::FOrdGreaterThanEqual,
+        (Bo::GreaterEqual, Sk::Sint) => Op::SGreaterThanEqual,
+        (Bo::GreaterEqual, Sk::Uint) => Op::UGreaterThanEqual,
+        (Bo::And, Sk::Bool) | (Bo::LogicalAnd, _) => Op::LogicalAnd,
+        (Bo::And, _) => Op::BitwiseAnd,
+        (Bo::InclusiveOr, Sk::Bool) | (Bo::LogicalOr, _) => Op::LogicalOr,
+        (Bo::InclusiveOr, _) => Op::BitwiseOr,
+        (Bo::ExclusiveOr, Sk::Bool) => Op::LogicalNotEqual,
+        (Bo::ExclusiveOr, _) => Op::BitwiseXor,
+        (Bo::ShiftLeftLogical, _) => Op::ShiftLeftLogical,
+        (Bo::ShiftRightLogical, _) => Op::ShiftRightLogical,
+        (Bo::ShiftRightArithmetic, _) => Op::ShiftRightArithmetic,
+        _ => return None,
+    })
+}
+
+fn map_glsl_function(name: &str, kind: crate::ScalarKind) -> Option<spirv::GLOp> {
+    use crate::ScalarKind as Sk;
+    use spirv::GLOp as Gl;
+
+    Some(match (name, kind) {
+        ("abs", Sk::Float) => Gl::FAbs,
+        ("abs", _) => Gl::SAbs,
+        ("sign", Sk::Float) => Gl::FSign,
+        ("sign", _) => Gl::SSign,
+        ("min", Sk::Float) => Gl::FMin,
+        ("min", Sk::Sint) => Gl::SMin,
+        ("min", _) => Gl::UMin,
+        ("max", Sk::Float) => Gl::FMax,
+        ("max", Sk::Sint) => Gl::SMax,
+        ("max", _) => Gl::UMax,
+        ("clamp", Sk::Float) | ("fclamp", _) => Gl::FClamp,
+        ("clamp", Sk::Sint) => Gl::SClamp,
+        ("clamp", _) => Gl::UClamp,
+        ("floor", _) => Gl::Floor,
+        ("ceil", _) => Gl::Ceil,
+        ("fract", _) => Gl::Fract,
+        ("sqrt", _) => Gl::Sqrt,
+        ("inversesqrt", _) => Gl::InverseSqrt,
+        ("exp", _) => Gl::Exp,
+        ("exp2", _) => Gl::Exp2,
+        ("log", _) => Gl::Log,
+        ("log2", _) => Gl::Log2,
+        ("sin", _) => Gl::Sin,
+        ("cos", _) => Gl::Cos,
+        ("tan", _) => Gl::Tan,
+        ("asin", _) => Gl::Asin,
+        ("acos", _) => Gl::Acos,
+        ("atan", _) => Gl::Atan,
+        ("atan2", _) => Gl::Atan2,
+        ("pow", _) => Gl::Pow,
+        ("mix", _) => Gl::FMix,
+        ("step", _) => Gl::Step,
+        ("smoothstep", _) => Gl::SmoothStep,
+        ("length", _) => Gl::Length,
+        ("distance", _) => Gl::Distance,
+        ("normalize", _) => Gl::Normalize,
+        ("reflect", _) => Gl::Reflect,
+        ("cross", _) => Gl::Cross,
+        ("faceforward", _) => Gl::FaceForward,
+        _ => return None,
+    })
+}
+
+/// Gather the globals used by a function and everything it calls.
+fn collect_global_usage(
+    ir_module: &crate::Module,
+    handle: crate::Handle<crate::Function>,
+    usage: &mut [crate::GlobalUse],
+) {
+    let function = &ir_module.functions[handle];
+    for (total, &used) in usage.iter_mut().zip(&function.global_usage) {
+        *total |= used;
+    }
+    for (_, expression) in function.expressions.iter() {
+        if let crate::Expression::Call {
+            origin: crate::FunctionOrigin::Local(callee),
+            ..
+        } = *expression
+        {
+            collect_global_usage(ir_module, callee, usage);
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Hash, Eq)]
 struct LookupFunctionType {
     parameter_type_ids: Vec<Word>,
     return_type_id: Word,
 }
 
+/// Where the control goes when a block runs to its end.
+#[derive(Clone, Copy)]
+enum BlockExit {
+    Branch(Word),
+    Return,
+}
+
+/// Targets of `Break` and `Continue` in the innermost construct.
+#[derive(Clone, Copy, Default)]
+struct LoopContext {
+    continuing_id: Option<Word>,
+    break_id: Option<Word>,
+}
+
+struct FunctionContext<'a> {
+    handle: crate::Handle<crate::Function>,
+    function: &'a crate::Function,
+    types: &'a crate::Arena<crate::Type>,
+    typifier: Typifier,
+    parameter_ids: Vec<Word>,
+    local_variable_ids: FastHashMap<crate::Handle<crate::LocalVariable>, Word>,
+    variables: Vec<Instruction>,
+    /// Values of the expressions already written for the current statement.
+    /// They can't be reused across statements, since a store in between
+    /// may change what a load returns.
+    cached_expressions: FastHashMap<crate::Handle<crate::Expression>, Word>,
+}
+
+impl FunctionContext<'_> {
+    fn error(
+        &self,
+        expression: crate::Handle<crate::Expression>,
+        reason: ExpressionError,
+    ) -> Error {
+        Error::Expression(self.handle, expression, reason)
+    }
+
+    fn resolve_type(&self, handle: crate::Handle<crate::Expression>) -> &crate::TypeInner {
+        &self.types[self.typifier.get(handle)].inner
+    }
+
+    fn scalar_kind(
+        &self,
+        handle: crate::Handle<crate::Expression>,
+    ) -> Result<crate::ScalarKind, Error> {
+        match *self.resolve_type(handle) {
+            crate::TypeInner::Scalar { kind, .. }
+            | crate::TypeInner::Vector { kind, .. }
+            | crate::TypeInner::Matrix { kind, .. } => Ok(kind),
+            ref other => Err(self.error(handle, ExpressionError::UnexpectedType(other.clone()))),
+        }
+    }
+
+    fn is_variable(&self, handle: crate::Handle<crate::Expression>) -> bool {
+        matches!(
+            self.function.expressions[handle],
+            crate::Expression::GlobalVariable(_)
+                | crate::Expression::LocalVariable(_)
+                | crate::Expression::FunctionParameter(_)
+        )
+    }
+
+    /// Variables and accesses into them are pointers in SPIR-V,
+    /// and have to be loaded when used as values.
+    fn is_pointer(&self, handle: crate::Handle<crate::Expression>) -> bool {
+        match self.function.expressions[handle] {
+            crate::Expression::GlobalVariable(_) | crate::Expression::LocalVariable(_) => true,
+            crate::Expression::Access { base, .. }
+            | crate::Expression::AccessIndex { base, .. } => self.is_pointer(base),
+            _ => false,
+        }
+    }
+}
+
 pub struct Writer {
     physical_layout: PhysicalLayout,
     logical_layout: LogicalLayout,
     id_count: u32,
     capabilities: FastHashSet<spirv::Capability>,
+    allowed_capabilities: Option<FastHashSet<spirv::Capability>>,
+    extensions: FastHashSet<&'static str>,
     debugs: Vec<Instruction>,
     annotations: Vec<Instruction>,
     writer_flags: WriterFlags,
+    target_env: TargetEnvironment,
+    version: (u8, u8),
     void_type: Option<u32>,
     lookup_type: FastHashMap<LookupType, Word>,
     lookup_function: FastHashMap<crate::Handle<crate::Function>, Word>,
     lookup_function_type: FastHashMap<LookupFunctionType, Word>,
     lookup_constant: FastHashMap<crate::Handle<crate::Constant>, Word>,
     lookup_global_variable: FastHashMap<crate::Handle<crate::GlobalVariable>, Word>,
+    lookup_index_constant: FastHashMap<Word, Word>,
+    lookup_source_file: FastHashMap<crate::Handle<crate::SourceFile>, Word>,
+    /// Types placed in buffers, which need explicit layout decorations.
+    layout_rules: FastHashMap<crate::Handle<crate::Type>, LayoutRule>,
+    block_types: FastHashSet<crate::Handle<crate::Type>>,
+    gl450_ext_inst_id: Word,
 }
 
 impl Writer {
-    pub fn new(header: &crate::Header, writer_flags: WriterFlags) -> Self {
+    pub fn new(header: &crate::Header, options: &Options) -> Self {
         Writer {
             physical_layout: PhysicalLayout::new(header),
             logical_layout: LogicalLayout::default(),
             id_count: 0,
             capabilities: FastHashSet::default(),
+            allowed_capabilities: options.capabilities.clone().or_else(|| {
+                let capabilities = options.target_env.capabilities()?;
+                Some(capabilities.iter().cloned().collect())
+            }),
+            extensions: FastHashSet::default(),
             debugs: vec![],
             annotations: vec![],
-            writer_flags,
+            writer_flags: options.flags,
+            target_env: options.target_env,
+            version: (1, 0),
             void_type: None,
             lookup_type: FastHashMap::default(),
             lookup_function: FastHashMap::default(),
             lookup_function_type: FastHashMap::default(),
             lookup_constant: FastHashMap::default(),
             lookup_global_variable: FastHashMap::default(),
+            lookup_index_constant: FastHashMap::default(),
+            lookup_source_file: FastHashMap::default(),
+            layout_rules: FastHashMap::default(),
+            block_types: FastHashSet::default(),
+            gl450_ext_inst_id: 0,
         }
     }
 
@@ -95,6 +317,41 @@ impl Writer {
         }
     }
 
+    /// Use an extension, unless it's already part of the target SPIR-V version.
+    fn try_add_extension(&mut self, name: &'static str, core_version: (u8, u8)) {
+        if self.version < core_version {
+            self.extensions.insert(name);
+        }
+    }
+
+    fn add_image_capabilities(&mut self, dim: crate::ImageDimension, flags: ImageFlags) {
+        use spirv::Capability as Cap;
+
+        let sampled = flags.contains(ImageFlags::SAMPLED);
+        match dim {
+            crate::ImageDimension::D1 if sampled => self.try_add_capabilities(&[Cap::Sampled1D]),
+            crate::ImageDimension::D1 => self.try_add_capabilities(&[Cap::Image1D]),
+            crate::ImageDimension::Cube if flags.contains(ImageFlags::ARRAYED) => {
+                if sampled {
+                    self.try_add_capabilities(&[Cap::SampledCubeArray])
+                } else {
+                    self.try_add_capabilities(&[Cap::ImageCubeArray])
+                }
+            }
+            _ => {}
+        }
+
+        // Storage images are declared without a format, which the IR doesn't have.
+        // That only needs a capability to read or write them, and not one of the
+        // extended formats, but the IR has no image loads or stores yet.
+        if !sampled && flags.contains(ImageFlags::MULTISAMPLED) {
+            self.try_add_capabilities(&[Cap::StorageImageMultisample]);
+            if flags.contains(ImageFlags::ARRAYED) {
+                self.try_add_capabilities(&[Cap::ImageMSArray]);
+            }
+        }
+    }
+
     fn get_type_id(&mut self, arena: &crate::Arena<crate::Type>, lookup_ty: LookupType) -> Word {
         if let Entry::Occupied(e) = self.lookup_type.entry(lookup_ty) {
             *e.get()
@@ -110,13 +367,13 @@ impl Writer {
         &mut self,
         handle: crate::Handle<crate::Constant>,
         ir_module: &crate::Module,
-    ) -> Word {
+    ) -> Result<Word, Error> {
         match self.lookup_constant.entry(handle) {
-            Entry::Occupied(e) => *e.get(),
+            Entry::Occupied(e) => Ok(*e.get()),
             _ => {
-                let (instruction, id) = self.write_constant_type(handle, ir_module);
+                let (instruction, id) = self.write_constant_type(handle, ir_module)?;
                 instruction.to_words(&mut self.logical_layout.declarations);
-                id
+                Ok(id)
             }
         }
     }
@@ -164,36 +421,56 @@ impl Writer {
         handle: crate::Handle<crate::Type>,
         class: spirv::StorageClass,
     ) -> Word {
-        let ty = &arena[handle];
-        let ty_id = self.get_type_id(arena, LookupType::Handle(handle));
-        match ty.inner {
-            crate::TypeInner::Pointer { .. } => ty_id,
-            _ => {
-                match self
-                    .lookup_type
-                    .entry(LookupType::Local(LocalType::Pointer {
-                        base: handle,
-                        class,
-                    })) {
-                    Entry::Occupied(e) => *e.get(),
-                    _ => {
-                        let pointer_id = self.generate_id();
-                        let instruction = self.instruction_type_pointer(pointer_id, class, ty_id);
-                        instruction.to_words(&mut self.logical_layout.declarations);
-                        self.lookup_type.insert(
-                            LookupType::Local(LocalType::Pointer {
-                                base: handle,
-                                class,
-                            }),
-                            pointer_id,
-                        );
-                        pointer_id
-                    }
-                }
+        match arena[handle].inner {
+            crate::TypeInner::Pointer { .. } => self.get_type_id(arena, LookupType::Handle(handle)),
+            _ => self.get_type_id(
+                arena,
+                LookupType::Local(LocalType::Pointer {
+                    base: handle,
+                    class,
+                }),
+            ),
+        }
+    }
+
+    fn get_index_constant(&mut self, arena: &crate::Arena<crate::Type>, index: Word) -> Word {
+        if let Some(&id) = self.lookup_index_constant.get(&index) {
+            return id;
+        }
+
+        let type_id = self.get_type_id(
+            arena,
+            LookupType::Local(LocalType::Scalar {
+                kind: crate::ScalarKind::Uint,
+                width: 4,
+            }),
+        );
+        let id = self.generate_id();
+        self.instruction_constant(type_id, id, &[index])
+            .to_words(&mut self.logical_layout.declarations);
+        self.lookup_index_constant.insert(index, id);
+        id
+    }
+
+    fn get_function_id(&mut self, handle: crate::Handle<crate::Function>) -> Word {
+        match self.lookup_function.get(&handle) {
+            Some(&id) => id,
+            None => {
+                let id = self.generate_id();
+                self.lookup_function.insert(handle, id);
+                id
             }
         }
     }
 
+    fn get_expression_type_id(
+        &mut self,
+        ctx: &FunctionContext,
+        handle: crate::Handle<crate::Expression>,
+    ) -> Word {
+        self.get_type_id(ctx.types, LookupType::Handle(ctx.typifier.get(handle)))
+    }
+
     ///
     /// Debug Instructions
     ///
@@ -216,6 +493,29 @@ impl Writer {
         instruction
     }
 
+    fn instruction_member_name(&self, target_id: Word, member: Word, name: &str) -> Instruction {
+        let mut instruction = Instruction::new(Op::MemberName);
+        instruction.add_operand(target_id);
+        instruction.add_operand(member);
+        instruction.add_operands(helpers::string_to_words(name));
+        instruction
+    }
+
+    fn instruction_string(&self, id: Word, string: &str) -> Instruction {
+        let mut instruction = Instruction::new(Op::String);
+        instruction.set_result(id);
+        instruction.add_operands(helpers::string_to_words(string));
+        instruction
+    }
+
+    fn instruction_line(&self, file_id: Word, line: Word, column: Word) -> Instruction {
+        let mut instruction = Instruction::new(Op::Line);
+        instruction.add_operand(file_id);
+        instruction.add_operand(line);
+        instruction.add_operand(column);
+        instruction
+    }
+
     ///
     /// Annotation Instructions
     ///
@@ -233,18 +533,55 @@ impl Writer {
         instruction
     }
 
+    fn instruction_member_decorate(
+        &self,
+        target_id: Word,
+        member_index: Word,
+        decoration: spirv::Decoration,
+        operands: &[Word],
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::MemberDecorate);
+        instruction.add_operand(target_id);
+        instruction.add_operand(member_index);
+        instruction.add_operand(decoration as u32);
+        instruction.add_operands(Vec::from(operands));
+        instruction
+    }
+
     ///
     /// Extension Instructions
     ///
 
-    fn instruction_ext_inst_import(&mut self, name: &str) -> Instruction {
+    fn instruction_extension(&self, name: &str) -> Instruction {
+        let mut instruction = Instruction::new(Op::Extension);
+        instruction.add_operands(helpers::string_to_words(name));
+        instruction
+    }
+
+    fn instruction_ext_inst_import(&self, id: Word, name: &str) -> Instruction {
         let mut instruction = Instruction::new(Op::ExtInstImport);
-        let id = self.generate_id();
         instruction.set_result(id);
         instruction.add_operands(helpers::string_to_words(name));
         instruction
     }
 
+    fn instruction_ext_inst(
+        &self,
+        set_id: Word,
+        op: spirv::GLOp,
+        result_type_id: Word,
+        id: Word,
+        operands: Vec<Word>,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::ExtInst);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(set_id);
+        instruction.add_operand(op as u32);
+        instruction.add_operands(operands);
+        instruction
+    }
+
     ///
     /// Mode-Setting Instructions
     ///
@@ -268,7 +605,7 @@ impl Writer {
     ) -> Instruction {
         let mut instruction = Instruction::new(Op::EntryPoint);
 
-        let function_id = *self.lookup_function.get(&entry_point.function).unwrap();
+        let function_id = self.get_function_id(entry_point.function);
 
         let exec_model = match entry_point.stage {
             crate::ShaderStage::Vertex => spirv::ExecutionModel::Vertex,
@@ -280,13 +617,16 @@ impl Writer {
         instruction.add_operand(function_id);
         instruction.add_operands(helpers::string_to_words(entry_point.name.as_str()));
 
-        let function = &ir_module.functions[entry_point.function];
-        for ((handle, _), &usage) in ir_module
-            .global_variables
-            .iter()
-            .zip(&function.global_usage)
-        {
-            if usage.contains(crate::GlobalUse::STORE) || usage.contains(crate::GlobalUse::LOAD) {
+        // Before SPIR-V 1.4 the interface only lists inputs and outputs,
+        // later it includes all globals used by the entry point.
+        let mut usage = vec![crate::GlobalUse::empty(); ir_module.global_variables.len()];
+        collect_global_usage(ir_module, entry_point.function, &mut usage);
+        for ((handle, variable), &usage) in ir_module.global_variables.iter().zip(&usage) {
+            let is_interface = match variable.class {
+                crate::StorageClass::Input | crate::StorageClass::Output => true,
+                _ => self.version >= (1, 4),
+            };
+            if is_interface && !usage.is_empty() {
                 let id = self.get_global_variable_id(
                     &ir_module.types,
                     &ir_module.global_variables,
@@ -308,7 +648,10 @@ impl Writer {
             crate::ShaderStage::Compute => {}
         }
 
-        if self.writer_flags.contains(WriterFlags::DEBUG) {
+        // named functions get their own name, when written
+        if self.writer_flags.contains(WriterFlags::DEBUG)
+            && ir_module.functions[entry_point.function].name.is_none()
+        {
             self.debugs
                 .push(self.instruction_name(function_id, entry_point.name.as_str()));
         }
@@ -426,18 +769,16 @@ impl Writer {
         // TODO Image Format defaults to Unknown, not yet in IR
         instruction.add_operand(spirv::ImageFormat::Unknown as u32);
 
-        // Access Qualifier
-        instruction.add_operand(
-            if flags.contains(crate::ImageFlags::CAN_STORE)
-                && flags.contains(crate::ImageFlags::CAN_LOAD)
-            {
-                2
-            } else if flags.contains(crate::ImageFlags::CAN_STORE) {
-                1
-            } else {
-                0
-            },
-        );
+        // Access Qualifier, only present for storage images
+        let can_load = flags.contains(crate::ImageFlags::CAN_LOAD);
+        let can_store = flags.contains(crate::ImageFlags::CAN_STORE);
+        if can_load || can_store {
+            instruction.add_operand(match (can_load, can_store) {
+                (true, true) => spirv::AccessQualifier::ReadWrite as u32,
+                (false, true) => spirv::AccessQualifier::WriteOnly as u32,
+                _ => spirv::AccessQualifier::ReadOnly as u32,
+            });
+        }
         instruction
     }
 
@@ -447,6 +788,13 @@ impl Writer {
         instruction
     }
 
+    fn instruction_type_sampled_image(&self, id: Word, image_type_id: Word) -> Instruction {
+        let mut instruction = Instruction::new(Op::TypeSampledImage);
+        instruction.set_result(id);
+        instruction.add_operand(image_type_id);
+        instruction
+    }
+
     fn instruction_type_array(
         &self,
         id: Word,
@@ -597,6 +945,21 @@ impl Writer {
         instruction
     }
 
+    fn instruction_access_chain(
+        &self,
+        pointer_type_id: Word,
+        id: Word,
+        base_id: Word,
+        index_ids: Vec<Word>,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::AccessChain);
+        instruction.set_type(pointer_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(base_id);
+        instruction.add_operands(index_ids);
+        instruction
+    }
+
     ///
     /// Function Instructions
     ///
@@ -616,14 +979,83 @@ impl Writer {
         instruction
     }
 
+    fn instruction_function_parameter(&self, result_type_id: Word, id: Word) -> Instruction {
+        let mut instruction = Instruction::new(Op::FunctionParameter);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction
+    }
+
     fn instruction_function_end(&self) -> Instruction {
         Instruction::new(Op::FunctionEnd)
     }
 
+    fn instruction_function_call(
+        &self,
+        result_type_id: Word,
+        id: Word,
+        function_id: Word,
+        argument_ids: Vec<Word>,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::FunctionCall);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(function_id);
+        instruction.add_operands(argument_ids);
+        instruction
+    }
+
     ///
     /// Image Instructions
     ///
 
+    fn instruction_sampled_image(
+        &self,
+        sampled_image_type_id: Word,
+        id: Word,
+        image_id: Word,
+        sampler_id: Word,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::SampledImage);
+        instruction.set_type(sampled_image_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(image_id);
+        instruction.add_operand(sampler_id);
+        instruction
+    }
+
+    fn instruction_image_sample_implicit_lod(
+        &self,
+        result_type_id: Word,
+        id: Word,
+        sampled_image_id: Word,
+        coordinate_id: Word,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::ImageSampleImplicitLod);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(sampled_image_id);
+        instruction.add_operand(coordinate_id);
+        instruction
+    }
+
+    fn instruction_image_sample_dref_implicit_lod(
+        &self,
+        result_type_id: Word,
+        id: Word,
+        sampled_image_id: Word,
+        coordinate_id: Word,
+        depth_ref_id: Word,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::ImageSampleDrefImplicitLod);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(sampled_image_id);
+        instruction.add_operand(coordinate_id);
+        instruction.add_operand(depth_ref_id);
+        instruction
+    }
+
     ///
     /// Conversion Instructions
     ///
@@ -645,22 +1077,71 @@ impl Writer {
         instruction
     }
 
+    fn instruction_composite_extract(
+        &self,
+        result_type_id: Word,
+        id: Word,
+        composite_id: Word,
+        indices: &[Word],
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::CompositeExtract);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(composite_id);
+        instruction.add_operands(Vec::from(indices));
+        instruction
+    }
+
+    fn instruction_vector_extract_dynamic(
+        &self,
+        result_type_id: Word,
+        id: Word,
+        vector_id: Word,
+        index_id: Word,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::VectorExtractDynamic);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(vector_id);
+        instruction.add_operand(index_id);
+        instruction
+    }
+
     ///
     /// Arithmetic Instructions
     ///
 
-    fn instruction_vector_times_scalar(
+    /// Any instruction of the form `%id = OpXxx %type %operand`,
+    /// which covers negation, conversions and derivatives.
+    fn instruction_unary(
+        &self,
+        op: Op,
+        result_type_id: Word,
+        id: Word,
+        operand_id: Word,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(op);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(operand_id);
+        instruction
+    }
+
+    /// Any instruction of the form `%id = OpXxx %type %left %right`,
+    /// which covers arithmetic, bit, relational and logical operators.
+    fn instruction_binary(
         &self,
-        float_type_id: Word,
+        op: Op,
+        result_type_id: Word,
         id: Word,
-        vector_type_id: Word,
-        scalar_type_id: Word,
+        left_id: Word,
+        right_id: Word,
     ) -> Instruction {
-        let mut instruction = Instruction::new(Op::VectorTimesScalar);
-        instruction.set_type(float_type_id);
+        let mut instruction = Instruction::new(op);
+        instruction.set_type(result_type_id);
         instruction.set_result(id);
-        instruction.add_operand(vector_type_id);
-        instruction.add_operand(scalar_type_id);
+        instruction.add_operand(left_id);
+        instruction.add_operand(right_id);
         instruction
     }
 
@@ -672,6 +1153,23 @@ impl Writer {
     /// Relational and Logical Instructions
     ///
 
+    fn instruction_select(
+        &self,
+        result_type_id: Word,
+        id: Word,
+        condition_id: Word,
+        accept_id: Word,
+        reject_id: Word,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::Select);
+        instruction.set_type(result_type_id);
+        instruction.set_result(id);
+        instruction.add_operand(condition_id);
+        instruction.add_operand(accept_id);
+        instruction.add_operand(reject_id);
+        instruction
+    }
+
     ///
     /// Derivative Instructions
     ///
@@ -686,6 +1184,69 @@ impl Writer {
         instruction
     }
 
+    fn instruction_selection_merge(
+        &self,
+        merge_id: Word,
+        selection_control: spirv::SelectionControl,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::SelectionMerge);
+        instruction.add_operand(merge_id);
+        instruction.add_operand(selection_control.bits());
+        instruction
+    }
+
+    fn instruction_loop_merge(
+        &self,
+        merge_id: Word,
+        continuing_id: Word,
+        loop_control: spirv::LoopControl,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::LoopMerge);
+        instruction.add_operand(merge_id);
+        instruction.add_operand(continuing_id);
+        instruction.add_operand(loop_control.bits());
+        instruction
+    }
+
+    fn instruction_branch(&self, id: Word) -> Instruction {
+        let mut instruction = Instruction::new(Op::Branch);
+        instruction.add_operand(id);
+        instruction
+    }
+
+    fn instruction_branch_conditional(
+        &self,
+        condition_id: Word,
+        accept_id: Word,
+        reject_id: Word,
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::BranchConditional);
+        instruction.add_operand(condition_id);
+        instruction.add_operand(accept_id);
+        instruction.add_operand(reject_id);
+        instruction
+    }
+
+    fn instruction_switch(
+        &self,
+        selector_id: Word,
+        default_id: Word,
+        cases: &[(Word, Word)],
+    ) -> Instruction {
+        let mut instruction = Instruction::new(Op::Switch);
+        instruction.add_operand(selector_id);
+        instruction.add_operand(default_id);
+        for &(literal, label_id) in cases {
+            instruction.add_operand(literal);
+            instruction.add_operand(label_id);
+        }
+        instruction
+    }
+
+    fn instruction_kill(&self) -> Instruction {
+        Instruction::new(Op::Kill)
+    }
+
     fn instruction_return(&self) -> Instruction {
         Instruction::new(Op::Return)
     }
@@ -696,6 +1257,10 @@ impl Writer {
         instruction
     }
 
+    fn instruction_unreachable(&self) -> Instruction {
+        Instruction::new(Op::Unreachable)
+    }
+
     ///
     /// Atomic Instructions
     ///
@@ -704,8 +1269,26 @@ impl Writer {
     /// Primitive Instructions
     ///
 
-    fn write_scalar(&self, id: Word, kind: crate::ScalarKind, width: Bytes) -> Instruction {
+    fn write_scalar(&mut self, id: Word, kind: crate::ScalarKind, width: Bytes) -> Instruction {
         let bits = (width * BITS_PER_BYTE) as u32;
+        let capability = match (kind, width) {
+            (crate::ScalarKind::Float, 2) => Some(spirv::Capability::Float16),
+            (crate::ScalarKind::Float, 8) => Some(spirv::Capability::Float64),
+            (crate::ScalarKind::Sint, 1) | (crate::ScalarKind::Uint, 1) => {
+                Some(spirv::Capability::Int8)
+            }
+            (crate::ScalarKind::Sint, 2) | (crate::ScalarKind::Uint, 2) => {
+                Some(spirv::Capability::Int16)
+            }
+            (crate::ScalarKind::Sint, 8) | (crate::ScalarKind::Uint, 8) => {
+                Some(spirv::Capability::Int64)
+            }
+            _ => None,
+        };
+        if let Some(capability) = capability {
+            self.try_add_capabilities(&[capability]);
+        }
+
         match kind {
             crate::ScalarKind::Sint => self.instruction_type_int(id, bits, Signedness::Signed),
             crate::ScalarKind::Uint => self.instruction_type_int(id, bits, Signedness::Unsigned),
@@ -735,11 +1318,19 @@ impl Writer {
         let id = self.generate_id();
         let instruction = match local_ty {
             LocalType::Scalar { kind, width } => self.write_scalar(id, kind, width),
-            LocalType::Vector { size, .. } => {
-                let scalar_id = self.get_type_id(arena, LookupType::Local(local_ty));
+            LocalType::Vector { size, kind, width } => {
+                let scalar_id =
+                    self.get_type_id(arena, LookupType::Local(LocalType::Scalar { kind, width }));
                 self.instruction_type_vector(id, scalar_id, size)
             }
-            LocalType::Pointer { .. } => unimplemented!(),
+            LocalType::Pointer { base, class } => {
+                let type_id = self.get_type_id(arena, LookupType::Handle(base));
+                self.instruction_type_pointer(id, class, type_id)
+            }
+            LocalType::SampledImage { image_type } => {
+                let image_type_id = self.get_type_id(arena, LookupType::Handle(image_type));
+                self.instruction_type_sampled_image(id, image_type_id)
+            }
         };
 
         self.lookup_type.insert(LookupType::Local(local_ty), id);
@@ -753,33 +1344,41 @@ impl Writer {
         handle: crate::Handle<crate::Type>,
     ) -> Word {
         let ty = &arena[handle];
-        let id = self.generate_id();
 
-        let instruction = match ty.inner {
-            crate::TypeInner::Scalar { kind, width } => {
-                self.lookup_type
-                    .insert(LookupType::Local(LocalType::Scalar { kind, width }), id);
-                self.write_scalar(id, kind, width)
-            }
+        // Scalars, vectors and pointers are shared with the types we declare
+        // on our own, and SPIR-V doesn't allow declaring them twice.
+        let local_ty = match ty.inner {
+            crate::TypeInner::Scalar { kind, width } => Some(LocalType::Scalar { kind, width }),
             crate::TypeInner::Vector { size, kind, width } => {
-                let scalar_id =
-                    self.get_type_id(arena, LookupType::Local(LocalType::Scalar { kind, width }));
-                self.lookup_type.insert(
-                    LookupType::Local(LocalType::Vector { size, kind, width }),
-                    id,
-                );
-                self.instruction_type_vector(id, scalar_id, size)
+                Some(LocalType::Vector { size, kind, width })
             }
+            crate::TypeInner::Pointer { base, class } => Some(LocalType::Pointer {
+                base,
+                class: self.parse_to_spirv_storage_class(class),
+            }),
+            _ => None,
+        };
+        if let Some(local_ty) = local_ty {
+            let id = self.get_type_id(arena, LookupType::Local(local_ty));
+            self.lookup_type.insert(LookupType::Handle(handle), id);
+            return id;
+        }
+
+        let id = self.generate_id();
+        let instruction = match ty.inner {
+            crate::TypeInner::Scalar { .. }
+            | crate::TypeInner::Vector { .. }
+            | crate::TypeInner::Pointer { .. } => unreachable!(),
             crate::TypeInner::Matrix {
                 columns,
-                rows: _,
+                rows,
                 kind,
                 width,
             } => {
                 let vector_id = self.get_type_id(
                     arena,
                     LookupType::Local(LocalType::Vector {
-                        size: columns,
+                        size: rows,
                         kind,
                         width,
                     }),
@@ -788,61 +1387,137 @@ impl Writer {
             }
             crate::TypeInner::Image { base, dim, flags } => {
                 let type_id = self.get_type_id(arena, LookupType::Handle(base));
-                let dim = map_dim(dim);
-                self.try_add_capabilities(dim.required_capabilities());
-                self.instruction_type_image(id, type_id, dim, flags, false)
+                self.add_image_capabilities(dim, flags);
+                self.instruction_type_image(id, type_id, map_dim(dim), flags, false)
             }
             crate::TypeInner::DepthImage { dim, arrayed } => {
-                let type_id = 0; //TODO!
-                let dim = map_dim(dim);
-                self.try_add_capabilities(dim.required_capabilities());
-
+                let type_id = self.get_type_id(
+                    arena,
+                    LookupType::Local(LocalType::Scalar {
+                        kind: crate::ScalarKind::Float,
+                        width: 4,
+                    }),
+                );
                 let flags = if arrayed {
-                    crate::ImageFlags::ARRAYED
+                    crate::ImageFlags::ARRAYED | crate::ImageFlags::SAMPLED
                 } else {
-                    crate::ImageFlags::empty()
+                    crate::ImageFlags::SAMPLED
                 };
-                self.instruction_type_image(id, type_id, dim, flags, true)
+                self.add_image_capabilities(dim, flags);
+                self.instruction_type_image(id, type_id, map_dim(dim), flags, true)
             }
             crate::TypeInner::Sampler { comparison: _ } => self.instruction_type_sampler(id),
-            crate::TypeInner::Array { size, stride, .. } => {
-                if let Some(array_stride) = stride {
+            crate::TypeInner::Array { base, size, stride } => {
+                let array_stride = match stride {
+                    Some(stride) => Some(stride.get()),
+                    None => self
+                        .layout_rules
+                        .get(&handle)
+                        .map(|rule| rule.array_stride(arena, base)),
+                };
+                if let Some(array_stride) = array_stride {
                     self.annotations.push(self.instruction_decorate(
                         id,
                         spirv::Decoration::ArrayStride,
-                        &[array_stride.get()],
+                        &[array_stride],
                     ));
                 }
 
-                let type_id = self.get_type_id(arena, LookupType::Handle(handle));
+                let type_id = self.get_type_id(arena, LookupType::Handle(base));
                 match size {
                     crate::ArraySize::Static(length) => {
-                        self.instruction_type_array(id, type_id, length)
+                        let length_id = self.get_index_constant(arena, length);
+                        self.instruction_type_array(id, type_id, length_id)
                     }
                     crate::ArraySize::Dynamic => self.instruction_type_runtime_array(id, type_id),
                 }
             }
             crate::TypeInner::Struct { ref members } => {
+                let layout_rule = self.layout_rules.get(&handle).cloned();
+                if self.block_types.contains(&handle) {
+                    self.annotations.push(self.instruction_decorate(
+                        id,
+                        spirv::Decoration::Block,
+                        &[],
+                    ));
+                }
+                let offsets = layout_rule.map(|rule| rule.member_offsets(arena, members));
+
                 let mut member_ids = Vec::with_capacity(members.len());
-                for member in members {
+                for (index, member) in members.iter().enumerate() {
                     let member_id = self.get_type_id(arena, LookupType::Handle(member.ty));
                     member_ids.push(member_id);
+
+                    let index = index as Word;
+                    match member.origin {
+                        crate::MemberOrigin::Empty => {
+                            if let Some(ref offsets) = offsets {
+                                self.annotations.push(self.instruction_member_decorate(
+                                    id,
+                                    index,
+                                    spirv::Decoration::Offset,
+                                    &[offsets[index as usize]],
+                                ));
+                            }
+                        }
+                        crate::MemberOrigin::BuiltIn(built_in) => {
+                            let built_in = self.map_built_in(built_in);
+                            self.annotations.push(self.instruction_member_decorate(
+                                id,
+                                index,
+                                spirv::Decoration::BuiltIn,
+                                &[built_in as u32],
+                            ));
+                        }
+                        crate::MemberOrigin::Offset(offset) => {
+                            self.annotations.push(self.instruction_member_decorate(
+                                id,
+                                index,
+                                spirv::Decoration::Offset,
+                                &[offset],
+                            ));
+                        }
+                    }
+
+                    // matrices, and arrays of them, need to know their column layout
+                    if let Some(rule) = layout_rule {
+                        let mut member_ty = member.ty;
+                        while let crate::TypeInner::Array { base, .. } = arena[member_ty].inner {
+                            member_ty = base;
+                        }
+                        if let crate::TypeInner::Matrix { rows, width, .. } = arena[member_ty].inner
+                        {
+                            self.annotations.push(self.instruction_member_decorate(
+                                id,
+                                index,
+                                spirv::Decoration::ColMajor,
+                                &[],
+                            ));
+                            self.annotations.push(self.instruction_member_decorate(
+                                id,
+                                index,
+                                spirv::Decoration::MatrixStride,
+                                &[rule.matrix_stride(rows, width)],
+                            ));
+                        }
+                    }
+                    if self.writer_flags.contains(WriterFlags::DEBUG) {
+                        if let Some(ref name) = member.name {
+                            self.debugs
+                                .push(self.instruction_member_name(id, index, name));
+                        }
+                    }
                 }
                 self.instruction_type_struct(id, member_ids)
             }
-            crate::TypeInner::Pointer { base, class } => {
-                let type_id = self.get_type_id(arena, LookupType::Handle(base));
-                self.lookup_type.insert(
-                    LookupType::Local(LocalType::Pointer {
-                        base,
-                        class: self.parse_to_spirv_storage_class(class),
-                    }),
-                    id,
-                );
-                self.instruction_type_pointer(id, self.parse_to_spirv_storage_class(class), type_id)
-            }
         };
 
+        if self.writer_flags.contains(WriterFlags::DEBUG) {
+            if let Some(ref name) = ty.name {
+                self.debugs.push(self.instruction_name(id, name));
+            }
+        }
+
         self.lookup_type.insert(LookupType::Handle(handle), id);
         instruction.to_words(&mut self.logical_layout.declarations);
         id
@@ -852,12 +1527,18 @@ impl Writer {
         &mut self,
         handle: crate::Handle<crate::Constant>,
         ir_module: &crate::Module,
-    ) -> (Instruction, Word) {
+    ) -> Result<(Instruction, Word), Error> {
         let id = self.generate_id();
         self.lookup_constant.insert(handle, id);
         let constant = &ir_module.constants[handle];
         let arena = &ir_module.types;
 
+        if self.writer_flags.contains(WriterFlags::DEBUG) {
+            if let Some(ref name) = constant.name {
+                self.debugs.push(self.instruction_name(id, name));
+            }
+        }
+
         match constant.inner {
             crate::ConstantInner::Sint(val) => {
                 let ty = &ir_module.types[constant.ty];
@@ -870,11 +1551,11 @@ impl Writer {
                             let (low, high) = ((val >> 32) as u32, val as u32);
                             self.instruction_constant(type_id, id, &[low, high])
                         }
-                        _ => unreachable!(),
+                        _ => return Err(Error::UnsupportedConstant(handle)),
                     },
-                    _ => unreachable!(),
+                    _ => return Err(Error::UnsupportedConstant(handle)),
                 };
-                (instruction, id)
+                Ok((instruction, id))
             }
             crate::ConstantInner::Uint(val) => {
                 let ty = &ir_module.types[constant.ty];
@@ -887,12 +1568,12 @@ impl Writer {
                             let (low, high) = ((val >> 32) as u32, val as u32);
                             self.instruction_constant(type_id, id, &[low, high])
                         }
-                        _ => unreachable!(),
+                        _ => return Err(Error::UnsupportedConstant(handle)),
                     },
-                    _ => unreachable!(),
+                    _ => return Err(Error::UnsupportedConstant(handle)),
                 };
 
-                (instruction, id)
+                Ok((instruction, id))
             }
             crate::ConstantInner::Float(val) => {
                 let ty = &ir_module.types[constant.ty];
@@ -906,11 +1587,11 @@ impl Writer {
                             let (low, high) = ((bits >> 32) as u32, bits as u32);
                             self.instruction_constant(type_id, id, &[low, high])
                         }
-                        _ => unreachable!(),
+                        _ => return Err(Error::UnsupportedConstant(handle)),
                     },
-                    _ => unreachable!(),
+                    _ => return Err(Error::UnsupportedConstant(handle)),
                 };
-                (instruction, id)
+                Ok((instruction, id))
             }
             crate::ConstantInner::Bool(val) => {
                 let type_id = self.get_type_id(arena, LookupType::Handle(constant.ty));
@@ -921,22 +1602,56 @@ impl Writer {
                     self.instruction_constant_false(type_id, id)
                 };
 
-                (instruction, id)
+                Ok((instruction, id))
             }
             crate::ConstantInner::Composite(ref constituents) => {
                 let mut constituent_ids = Vec::with_capacity(constituents.len());
                 for constituent in constituents.iter() {
-                    let constituent_id = self.get_constant_id(*constituent, &ir_module);
+                    let constituent_id = self.get_constant_id(*constituent, &ir_module)?;
                     constituent_ids.push(constituent_id);
                 }
 
                 let type_id = self.get_type_id(arena, LookupType::Handle(constant.ty));
                 let instruction = self.instruction_constant_composite(type_id, id, constituent_ids);
-                (instruction, id)
+                Ok((instruction, id))
             }
         }
     }
 
+    fn map_built_in(&mut self, built_in: crate::BuiltIn) -> spirv::BuiltIn {
+        let built_in = match built_in {
+            crate::BuiltIn::BaseInstance => spirv::BuiltIn::BaseInstance,
+            crate::BuiltIn::BaseVertex => spirv::BuiltIn::BaseVertex,
+            crate::BuiltIn::ClipDistance => spirv::BuiltIn::ClipDistance,
+            crate::BuiltIn::InstanceIndex => spirv::BuiltIn::InstanceIndex,
+            crate::BuiltIn::Position => spirv::BuiltIn::Position,
+            crate::BuiltIn::VertexIndex => spirv::BuiltIn::VertexIndex,
+            crate::BuiltIn::PointSize => spirv::BuiltIn::PointSize,
+            crate::BuiltIn::FragCoord => spirv::BuiltIn::FragCoord,
+            crate::BuiltIn::FrontFacing => spirv::BuiltIn::FrontFacing,
+            crate::BuiltIn::SampleIndex => spirv::BuiltIn::SampleId,
+            crate::BuiltIn::FragDepth => spirv::BuiltIn::FragDepth,
+            crate::BuiltIn::GlobalInvocationId => spirv::BuiltIn::GlobalInvocationId,
+            crate::BuiltIn::LocalInvocationId => spirv::BuiltIn::LocalInvocationId,
+            crate::BuiltIn::LocalInvocationIndex => spirv::BuiltIn::LocalInvocationIndex,
+            crate::BuiltIn::WorkGroupId => spirv::BuiltIn::WorkgroupId,
+        };
+        match built_in {
+            spirv::BuiltIn::BaseInstance | spirv::BuiltIn::BaseVertex => {
+                self.try_add_capabilities(&[spirv::Capability::DrawParameters]);
+                self.try_add_extension("SPV_KHR_shader_draw_parameters", (1, 3));
+            }
+            spirv::BuiltIn::ClipDistance => {
+                self.try_add_capabilities(&[spirv::Capability::ClipDistance]);
+            }
+            spirv::BuiltIn::SampleId => {
+                self.try_add_capabilities(&[spirv::Capability::SampleRateShading]);
+            }
+            _ => {}
+        }
+        built_in
+    }
+
     fn write_global_variable(
         &mut self,
         arena: &crate::Arena<crate::Type>,
@@ -947,13 +1662,29 @@ impl Writer {
 
         let class = self.parse_to_spirv_storage_class(global_variable.class);
         self.try_add_capabilities(class.required_capabilities());
+        if class == spirv::StorageClass::StorageBuffer {
+            self.try_add_extension("SPV_KHR_storage_buffer_storage_class", (1, 3));
+        }
 
         let pointer_id = self.get_pointer_id(arena, global_variable.ty, class);
         let instruction = self.instruction_variable(pointer_id, id, class, None);
 
+        if global_variable.class == crate::StorageClass::StorageBuffer
+            && !global_variable
+                .storage_access
+                .contains(crate::StorageAccess::STORE)
+        {
+            self.annotations.push(self.instruction_decorate(
+                id,
+                spirv::Decoration::NonWritable,
+                &[],
+            ));
+        }
+
         if self.writer_flags.contains(WriterFlags::DEBUG) {
-            self.debugs
-                .push(self.instruction_name(id, global_variable.name.as_ref().unwrap().as_str()));
+            if let Some(ref name) = global_variable.name {
+                self.debugs.push(self.instruction_name(id, name.as_str()));
+            }
         }
 
         if let Some(interpolation) = global_variable.interpolation {
@@ -971,51 +1702,35 @@ impl Writer {
             }
         }
 
-        match global_variable.binding.as_ref().unwrap() {
-            crate::Binding::Location(location) => {
+        match global_variable.binding {
+            Some(crate::Binding::Location(location)) => {
                 self.annotations.push(self.instruction_decorate(
                     id,
                     spirv::Decoration::Location,
-                    &[*location],
+                    &[location],
                 ));
             }
-            crate::Binding::Descriptor { set, binding } => {
+            Some(crate::Binding::Descriptor { set, binding }) => {
                 self.annotations.push(self.instruction_decorate(
                     id,
                     spirv::Decoration::DescriptorSet,
-                    &[*set],
+                    &[set],
                 ));
                 self.annotations.push(self.instruction_decorate(
                     id,
                     spirv::Decoration::Binding,
-                    &[*binding],
+                    &[binding],
                 ));
             }
-            crate::Binding::BuiltIn(built_in) => {
-                let built_in = match built_in {
-                    crate::BuiltIn::BaseInstance => spirv::BuiltIn::BaseInstance,
-                    crate::BuiltIn::BaseVertex => spirv::BuiltIn::BaseVertex,
-                    crate::BuiltIn::ClipDistance => spirv::BuiltIn::ClipDistance,
-                    crate::BuiltIn::InstanceIndex => spirv::BuiltIn::InstanceIndex,
-                    crate::BuiltIn::Position => spirv::BuiltIn::Position,
-                    crate::BuiltIn::VertexIndex => spirv::BuiltIn::VertexIndex,
-                    crate::BuiltIn::PointSize => spirv::BuiltIn::PointSize,
-                    crate::BuiltIn::FragCoord => spirv::BuiltIn::FragCoord,
-                    crate::BuiltIn::FrontFacing => spirv::BuiltIn::FrontFacing,
-                    crate::BuiltIn::SampleIndex => spirv::BuiltIn::SampleId,
-                    crate::BuiltIn::FragDepth => spirv::BuiltIn::FragDepth,
-                    crate::BuiltIn::GlobalInvocationId => spirv::BuiltIn::GlobalInvocationId,
-                    crate::BuiltIn::LocalInvocationId => spirv::BuiltIn::LocalInvocationId,
-                    crate::BuiltIn::LocalInvocationIndex => spirv::BuiltIn::LocalInvocationIndex,
-                    crate::BuiltIn::WorkGroupId => spirv::BuiltIn::WorkgroupId,
-                };
-
+            Some(crate::Binding::BuiltIn(built_in)) => {
+                let built_in = self.map_built_in(built_in);
                 self.annotations.push(self.instruction_decorate(
                     id,
                     spirv::Decoration::BuiltIn,
                     &[built_in as u32],
                 ));
             }
+            None => {}
         }
 
         // TODO Initializer is optional and not (yet) included in the IR
@@ -1050,7 +1765,7 @@ impl Writer {
         function: &crate::Function,
         arena: &crate::Arena<crate::Type>,
     ) -> Instruction {
-        let id = self.generate_id();
+        let id = self.get_function_id(handle);
 
         let return_type_id = self.get_function_type(function.return_type, arena);
 
@@ -1066,193 +1781,958 @@ impl Writer {
 
         let type_function_id = self.write_function_type(lookup_function_type);
 
-        let instruction = self.instruction_function(
+        self.instruction_function(
             return_type_id,
             id,
             spirv::FunctionControl::empty(),
             type_function_id,
-        );
-
-        self.lookup_function.insert(handle, id);
-        instruction
+        )
     }
 
-    fn write_expression<'a>(
+    fn write_function_definition(
         &mut self,
-        ir_module: &'a crate::Module,
+        ir_module: &crate::Module,
+        types: &mut crate::Arena<crate::Type>,
+        handle: crate::Handle<crate::Function>,
         function: &crate::Function,
-        expression: &crate::Expression,
+    ) -> Result<(), Error> {
+        let mut typifier = Typifier::new();
+        if let Some((last, _)) = function.expressions.iter().last() {
+            typifier
+                .resolve(
+                    last,
+                    &function.expressions,
+                    types,
+                    &ir_module.constants,
+                    &ir_module.global_variables,
+                    &function.local_variables,
+                    &ir_module.functions,
+                    &function.parameter_types,
+                )
+                .map_err(|e| Error::Resolve(handle, e))?;
+        }
+        let types = &*types;
+
+        let mut function_instructions = vec![self.write_function(handle, function, types)];
+        let mut ctx = FunctionContext {
+            handle,
+            function,
+            types,
+            typifier,
+            parameter_ids: Vec::with_capacity(function.parameter_types.len()),
+            local_variable_ids: FastHashMap::default(),
+            variables: vec![],
+            cached_expressions: FastHashMap::default(),
+        };
+
+        let debug = self.writer_flags.contains(WriterFlags::DEBUG);
+        if debug {
+            if let Some(ref name) = function.name {
+                let function_id = self.get_function_id(handle);
+                self.debugs.push(self.instruction_name(function_id, name));
+            }
+        }
+
+        for (index, &parameter_type) in function.parameter_types.iter().enumerate() {
+            let id = self.generate_id();
+            let type_id = self.get_type_id(types, LookupType::Handle(parameter_type));
+            function_instructions.push(self.instruction_function_parameter(type_id, id));
+            ctx.parameter_ids.push(id);
+            if debug {
+                if let Some(Some(name)) = function.parameter_names.get(index) {
+                    self.debugs.push(self.instruction_name(id, name));
+                }
+            }
+        }
+
+        let label_id = self.generate_id();
+        function_instructions.push(self.instruction_label(label_id));
+
+        for (variable_handle, variable) in function.local_variables.iter() {
+            let id = self.generate_id();
+            ctx.local_variable_ids.insert(variable_handle, id);
+            if debug {
+                if let Some(ref name) = variable.name {
+                    self.debugs.push(self.instruction_name(id, name));
+                }
+            }
+        }
+
+        let mut output = vec![];
+        if let Some(span) = ir_module.spans.functions.get(&handle) {
+            self.write_line(span, &mut output);
+        }
+        for (variable_handle, variable) in function.local_variables.iter() {
+            let id = ctx.local_variable_ids[&variable_handle];
+            let pointer_id = self.get_pointer_id(types, variable.ty, spirv::StorageClass::Function);
+            let initializer_id = match variable.init {
+                Some(init) => match function.expressions[init] {
+                    crate::Expression::Constant(constant) => {
+                        Some(self.get_constant_id(constant, ir_module)?)
+                    }
+                    _ => {
+                        ctx.cached_expressions.clear();
+                        let value_id =
+                            self.write_expression(ir_module, &mut ctx, init, &mut output)?;
+                        output.push(self.instruction_store(id, value_id));
+                        None
+                    }
+                },
+                None => None,
+            };
+            ctx.variables.push(self.instruction_variable(
+                pointer_id,
+                id,
+                spirv::StorageClass::Function,
+                initializer_id,
+            ));
+        }
+
+        self.write_block(
+            ir_module,
+            &mut ctx,
+            &function.body,
+            BlockExit::Return,
+            LoopContext::default(),
+            &mut output,
+        )?;
+
+        function_instructions.append(&mut ctx.variables);
+        function_instructions.append(&mut output);
+        function_instructions.push(self.instruction_function_end());
+        for instruction in function_instructions.iter() {
+            instruction.to_words(&mut self.logical_layout.function_definitions);
+        }
+        Ok(())
+    }
+
+    /// Write an expression that is a pointer in SPIR-V, returning its id
+    /// together with the storage class it points into.
+    fn write_expression_pointer(
+        &mut self,
+        ir_module: &crate::Module,
+        ctx: &mut FunctionContext,
+        handle: crate::Handle<crate::Expression>,
         output: &mut Vec<Instruction>,
-    ) -> (Word, &'a crate::TypeInner) {
-        match expression {
-            crate::Expression::GlobalVariable(handle) => {
-                let var = &ir_module.global_variables[*handle];
-                let inner = &ir_module.types[var.ty].inner;
+    ) -> Result<(Word, spirv::StorageClass), Error> {
+        let function = ctx.function;
+        let (base_id, class, index_id) = match function.expressions[handle] {
+            crate::Expression::GlobalVariable(variable) => {
+                let class = ir_module.global_variables[variable].class;
                 let id = self.get_global_variable_id(
                     &ir_module.types,
                     &ir_module.global_variables,
-                    *handle,
+                    variable,
                 );
-                (id, inner)
+                return Ok((id, self.parse_to_spirv_storage_class(class)));
             }
-            crate::Expression::Constant(handle) => {
-                let var = &ir_module.constants[*handle];
-                let inner = &ir_module.types[var.ty].inner;
-                let id = self.get_constant_id(*handle, ir_module);
-                (id, inner)
+            crate::Expression::LocalVariable(variable) => {
+                return Ok((
+                    ctx.local_variable_ids[&variable],
+                    spirv::StorageClass::Function,
+                ));
             }
-            crate::Expression::Compose { ty, components } => {
-                let var = &ir_module.types[*ty];
-                let inner = &var.inner;
-                let id = self.generate_id();
-                let type_id = self.get_type_id(&ir_module.types, LookupType::Handle(*ty));
-
-                let mut constituent_ids = Vec::with_capacity(components.len());
-                for component in components {
-                    let expression = &function.expressions[*component];
-                    let (component_id, _) =
-                        self.write_expression(ir_module, &function, expression, output);
-                    constituent_ids.push(component_id);
+            crate::Expression::FunctionParameter(index) => {
+                let ty = function.parameter_types[index as usize];
+                match ctx.types[ty].inner {
+                    crate::TypeInner::Pointer { class, .. } => {
+                        return Ok((
+                            ctx.parameter_ids[index as usize],
+                            self.parse_to_spirv_storage_class(class),
+                        ));
+                    }
+                    _ => return Err(ctx.error(handle, ExpressionError::NotPointer)),
                 }
+            }
+            crate::Expression::Access { base, index } => {
+                let (base_id, class) =
+                    self.write_expression_pointer(ir_module, ctx, base, output)?;
+                let index_id = self.write_expression(ir_module, ctx, index, output)?;
+                (base_id, class, index_id)
+            }
+            crate::Expression::AccessIndex { base, index } => {
+                let (base_id, class) =
+                    self.write_expression_pointer(ir_module, ctx, base, output)?;
+                let index_id = self.get_index_constant(ctx.types, index);
+                (base_id, class, index_id)
+            }
+            _ => return Err(ctx.error(handle, ExpressionError::NotPointer)),
+        };
 
-                let instruction =
-                    self.instruction_composite_construct(type_id, id, constituent_ids);
-                output.push(instruction);
+        let pointer_type_id = self.get_pointer_id(ctx.types, ctx.typifier.get(handle), class);
+        let id = self.generate_id();
+        output.push(self.instruction_access_chain(pointer_type_id, id, base_id, vec![index_id]));
+        Ok((id, class))
+    }
 
-                (id, inner)
+    /// Construct a vector out of a scalar, if the other operand is a vector.
+    ///
+    /// The vector keeps the kind of the scalar, which may differ from the other
+    /// operand for shifts.
+    fn write_splat(
+        &mut self,
+        ctx: &FunctionContext,
+        scalar: (crate::Handle<crate::Expression>, Word),
+        other: crate::Handle<crate::Expression>,
+        output: &mut Vec<Instruction>,
+    ) -> Word {
+        match (ctx.resolve_type(scalar.0), ctx.resolve_type(other)) {
+            (&crate::TypeInner::Scalar { kind, width }, &crate::TypeInner::Vector { size, .. }) => {
+                let type_id = self.get_type_id(
+                    ctx.types,
+                    LookupType::Local(LocalType::Vector { size, kind, width }),
+                );
+                let id = self.generate_id();
+                output.push(self.instruction_composite_construct(
+                    type_id,
+                    id,
+                    vec![scalar.1; size as usize],
+                ));
+                id
             }
-            crate::Expression::Binary { op, left, right } => {
-                match op {
-                    crate::BinaryOperator::Multiply => {
-                        // TODO OpVectorTimesScalar is only supported
-                        let id = self.generate_id();
-                        let left_expression = &function.expressions[*left];
-                        let right_expression = &function.expressions[*right];
-                        let (left_id, left_inner) =
-                            self.write_expression(ir_module, function, left_expression, output);
-                        let (right_id, right_inner) =
-                            self.write_expression(ir_module, function, right_expression, output);
-
-                        let (result_type_id, vector_id, scalar_id) = match (left_inner, right_inner)
-                        {
-                            (
-                                crate::TypeInner::Vector { size, kind, width },
-                                crate::TypeInner::Scalar { .. },
-                            ) => {
-                                let result_type_id = *self
-                                    .lookup_type
-                                    .get(&LookupType::Local(LocalType::Vector {
-                                        size: *size,
-                                        kind: *kind,
-                                        width: *width,
-                                    }))
-                                    .unwrap();
-
-                                (result_type_id, left_id, right_id)
-                            }
-                            (
-                                crate::TypeInner::Scalar { .. },
-                                crate::TypeInner::Vector { size, kind, width },
-                            ) => {
-                                let result_type_id = *self
-                                    .lookup_type
-                                    .get(&LookupType::Local(LocalType::Vector {
-                                        size: *size,
-                                        kind: *kind,
-                                        width: *width,
-                                    }))
-                                    .unwrap();
-                                (result_type_id, right_id, left_id)
-                            }
-                            _ => unreachable!("Expression requires both a scalar and vector"),
-                        };
+            _ => scalar.1,
+        }
+    }
 
-                        // TODO Quick fix
-                        let load_id = self.generate_id();
+    fn write_expression(
+        &mut self,
+        ir_module: &crate::Module,
+        ctx: &mut FunctionContext,
+        handle: crate::Handle<crate::Expression>,
+        output: &mut Vec<Instruction>,
+    ) -> Result<Word, Error> {
+        if let Some(&id) = ctx.cached_expressions.get(&handle) {
+            return Ok(id);
+        }
 
-                        let load_instruction =
-                            self.instruction_load(result_type_id, load_id, vector_id, None);
-                        output.push(load_instruction);
+        if ctx.is_pointer(handle) {
+            let (pointer_id, _) = self.write_expression_pointer(ir_module, ctx, handle, output)?;
+            let type_id = self.get_expression_type_id(ctx, handle);
+            let id = self.generate_id();
+            output.push(self.instruction_load(type_id, id, pointer_id, None));
+            ctx.cached_expressions.insert(handle, id);
+            return Ok(id);
+        }
 
-                        let instruction = self.instruction_vector_times_scalar(
-                            result_type_id,
-                            id,
-                            load_id,
-                            scalar_id,
+        let function = ctx.function;
+        // Constants and parameters are plain ids, everything else gets an instruction.
+        let id = match function.expressions[handle] {
+            crate::Expression::Constant(constant) => {
+                return self.get_constant_id(constant, ir_module);
+            }
+            crate::Expression::FunctionParameter(index) => {
+                return Ok(ctx.parameter_ids[index as usize]);
+            }
+            _ => self.generate_id(),
+        };
+        let instruction = match function.expressions[handle] {
+            crate::Expression::Access { base, index } => {
+                let base_id = self.write_expression(ir_module, ctx, base, output)?;
+                let index_id = self.write_expression(ir_module, ctx, index, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                match *ctx.resolve_type(base) {
+                    crate::TypeInner::Vector { .. } => {
+                        self.instruction_vector_extract_dynamic(type_id, id, base_id, index_id)
+                    }
+                    // SPIR-V can only index arrays and matrices dynamically through a pointer,
+                    // so the value is spilled into a temporary variable.
+                    _ => {
+                        let variable_id = self.generate_id();
+                        let variable_type_id = self.get_pointer_id(
+                            ctx.types,
+                            ctx.typifier.get(base),
+                            spirv::StorageClass::Function,
                         );
-                        output.push(instruction);
+                        ctx.variables.push(self.instruction_variable(
+                            variable_type_id,
+                            variable_id,
+                            spirv::StorageClass::Function,
+                            None,
+                        ));
+                        output.push(self.instruction_store(variable_id, base_id));
+
+                        let pointer_type_id = self.get_pointer_id(
+                            ctx.types,
+                            ctx.typifier.get(handle),
+                            spirv::StorageClass::Function,
+                        );
+                        let pointer_id = self.generate_id();
+                        output.push(self.instruction_access_chain(
+                            pointer_type_id,
+                            pointer_id,
+                            variable_id,
+                            vec![index_id],
+                        ));
+                        self.instruction_load(type_id, id, pointer_id, None)
+                    }
+                }
+            }
+            crate::Expression::AccessIndex { base, index } => {
+                let base_id = self.write_expression(ir_module, ctx, base, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                self.instruction_composite_extract(type_id, id, base_id, &[index])
+            }
+            crate::Expression::Compose { ty, ref components } => {
+                let mut constituent_ids = Vec::with_capacity(components.len());
+                for &component in components {
+                    constituent_ids.push(self.write_expression(ir_module, ctx, component, output)?);
+                }
+                let type_id = self.get_type_id(ctx.types, LookupType::Handle(ty));
+                self.instruction_composite_construct(type_id, id, constituent_ids)
+            }
+            crate::Expression::Constant(_)
+            | crate::Expression::FunctionParameter(_)
+            | crate::Expression::GlobalVariable(_)
+            | crate::Expression::LocalVariable(_) => unreachable!(),
+            crate::Expression::Load { pointer } => {
+                let (pointer_id, _) =
+                    self.write_expression_pointer(ir_module, ctx, pointer, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                self.instruction_load(type_id, id, pointer_id, None)
+            }
+            crate::Expression::ImageSample {
+                image,
+                sampler,
+                coordinate,
+                depth_ref,
+            } => {
+                let image_id = self.write_expression(ir_module, ctx, image, output)?;
+                let sampler_id = self.write_expression(ir_module, ctx, sampler, output)?;
+                let coordinate_id = self.write_expression(ir_module, ctx, coordinate, output)?;
+
+                let sampled_image_type_id = self.get_type_id(
+                    ctx.types,
+                    LookupType::Local(LocalType::SampledImage {
+                        image_type: ctx.typifier.get(image),
+                    }),
+                );
+                let sampled_image_id = self.generate_id();
+                output.push(self.instruction_sampled_image(
+                    sampled_image_type_id,
+                    sampled_image_id,
+                    image_id,
+                    sampler_id,
+                ));
 
-                        // TODO Not sure how or what to return
-                        (
+                let type_id = self.get_expression_type_id(ctx, handle);
+                match depth_ref {
+                    Some(depth_ref) => {
+                        let depth_ref_id =
+                            self.write_expression(ir_module, ctx, depth_ref, output)?;
+                        self.instruction_image_sample_dref_implicit_lod(
+                            type_id,
                             id,
-                            &crate::TypeInner::Scalar {
-                                kind: crate::ScalarKind::Float,
-                                width: 10,
-                            },
+                            sampled_image_id,
+                            coordinate_id,
+                            depth_ref_id,
                         )
                     }
-                    _ => unimplemented!("{:?}", op),
+                    None => self.instruction_image_sample_implicit_lod(
+                        type_id,
+                        id,
+                        sampled_image_id,
+                        coordinate_id,
+                    ),
                 }
             }
-            crate::Expression::LocalVariable(variable) => {
-                let id = self.generate_id();
-                let var = &function.local_variables[*variable];
-                let ty = &ir_module.types[var.ty];
+            crate::Expression::Unary { op, expr } => {
+                let expr_id = self.write_expression(ir_module, ctx, expr, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                let spirv_op = match (op, ctx.scalar_kind(expr)?) {
+                    (crate::UnaryOperator::Negate, crate::ScalarKind::Float) => Op::FNegate,
+                    (crate::UnaryOperator::Negate, _) => Op::SNegate,
+                    (crate::UnaryOperator::Not, crate::ScalarKind::Bool) => Op::LogicalNot,
+                    (crate::UnaryOperator::Not, _) => Op::Not,
+                };
+                self.instruction_unary(spirv_op, type_id, id, expr_id)
+            }
+            crate::Expression::Binary { op, left, right } => {
+                let left_id = self.write_expression(ir_module, ctx, left, output)?;
+                let right_id = self.write_expression(ir_module, ctx, right, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+
+                let spirv_op = match (op, ctx.resolve_type(left), ctx.resolve_type(right)) {
+                    (
+                        crate::BinaryOperator::Multiply,
+                        &crate::TypeInner::Vector {
+                            kind: crate::ScalarKind::Float,
+                            ..
+                        },
+                        &crate::TypeInner::Scalar { .. },
+                    ) => Some((Op::VectorTimesScalar, left_id, right_id)),
+                    (
+                        crate::BinaryOperator::Multiply,
+                        &crate::TypeInner::Scalar { .. },
+                        &crate::TypeInner::Vector {
+                            kind: crate::ScalarKind::Float,
+                            ..
+                        },
+                    ) => Some((Op::VectorTimesScalar, right_id, left_id)),
+                    (
+                        crate::BinaryOperator::Multiply,
+                        &crate::TypeInner::Matrix { .. },
+                        &crate::TypeInner::Scalar { .. },
+                    ) => Some((Op::MatrixTimesScalar, left_id, right_id)),
+                    (
+                        crate::BinaryOperator::Multiply,
+                        &crate::TypeInner::Scalar { .. },
+                        &crate::TypeInner::Matrix { .. },
+                    ) => Some((Op::MatrixTimesScalar, right_id, left_id)),
+                    (
+                        crate::BinaryOperator::Multiply,
+                        &crate::TypeInner::Matrix { .. },
+                        &crate::TypeInner::Vector { .. },
+                    ) => Some((Op::MatrixTimesVector, left_id, right_id)),
+                    (
+                        crate::BinaryOperator::Multiply,
+                        &crate::TypeInner::Vector { .. },
+                        &crate::TypeInner::Matrix { .. },
+                    ) => Some((Op::VectorTimesMatrix, left_id, right_id)),
+                    (
+                        crate::BinaryOperator::Multiply,
+                        &crate::TypeInner::Matrix { .. },
+                        &crate::TypeInner::Matrix { .. },
+                    ) => Some((Op::MatrixTimesMatrix, left_id, right_id)),
+                    _ => None,
+                };
 
-                let pointer_id =
-                    self.get_pointer_id(&ir_module.types, var.ty, spirv::StorageClass::Function);
+                match spirv_op {
+                    Some((spirv_op, left_id, right_id)) => {
+                        self.instruction_binary(spirv_op, type_id, id, left_id, right_id)
+                    }
+                    None => {
+                        let kind = ctx.scalar_kind(left)?;
+                        let spirv_op = map_binary_operator(op, kind).ok_or_else(|| {
+                            ctx.error(handle, ExpressionError::UnsupportedBinaryOp(op, kind))
+                        })?;
+                        // The operands need matching shapes, including the base
+                        // and the amount of shifts.
+                        let left_id = self.write_splat(ctx, (left, left_id), right, output);
+                        let right_id = self.write_splat(ctx, (right, right_id), left, output);
+                        self.instruction_binary(spirv_op, type_id, id, left_id, right_id)
+                    }
+                }
+            }
+            crate::Expression::Intrinsic { fun, argument } => {
+                let argument_id = self.write_expression(ir_module, ctx, argument, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                match fun {
+                    crate::IntrinsicFunction::Any => {
+                        self.instruction_unary(Op::Any, type_id, id, argument_id)
+                    }
+                    crate::IntrinsicFunction::All => {
+                        self.instruction_unary(Op::All, type_id, id, argument_id)
+                    }
+                    crate::IntrinsicFunction::IsNan => {
+                        self.instruction_unary(Op::IsNan, type_id, id, argument_id)
+                    }
+                    crate::IntrinsicFunction::IsInf => {
+                        self.instruction_unary(Op::IsInf, type_id, id, argument_id)
+                    }
+                    // OpIsFinite requires the Kernel capability
+                    crate::IntrinsicFunction::IsFinite => {
+                        let nan_id = self.generate_id();
+                        output.push(self.instruction_unary(
+                            Op::IsNan,
+                            type_id,
+                            nan_id,
+                            argument_id,
+                        ));
+                        let inf_id = self.generate_id();
+                        output.push(self.instruction_unary(
+                            Op::IsInf,
+                            type_id,
+                            inf_id,
+                            argument_id,
+                        ));
+                        let either_id = self.generate_id();
+                        output.push(self.instruction_binary(
+                            Op::LogicalOr,
+                            type_id,
+                            either_id,
+                            nan_id,
+                            inf_id,
+                        ));
+                        self.instruction_unary(Op::LogicalNot, type_id, id, either_id)
+                    }
+                    crate::IntrinsicFunction::IsNormal => {
+                        return Err(ctx.error(handle, ExpressionError::UnsupportedIntrinsic(fun)));
+                    }
+                }
+            }
+            crate::Expression::DotProduct(left, right) => {
+                let left_id = self.write_expression(ir_module, ctx, left, output)?;
+                let right_id = self.write_expression(ir_module, ctx, right, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                self.instruction_binary(Op::Dot, type_id, id, left_id, right_id)
+            }
+            crate::Expression::CrossProduct(left, right) => {
+                let left_id = self.write_expression(ir_module, ctx, left, output)?;
+                let right_id = self.write_expression(ir_module, ctx, right, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                self.instruction_ext_inst(
+                    self.gl450_ext_inst_id,
+                    spirv::GLOp::Cross,
+                    type_id,
+                    id,
+                    vec![left_id, right_id],
+                )
+            }
+            crate::Expression::Derivative { axis, expr } => {
+                let expr_id = self.write_expression(ir_module, ctx, expr, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                let spirv_op = match axis {
+                    crate::DerivativeAxis::X => Op::DPdx,
+                    crate::DerivativeAxis::Y => Op::DPdy,
+                    crate::DerivativeAxis::Width => Op::Fwidth,
+                };
+                self.instruction_unary(spirv_op, type_id, id, expr_id)
+            }
+            crate::Expression::Select {
+                condition,
+                accept,
+                reject,
+            } => {
+                let condition_id = self.write_expression(ir_module, ctx, condition, output)?;
+                let accept_id = self.write_expression(ir_module, ctx, accept, output)?;
+                let reject_id = self.write_expression(ir_module, ctx, reject, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+
+                // Before SPIR-V 1.4 the condition has to match the shape of the result
+                let condition_id = match (ctx.resolve_type(condition), ctx.resolve_type(handle)) {
+                    (&crate::TypeInner::Scalar { .. }, &crate::TypeInner::Vector { size, .. }) => {
+                        let bool_type_id = self.get_type_id(
+                            ctx.types,
+                            LookupType::Local(LocalType::Vector {
+                                size,
+                                kind: crate::ScalarKind::Bool,
+                                width: 1,
+                            }),
+                        );
+                        let splat_id = self.generate_id();
+                        output.push(self.instruction_composite_construct(
+                            bool_type_id,
+                            splat_id,
+                            vec![condition_id; size as usize],
+                        ));
+                        splat_id
+                    }
+                    _ => condition_id,
+                };
+                self.instruction_select(type_id, id, condition_id, accept_id, reject_id)
+            }
+            crate::Expression::As {
+                expr,
+                kind,
+                convert,
+            } => {
+                let expr_id = self.write_expression(ir_module, ctx, expr, output)?;
+                let type_id = self.get_expression_type_id(ctx, handle);
+                let spirv_op = if convert {
+                    match (ctx.scalar_kind(expr)?, kind) {
+                        (crate::ScalarKind::Float, crate::ScalarKind::Sint) => Op::ConvertFToS,
+                        (crate::ScalarKind::Float, crate::ScalarKind::Uint) => Op::ConvertFToU,
+                        (crate::ScalarKind::Sint, crate::ScalarKind::Float) => Op::ConvertSToF,
+                        (crate::ScalarKind::Uint, crate::ScalarKind::Float) => Op::ConvertUToF,
+                        (crate::ScalarKind::Sint, crate::ScalarKind::Uint)
+                        | (crate::ScalarKind::Uint, crate::ScalarKind::Sint) => Op::Bitcast,
+                        (from, to) if from == to => Op::CopyObject,
+                        (from, to) => {
+                            return Err(
+                                ctx.error(handle, ExpressionError::UnsupportedConversion(from, to))
+                            );
+                        }
+                    }
+                } else {
+                    Op::Bitcast
+                };
+                self.instruction_unary(spirv_op, type_id, id, expr_id)
+            }
+            crate::Expression::Call {
+                origin: crate::FunctionOrigin::Local(callee),
+                ref arguments,
+            } => {
+                let callee_function = &ir_module.functions[callee];
+                let mut argument_ids = Vec::with_capacity(arguments.len());
+                for (&argument, &parameter_type) in
+                    arguments.iter().zip(callee_function.parameter_types.iter())
+                {
+                    let argument_id = match ctx.types[parameter_type].inner {
+                        crate::TypeInner::Pointer { .. } => {
+                            let (pointer_id, class) =
+                                self.write_expression_pointer(ir_module, ctx, argument, output)?;
+                            // Only variables can be passed by pointer without variable pointers
+                            if !ctx.is_variable(argument) {
+                                self.try_add_capabilities(&[
+                                    if class == spirv::StorageClass::StorageBuffer {
+                                        spirv::Capability::VariablePointersStorageBuffer
+                                    } else {
+                                        spirv::Capability::VariablePointers
+                                    },
+                                ]);
+                                self.try_add_extension("SPV_KHR_variable_pointers", (1, 3));
+                            }
+                            pointer_id
+                        }
+                        _ => self.write_expression(ir_module, ctx, argument, output)?,
+                    };
+                    argument_ids.push(argument_id);
+                }
+                let type_id = self.get_function_type(callee_function.return_type, ctx.types);
+                let function_id = self.get_function_id(callee);
+                self.instruction_function_call(type_id, id, function_id, argument_ids)
+            }
+            crate::Expression::Call {
+                origin: crate::FunctionOrigin::External(ref name),
+                ref arguments,
+            } => {
+                let mut argument_ids = Vec::with_capacity(arguments.len());
+                for &argument in arguments {
+                    argument_ids.push(self.write_expression(ir_module, ctx, argument, output)?);
+                }
+                let type_id = self.get_expression_type_id(ctx, handle);
+                match name.as_str() {
+                    "dot" => self.instruction_binary(
+                        Op::Dot,
+                        type_id,
+                        id,
+                        argument_ids[0],
+                        argument_ids[1],
+                    ),
+                    "transpose" => {
+                        self.instruction_unary(Op::Transpose, type_id, id, argument_ids[0])
+                    }
+                    _ => {
+                        let kind = ctx.scalar_kind(arguments[0])?;
+                        let gl_op = map_glsl_function(name, kind).ok_or_else(|| {
+                            ctx.error(handle, ExpressionError::UnsupportedCall(name.clone()))
+                        })?;
+                        self.instruction_ext_inst(
+                            self.gl450_ext_inst_id,
+                            gl_op,
+                            type_id,
+                            id,
+                            argument_ids,
+                        )
+                    }
+                }
+            }
+        };
 
-                let instruction =
-                    self.instruction_variable(pointer_id, id, spirv::StorageClass::Function, None);
-                output.push(instruction);
-                (id, &ty.inner)
+        if let Some(span) = ir_module.spans.expressions.get(&(ctx.handle, handle)) {
+            self.write_line(span, output);
+        }
+        output.push(instruction);
+        ctx.cached_expressions.insert(handle, id);
+        Ok(id)
+    }
+
+    /// Write the source location of the following instructions, when debugging.
+    fn write_line(&mut self, span: &crate::Span, output: &mut Vec<Instruction>) {
+        if !self.writer_flags.contains(WriterFlags::DEBUG) {
+            return;
+        }
+        if let Some(&file_id) = self.lookup_source_file.get(&span.file) {
+            output.push(self.instruction_line(file_id, span.line, span.column));
+        }
+    }
+
+    /// Write the statements of a block, following a label that was already written,
+    /// and terminate it with the given exit unless a statement did already.
+    fn write_block(
+        &mut self,
+        ir_module: &crate::Module,
+        ctx: &mut FunctionContext,
+        statements: &[crate::Statement],
+        exit: BlockExit,
+        loop_context: LoopContext,
+        output: &mut Vec<Instruction>,
+    ) -> Result<(), Error> {
+        for statement in statements {
+            if self.write_statement(ir_module, ctx, statement, loop_context, output)? {
+                // anything after a terminator is unreachable
+                return Ok(());
             }
-            _ => unimplemented!("{:?}", expression),
         }
+
+        let instruction = match exit {
+            BlockExit::Branch(target_id) => self.instruction_branch(target_id),
+            BlockExit::Return => match ctx.function.return_type {
+                Some(_) => self.instruction_unreachable(),
+                None => self.instruction_return(),
+            },
+        };
+        output.push(instruction);
+        Ok(())
     }
 
-    fn write_function_block(
+    /// Write a statement, returning `true` if it terminated the current block.
+    fn write_statement(
         &mut self,
         ir_module: &crate::Module,
-        function: &crate::Function,
+        ctx: &mut FunctionContext,
         statement: &crate::Statement,
+        loop_context: LoopContext,
         output: &mut Vec<Instruction>,
-    ) -> Instruction {
-        match statement {
-            crate::Statement::Return { .. } => match function.return_type {
-                Some(ty) => {
-                    let value_id = self.get_type_id(&ir_module.types, LookupType::Handle(ty));
-                    self.instruction_return_value(value_id)
+    ) -> Result<bool, Error> {
+        ctx.cached_expressions.clear();
+        match *statement {
+            crate::Statement::Empty => {}
+            crate::Statement::Block(ref block) => {
+                for statement in block {
+                    if self.write_statement(ir_module, ctx, statement, loop_context, output)? {
+                        return Ok(true);
+                    }
                 }
-                None => self.instruction_return(),
-            },
+            }
+            crate::Statement::If {
+                condition,
+                ref accept,
+                ref reject,
+            } => {
+                let condition_id = self.write_expression(ir_module, ctx, condition, output)?;
+                let merge_id = self.generate_id();
+                let accept_id = self.generate_id();
+                let reject_id = if reject.is_empty() {
+                    merge_id
+                } else {
+                    self.generate_id()
+                };
+
+                output.push(
+                    self.instruction_selection_merge(merge_id, spirv::SelectionControl::NONE),
+                );
+                output.push(self.instruction_branch_conditional(
+                    condition_id,
+                    accept_id,
+                    reject_id,
+                ));
+
+                output.push(self.instruction_label(accept_id));
+                self.write_block(
+                    ir_module,
+                    ctx,
+                    accept,
+                    BlockExit::Branch(merge_id),
+                    loop_context,
+                    output,
+                )?;
+                if !reject.is_empty() {
+                    output.push(self.instruction_label(reject_id));
+                    self.write_block(
+                        ir_module,
+                        ctx,
+                        reject,
+                        BlockExit::Branch(merge_id),
+                        loop_context,
+                        output,
+                    )?;
+                }
+
+                output.push(self.instruction_label(merge_id));
+            }
+            crate::Statement::Switch {
+                selector,
+                ref cases,
+                ref default,
+            } => {
+                let selector_id = self.write_expression(ir_module, ctx, selector, output)?;
+                let merge_id = self.generate_id();
+                let default_id = self.generate_id();
+
+                let mut case_values = cases.keys().cloned().collect::<Vec<_>>();
+                case_values.sort();
+                let mut case_labels = Vec::with_capacity(case_values.len());
+                for &value in case_values.iter() {
+                    case_labels.push((value as Word, self.generate_id()));
+                }
+
+                output.push(
+                    self.instruction_selection_merge(merge_id, spirv::SelectionControl::NONE),
+                );
+                output.push(self.instruction_switch(selector_id, default_id, &case_labels));
+
+                let inner_context = LoopContext {
+                    break_id: Some(merge_id),
+                    ..loop_context
+                };
+                for (i, value) in case_values.iter().enumerate() {
+                    let (ref block, ref fall_through) = cases[value];
+                    // a case falls through into the one written right after it
+                    let exit_id = match *fall_through {
+                        Some(_) => case_labels
+                            .get(i + 1)
+                            .map_or(default_id, |&(_, label_id)| label_id),
+                        None => merge_id,
+                    };
+                    output.push(self.instruction_label(case_labels[i].1));
+                    self.write_block(
+                        ir_module,
+                        ctx,
+                        block,
+                        BlockExit::Branch(exit_id),
+                        inner_context,
+                        output,
+                    )?;
+                }
+
+                output.push(self.instruction_label(default_id));
+                self.write_block(
+                    ir_module,
+                    ctx,
+                    default,
+                    BlockExit::Branch(merge_id),
+                    inner_context,
+                    output,
+                )?;
+
+                output.push(self.instruction_label(merge_id));
+            }
+            crate::Statement::Loop {
+                ref body,
+                ref continuing,
+            } => {
+                let header_id = self.generate_id();
+                let body_id = self.generate_id();
+                let continuing_id = self.generate_id();
+                let merge_id = self.generate_id();
+
+                output.push(self.instruction_branch(header_id));
+                output.push(self.instruction_label(header_id));
+                output.push(self.instruction_loop_merge(
+                    merge_id,
+                    continuing_id,
+                    spirv::LoopControl::NONE,
+                ));
+                output.push(self.instruction_branch(body_id));
+
+                output.push(self.instruction_label(body_id));
+                self.write_block(
+                    ir_module,
+                    ctx,
+                    body,
+                    BlockExit::Branch(continuing_id),
+                    LoopContext {
+                        continuing_id: Some(continuing_id),
+                        break_id: Some(merge_id),
+                    },
+                    output,
+                )?;
+
+                output.push(self.instruction_label(continuing_id));
+                self.write_block(
+                    ir_module,
+                    ctx,
+                    continuing,
+                    BlockExit::Branch(header_id),
+                    // the back edge can be conditional, like in a do-while loop
+                    LoopContext {
+                        continuing_id: None,
+                        break_id: Some(merge_id),
+                    },
+                    output,
+                )?;
+
+                output.push(self.instruction_label(merge_id));
+            }
+            crate::Statement::Break => {
+                let break_id = loop_context
+                    .break_id
+                    .ok_or(Error::MisplacedBreak(ctx.handle))?;
+                output.push(self.instruction_branch(break_id));
+                return Ok(true);
+            }
+            crate::Statement::Continue => {
+                let continuing_id = loop_context
+                    .continuing_id
+                    .ok_or(Error::MisplacedContinue(ctx.handle))?;
+                output.push(self.instruction_branch(continuing_id));
+                return Ok(true);
+            }
+            crate::Statement::Return { value } => {
+                let instruction = match value {
+                    Some(value) => {
+                        let value_id = self.write_expression(ir_module, ctx, value, output)?;
+                        self.instruction_return_value(value_id)
+                    }
+                    None => self.instruction_return(),
+                };
+                output.push(instruction);
+                return Ok(true);
+            }
+            crate::Statement::Kill => {
+                output.push(self.instruction_kill());
+                return Ok(true);
+            }
             crate::Statement::Store { pointer, value } => {
-                let pointer_expression = &function.expressions[*pointer];
-                let value_expression = &function.expressions[*value];
                 let (pointer_id, _) =
-                    self.write_expression(ir_module, function, pointer_expression, output);
-                let (value_id, _) =
-                    self.write_expression(ir_module, function, value_expression, output);
+                    self.write_expression_pointer(ir_module, ctx, pointer, output)?;
+                let value_id = self.write_expression(ir_module, ctx, value, output)?;
+                output.push(self.instruction_store(pointer_id, value_id));
+            }
+        }
+        Ok(false)
+    }
 
-                self.instruction_store(pointer_id, value_id)
+    /// Remember the layout rule of a buffer type and the types it contains.
+    fn collect_layout_rules(
+        &mut self,
+        arena: &crate::Arena<crate::Type>,
+        handle: crate::Handle<crate::Type>,
+        rule: LayoutRule,
+    ) {
+        if self.layout_rules.contains_key(&handle) {
+            return;
+        }
+        match arena[handle].inner {
+            crate::TypeInner::Array { base, .. } => {
+                self.layout_rules.insert(handle, rule);
+                self.collect_layout_rules(arena, base, rule);
             }
-            _ => unimplemented!(),
+            crate::TypeInner::Struct { ref members } => {
+                self.layout_rules.insert(handle, rule);
+                for member in members {
+                    self.collect_layout_rules(arena, member.ty, rule);
+                }
+            }
+            _ => {}
         }
     }
 
     fn write_physical_layout(&mut self) {
+        self.physical_layout.version =
+            ((self.version.0 as u32) << 16) | ((self.version.1 as u32) << 8);
         self.physical_layout.bound = self.id_count + 1;
     }
 
-    fn write_logical_layout(&mut self, ir_module: &crate::Module) {
-        self.instruction_ext_inst_import("GLSL.std.450")
+    fn write_logical_layout(&mut self, ir_module: &crate::Module) -> Result<(), Error> {
+        self.gl450_ext_inst_id = self.generate_id();
+        self.instruction_ext_inst_import(self.gl450_ext_inst_id, "GLSL.std.450")
             .to_words(&mut self.logical_layout.ext_inst_imports);
 
         if self.writer_flags.contains(WriterFlags::DEBUG) {
+            for (handle, file) in ir_module.spans.files.iter() {
+                let id = self.generate_id();
+                let name = file.name.as_ref().map_or("", String::as_str);
+                self.debugs.push(self.instruction_string(id, name));
+                self.lookup_source_file.insert(handle, id);
+            }
             self.debugs
                 .push(self.instruction_source(spirv::SourceLanguage::GLSL, 450));
         }
 
+        for (_, variable) in ir_module.global_variables.iter() {
+            let rule = match variable.class {
+                crate::StorageClass::Uniform => LayoutRule::Std140,
+                crate::StorageClass::StorageBuffer => LayoutRule::Std430,
+                _ => continue,
+            };
+            if let crate::TypeInner::Struct { .. } = ir_module.types[variable.ty].inner {
+                self.block_types.insert(variable.ty);
+            }
+            self.collect_layout_rules(&ir_module.types, variable.ty, rule);
+        }
+
         // Looking through all global variable, types, constants.
         // Doing this because we also want to include not used parts of the module
         // to be included in the output
@@ -1265,37 +2745,14 @@ impl Writer {
         }
 
         for (handle, _) in ir_module.constants.iter() {
-            self.get_constant_id(handle, &ir_module);
-        }
-
-        for annotation in self.annotations.iter() {
-            annotation.to_words(&mut self.logical_layout.annotations);
-        }
-
-        for capability in self.capabilities.iter() {
-            self.instruction_capability(*capability)
-                .to_words(&mut self.logical_layout.capabilities);
+            self.get_constant_id(handle, &ir_module)?;
         }
 
+        // Expressions may need types that aren't in the module yet,
+        // the typifier adds them to this copy.
+        let mut types = ir_module.types.clone();
         for (handle, function) in ir_module.functions.iter() {
-            let mut function_instructions: Vec<Instruction> = vec![];
-            function_instructions.push(self.write_function(handle, function, &ir_module.types));
-
-            let id = self.generate_id();
-            function_instructions.push(self.instruction_label(id));
-
-            for block in function.body.iter() {
-                let mut output: Vec<Instruction> = vec![];
-                let instruction =
-                    self.write_function_block(ir_module, function, &block, &mut output);
-                function_instructions.append(&mut output);
-                function_instructions.push(instruction);
-            }
-
-            function_instructions.push(self.instruction_function_end());
-            for instruction in function_instructions.iter() {
-                instruction.to_words(&mut self.logical_layout.function_definitions);
-            }
+            self.write_function_definition(ir_module, &mut types, handle, function)?;
         }
 
         for entry_point in ir_module.entry_points.iter() {
@@ -1306,29 +2763,62 @@ impl Writer {
         self.instruction_memory_model()
             .to_words(&mut self.logical_layout.memory_model);
 
+        for annotation in self.annotations.iter() {
+            annotation.to_words(&mut self.logical_layout.annotations);
+        }
+
+        let mut capabilities = self.capabilities.iter().cloned().collect::<Vec<_>>();
+        capabilities.sort_by_key(|&capability| capability as u32);
+        if let Some(ref allowed) = self.allowed_capabilities {
+            let missing = capabilities
+                .iter()
+                .filter(|capability| !allowed.contains(capability))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                return Err(Error::MissingCapabilities(missing));
+            }
+        }
+        for capability in capabilities {
+            self.instruction_capability(capability)
+                .to_words(&mut self.logical_layout.capabilities);
+        }
+
+        let mut extensions = self.extensions.iter().cloned().collect::<Vec<_>>();
+        extensions.sort();
+        for extension in extensions {
+            self.instruction_extension(extension)
+                .to_words(&mut self.logical_layout.extensions);
+        }
+
         if self.writer_flags.contains(WriterFlags::DEBUG) {
             for debug in self.debugs.iter() {
                 debug.to_words(&mut self.logical_layout.debugs);
             }
         }
+        Ok(())
     }
 
-    pub fn write(&mut self, ir_module: &crate::Module) -> Vec<Word> {
+    pub fn write(&mut self, ir_module: &crate::Module) -> Result<Vec<Word>, Error> {
         let mut words: Vec<Word> = vec![];
 
-        self.write_logical_layout(ir_module);
+        self.version = self
+            .target_env
+            .spirv_version()
+            .ok_or(Error::UnsupportedTarget(self.target_env))?;
+        self.write_logical_layout(ir_module)?;
         self.write_physical_layout();
 
         self.physical_layout.in_words(&mut words);
         self.logical_layout.in_words(&mut words);
-        words
+        Ok(words)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::back::spv::test_framework::*;
-    use crate::back::spv::{Writer, WriterFlags};
+    use crate::back::spv::{helpers, test_framework::*};
+    use crate::back::spv::{Error, Options, TargetEnvironment, Writer};
     use crate::Header;
     use spirv::*;
 
@@ -1375,9 +2865,9 @@ mod tests {
 
     #[test]
     fn test_instruction_ext_inst_import() {
-        let mut writer = create_writer();
+        let writer = create_writer();
         let import_name = "GLSL.std.450";
-        let instruction = writer.instruction_ext_inst_import(import_name);
+        let instruction = writer.instruction_ext_inst_import(1, import_name);
         let mut output = vec![];
 
         let requirements = SpecRequirements {
@@ -1432,6 +2922,63 @@ mod tests {
         validate_instruction(output.as_slice(), &instruction);
     }
 
+    #[test]
+    fn test_instruction_member_name() {
+        let writer = create_writer();
+        let instruction = writer.instruction_member_name(1, 0, "Test");
+        let mut output = vec![];
+
+        let requirements = SpecRequirements {
+            op: Op::MemberName,
+            wc: 4,
+            type_id: false,
+            result_id: false,
+            operands: true,
+        };
+        validate_spec_requirements(requirements, &instruction);
+
+        instruction.to_words(&mut output);
+        validate_instruction(output.as_slice(), &instruction);
+    }
+
+    #[test]
+    fn test_instruction_string() {
+        let writer = create_writer();
+        let instruction = writer.instruction_string(1, "test.wgsl");
+        let mut output = vec![];
+
+        let requirements = SpecRequirements {
+            op: Op::String,
+            wc: 3,
+            type_id: false,
+            result_id: true,
+            operands: true,
+        };
+        validate_spec_requirements(requirements, &instruction);
+
+        instruction.to_words(&mut output);
+        validate_instruction(output.as_slice(), &instruction);
+    }
+
+    #[test]
+    fn test_instruction_line() {
+        let writer = create_writer();
+        let instruction = writer.instruction_line(1, 2, 3);
+        let mut output = vec![];
+
+        let requirements = SpecRequirements {
+            op: Op::Line,
+            wc: 4,
+            type_id: false,
+            result_id: false,
+            operands: true,
+        };
+        validate_spec_requirements(requirements, &instruction);
+
+        instruction.to_words(&mut output);
+        validate_instruction(output.as_slice(), &instruction);
+    }
+
     #[test]
     fn test_instruction_execution_mode() {
         let writer = create_writer();
@@ -1528,6 +3075,63 @@ mod tests {
         validate_instruction(output.as_slice(), &instruction);
     }
 
+    #[test]
+    fn test_instruction_access_chain() {
+        let writer = create_writer();
+        let instruction = writer.instruction_access_chain(1, 2, 3, vec![4, 5]);
+        let mut output = vec![];
+
+        let requirements = SpecRequirements {
+            op: Op::AccessChain,
+            wc: 4,
+            type_id: true,
+            result_id: true,
+            operands: true,
+        };
+        validate_spec_requirements(requirements, &instruction);
+
+        instruction.to_words(&mut output);
+        validate_instruction(output.as_slice(), &instruction);
+    }
+
+    #[test]
+    fn test_instruction_ext_inst() {
+        let writer = create_writer();
+        let instruction = writer.instruction_ext_inst(1, GLOp::Normalize, 2, 3, vec![4]);
+        let mut output = vec![];
+
+        let requirements = SpecRequirements {
+            op: Op::ExtInst,
+            wc: 5,
+            type_id: true,
+            result_id: true,
+            operands: true,
+        };
+        validate_spec_requirements(requirements, &instruction);
+
+        instruction.to_words(&mut output);
+        validate_instruction(output.as_slice(), &instruction);
+    }
+
+    #[test]
+    fn test_instruction_function_call() {
+        let writer = create_writer();
+        let instruction = writer.instruction_function_call(1, 2, 3, vec![4, 5]);
+        let mut output = vec![];
+
+        let requirements = SpecRequirements {
+            op: Op::FunctionCall,
+            wc: 4,
+            type_id: true,
+            result_id: true,
+            operands: true,
+        };
+        validate_spec_requirements(requirements, &instruction);
+
+        instruction.to_words(&mut output);
+        validate_instruction(output.as_slice(), &instruction);
+    }
+
     #[test]
     fn test_write_physical_layout() {
         let mut writer = create_writer();
@@ -1536,11 +3140,506 @@ mod tests {
         assert_eq!(writer.physical_layout.bound, 1);
     }
 
+    #[test]
+    fn test_write_expressions() {
+        use crate::{
+            Arena, BinaryOperator, Expression as E, FunctionOrigin, ScalarKind, Statement,
+            TypeInner,
+        };
+
+        let mut module = crate::Module {
+            header: Header {
+                version: (1, 0, 0),
+                generator: 0,
+            },
+            types: Arena::new(),
+            constants: Arena::new(),
+            global_variables: Arena::new(),
+            functions: Arena::new(),
+            entry_points: Vec::new(),
+            spans: crate::SpanTable::default(),
+        };
+        let float_ty = module.types.append(crate::Type {
+            name: None,
+            inner: TypeInner::Scalar {
+                kind: ScalarKind::Float,
+                width: 4,
+            },
+        });
+        let vec4_ty = module.types.append(crate::Type {
+            name: None,
+            inner: TypeInner::Vector {
+                size: crate::VectorSize::Quad,
+                kind: ScalarKind::Float,
+                width: 4,
+            },
+        });
+        let two = module.constants.append(crate::Constant {
+            name: None,
+            specialization: None,
+            inner: crate::ConstantInner::Float(2.0),
+            ty: float_ty,
+        });
+        let output = module.global_variables.append(crate::GlobalVariable {
+            name: Some("output".to_string()),
+            class: crate::StorageClass::Output,
+            binding: Some(crate::Binding::Location(0)),
+            ty: vec4_ty,
+            interpolation: None,
+            storage_access: crate::StorageAccess::empty(),
+        });
+
+        let mut expressions = Arena::new();
+        let scale = expressions.append(E::FunctionParameter(0));
+        let vector = expressions.append(E::FunctionParameter(1));
+        let constant = expressions.append(E::Constant(two));
+        let product = expressions.append(E::Binary {
+            op: BinaryOperator::Multiply,
+            left: vector,
+            right: scale,
+        });
+        let sum = expressions.append(E::Binary {
+            op: BinaryOperator::Add,
+            left: product,
+            right: constant,
+        });
+        let condition = expressions.append(E::Binary {
+            op: BinaryOperator::Less,
+            left: scale,
+            right: constant,
+        });
+        let select = expressions.append(E::Select {
+            condition,
+            accept: sum,
+            reject: vector,
+        });
+        let length = expressions.append(E::Call {
+            origin: FunctionOrigin::External("length".to_string()),
+            arguments: vec![select],
+        });
+        let component = expressions.append(E::AccessIndex {
+            base: select,
+            index: 0,
+        });
+        let total = expressions.append(E::Binary {
+            op: BinaryOperator::Add,
+            left: component,
+            right: length,
+        });
+        let integer = expressions.append(E::As {
+            expr: total,
+            kind: ScalarKind::Sint,
+            convert: true,
+        });
+        let float = expressions.append(E::As {
+            expr: integer,
+            kind: ScalarKind::Float,
+            convert: true,
+        });
+        let result = expressions.append(E::Binary {
+            op: BinaryOperator::Multiply,
+            left: float,
+            right: select,
+        });
+        let helper = module.functions.append(crate::Function {
+            name: Some("helper".to_string()),
+            parameter_types: vec![float_ty, vec4_ty],
+            parameter_names: vec![Some("scale".to_string()), Some("color".to_string())],
+            return_type: Some(vec4_ty),
+            global_usage: vec![crate::GlobalUse::empty()],
+            local_variables: Arena::new(),
+            expressions,
+            body: vec![Statement::Return {
+                value: Some(result),
+            }],
+        });
+
+        let mut expressions = Arena::new();
+        let pointer = expressions.append(E::GlobalVariable(output));
+        let constant = expressions.append(E::Constant(two));
+        let loaded = expressions.append(E::Load { pointer });
+        let call = expressions.append(E::Call {
+            origin: FunctionOrigin::Local(helper),
+            arguments: vec![constant, loaded],
+        });
+        let main = module.functions.append(crate::Function {
+            name: Some("main".to_string()),
+            parameter_types: Vec::new(),
+            parameter_names: Vec::new(),
+            return_type: None,
+            global_usage: vec![crate::GlobalUse::LOAD | crate::GlobalUse::STORE],
+            local_variables: Arena::new(),
+            expressions,
+            body: vec![
+                Statement::Store {
+                    pointer,
+                    value: call,
+                },
+                Statement::Return { value: None },
+            ],
+        });
+        module.entry_points.push(crate::EntryPoint {
+            stage: crate::ShaderStage::Fragment,
+            name: "main".to_string(),
+            function: main,
+        });
+
+        let words = Writer::new(&module.header, &Options::default())
+            .write(&module)
+            .unwrap();
+        let reparsed = crate::front::spv::Parser::new(words.into_iter(), &Default::default())
+            .parse()
+            .unwrap();
+        assert_eq!(reparsed.functions.len(), 2);
+        assert_eq!(reparsed.entry_points.len(), 1);
+    }
+
+    #[test]
+    fn test_write_vector_shift() {
+        let source = "
+            [[location 0]] var<in> a_amount : u32;
+            [[location 0]] var<out> o_value : vec2<i32>;
+            fn main() -> void {
+                var value : vec2<i32> = vec2<i32>(1, 2);
+                o_value = value << a_amount;
+                return;
+            }
+            entry_point fragment as \"main\" = main;
+        ";
+        let module = crate::front::wgsl::parse_str(source).unwrap();
+        let words = Writer::new(&module.header, &Options::default())
+            .write(&module)
+            .unwrap();
+
+        // the amount is splatted into a vector of unsigned integers
+        let uint_id = find_instructions(&words, Op::TypeInt)
+            .into_iter()
+            .find(|operands| operands[2] == 0)
+            .unwrap()[0];
+        let uvec2_id = find_instructions(&words, Op::TypeVector)
+            .into_iter()
+            .find(|operands| operands[1] == uint_id)
+            .unwrap()[0];
+        let shift = find_instructions(&words, Op::ShiftLeftLogical)[0];
+        let amount = find_instructions(&words, Op::CompositeConstruct)
+            .into_iter()
+            .find(|operands| operands[1] == shift[3])
+            .unwrap();
+        assert_eq!(amount[0], uvec2_id);
+    }
+
+    #[test]
+    fn test_write_control_flow() {
+        let source = "
+            [[location 0]] var<out> o_color : vec4<f32>;
+            fn main() -> void {
+                var i : i32 = 0;
+                loop {
+                    if (i >= 4) {
+                        break;
+                    }
+                    if (i == 2) {
+                        continue;
+                    }
+                    continuing {
+                        i = i + 1;
+                    }
+                }
+                if (i == 4) {
+                    o_color = vec4<f32>(1.0, 0.0, 0.0, 1.0);
+                } else {
+                    o_color = vec4<f32>(0.0, 1.0, 0.0, 1.0);
+                }
+                loop {
+                    i = i - 1;
+                    continuing {
+                        if (i <= 0) {
+                            break;
+                        }
+                    }
+                }
+                return;
+            }
+            entry_point fragment as \"main\" = main;
+        ";
+        let module = crate::front::wgsl::parse_str(source).unwrap();
+        let words = Writer::new(&module.header, &Options::default())
+            .write(&module)
+            .unwrap();
+        let reparsed = crate::front::spv::Parser::new(words.into_iter(), &Default::default())
+            .parse()
+            .unwrap();
+
+        let body = &reparsed.functions[reparsed.entry_points[0].function].body;
+        match body[..] {
+            [crate::Statement::Loop { .. }, crate::Statement::If { .. }, crate::Statement::Loop { ref continuing, .. }, ..] =>
+            {
+                // the do-while condition stays in the continuing block
+                match continuing[..] {
+                    [crate::Statement::If { ref accept, .. }] => match accept[..] {
+                        [crate::Statement::Break] => {}
+                        ref other => panic!("Unexpected exit {:?}", other),
+                    },
+                    ref other => panic!("Unexpected continuing {:?}", other),
+                }
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_write_misplaced_break() {
+        let source = "
+            fn main() -> void {
+                break;
+            }
+            entry_point compute as \"main\" = main;
+        ";
+        let module = crate::front::wgsl::parse_str(source).unwrap();
+        match Writer::new(&module.header, &Options::default()).write(&module) {
+            Err(Error::MisplacedBreak(function)) => {
+                assert_eq!(function, module.entry_points[0].function)
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    const STORAGE_SOURCE: &str = "
+        type Data = struct {
+            [[offset 0]] value : f32;
+        };
+        [[binding 0, set 0]] var<storage_buffer> data : Data;
+        fn main() -> void {
+            data.value = 1.0;
+            return;
+        }
+        entry_point compute as \"main\" = main;
+    ";
+
+    /// Operands of the instructions with the given opcode.
+    fn find_instructions(words: &[Word], op: Op) -> Vec<&[Word]> {
+        let mut found = vec![];
+        let mut offset = 5;
+        while offset < words.len() {
+            let word_count = (words[offset] >> 16) as usize;
+            if words[offset] & 0xFFFF == op as u32 {
+                found.push(&words[offset + 1..offset + word_count]);
+            }
+            offset += word_count;
+        }
+        found
+    }
+
+    #[test]
+    fn test_write_target_version() {
+        let module = crate::front::wgsl::parse_str(STORAGE_SOURCE).unwrap();
+
+        let words = Writer::new(&module.header, &Options::default())
+            .write(&module)
+            .unwrap();
+        assert_eq!(words[1], 0x0001_0000);
+        assert_eq!(find_instructions(&words, Op::Extension).len(), 1);
+        // storage buffers aren't a part of the interface before SPIR-V 1.4
+        assert_eq!(find_instructions(&words, Op::EntryPoint)[0].len(), 4);
+
+        let options = Options {
+            target_env: TargetEnvironment::Universal(1, 5),
+            ..Default::default()
+        };
+        let words = Writer::new(&module.header, &options)
+            .write(&module)
+            .unwrap();
+        assert_eq!(words[1], 0x0001_0500);
+        assert!(find_instructions(&words, Op::Extension).is_empty());
+        assert_eq!(find_instructions(&words, Op::EntryPoint)[0].len(), 5);
+    }
+
+    #[test]
+    fn test_write_capability_errors() {
+        let module = crate::front::wgsl::parse_str(STORAGE_SOURCE).unwrap();
+
+        let options = Options {
+            target_env: TargetEnvironment::Vulkan(1, 3),
+            ..Default::default()
+        };
+        match Writer::new(&module.header, &options).write(&module) {
+            Err(Error::UnsupportedTarget(TargetEnvironment::Vulkan(1, 3))) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let options = Options {
+            capabilities: Some(Default::default()),
+            ..Default::default()
+        };
+        match Writer::new(&module.header, &options).write(&module) {
+            Err(Error::MissingCapabilities(missing)) => {
+                assert_eq!(missing, vec![spirv::Capability::Shader])
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        // 64-bit floats are optional in Vulkan, and have to be allowed explicitly
+        let mut module = module;
+        module.types.append(crate::Type {
+            name: None,
+            inner: crate::TypeInner::Scalar {
+                kind: crate::ScalarKind::Float,
+                width: 8,
+            },
+        });
+        match Writer::new(&module.header, &Options::default()).write(&module) {
+            Err(Error::MissingCapabilities(missing)) => {
+                assert_eq!(missing, vec![spirv::Capability::Float64])
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        let options = Options {
+            target_env: TargetEnvironment::Universal(1, 0),
+            ..Default::default()
+        };
+        assert!(Writer::new(&module.header, &options).write(&module).is_ok());
+        let options = Options {
+            capabilities: Some(
+                [spirv::Capability::Shader, spirv::Capability::Float64]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        assert!(Writer::new(&module.header, &options).write(&module).is_ok());
+    }
+
+    #[test]
+    fn test_write_layout_decorations() {
+        let source = "
+            type Globals = struct {
+                [[offset 0]] transform : mat4x4<f32>;
+                [[offset 64]] weights : array<f32, 4>;
+            };
+            type Data = struct {
+                [[offset 0]] value : f32;
+            };
+            [[binding 0, set 0]] var<uniform> globals : Globals;
+            [[binding 1, set 0]] var<storage_buffer> data : Data;
+            fn main() -> void {
+                return;
+            }
+            entry_point compute as \"main\" = main;
+        ";
+        let mut module = crate::front::wgsl::parse_str(source).unwrap();
+        let (data, _) = module
+            .global_variables
+            .iter()
+            .find(|(_, var)| var.class == crate::StorageClass::StorageBuffer)
+            .unwrap();
+        module.global_variables.get_mut(data).storage_access = crate::StorageAccess::LOAD;
+        let words = Writer::new(&module.header, &Options::default())
+            .write(&module)
+            .unwrap();
+
+        let decorations = find_instructions(&words, Op::Decorate);
+        let count = |decoration: spirv::Decoration| {
+            decorations
+                .iter()
+                .filter(|operands| operands[1] == decoration as u32)
+                .count()
+        };
+        assert_eq!(count(spirv::Decoration::Block), 2);
+        assert_eq!(count(spirv::Decoration::NonWritable), 1);
+        // std140 rounds the stride of scalar arrays up to 16 bytes
+        assert!(decorations.iter().any(|operands| {
+            operands[1] == spirv::Decoration::ArrayStride as u32 && operands[2] == 16
+        }));
+
+        let member_decorations = find_instructions(&words, Op::MemberDecorate);
+        let offsets: Vec<Word> = member_decorations
+            .iter()
+            .filter(|operands| operands[2] == spirv::Decoration::Offset as u32)
+            .map(|operands| operands[3])
+            .collect();
+        assert_eq!(offsets, vec![0, 64, 0]);
+        assert!(member_decorations.iter().any(|operands| {
+            operands[2] == spirv::Decoration::MatrixStride as u32 && operands[3] == 16
+        }));
+    }
+
+    #[test]
+    fn test_write_debug_info() {
+        let options = Options {
+            flags: crate::back::spv::WriterFlags::DEBUG,
+            ..Default::default()
+        };
+
+        let module = crate::front::wgsl::parse_str(STORAGE_SOURCE).unwrap();
+        let words = Writer::new(&module.header, &options)
+            .write(&module)
+            .unwrap();
+        let member_names = find_instructions(&words, Op::MemberName);
+        assert_eq!(member_names.len(), 1);
+        assert_eq!(member_names[0][1], 0);
+        assert_eq!(member_names[0][2..], helpers::string_to_words("value")[..]);
+
+        let source = "
+            [[location 0]] var<out> o_color : vec4<f32>;
+            fn shade(intensity : f32) -> vec4<f32> {
+                return vec4<f32>(1.0, 1.0, 1.0, 1.0) * intensity;
+            }
+            fn main() -> void {
+                var intensity : f32 = 0.5;
+                o_color = vec4<f32>(intensity, intensity, intensity, 1.0);
+                return;
+            }
+            entry_point fragment as \"main\" = main;
+        ";
+        let mut module = crate::front::wgsl::parse_str(source).unwrap();
+        let file = module.spans.files.append(crate::SourceFile {
+            name: Some("debug.wgsl".to_string()),
+            text: None,
+        });
+        let (shade, _) = module.functions.iter().next().unwrap();
+        let (expression, _) = module.functions[shade].expressions.iter().last().unwrap();
+        let span = crate::Span {
+            file,
+            line: 4,
+            column: 17,
+        };
+        module.spans.expressions.insert((shade, expression), span);
+
+        let words = Writer::new(&module.header, &options)
+            .write(&module)
+            .unwrap();
+        let reparsed = crate::front::spv::Parser::new(words.into_iter(), &Default::default())
+            .parse()
+            .unwrap();
+
+        let (shade, function) = reparsed
+            .functions
+            .iter()
+            .find(|&(_, function)| function.name.as_deref() == Some("shade"))
+            .unwrap();
+        assert_eq!(
+            function.parameter_names,
+            vec![Some("intensity".to_string())]
+        );
+        let (_, main) = reparsed
+            .functions
+            .iter()
+            .find(|&(_, function)| function.name.as_deref() == Some("main"))
+            .unwrap();
+        let (_, variable) = main.local_variables.iter().next().unwrap();
+        assert_eq!(variable.name.as_deref(), Some("intensity"));
+
+        let (_, file) = reparsed.spans.files.iter().next().unwrap();
+        assert_eq!(file.name.as_deref(), Some("debug.wgsl"));
+        assert_eq!(reparsed.spans.functions[&shade].line, 4);
+    }
+
     fn create_writer() -> Writer {
         let header = Header {
             generator: 0,
             version: (1, 0, 0),
         };
-        Writer::new(&header, WriterFlags::NONE)
+        Writer::new(&header, &Options::default())
     }
 }
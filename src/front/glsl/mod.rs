@@ -2,8 +2,8 @@
 use crate::{
     Arena, ArraySize, BinaryOperator, Binding, BuiltIn, Constant, ConstantInner, EntryPoint,
     Expression, FastHashMap, Function, GlobalVariable, Handle, Header, Interpolation,
-    LocalVariable, Module, ScalarKind, ShaderStage, StorageClass, StructMember, Type, TypeInner,
-    VectorSize,
+    LocalVariable, Module, ScalarKind, ShaderStage, StorageAccess, StorageClass, StructMember,
+    Type, TypeInner, VectorSize,
 };
 use glsl::{
     parser::{Parse, ParseError},
@@ -119,8 +119,7 @@ impl<'a> Parser<'a> {
 
         for declaration in ast {
             match declaration {
-                ExternalDeclaration::Preprocessor(_) =>
-                {
+                ExternalDeclaration::Preprocessor(_) => {
                     #[cfg(feature = "glsl_preprocessor")]
                     unreachable!()
                 }
@@ -178,7 +177,7 @@ impl<'a> Parser<'a> {
 
                             for ident in field.identifiers {
                                 let field_name = ident.ident.0;
-                                let origin = crate::MemberOrigin::Offset(0); //TODO
+                                let origin = crate::MemberOrigin::Empty;
 
                                 fields.push(StructMember {
                                     name: Some(field_name.clone()),
@@ -235,6 +234,11 @@ impl<'a> Parser<'a> {
                             name,
                             ty,
                             interpolation,
+                            storage_access: if class == StorageClass::StorageBuffer {
+                                StorageAccess::all()
+                            } else {
+                                StorageAccess::empty()
+                            },
                         });
 
                         for (name, index) in reexports {
@@ -261,6 +265,7 @@ impl<'a> Parser<'a> {
                 function: entry_point.unwrap(),
                 name: entry,
             }],
+            spans: Default::default(),
         })
     }
 
@@ -274,6 +279,7 @@ impl<'a> Parser<'a> {
         let ty = self.parse_type(function.prototype.ty.ty);
 
         let mut parameter_types = Vec::with_capacity(function.prototype.parameters.len());
+        let mut parameter_names = Vec::with_capacity(function.prototype.parameters.len());
         let mut parameter_lookup = FastHashMap::default();
 
         let mut local_variables = Arena::<LocalVariable>::new();
@@ -302,6 +308,7 @@ impl<'a> Parser<'a> {
                     };
 
                     parameter_types.push(ty);
+                    parameter_names.push(Some(decl.ident.ident.0.clone()));
                     parameter_lookup.insert(
                         decl.ident.ident.0,
                         Expression::FunctionParameter(index as u32),
@@ -309,6 +316,7 @@ impl<'a> Parser<'a> {
                 }
                 FunctionParameterDeclaration::Unnamed(_, ty) => {
                     parameter_types.push(self.parse_type(ty).unwrap());
+                    parameter_names.push(None);
                 }
             }
         }
@@ -369,6 +377,7 @@ impl<'a> Parser<'a> {
         let handle = self.functions.append(Function {
             name: Some(name),
             parameter_types,
+            parameter_names,
             return_type: ty,
             global_usage: vec![],
             local_variables,
@@ -574,6 +583,7 @@ impl<'a> Parser<'a> {
                                 },
                             }),
                             interpolation: None,
+                            storage_access: StorageAccess::empty(),
                         }),
                     )),
                     "gl_InstanceIndex" => Ok(Expression::GlobalVariable(
@@ -589,6 +599,7 @@ impl<'a> Parser<'a> {
                                 },
                             }),
                             interpolation: None,
+                            storage_access: StorageAccess::empty(),
                         }),
                     )),
                     "gl_BaseVertex" => Ok(Expression::GlobalVariable(
@@ -604,6 +615,7 @@ impl<'a> Parser<'a> {
                                 },
                             }),
                             interpolation: None,
+                            storage_access: StorageAccess::empty(),
                         }),
                     )),
                     "gl_BaseInstance" => Ok(Expression::GlobalVariable(
@@ -619,6 +631,7 @@ impl<'a> Parser<'a> {
                                 },
                             }),
                             interpolation: None,
+                            storage_access: StorageAccess::empty(),
                         }),
                     )),
                     "gl_Position" => Ok(Expression::GlobalVariable(self.globals.fetch_or_append(
@@ -639,6 +652,7 @@ impl<'a> Parser<'a> {
                                 },
                             }),
                             interpolation: None,
+                            storage_access: StorageAccess::empty(),
                         },
                     ))),
                     "gl_PointSize" => Ok(Expression::GlobalVariable(self.globals.fetch_or_append(
@@ -654,6 +668,7 @@ impl<'a> Parser<'a> {
                                 },
                             }),
                             interpolation: None,
+                            storage_access: StorageAccess::empty(),
                         },
                     ))),
                     "gl_ClipDistance" => Ok(Expression::GlobalVariable(
@@ -669,6 +684,7 @@ impl<'a> Parser<'a> {
                                 },
                             }),
                             interpolation: None,
+                            storage_access: StorageAccess::empty(),
                         }),
                     )),
                     other => {
@@ -923,6 +939,7 @@ impl<'a> Parser<'a> {
                         &self.globals,
                         locals,
                         &self.functions,
+                        &[],
                     )
                     .map_err(|e| Error { kind: e.into() })?;
                 let base_type = &self.types[type_handle];
@@ -1061,6 +1078,11 @@ impl<'a> Parser<'a> {
             binding,
             ty,
             interpolation,
+            storage_access: if class == StorageClass::StorageBuffer {
+                StorageAccess::all()
+            } else {
+                StorageAccess::empty()
+            },
         }))
     }
 
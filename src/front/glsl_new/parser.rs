@@ -8,7 +8,7 @@ pomelo! {
         use super::super::{error::ErrorKind, token::*, ast::*};
         use crate::{Arena, BinaryOperator, Binding, Block, BuiltIn, Constant, ConstantInner, Expression,
             Function, GlobalVariable, Handle, LocalVariable, ScalarKind,
-            ShaderStage, Statement, StorageClass, Type, TypeInner, VectorSize, Bytes, Interpolation};
+            ShaderStage, Statement, StorageAccess, StorageClass, Type, TypeInner, VectorSize, Bytes, Interpolation};
     }
     %token #[derive(Debug)] pub enum Token {};
     %parser pub struct Parser<'a> {};
@@ -154,6 +154,7 @@ pomelo! {
                         },
                     }),
                     interpolation: None,
+                    storage_access: StorageAccess::empty(),
                 },
             );
             extra.lookup_global_variables.insert(v.1, h);
@@ -632,6 +633,7 @@ pomelo! {
         Function {
             name: Some(n.1),
             parameter_types: vec![],
+            parameter_names: vec![],
             return_type: t.1,
             global_usage: vec![],
             local_variables: Arena::<LocalVariable>::new(),
@@ -758,6 +760,11 @@ pomelo! {
                     binding: binding.clone(),
                     ty: d.ty,
                     interpolation,
+                    storage_access: if class == StorageClass::StorageBuffer {
+                        StorageAccess::all()
+                    } else {
+                        StorageAccess::empty()
+                    },
                 },
             );
             extra.lookup_global_variables.insert(id, h);
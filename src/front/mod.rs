@@ -21,6 +21,7 @@ impl crate::Module {
             global_variables: Arena::new(),
             functions: Arena::new(),
             entry_points: Vec::new(),
+            spans: crate::SpanTable::default(),
         }
     }
 
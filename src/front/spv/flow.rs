@@ -0,0 +1,405 @@
+/*! Structured control flow reconstruction.
+
+SPIR-V functions are made of basic blocks, connected by branches. Since the
+shader modules are required to be structured, every selection and loop header
+is annotated with a merge instruction, which tells where the control flow
+re-converges. We follow the merge and continue targets in order to rebuild the
+nested `If`, `Switch` and `Loop` statements of our IR.
+!*/
+
+use super::Error;
+use crate::{
+    arena::{Arena, Handle},
+    FastHashMap,
+};
+use std::collections::hash_map::Entry;
+
+pub(super) enum Terminator {
+    /// Return from the function, possibly with a value.
+    Return {
+        value: Option<Handle<crate::Expression>>,
+    },
+    /// Unconditional branch to another block.
+    Branch { target: spirv::Word },
+    /// Two-way conditional branch.
+    BranchConditional {
+        condition: Handle<crate::Expression>,
+        true_target: spirv::Word,
+        false_target: spirv::Word,
+    },
+    /// Multi-way branch on an integer selector.
+    Switch {
+        selector: Handle<crate::Expression>,
+        default: spirv::Word,
+        targets: Vec<(i32, spirv::Word)>,
+    },
+    /// Fragment discard.
+    Kill,
+    /// The end of the block is not reachable.
+    Unreachable,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(super) enum MergeInstruction {
+    /// The block is a header of a selection construct.
+    Selection { merge: spirv::Word },
+    /// The block is a loop header.
+    Loop {
+        merge: spirv::Word,
+        continuing: spirv::Word,
+    },
+}
+
+pub(super) struct ControlFlowNode {
+    pub(super) block: crate::Block,
+    pub(super) merge: Option<MergeInstruction>,
+    pub(super) terminator: Terminator,
+}
+
+/// Where the control flow goes when it reaches a given block.
+#[derive(Clone, Copy, Default)]
+struct Context {
+    /// Merge block of the innermost construct, reaching it finishes the construct.
+    stop: Option<spirv::Word>,
+    /// Block to branch to in order to `break` out of the innermost loop or switch.
+    break_target: Option<spirv::Word>,
+    /// Block to branch to in order to `continue` the innermost loop.
+    continue_target: Option<spirv::Word>,
+    /// Merge block of the innermost loop, which differs from `break_target`
+    /// inside of a switch.
+    loop_break: Option<spirv::Word>,
+}
+
+/// The arenas of the function and module the structuring adds variables to.
+pub(super) struct Arenas<'a> {
+    pub(super) expressions: &'a mut Arena<crate::Expression>,
+    pub(super) local_variables: &'a mut Arena<crate::LocalVariable>,
+    pub(super) types: &'a mut Arena<crate::Type>,
+    pub(super) constants: &'a mut Arena<crate::Constant>,
+}
+
+/// A boolean variable, set by a switch case in order to leave the enclosing loop.
+///
+/// A `Break` in a switch only leaves the switch, so the flag is checked
+/// right after it, and cleared once the loop is left.
+#[derive(Clone, Copy)]
+struct BreakFlag {
+    pointer: Handle<crate::Expression>,
+    value: Handle<crate::Expression>,
+    set: Handle<crate::Expression>,
+    clear: Handle<crate::Expression>,
+}
+
+fn is_exit(block: &[crate::Statement]) -> bool {
+    matches!(
+        *block,
+        [crate::Statement::Break] | [crate::Statement::Continue]
+    )
+}
+
+#[derive(Default)]
+pub(super) struct FlowGraph {
+    nodes: FastHashMap<spirv::Word, ControlFlowNode>,
+    entry: Option<spirv::Word>,
+    break_flag: Option<BreakFlag>,
+    /// Whether a switch case set the break flag since the innermost loop started.
+    sets_break_flag: bool,
+}
+
+impl FlowGraph {
+    pub(super) fn add_node(&mut self, label: spirv::Word, node: ControlFlowNode) {
+        if self.entry.is_none() {
+            self.entry = Some(label);
+        }
+        self.nodes.insert(label, node);
+    }
+
+    /// Append a statement to the end of a block, before its terminator.
+    pub(super) fn append_to(
+        &mut self,
+        label: spirv::Word,
+        statement: crate::Statement,
+    ) -> Result<(), Error> {
+        let node = self.nodes.get_mut(&label).ok_or(Error::InvalidId(label))?;
+        node.block.push(statement);
+        Ok(())
+    }
+
+    /// Consume the graph, producing the body of the function.
+    pub(super) fn finish(mut self, mut arenas: Arenas) -> Result<crate::Block, Error> {
+        let mut body = Vec::new();
+        if let Some(entry) = self.entry {
+            self.structure(entry, Context::default(), &mut arenas, &mut body)?;
+        }
+        if !self.nodes.is_empty() {
+            log::warn!("Unreachable blocks: {:?}", self.nodes.keys());
+        }
+        Ok(body)
+    }
+
+    fn break_flag(&mut self, arenas: &mut Arenas) -> BreakFlag {
+        if let Some(flag) = self.break_flag {
+            return flag;
+        }
+        let ty = arenas.types.fetch_or_append(crate::Type {
+            name: None,
+            inner: crate::TypeInner::Scalar {
+                kind: crate::ScalarKind::Bool,
+                width: 1,
+            },
+        });
+        let mut constant = |value| {
+            let handle = arenas.constants.fetch_or_append(crate::Constant {
+                name: None,
+                specialization: None,
+                inner: crate::ConstantInner::Bool(value),
+                ty,
+            });
+            arenas
+                .expressions
+                .append(crate::Expression::Constant(handle))
+        };
+        let set = constant(true);
+        let clear = constant(false);
+        let variable = arenas.local_variables.append(crate::LocalVariable {
+            name: Some("loop_break".to_string()),
+            ty,
+            init: Some(clear),
+        });
+        let pointer = arenas
+            .expressions
+            .append(crate::Expression::LocalVariable(variable));
+        let value = arenas
+            .expressions
+            .append(crate::Expression::Load { pointer });
+        let flag = BreakFlag {
+            pointer,
+            value,
+            set,
+            clear,
+        };
+        self.break_flag = Some(flag);
+        flag
+    }
+
+    fn structure(
+        &mut self,
+        mut label: spirv::Word,
+        context: Context,
+        arenas: &mut Arenas,
+        out: &mut crate::Block,
+    ) -> Result<(), Error> {
+        loop {
+            if context.stop == Some(label) {
+                return Ok(());
+            }
+            if context.break_target == Some(label) {
+                out.push(crate::Statement::Break);
+                return Ok(());
+            }
+            if context.continue_target == Some(label) {
+                out.push(crate::Statement::Continue);
+                return Ok(());
+            }
+            if context.loop_break == Some(label) {
+                let flag = self.break_flag(arenas);
+                out.push(crate::Statement::Store {
+                    pointer: flag.pointer,
+                    value: flag.set,
+                });
+                out.push(crate::Statement::Break);
+                self.sets_break_flag = true;
+                return Ok(());
+            }
+
+            let node = self
+                .nodes
+                .remove(&label)
+                .ok_or(Error::UnsupportedControlFlow(label))?;
+            let next = match node.merge {
+                Some(MergeInstruction::Loop { merge, continuing }) => {
+                    let inner = Context {
+                        stop: None,
+                        break_target: Some(merge),
+                        continue_target: Some(continuing),
+                        loop_break: Some(merge),
+                    };
+                    let outer_sets_break_flag = std::mem::replace(&mut self.sets_break_flag, false);
+                    let mut body = Vec::new();
+                    if let Some(next) = self.structure_node(node, inner, arenas, &mut body)? {
+                        self.structure(next, inner, arenas, &mut body)?;
+                    }
+                    if let Some(&crate::Statement::Continue) = body.last() {
+                        body.pop();
+                    }
+
+                    let mut continuing_block = Vec::new();
+                    if continuing != label {
+                        let continuing_context = Context {
+                            stop: Some(label),
+                            break_target: Some(merge),
+                            continue_target: None,
+                            loop_break: Some(merge),
+                        };
+                        self.structure(
+                            continuing,
+                            continuing_context,
+                            arenas,
+                            &mut continuing_block,
+                        )?;
+                    }
+                    self.sets_break_flag = outer_sets_break_flag;
+
+                    out.push(crate::Statement::Loop {
+                        body,
+                        continuing: continuing_block,
+                    });
+                    Some(merge)
+                }
+                _ => self.structure_node(node, context, arenas, out)?,
+            };
+            match next {
+                Some(next) => label = next,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    /// Emit the contents of a node, returning the label of the block
+    /// where the control flow continues, if any.
+    fn structure_node(
+        &mut self,
+        node: ControlFlowNode,
+        context: Context,
+        arenas: &mut Arenas,
+        out: &mut crate::Block,
+    ) -> Result<Option<spirv::Word>, Error> {
+        out.extend(node.block);
+        let selection_merge = match node.merge {
+            Some(MergeInstruction::Selection { merge }) => Some(merge),
+            _ => None,
+        };
+
+        match node.terminator {
+            Terminator::Return { value } => {
+                out.push(crate::Statement::Return { value });
+                Ok(None)
+            }
+            Terminator::Kill => {
+                out.push(crate::Statement::Kill);
+                Ok(None)
+            }
+            Terminator::Unreachable => Ok(None),
+            Terminator::Branch { target } => Ok(Some(target)),
+            Terminator::BranchConditional {
+                condition,
+                true_target,
+                false_target,
+            } => {
+                let branch_context = match selection_merge {
+                    Some(merge) => Context {
+                        stop: Some(merge),
+                        ..context
+                    },
+                    None => context,
+                };
+                let mut accept = Vec::new();
+                self.structure(true_target, branch_context, arenas, &mut accept)?;
+                let mut reject = Vec::new();
+                self.structure(false_target, branch_context, arenas, &mut reject)?;
+
+                if selection_merge.is_some() {
+                    out.push(crate::Statement::If {
+                        condition,
+                        accept,
+                        reject,
+                    });
+                    return Ok(selection_merge);
+                }
+
+                // Without a merge, one of the branches has to leave the construct.
+                // We keep the other branch at the current level of nesting.
+                if is_exit(&reject) {
+                    out.push(crate::Statement::If {
+                        condition,
+                        accept: Vec::new(),
+                        reject,
+                    });
+                    out.extend(accept);
+                } else if is_exit(&accept) {
+                    out.push(crate::Statement::If {
+                        condition,
+                        accept,
+                        reject: Vec::new(),
+                    });
+                    out.extend(reject);
+                } else {
+                    out.push(crate::Statement::If {
+                        condition,
+                        accept,
+                        reject,
+                    });
+                }
+                Ok(None)
+            }
+            Terminator::Switch {
+                selector,
+                default,
+                targets,
+            } => {
+                let merge = selection_merge.ok_or(Error::UnsupportedControlFlow(default))?;
+                let case_context = Context {
+                    stop: Some(merge),
+                    break_target: Some(merge),
+                    ..context
+                };
+                let outer_sets_break_flag = std::mem::replace(&mut self.sets_break_flag, false);
+
+                let mut blocks = FastHashMap::<spirv::Word, crate::Block>::default();
+                let mut cases = FastHashMap::default();
+                for (value, target) in targets {
+                    if let Entry::Vacant(entry) = blocks.entry(target) {
+                        let mut case = Vec::new();
+                        self.structure(target, case_context, arenas, &mut case)?;
+                        entry.insert(case);
+                    }
+                    cases.insert(value, (blocks[&target].clone(), None));
+                }
+                let default = match blocks.remove(&default) {
+                    Some(block) => block,
+                    None => {
+                        let mut block = Vec::new();
+                        self.structure(default, case_context, arenas, &mut block)?;
+                        block
+                    }
+                };
+
+                out.push(crate::Statement::Switch {
+                    selector,
+                    cases,
+                    default,
+                });
+
+                if self.sets_break_flag {
+                    // leave the loop, or the enclosing switch on the way to it
+                    let flag = self.break_flag(arenas);
+                    let mut accept = Vec::new();
+                    if context.break_target == context.loop_break {
+                        accept.push(crate::Statement::Store {
+                            pointer: flag.pointer,
+                            value: flag.clear,
+                        });
+                    }
+                    accept.push(crate::Statement::Break);
+                    out.push(crate::Statement::If {
+                        condition: flag.value,
+                        accept,
+                        reject: Vec::new(),
+                    });
+                }
+                self.sets_break_flag |= outer_sets_break_flag;
+                Ok(Some(merge))
+            }
+        }
+    }
+}
@@ -0,0 +1,4027 @@
+/*! SPIR-V frontend
+
+## ID lookups
+
+Our IR links to everything with `Handle`, while SPIR-V uses IDs.
+In order to keep track of the associations, the parser has many lookup tables.
+There map `spv::Word` into a specific IR handle, plus potentially a bit of
+extra info, such as the related SPIR-V type ID.
+TODO: would be nice to find ways that avoid looking up as much
+
+## Control flow
+
+Each function body is first read as a graph of basic blocks, where every block
+is a list of statements followed by a terminator. Once all the blocks are known,
+the graph is turned into nested IR statements by following the merge and
+continue targets, see the `flow` module.
+
+`OpPhi` instructions are replaced by local variables, which are assigned
+at the end of each of the predecessor blocks.
+
+## Debug information
+
+`OpString` and `OpSource` instructions describe the source files, and `OpLine`
+sets the location of the following instructions. These are collected into
+the span table of the module.
+
+!*/
+
+mod flow;
+
+use crate::{
+    arena::{Arena, Handle},
+    FastHashMap, FastHashSet,
+};
+
+use flow::{ControlFlowNode, FlowGraph, MergeInstruction, Terminator};
+use num_traits::cast::FromPrimitive;
+use std::{convert::TryInto, num::NonZeroU32};
+
+pub const SUPPORTED_CAPABILITIES: &[spirv::Capability] = &[
+    spirv::Capability::Shader,
+    spirv::Capability::ClipDistance,
+    spirv::Capability::Sampled1D,
+    spirv::Capability::Image1D,
+    spirv::Capability::SampledCubeArray,
+    spirv::Capability::ImageCubeArray,
+];
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[];
+pub const SUPPORTED_EXT_SETS: &[&str] = &["GLSL.std.450"];
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidHeader,
+    InvalidWordCount,
+    UnknownInstruction(u16),
+    UnknownCapability(spirv::Word),
+    UnsupportedInstruction(ModuleState, spirv::Op),
+    UnsupportedCapability(spirv::Capability),
+    UnsupportedExtension(String),
+    UnsupportedExtSet(String),
+    UnsupportedExtInstSet(spirv::Word),
+    UnsupportedExtInst(spirv::Word),
+    UnsupportedType(Handle<crate::Type>),
+    UnsupportedExecutionModel(spirv::Word),
+    UnsupportedStorageClass(spirv::Word),
+    UnsupportedImageDim(spirv::Word),
+    UnsupportedBuiltIn(spirv::Word),
+    UnsupportedControlFlow(spirv::Word),
+    InvalidParameter(spirv::Op),
+    InvalidOperandCount(spirv::Op, u16),
+    InvalidOperand,
+    InvalidId(spirv::Word),
+    InvalidDecoration(spirv::Word),
+    InvalidTypeWidth(spirv::Word),
+    InvalidSign(spirv::Word),
+    InvalidInnerType(spirv::Word),
+    InvalidVectorSize(spirv::Word),
+    InvalidArraySize(Handle<crate::Constant>),
+    InvalidMatrixStride(spirv::Word),
+    InvalidVariableClass(spirv::StorageClass),
+    InvalidAccessType(spirv::Word),
+    InvalidAccess(Handle<crate::Expression>),
+    InvalidAccessIndex(spirv::Word),
+    InvalidLoadType(spirv::Word),
+    InvalidStoreType(spirv::Word),
+    InvalidBinding(spirv::Word),
+    InvalidImageExpression(Handle<crate::Expression>),
+    InvalidSamplerExpression(Handle<crate::Expression>),
+    InvalidSampleImage(Handle<crate::Type>),
+    InvalidSampleSampler(Handle<crate::Type>),
+    InvalidSampleCoordinates(Handle<crate::Type>),
+    InvalidDepthReference(Handle<crate::Type>),
+    InconsistentComparisonSampling(Handle<crate::Type>),
+    WrongFunctionResultType(spirv::Word),
+    WrongFunctionParameterType(spirv::Word),
+    MissingDecoration(spirv::Decoration),
+    UnsupportedDecoration(spirv::Decoration),
+    BadString,
+    IncompleteData,
+}
+
+#[derive(Clone, Copy)]
+struct Instruction {
+    op: spirv::Op,
+    wc: u16,
+}
+
+impl Instruction {
+    fn expect(&self, count: u16) -> Result<(), Error> {
+        if self.wc == count {
+            Ok(())
+        } else {
+            Err(Error::InvalidOperandCount(self.op, self.wc))
+        }
+    }
+
+    fn expect_at_least(&self, count: u16) -> Result<(), Error> {
+        if self.wc >= count {
+            Ok(())
+        } else {
+            Err(Error::InvalidOperandCount(self.op, self.wc))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum ModuleState {
+    Empty,
+    Capability,
+    Extension,
+    ExtInstImport,
+    MemoryModel,
+    EntryPoint,
+    ExecutionMode,
+    Source,
+    Name,
+    ModuleProcessed,
+    Annotation,
+    Type,
+    Function,
+}
+
+trait LookupHelper {
+    type Target;
+    fn lookup(&self, key: spirv::Word) -> Result<&Self::Target, Error>;
+}
+
+impl<T> LookupHelper for FastHashMap<spirv::Word, T> {
+    type Target = T;
+    fn lookup(&self, key: spirv::Word) -> Result<&T, Error> {
+        self.get(&key).ok_or(Error::InvalidId(key))
+    }
+}
+
+fn map_vector_size(word: spirv::Word) -> Result<crate::VectorSize, Error> {
+    match word {
+        2 => Ok(crate::VectorSize::Bi),
+        3 => Ok(crate::VectorSize::Tri),
+        4 => Ok(crate::VectorSize::Quad),
+        _ => Err(Error::InvalidVectorSize(word)),
+    }
+}
+
+fn map_storage_class(word: spirv::Word) -> Result<crate::StorageClass, Error> {
+    use spirv::StorageClass as Sc;
+    match Sc::from_u32(word) {
+        Some(Sc::UniformConstant) => Ok(crate::StorageClass::Constant),
+        Some(Sc::Function) => Ok(crate::StorageClass::Function),
+        Some(Sc::Input) => Ok(crate::StorageClass::Input),
+        Some(Sc::Output) => Ok(crate::StorageClass::Output),
+        Some(Sc::Private) => Ok(crate::StorageClass::Private),
+        Some(Sc::StorageBuffer) => Ok(crate::StorageClass::StorageBuffer),
+        Some(Sc::Uniform) => Ok(crate::StorageClass::Uniform),
+        Some(Sc::Workgroup) => Ok(crate::StorageClass::WorkGroup),
+        _ => Err(Error::UnsupportedStorageClass(word)),
+    }
+}
+
+fn map_image_dim(word: spirv::Word) -> Result<crate::ImageDimension, Error> {
+    match spirv::Dim::from_u32(word) {
+        Some(spirv::Dim::Dim1D) => Ok(crate::ImageDimension::D1),
+        Some(spirv::Dim::Dim2D) => Ok(crate::ImageDimension::D2),
+        Some(spirv::Dim::Dim3D) => Ok(crate::ImageDimension::D3),
+        Some(spirv::Dim::DimCube) => Ok(crate::ImageDimension::Cube),
+        _ => Err(Error::UnsupportedImageDim(word)),
+    }
+}
+
+fn map_width(word: spirv::Word) -> Result<crate::Bytes, Error> {
+    (word >> 3) // bits to bytes
+        .try_into()
+        .map_err(|_| Error::InvalidTypeWidth(word))
+}
+
+fn scalar_kind_width(inner: &crate::TypeInner) -> Option<(crate::ScalarKind, crate::Bytes)> {
+    match *inner {
+        crate::TypeInner::Scalar { kind, width } | crate::TypeInner::Vector { kind, width, .. } => {
+            Some((kind, width))
+        }
+        _ => None,
+    }
+}
+
+/// Builds `left < right || left > right`, which is false if an operand is NaN.
+fn ordered_not_equal(
+    expressions: &mut Arena<crate::Expression>,
+    left: Handle<crate::Expression>,
+    right: Handle<crate::Expression>,
+) -> Handle<crate::Expression> {
+    let less = expressions.append(crate::Expression::Binary {
+        op: crate::BinaryOperator::Less,
+        left,
+        right,
+    });
+    let greater = expressions.append(crate::Expression::Binary {
+        op: crate::BinaryOperator::Greater,
+        left,
+        right,
+    });
+    expressions.append(crate::Expression::Binary {
+        op: crate::BinaryOperator::LogicalOr,
+        left: less,
+        right: greater,
+    })
+}
+
+//TODO: this method may need to be gone, depending on whether
+// WGSL allows treating images and samplers as expressions and pass them around.
+fn reach_global_type(
+    mut expr_handle: Handle<crate::Expression>,
+    expressions: &Arena<crate::Expression>,
+    globals: &Arena<crate::GlobalVariable>,
+) -> Option<Handle<crate::Type>> {
+    loop {
+        expr_handle = match expressions[expr_handle] {
+            crate::Expression::Load { pointer } => pointer,
+            crate::Expression::GlobalVariable(var) => return Some(globals[var].ty),
+            _ => return None,
+        };
+    }
+}
+
+fn check_sample_coordinates(
+    ty: &crate::Type,
+    expect_kind: crate::ScalarKind,
+    dim: crate::ImageDimension,
+    is_array: bool,
+) -> bool {
+    let base_count = match dim {
+        crate::ImageDimension::D1 => 1,
+        crate::ImageDimension::D2 => 2,
+        crate::ImageDimension::D3 | crate::ImageDimension::Cube => 3,
+    };
+    let extra_count = if is_array { 1 } else { 0 };
+    let count = base_count + extra_count;
+    match ty.inner {
+        crate::TypeInner::Scalar { kind, width: _ } => count == 1 && kind == expect_kind,
+        crate::TypeInner::Vector {
+            size,
+            kind,
+            width: _,
+        } => size as u8 == count && kind == expect_kind,
+        _ => false,
+    }
+}
+
+type MemberIndex = u32;
+
+#[derive(Clone, Debug, Default)]
+struct Block {
+    buffer: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Decoration {
+    name: Option<String>,
+    built_in: Option<crate::BuiltIn>,
+    location: Option<spirv::Word>,
+    desc_set: Option<spirv::Word>,
+    desc_index: Option<spirv::Word>,
+    block: Option<Block>,
+    offset: Option<spirv::Word>,
+    array_stride: Option<NonZeroU32>,
+    matrix_stride: Option<spirv::Word>,
+    row_major: bool,
+    interpolation: Option<crate::Interpolation>,
+    non_writable: bool,
+    non_readable: bool,
+    specialization: Option<spirv::Word>,
+}
+
+impl Decoration {
+    /// Fill in the decorations applied by a decoration group.
+    fn inherit(&mut self, group: &Decoration) {
+        self.name = self.name.take().or_else(|| group.name.clone());
+        self.built_in = self.built_in.or(group.built_in);
+        self.location = self.location.or(group.location);
+        self.desc_set = self.desc_set.or(group.desc_set);
+        self.desc_index = self.desc_index.or(group.desc_index);
+        self.block = self.block.take().or_else(|| group.block.clone());
+        self.offset = self.offset.or(group.offset);
+        self.array_stride = self.array_stride.or(group.array_stride);
+        self.matrix_stride = self.matrix_stride.or(group.matrix_stride);
+        self.row_major |= group.row_major;
+        self.interpolation = self.interpolation.or(group.interpolation);
+        self.non_writable |= group.non_writable;
+        self.non_readable |= group.non_readable;
+        self.specialization = self.specialization.or(group.specialization);
+    }
+
+    fn storage_access(&self) -> crate::StorageAccess {
+        let mut access = crate::StorageAccess::all();
+        if self.non_writable {
+            access -= crate::StorageAccess::STORE;
+        }
+        if self.non_readable {
+            access -= crate::StorageAccess::LOAD;
+        }
+        access
+    }
+
+    fn get_binding(&self) -> Option<crate::Binding> {
+        //TODO: validate this better
+        match *self {
+            Decoration {
+                built_in: Some(built_in),
+                location: None,
+                desc_set: None,
+                desc_index: None,
+                ..
+            } => Some(crate::Binding::BuiltIn(built_in)),
+            Decoration {
+                built_in: None,
+                location: Some(loc),
+                desc_set: None,
+                desc_index: None,
+                ..
+            } => Some(crate::Binding::Location(loc)),
+            Decoration {
+                built_in: None,
+                location: None,
+                desc_set: Some(set),
+                desc_index: Some(binding),
+                ..
+            } => Some(crate::Binding::Descriptor { set, binding }),
+            _ => None,
+        }
+    }
+
+    fn get_origin(&self) -> Result<crate::MemberOrigin, Error> {
+        match *self {
+            Decoration {
+                location: Some(_), ..
+            }
+            | Decoration {
+                desc_set: Some(_), ..
+            }
+            | Decoration {
+                desc_index: Some(_),
+                ..
+            } => Err(Error::MissingDecoration(spirv::Decoration::Offset)),
+            Decoration {
+                built_in: Some(built_in),
+                offset: None,
+                ..
+            } => Ok(crate::MemberOrigin::BuiltIn(built_in)),
+            Decoration {
+                built_in: None,
+                offset: Some(offset),
+                ..
+            } => Ok(crate::MemberOrigin::Offset(offset)),
+            Decoration {
+                built_in: None,
+                offset: None,
+                ..
+            } => Ok(crate::MemberOrigin::Empty),
+            _ => Err(Error::MissingDecoration(spirv::Decoration::Offset)),
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags describing sampling method.
+    pub struct SamplingFlags: u32 {
+        /// Regular sampling.
+        const REGULAR = 0x1;
+        /// Comparison sampling.
+        const COMPARISON = 0x2;
+    }
+}
+
+#[derive(Debug)]
+struct LookupBlock {
+    buffer: bool,
+    /// Access allowed to the members of the block.
+    access: crate::StorageAccess,
+}
+
+#[derive(Debug)]
+struct LookupFunctionType {
+    parameter_type_ids: Vec<spirv::Word>,
+    return_type_id: spirv::Word,
+}
+
+#[derive(Debug)]
+struct EntryPoint {
+    exec_model: spirv::ExecutionModel,
+    name: String,
+    function_id: spirv::Word,
+    variable_ids: Vec<spirv::Word>,
+}
+
+#[derive(Debug)]
+struct LookupType {
+    handle: Handle<crate::Type>,
+    base_id: Option<spirv::Word>,
+}
+
+#[derive(Debug)]
+struct LookupConstant {
+    handle: Handle<crate::Constant>,
+    type_id: spirv::Word,
+}
+
+#[derive(Debug)]
+struct LookupVariable {
+    handle: Handle<crate::GlobalVariable>,
+    type_id: spirv::Word,
+}
+
+#[derive(Clone, Debug)]
+struct LookupExpression {
+    handle: Handle<crate::Expression>,
+    type_id: spirv::Word,
+}
+
+#[derive(Clone, Debug)]
+struct LookupSampledImage {
+    image: Handle<crate::Expression>,
+    sampler: Handle<crate::Expression>,
+}
+
+struct DeferredFunctionCall {
+    source_handle: Handle<crate::Function>,
+    expr_handle: Handle<crate::Expression>,
+    dst_id: spirv::Word,
+}
+
+struct PhiInstruction {
+    pointer: Handle<crate::Expression>,
+    incoming: Vec<(spirv::Word, spirv::Word)>,
+}
+
+/// Parsing options.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Fail on any instruction, capability, or extension that can't be translated.
+    ///
+    /// Otherwise, the parser skips the non-semantic instructions, as well as the
+    /// unknown and unsupported ones, and reports them as warnings.
+    pub strict: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { strict: true }
+    }
+}
+
+pub struct Parser<I> {
+    data: I,
+    options: Options,
+    warnings: Vec<Error>,
+    state: ModuleState,
+    temp_bytes: Vec<u8>,
+    ext_glsl_id: Option<spirv::Word>,
+    /// Extended instruction sets, which instructions are skipped.
+    ignored_ext_sets: FastHashSet<spirv::Word>,
+    future_decor: FastHashMap<spirv::Word, Decoration>,
+    future_member_decor: FastHashMap<(spirv::Word, MemberIndex), Decoration>,
+    lookup_member_type_id: FastHashMap<(spirv::Word, MemberIndex), spirv::Word>,
+    handle_sampling: FastHashMap<Handle<crate::Type>, SamplingFlags>,
+    lookup_type: FastHashMap<spirv::Word, LookupType>,
+    lookup_block: FastHashMap<Handle<crate::Type>, LookupBlock>,
+    lookup_void_type: FastHashSet<spirv::Word>,
+    // Lookup for samplers and sampled images, storing flags on how they are used.
+    lookup_constant: FastHashMap<spirv::Word, LookupConstant>,
+    lookup_variable: FastHashMap<spirv::Word, LookupVariable>,
+    lookup_expression: FastHashMap<spirv::Word, LookupExpression>,
+    lookup_sampled_image: FastHashMap<spirv::Word, LookupSampledImage>,
+    lookup_function_type: FastHashMap<spirv::Word, LookupFunctionType>,
+    lookup_function: FastHashMap<spirv::Word, Handle<crate::Function>>,
+    deferred_function_calls: Vec<DeferredFunctionCall>,
+    lookup_source_file: FastHashMap<spirv::Word, Handle<crate::SourceFile>>,
+    /// The file receiving the text of `OpSourceContinued`.
+    last_source_file: Option<Handle<crate::SourceFile>>,
+    /// Location set by the last `OpLine`, if it's still in scope.
+    current_span: Option<crate::Span>,
+    /// Locations of the expressions in the function being parsed.
+    expression_spans: Vec<(Handle<crate::Expression>, crate::Span)>,
+}
+
+impl<I: Iterator<Item = u32>> Parser<I> {
+    pub fn new(data: I, options: &Options) -> Self {
+        Parser {
+            data,
+            options: options.clone(),
+            warnings: Vec::new(),
+            state: ModuleState::Empty,
+            temp_bytes: Vec::new(),
+            ext_glsl_id: None,
+            ignored_ext_sets: FastHashSet::default(),
+            future_decor: FastHashMap::default(),
+            future_member_decor: FastHashMap::default(),
+            handle_sampling: FastHashMap::default(),
+            lookup_member_type_id: FastHashMap::default(),
+            lookup_type: FastHashMap::default(),
+            lookup_block: FastHashMap::default(),
+            lookup_void_type: FastHashSet::default(),
+            lookup_constant: FastHashMap::default(),
+            lookup_variable: FastHashMap::default(),
+            lookup_expression: FastHashMap::default(),
+            lookup_sampled_image: FastHashMap::default(),
+            lookup_function_type: FastHashMap::default(),
+            lookup_function: FastHashMap::default(),
+            deferred_function_calls: Vec::new(),
+            lookup_source_file: FastHashMap::default(),
+            last_source_file: None,
+            current_span: None,
+            expression_spans: Vec::new(),
+        }
+    }
+
+    /// Problems that were skipped over during parsing.
+    pub fn warnings(&self) -> &[Error] {
+        &self.warnings
+    }
+
+    fn next(&mut self) -> Result<u32, Error> {
+        self.data.next().ok_or(Error::IncompleteData)
+    }
+
+    /// Fail with the given error in strict mode, or record it as a warning otherwise.
+    fn check(&mut self, error: Error) -> Result<(), Error> {
+        if self.options.strict {
+            Err(error)
+        } else {
+            self.warn(error);
+            Ok(())
+        }
+    }
+
+    fn warn(&mut self, warning: Error) {
+        log::warn!("{:?}", warning);
+        self.warnings.push(warning);
+    }
+
+    /// Skip the operands of an instruction.
+    fn skip(&mut self, inst: Instruction) -> Result<(), Error> {
+        for _ in 1..inst.wc {
+            let _ = self.next()?;
+        }
+        Ok(())
+    }
+
+    /// Read the next instruction, keeping track of the line information on the way.
+    fn next_inst(&mut self) -> Result<Instruction, Error> {
+        loop {
+            let word = self.next()?;
+            let (wc, opcode) = ((word >> 16) as u16, (word & 0xffff) as u16);
+            if wc == 0 {
+                return Err(Error::InvalidWordCount);
+            }
+            let op = match spirv::Op::from_u16(opcode) {
+                Some(op) => op,
+                // skipping an instruction of a function could drop a result its body uses
+                None if self.state == ModuleState::Function => {
+                    return Err(Error::UnknownInstruction(opcode))
+                }
+                None => {
+                    self.check(Error::UnknownInstruction(opcode))?;
+                    for _ in 1..wc {
+                        let _ = self.next()?;
+                    }
+                    continue;
+                }
+            };
+            let inst = Instruction { op, wc };
+
+            match op {
+                spirv::Op::Nop | spirv::Op::ModuleProcessed if !self.options.strict => {
+                    self.skip(inst)?;
+                }
+                spirv::Op::Line => {
+                    inst.expect(4)?;
+                    let file_id = self.next()?;
+                    let line = self.next()?;
+                    let column = self.next()?;
+                    self.current_span = Some(crate::Span {
+                        file: *self.lookup_source_file.lookup(file_id)?,
+                        line,
+                        column,
+                    });
+                }
+                spirv::Op::NoLine => {
+                    inst.expect(1)?;
+                    self.current_span = None;
+                }
+                _ => return Ok(inst),
+            }
+        }
+    }
+
+    fn next_string(&mut self, mut count: u16) -> Result<(String, u16), Error> {
+        self.temp_bytes.clear();
+        loop {
+            if count == 0 {
+                return Err(Error::BadString);
+            }
+            count -= 1;
+            let chars = self.next()?.to_le_bytes();
+            let pos = chars.iter().position(|&c| c == 0).unwrap_or(4);
+            self.temp_bytes.extend_from_slice(&chars[..pos]);
+            if pos < 4 {
+                break;
+            }
+        }
+        std::str::from_utf8(&self.temp_bytes)
+            .map(|s| (s.to_owned(), count))
+            .map_err(|_| Error::BadString)
+    }
+
+    fn next_decoration(
+        &mut self,
+        inst: Instruction,
+        base_words: u16,
+        dec: &mut Decoration,
+    ) -> Result<(), Error> {
+        let raw = self.next()?;
+        let dec_typed = spirv::Decoration::from_u32(raw).ok_or(Error::InvalidDecoration(raw))?;
+        log::trace!("\t\t{:?}", dec_typed);
+        match dec_typed {
+            spirv::Decoration::BuiltIn => {
+                use spirv::BuiltIn as Bi;
+                inst.expect(base_words + 2)?;
+                let raw = self.next()?;
+                dec.built_in = Some(match spirv::BuiltIn::from_u32(raw) {
+                    Some(Bi::BaseInstance) => crate::BuiltIn::BaseInstance,
+                    Some(Bi::BaseVertex) => crate::BuiltIn::BaseVertex,
+                    Some(Bi::ClipDistance) => crate::BuiltIn::ClipDistance,
+                    Some(Bi::InstanceIndex) => crate::BuiltIn::InstanceIndex,
+                    Some(Bi::Position) => crate::BuiltIn::Position,
+                    Some(Bi::VertexIndex) => crate::BuiltIn::VertexIndex,
+                    // fragment
+                    Some(Bi::PointSize) => crate::BuiltIn::PointSize,
+                    Some(Bi::FragCoord) => crate::BuiltIn::FragCoord,
+                    Some(Bi::FrontFacing) => crate::BuiltIn::FrontFacing,
+                    Some(Bi::SampleId) => crate::BuiltIn::SampleIndex,
+                    Some(Bi::FragDepth) => crate::BuiltIn::FragDepth,
+                    // compute
+                    Some(Bi::GlobalInvocationId) => crate::BuiltIn::GlobalInvocationId,
+                    Some(Bi::LocalInvocationId) => crate::BuiltIn::LocalInvocationId,
+                    Some(Bi::LocalInvocationIndex) => crate::BuiltIn::LocalInvocationIndex,
+                    Some(Bi::WorkgroupId) => crate::BuiltIn::WorkGroupId,
+                    _ => return Err(Error::UnsupportedBuiltIn(raw)),
+                });
+            }
+            spirv::Decoration::Location => {
+                inst.expect(base_words + 2)?;
+                dec.location = Some(self.next()?);
+            }
+            spirv::Decoration::DescriptorSet => {
+                inst.expect(base_words + 2)?;
+                dec.desc_set = Some(self.next()?);
+            }
+            spirv::Decoration::Binding => {
+                inst.expect(base_words + 2)?;
+                dec.desc_index = Some(self.next()?);
+            }
+            spirv::Decoration::Block => {
+                dec.block = Some(Block { buffer: false });
+            }
+            spirv::Decoration::BufferBlock => {
+                dec.block = Some(Block { buffer: true });
+            }
+            spirv::Decoration::Offset => {
+                inst.expect(base_words + 2)?;
+                dec.offset = Some(self.next()?);
+            }
+            spirv::Decoration::ArrayStride => {
+                inst.expect(base_words + 2)?;
+                dec.array_stride = NonZeroU32::new(self.next()?);
+            }
+            spirv::Decoration::NoPerspective => {
+                dec.interpolation = Some(crate::Interpolation::Linear);
+            }
+            spirv::Decoration::Flat => {
+                dec.interpolation = Some(crate::Interpolation::Flat);
+            }
+            spirv::Decoration::Patch => {
+                dec.interpolation = Some(crate::Interpolation::Patch);
+            }
+            spirv::Decoration::Centroid => {
+                dec.interpolation = Some(crate::Interpolation::Centroid);
+            }
+            spirv::Decoration::Sample => {
+                dec.interpolation = Some(crate::Interpolation::Sample);
+            }
+            spirv::Decoration::NonWritable => {
+                dec.non_writable = true;
+            }
+            spirv::Decoration::NonReadable => {
+                dec.non_readable = true;
+            }
+            spirv::Decoration::SpecId => {
+                inst.expect(base_words + 2)?;
+                dec.specialization = Some(self.next()?);
+            }
+            spirv::Decoration::Invariant => {
+                self.warn(Error::UnsupportedDecoration(dec_typed));
+            }
+            spirv::Decoration::RowMajor => {
+                // the matrices of the IR are laid out by columns
+                self.check(Error::UnsupportedDecoration(dec_typed))?;
+                dec.row_major = true;
+            }
+            spirv::Decoration::ColMajor => {
+                // this is the only supported matrix layout
+            }
+            spirv::Decoration::MatrixStride => {
+                // validated against the layout rules once the member type is known
+                inst.expect(base_words + 2)?;
+                dec.matrix_stride = Some(self.next()?);
+            }
+            spirv::Decoration::RelaxedPrecision => {
+                // it's only a hint, so we keep the full precision
+            }
+            other => {
+                self.warn(Error::UnsupportedDecoration(other));
+                for _ in base_words + 1..inst.wc {
+                    let _var = self.next()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_expr_binary_op(
+        &mut self,
+        expressions: &mut Arena<crate::Expression>,
+        op: crate::BinaryOperator,
+    ) -> Result<(), Error> {
+        let result_type_id = self.next()?;
+        let result_id = self.next()?;
+        let p1_id = self.next()?;
+        let p2_id = self.next()?;
+
+        let p1_lexp = self.lookup_expression.lookup(p1_id)?;
+        let p2_lexp = self.lookup_expression.lookup(p2_id)?;
+
+        let expr = crate::Expression::Binary {
+            op,
+            left: p1_lexp.handle,
+            right: p2_lexp.handle,
+        };
+        self.lookup_expression.insert(
+            result_id,
+            LookupExpression {
+                handle: expressions.append(expr),
+                type_id: result_type_id,
+            },
+        );
+        Ok(())
+    }
+
+    /// Parses a binary instruction without an IR equivalent,
+    /// building the result out of its operands with `lower`.
+    fn parse_expr_lowered_op(
+        &mut self,
+        expressions: &mut Arena<crate::Expression>,
+        lower: impl FnOnce(
+            &mut Arena<crate::Expression>,
+            Handle<crate::Expression>,
+            Handle<crate::Expression>,
+        ) -> Handle<crate::Expression>,
+    ) -> Result<(), Error> {
+        let result_type_id = self.next()?;
+        let result_id = self.next()?;
+        let p1_id = self.next()?;
+        let p2_id = self.next()?;
+
+        let p1_lexp = self.lookup_expression.lookup(p1_id)?;
+        let p2_lexp = self.lookup_expression.lookup(p2_id)?;
+
+        let handle = lower(expressions, p1_lexp.handle, p2_lexp.handle);
+        self.lookup_expression.insert(
+            result_id,
+            LookupExpression {
+                handle,
+                type_id: result_type_id,
+            },
+        );
+        Ok(())
+    }
+
+    /// Returns an expression with the value zero for the given scalar or vector type.
+    fn zero_expression(
+        &self,
+        expressions: &mut Arena<crate::Expression>,
+        type_arena: &Arena<crate::Type>,
+        const_arena: &mut Arena<crate::Constant>,
+        type_id: spirv::Word,
+    ) -> Result<Handle<crate::Expression>, Error> {
+        let ty = self.lookup_type.lookup(type_id)?.handle;
+        let (kind, width, size) = match type_arena[ty].inner {
+            crate::TypeInner::Scalar { kind, width } => (kind, width, None),
+            crate::TypeInner::Vector { size, kind, width } => (kind, width, Some(size)),
+            _ => return Err(Error::UnsupportedType(ty)),
+        };
+        let inner = match kind {
+            crate::ScalarKind::Sint => crate::ConstantInner::Sint(0),
+            crate::ScalarKind::Uint => crate::ConstantInner::Uint(0),
+            crate::ScalarKind::Float => crate::ConstantInner::Float(0.0),
+            crate::ScalarKind::Bool => return Err(Error::UnsupportedType(ty)),
+        };
+        let scalar_ty = match size {
+            None => ty,
+            Some(_) => type_arena
+                .iter()
+                .find(|&(_, other)| other.inner == crate::TypeInner::Scalar { kind, width })
+                .map(|(handle, _)| handle)
+                .ok_or(Error::UnsupportedType(ty))?,
+        };
+        let constant = const_arena.fetch_or_append(crate::Constant {
+            name: None,
+            specialization: None,
+            inner,
+            ty: scalar_ty,
+        });
+        let zero = expressions.append(crate::Expression::Constant(constant));
+        Ok(match size {
+            None => zero,
+            Some(size) => expressions.append(crate::Expression::Compose {
+                ty,
+                components: vec![zero; size as usize],
+            }),
+        })
+    }
+
+    fn parse_expr_unary_op(
+        &mut self,
+        expressions: &mut Arena<crate::Expression>,
+        op: crate::UnaryOperator,
+    ) -> Result<(), Error> {
+        let result_type_id = self.next()?;
+        let result_id = self.next()?;
+        let p_id = self.next()?;
+
+        let p_lexp = self.lookup_expression.lookup(p_id)?;
+
+        let expr = crate::Expression::Unary {
+            op,
+            expr: p_lexp.handle,
+        };
+        self.lookup_expression.insert(
+            result_id,
+            LookupExpression {
+                handle: expressions.append(expr),
+                type_id: result_type_id,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_expr_intrinsic(
+        &mut self,
+        expressions: &mut Arena<crate::Expression>,
+        fun: crate::IntrinsicFunction,
+    ) -> Result<(), Error> {
+        let result_type_id = self.next()?;
+        let result_id = self.next()?;
+        let p_id = self.next()?;
+
+        let p_lexp = self.lookup_expression.lookup(p_id)?;
+
+        let expr = crate::Expression::Intrinsic {
+            fun,
+            argument: p_lexp.handle,
+        };
+        self.lookup_expression.insert(
+            result_id,
+            LookupExpression {
+                handle: expressions.append(expr),
+                type_id: result_type_id,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_expr_derivative(
+        &mut self,
+        expressions: &mut Arena<crate::Expression>,
+        axis: crate::DerivativeAxis,
+    ) -> Result<(), Error> {
+        let result_type_id = self.next()?;
+        let result_id = self.next()?;
+        let p_id = self.next()?;
+
+        let p_lexp = self.lookup_expression.lookup(p_id)?;
+
+        let expr = crate::Expression::Derivative {
+            axis,
+            expr: p_lexp.handle,
+        };
+        self.lookup_expression.insert(
+            result_id,
+            LookupExpression {
+                handle: expressions.append(expr),
+                type_id: result_type_id,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_expr_cast(
+        &mut self,
+        expressions: &mut Arena<crate::Expression>,
+        type_arena: &Arena<crate::Type>,
+        convert: bool,
+    ) -> Result<(), Error> {
+        let result_type_id = self.next()?;
+        let result_id = self.next()?;
+        let p_id = self.next()?;
+
+        let p_lexp = self.lookup_expression.lookup(p_id)?;
+        let src_handle = self.lookup_type.lookup(p_lexp.type_id)?.handle;
+        let dst_handle = self.lookup_type.lookup(result_type_id)?.handle;
+        let (_, src_width) = scalar_kind_width(&type_arena[src_handle].inner)
+            .ok_or(Error::UnsupportedType(src_handle))?;
+        let (kind, dst_width) = scalar_kind_width(&type_arena[dst_handle].inner)
+            .ok_or(Error::UnsupportedType(dst_handle))?;
+        // Our IR doesn't have a way to change the width of a value
+        if src_width != dst_width {
+            return Err(Error::InvalidTypeWidth(dst_width as spirv::Word));
+        }
+
+        let expr = crate::Expression::As {
+            expr: p_lexp.handle,
+            kind,
+            convert,
+        };
+        self.lookup_expression.insert(
+            result_id,
+            LookupExpression {
+                handle: expressions.append(expr),
+                type_id: result_type_id,
+            },
+        );
+        Ok(())
+    }
+
+    /// Build a composite equal to `composite`, with the component
+    /// at the `indices` path replaced by `object`.
+    fn insert_composite(
+        &self,
+        expressions: &mut Arena<crate::Expression>,
+        type_arena: &Arena<crate::Type>,
+        composite: Handle<crate::Expression>,
+        type_id: spirv::Word,
+        object: Handle<crate::Expression>,
+        indices: &[spirv::Word],
+    ) -> Result<Handle<crate::Expression>, Error> {
+        let (&index, rest) = indices.split_first().ok_or(Error::InvalidOperand)?;
+        let type_lookup = self.lookup_type.lookup(type_id)?;
+        let count = match type_arena[type_lookup.handle].inner {
+            crate::TypeInner::Vector { size, .. } => size as u32,
+            crate::TypeInner::Matrix { columns, .. } => columns as u32,
+            crate::TypeInner::Array {
+                size: crate::ArraySize::Static(size),
+                ..
+            } => size,
+            crate::TypeInner::Struct { ref members } => members.len() as u32,
+            _ => return Err(Error::UnsupportedType(type_lookup.handle)),
+        };
+        if index >= count {
+            return Err(Error::InvalidAccessIndex(index));
+        }
+
+        let mut components = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let mut component = object;
+            if i != index || !rest.is_empty() {
+                component = expressions.append(crate::Expression::AccessIndex {
+                    base: composite,
+                    index: i,
+                });
+            }
+            if i == index && !rest.is_empty() {
+                let component_type_id = match type_arena[type_lookup.handle].inner {
+                    crate::TypeInner::Struct { .. } => *self
+                        .lookup_member_type_id
+                        .get(&(type_id, index))
+                        .ok_or(Error::InvalidAccessType(type_id))?,
+                    _ => type_lookup
+                        .base_id
+                        .ok_or(Error::InvalidAccessType(type_id))?,
+                };
+                component = self.insert_composite(
+                    expressions,
+                    type_arena,
+                    component,
+                    component_type_id,
+                    object,
+                    rest,
+                )?;
+            }
+            components.push(component);
+        }
+        Ok(expressions.append(crate::Expression::Compose {
+            ty: type_lookup.handle,
+            components,
+        }))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn next_block(
+        &mut self,
+        expressions: &mut Arena<crate::Expression>,
+        local_arena: &mut Arena<crate::LocalVariable>,
+        type_arena: &Arena<crate::Type>,
+        const_arena: &mut Arena<crate::Constant>,
+        global_arena: &Arena<crate::GlobalVariable>,
+        local_function_calls: &mut FastHashMap<Handle<crate::Expression>, spirv::Word>,
+        phis: &mut Vec<PhiInstruction>,
+    ) -> Result<ControlFlowNode, Error> {
+        let mut block = Vec::new();
+        let mut merge = None;
+        let terminator = loop {
+            use spirv::Op;
+            let inst = self.next_inst()?;
+            log::debug!("\t\t{:?} [{}]", inst.op, inst.wc);
+            let expression_count = expressions.len();
+            match inst.op {
+                Op::Variable => {
+                    inst.expect_at_least(4)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let storage = self.next()?;
+                    match spirv::StorageClass::from_u32(storage) {
+                        Some(spirv::StorageClass::Function) => (),
+                        Some(class) => return Err(Error::InvalidVariableClass(class)),
+                        None => return Err(Error::UnsupportedStorageClass(storage)),
+                    }
+                    let init = if inst.wc > 4 {
+                        inst.expect(5)?;
+                        let init_id = self.next()?;
+                        let lexp = self.lookup_expression.lookup(init_id)?;
+                        Some(lexp.handle)
+                    } else {
+                        None
+                    };
+                    let name = self
+                        .future_decor
+                        .remove(&result_id)
+                        .and_then(|decor| decor.name);
+                    let var_handle = local_arena.append(crate::LocalVariable {
+                        name,
+                        ty: self.lookup_type.lookup(result_type_id)?.handle,
+                        init,
+                    });
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions
+                                .append(crate::Expression::LocalVariable(var_handle)),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::AccessChain => {
+                    struct AccessExpression {
+                        base_handle: Handle<crate::Expression>,
+                        type_id: spirv::Word,
+                    }
+                    inst.expect_at_least(4)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let base_id = self.next()?;
+                    log::trace!("\t\t\tlooking up expr {:?}", base_id);
+                    let mut acex = {
+                        let expr = self.lookup_expression.lookup(base_id)?;
+                        let ptr_type = self.lookup_type.lookup(expr.type_id)?;
+                        AccessExpression {
+                            base_handle: expr.handle,
+                            type_id: ptr_type.base_id.unwrap(),
+                        }
+                    };
+                    for _ in 4..inst.wc {
+                        let access_id = self.next()?;
+                        log::trace!("\t\t\tlooking up expr {:?}", access_id);
+                        let index_expr = self.lookup_expression.lookup(access_id)?.clone();
+                        let index_type_handle = self.lookup_type.lookup(index_expr.type_id)?.handle;
+                        match type_arena[index_type_handle].inner {
+                            crate::TypeInner::Scalar {
+                                kind: crate::ScalarKind::Uint,
+                                ..
+                            }
+                            | crate::TypeInner::Scalar {
+                                kind: crate::ScalarKind::Sint,
+                                ..
+                            } => (),
+                            _ => return Err(Error::UnsupportedType(index_type_handle)),
+                        }
+                        log::trace!("\t\t\tlooking up type {:?}", acex.type_id);
+                        let type_lookup = self.lookup_type.lookup(acex.type_id)?;
+                        acex = match type_arena[type_lookup.handle].inner {
+                            crate::TypeInner::Struct { .. } => {
+                                let index = match expressions[index_expr.handle] {
+                                    crate::Expression::Constant(const_handle) => {
+                                        match const_arena[const_handle].inner {
+                                            crate::ConstantInner::Uint(v) => v as u32,
+                                            crate::ConstantInner::Sint(v) => v as u32,
+                                            _ => {
+                                                return Err(Error::InvalidAccess(index_expr.handle))
+                                            }
+                                        }
+                                    }
+                                    _ => return Err(Error::InvalidAccess(index_expr.handle)),
+                                };
+                                AccessExpression {
+                                    base_handle: expressions.append(
+                                        crate::Expression::AccessIndex {
+                                            base: acex.base_handle,
+                                            index,
+                                        },
+                                    ),
+                                    type_id: *self
+                                        .lookup_member_type_id
+                                        .get(&(acex.type_id, index))
+                                        .ok_or(Error::InvalidAccessType(acex.type_id))?,
+                                }
+                            }
+                            crate::TypeInner::Array { .. }
+                            | crate::TypeInner::Vector { .. }
+                            | crate::TypeInner::Matrix { .. } => AccessExpression {
+                                base_handle: expressions.append(crate::Expression::Access {
+                                    base: acex.base_handle,
+                                    index: index_expr.handle,
+                                }),
+                                type_id: type_lookup
+                                    .base_id
+                                    .ok_or(Error::InvalidAccessType(acex.type_id))?,
+                            },
+                            _ => return Err(Error::UnsupportedType(type_lookup.handle)),
+                        };
+                    }
+
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: acex.base_handle,
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::CompositeExtract => {
+                    inst.expect_at_least(4)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let base_id = self.next()?;
+                    log::trace!("\t\t\tlooking up expr {:?}", base_id);
+                    let mut lexp = {
+                        let expr = self.lookup_expression.lookup(base_id)?;
+                        LookupExpression {
+                            handle: expr.handle,
+                            type_id: expr.type_id,
+                        }
+                    };
+                    for _ in 4..inst.wc {
+                        let index = self.next()?;
+                        log::trace!("\t\t\tlooking up type {:?}", lexp.type_id);
+                        let type_lookup = self.lookup_type.lookup(lexp.type_id)?;
+                        let type_id = match type_arena[type_lookup.handle].inner {
+                            crate::TypeInner::Struct { .. } => *self
+                                .lookup_member_type_id
+                                .get(&(lexp.type_id, index))
+                                .ok_or(Error::InvalidAccessType(lexp.type_id))?,
+                            crate::TypeInner::Array { .. }
+                            | crate::TypeInner::Vector { .. }
+                            | crate::TypeInner::Matrix { .. } => type_lookup
+                                .base_id
+                                .ok_or(Error::InvalidAccessType(lexp.type_id))?,
+                            _ => return Err(Error::UnsupportedType(type_lookup.handle)),
+                        };
+                        lexp = LookupExpression {
+                            handle: expressions.append(crate::Expression::AccessIndex {
+                                base: lexp.handle,
+                                index,
+                            }),
+                            type_id,
+                        };
+                    }
+
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: lexp.handle,
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::CompositeConstruct => {
+                    inst.expect_at_least(3)?;
+                    let result_type_id = self.next()?;
+                    let id = self.next()?;
+                    let mut components = Vec::with_capacity(inst.wc as usize - 2);
+                    for _ in 3..inst.wc {
+                        let comp_id = self.next()?;
+                        log::trace!("\t\t\tlooking up expr {:?}", comp_id);
+                        let lexp = self.lookup_expression.lookup(comp_id)?;
+                        components.push(lexp.handle);
+                    }
+                    let expr = crate::Expression::Compose {
+                        ty: self.lookup_type.lookup(result_type_id)?.handle,
+                        components,
+                    };
+                    self.lookup_expression.insert(
+                        id,
+                        LookupExpression {
+                            handle: expressions.append(expr),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::Load => {
+                    inst.expect_at_least(4)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let pointer_id = self.next()?;
+                    if inst.wc != 4 {
+                        inst.expect(5)?;
+                        let _memory_access = self.next()?;
+                    }
+                    let base_expr = self.lookup_expression.lookup(pointer_id)?;
+                    let base_type = self.lookup_type.lookup(base_expr.type_id)?;
+                    if base_type.base_id != Some(result_type_id) {
+                        return Err(Error::InvalidLoadType(result_type_id));
+                    }
+                    match type_arena[base_type.handle].inner {
+                        crate::TypeInner::Pointer { .. } => (),
+                        _ => return Err(Error::UnsupportedType(base_type.handle)),
+                    }
+                    let expr = crate::Expression::Load {
+                        pointer: base_expr.handle,
+                    };
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions.append(expr),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::Store => {
+                    inst.expect_at_least(3)?;
+                    let pointer_id = self.next()?;
+                    let value_id = self.next()?;
+                    if inst.wc != 3 {
+                        inst.expect(4)?;
+                        let _memory_access = self.next()?;
+                    }
+                    let base_expr = self.lookup_expression.lookup(pointer_id)?;
+                    let base_type = self.lookup_type.lookup(base_expr.type_id)?;
+                    match type_arena[base_type.handle].inner {
+                        crate::TypeInner::Pointer { .. } => (),
+                        _ => return Err(Error::UnsupportedType(base_type.handle)),
+                    };
+                    let value_expr = self.lookup_expression.lookup(value_id)?;
+                    if base_type.base_id != Some(value_expr.type_id) {
+                        return Err(Error::InvalidStoreType(value_expr.type_id));
+                    }
+                    block.push(crate::Statement::Store {
+                        pointer: base_expr.handle,
+                        value: value_expr.handle,
+                    });
+                }
+                Op::Phi => {
+                    inst.expect_at_least(3)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let name = self
+                        .future_decor
+                        .remove(&result_id)
+                        .and_then(|decor| decor.name);
+                    let var_handle = local_arena.append(crate::LocalVariable {
+                        name,
+                        ty: self.lookup_type.lookup(result_type_id)?.handle,
+                        init: None,
+                    });
+                    let pointer = expressions.append(crate::Expression::LocalVariable(var_handle));
+                    let mut incoming = Vec::with_capacity((inst.wc as usize - 3) / 2);
+                    for _ in 0..incoming.capacity() {
+                        let value_id = self.next()?;
+                        let parent_id = self.next()?;
+                        incoming.push((value_id, parent_id));
+                    }
+                    phis.push(PhiInstruction { pointer, incoming });
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: pointer,
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::SelectionMerge => {
+                    inst.expect(3)?;
+                    let merge_id = self.next()?;
+                    let _selection_control = self.next()?;
+                    merge = Some(MergeInstruction::Selection { merge: merge_id });
+                }
+                Op::LoopMerge => {
+                    inst.expect_at_least(4)?;
+                    let merge_id = self.next()?;
+                    let continuing = self.next()?;
+                    for _ in 3..inst.wc {
+                        let _loop_control = self.next()?;
+                    }
+                    merge = Some(MergeInstruction::Loop {
+                        merge: merge_id,
+                        continuing,
+                    });
+                }
+                Op::Return => {
+                    inst.expect(1)?;
+                    break Terminator::Return { value: None };
+                }
+                Op::ReturnValue => {
+                    inst.expect(2)?;
+                    let value_id = self.next()?;
+                    let value = self.lookup_expression.lookup(value_id)?.handle;
+                    break Terminator::Return { value: Some(value) };
+                }
+                Op::Branch => {
+                    inst.expect(2)?;
+                    let target = self.next()?;
+                    break Terminator::Branch { target };
+                }
+                Op::BranchConditional => {
+                    inst.expect_at_least(4)?;
+                    let condition_id = self.next()?;
+                    let condition = self.lookup_expression.lookup(condition_id)?.handle;
+                    let true_target = self.next()?;
+                    let false_target = self.next()?;
+                    for _ in 4..inst.wc {
+                        let _weight = self.next()?;
+                    }
+                    break Terminator::BranchConditional {
+                        condition,
+                        true_target,
+                        false_target,
+                    };
+                }
+                Op::Switch => {
+                    inst.expect_at_least(3)?;
+                    let selector_id = self.next()?;
+                    let selector_lexp = self.lookup_expression.lookup(selector_id)?;
+                    let selector = selector_lexp.handle;
+                    let selector_type = self.lookup_type.lookup(selector_lexp.type_id)?.handle;
+                    match type_arena[selector_type].inner {
+                        crate::TypeInner::Scalar {
+                            kind: crate::ScalarKind::Sint,
+                            width: 4,
+                        }
+                        | crate::TypeInner::Scalar {
+                            kind: crate::ScalarKind::Uint,
+                            width: 4,
+                        } => (),
+                        _ => return Err(Error::UnsupportedType(selector_type)),
+                    }
+                    let default = self.next()?;
+                    if (inst.wc - 3) % 2 != 0 {
+                        return Err(Error::InvalidOperandCount(inst.op, inst.wc));
+                    }
+                    let mut targets = Vec::with_capacity((inst.wc as usize - 3) / 2);
+                    for _ in 0..targets.capacity() {
+                        let literal = self.next()?;
+                        let target = self.next()?;
+                        targets.push((literal as i32, target));
+                    }
+                    break Terminator::Switch {
+                        selector,
+                        default,
+                        targets,
+                    };
+                }
+                Op::Kill => {
+                    inst.expect(1)?;
+                    break Terminator::Kill;
+                }
+                Op::Unreachable => {
+                    inst.expect(1)?;
+                    break Terminator::Unreachable;
+                }
+                Op::Undef => {
+                    inst.expect(3)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    // An uninitialized variable can hold any value
+                    let var_handle = local_arena.append(crate::LocalVariable {
+                        name: None,
+                        ty: self.lookup_type.lookup(result_type_id)?.handle,
+                        init: None,
+                    });
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions
+                                .append(crate::Expression::LocalVariable(var_handle)),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::CopyObject => {
+                    inst.expect(4)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let operand_id = self.next()?;
+                    let handle = self.lookup_expression.lookup(operand_id)?.handle;
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle,
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::CompositeInsert => {
+                    inst.expect_at_least(6)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let object_id = self.next()?;
+                    let composite_id = self.next()?;
+                    let mut indices = Vec::with_capacity(inst.wc as usize - 5);
+                    for _ in 5..inst.wc {
+                        indices.push(self.next()?);
+                    }
+                    let object = self.lookup_expression.lookup(object_id)?.handle;
+                    let composite = self.lookup_expression.lookup(composite_id)?.handle;
+                    let handle = self.insert_composite(
+                        expressions,
+                        type_arena,
+                        composite,
+                        result_type_id,
+                        object,
+                        &indices,
+                    )?;
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle,
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::SNegate | Op::FNegate => {
+                    inst.expect(4)?;
+                    self.parse_expr_unary_op(expressions, crate::UnaryOperator::Negate)?;
+                }
+                Op::Not | Op::LogicalNot => {
+                    inst.expect(4)?;
+                    self.parse_expr_unary_op(expressions, crate::UnaryOperator::Not)?;
+                }
+                Op::IAdd | Op::FAdd => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::Add)?;
+                }
+                Op::ISub | Op::FSub => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::Subtract)?;
+                }
+                Op::IMul
+                | Op::FMul
+                | Op::VectorTimesScalar
+                | Op::MatrixTimesScalar
+                | Op::VectorTimesMatrix
+                | Op::MatrixTimesVector
+                | Op::MatrixTimesMatrix => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::Multiply)?;
+                }
+                Op::UDiv | Op::SDiv | Op::FDiv => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::Divide)?;
+                }
+                // the modulo of the IR takes the sign of the dividend,
+                // which only the remainder instructions do for negative operands
+                Op::UMod | Op::SRem | Op::FRem => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::Modulo)?;
+                }
+                Op::SMod => {
+                    // the remainder is moved to the sign of the divisor
+                    // by adding the divisor, if both signs differ
+                    inst.expect(5)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let p1_id = self.next()?;
+                    let p2_id = self.next()?;
+                    let left = self.lookup_expression.lookup(p1_id)?.handle;
+                    let right = self.lookup_expression.lookup(p2_id)?.handle;
+                    let zero =
+                        self.zero_expression(expressions, type_arena, const_arena, result_type_id)?;
+                    let mut binary = |op, left, right| {
+                        expressions.append(crate::Expression::Binary { op, left, right })
+                    };
+                    let remainder = binary(crate::BinaryOperator::Modulo, left, right);
+                    let nonzero = binary(crate::BinaryOperator::NotEqual, remainder, zero);
+                    let remainder_negative = binary(crate::BinaryOperator::Less, remainder, zero);
+                    let divisor_negative = binary(crate::BinaryOperator::Less, right, zero);
+                    let signs_differ = binary(
+                        crate::BinaryOperator::NotEqual,
+                        remainder_negative,
+                        divisor_negative,
+                    );
+                    let condition =
+                        binary(crate::BinaryOperator::LogicalAnd, nonzero, signs_differ);
+                    let moved = binary(crate::BinaryOperator::Add, remainder, right);
+                    let handle = expressions.append(crate::Expression::Select {
+                        condition,
+                        accept: moved,
+                        reject: remainder,
+                    });
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle,
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::FMod => {
+                    // `x - y * floor(x / y)`, taking the sign of the divisor
+                    inst.expect(5)?;
+                    self.parse_expr_lowered_op(expressions, |expressions, left, right| {
+                        let quotient = expressions.append(crate::Expression::Binary {
+                            op: crate::BinaryOperator::Divide,
+                            left,
+                            right,
+                        });
+                        let floor = expressions.append(crate::Expression::Call {
+                            origin: crate::FunctionOrigin::External("floor".to_string()),
+                            arguments: vec![quotient],
+                        });
+                        let product = expressions.append(crate::Expression::Binary {
+                            op: crate::BinaryOperator::Multiply,
+                            left: right,
+                            right: floor,
+                        });
+                        expressions.append(crate::Expression::Binary {
+                            op: crate::BinaryOperator::Subtract,
+                            left,
+                            right: product,
+                        })
+                    })?;
+                }
+                // the comparisons of the IR are false if an operand is NaN, except `NotEqual`,
+                // so the unordered ones are the negations of the opposite ordered comparisons
+                Op::FUnordEqual
+                | Op::FUnordLessThan
+                | Op::FUnordLessThanEqual
+                | Op::FUnordGreaterThan
+                | Op::FUnordGreaterThanEqual => {
+                    inst.expect(5)?;
+                    let opposite = match inst.op {
+                        Op::FUnordLessThan => Some(crate::BinaryOperator::GreaterEqual),
+                        Op::FUnordLessThanEqual => Some(crate::BinaryOperator::Greater),
+                        Op::FUnordGreaterThan => Some(crate::BinaryOperator::LessEqual),
+                        Op::FUnordGreaterThanEqual => Some(crate::BinaryOperator::Less),
+                        _ => None,
+                    };
+                    self.parse_expr_lowered_op(expressions, |expressions, left, right| {
+                        let ordered = match opposite {
+                            Some(op) => {
+                                expressions.append(crate::Expression::Binary { op, left, right })
+                            }
+                            None => ordered_not_equal(expressions, left, right),
+                        };
+                        expressions.append(crate::Expression::Unary {
+                            op: crate::UnaryOperator::Not,
+                            expr: ordered,
+                        })
+                    })?;
+                }
+                Op::FOrdNotEqual => {
+                    inst.expect(5)?;
+                    self.parse_expr_lowered_op(expressions, ordered_not_equal)?;
+                }
+                Op::IEqual | Op::FOrdEqual | Op::LogicalEqual => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::Equal)?;
+                }
+                Op::INotEqual | Op::FUnordNotEqual | Op::LogicalNotEqual => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::NotEqual)?;
+                }
+                Op::ULessThan | Op::SLessThan | Op::FOrdLessThan => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::Less)?;
+                }
+                Op::ULessThanEqual | Op::SLessThanEqual | Op::FOrdLessThanEqual => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::LessEqual)?;
+                }
+                Op::UGreaterThan | Op::SGreaterThan | Op::FOrdGreaterThan => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::Greater)?;
+                }
+                Op::UGreaterThanEqual | Op::SGreaterThanEqual | Op::FOrdGreaterThanEqual => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::GreaterEqual)?;
+                }
+                Op::LogicalAnd => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::LogicalAnd)?;
+                }
+                Op::LogicalOr => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::LogicalOr)?;
+                }
+                Op::BitwiseAnd => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::And)?;
+                }
+                Op::BitwiseOr => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::InclusiveOr)?;
+                }
+                Op::BitwiseXor => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(expressions, crate::BinaryOperator::ExclusiveOr)?;
+                }
+                Op::ShiftLeftLogical => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(
+                        expressions,
+                        crate::BinaryOperator::ShiftLeftLogical,
+                    )?;
+                }
+                Op::ShiftRightLogical => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(
+                        expressions,
+                        crate::BinaryOperator::ShiftRightLogical,
+                    )?;
+                }
+                Op::ShiftRightArithmetic => {
+                    inst.expect(5)?;
+                    self.parse_expr_binary_op(
+                        expressions,
+                        crate::BinaryOperator::ShiftRightArithmetic,
+                    )?;
+                }
+                Op::Any => {
+                    inst.expect(4)?;
+                    self.parse_expr_intrinsic(expressions, crate::IntrinsicFunction::Any)?;
+                }
+                Op::All => {
+                    inst.expect(4)?;
+                    self.parse_expr_intrinsic(expressions, crate::IntrinsicFunction::All)?;
+                }
+                Op::IsNan => {
+                    inst.expect(4)?;
+                    self.parse_expr_intrinsic(expressions, crate::IntrinsicFunction::IsNan)?;
+                }
+                Op::IsInf => {
+                    inst.expect(4)?;
+                    self.parse_expr_intrinsic(expressions, crate::IntrinsicFunction::IsInf)?;
+                }
+                Op::DPdx | Op::DPdxFine | Op::DPdxCoarse => {
+                    inst.expect(4)?;
+                    self.parse_expr_derivative(expressions, crate::DerivativeAxis::X)?;
+                }
+                Op::DPdy | Op::DPdyFine | Op::DPdyCoarse => {
+                    inst.expect(4)?;
+                    self.parse_expr_derivative(expressions, crate::DerivativeAxis::Y)?;
+                }
+                Op::Fwidth | Op::FwidthFine | Op::FwidthCoarse => {
+                    inst.expect(4)?;
+                    self.parse_expr_derivative(expressions, crate::DerivativeAxis::Width)?;
+                }
+                Op::Dot => {
+                    inst.expect(5)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let left_id = self.next()?;
+                    let right_id = self.next()?;
+                    let left = self.lookup_expression.lookup(left_id)?.handle;
+                    let right = self.lookup_expression.lookup(right_id)?.handle;
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions.append(crate::Expression::DotProduct(left, right)),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::Transpose => {
+                    inst.expect(4)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let matrix_id = self.next()?;
+                    let matrix = self.lookup_expression.lookup(matrix_id)?.handle;
+                    let expr = crate::Expression::Call {
+                        origin: crate::FunctionOrigin::External("transpose".to_string()),
+                        arguments: vec![matrix],
+                    };
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions.append(expr),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::Select => {
+                    inst.expect(6)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let condition_id = self.next()?;
+                    let accept_id = self.next()?;
+                    let reject_id = self.next()?;
+                    let expr = crate::Expression::Select {
+                        condition: self.lookup_expression.lookup(condition_id)?.handle,
+                        accept: self.lookup_expression.lookup(accept_id)?.handle,
+                        reject: self.lookup_expression.lookup(reject_id)?.handle,
+                    };
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions.append(expr),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::ConvertFToU | Op::ConvertFToS | Op::ConvertSToF | Op::ConvertUToF => {
+                    inst.expect(4)?;
+                    self.parse_expr_cast(expressions, type_arena, true)?;
+                }
+                // the IR can't change the width of a value
+                Op::UConvert | Op::SConvert | Op::FConvert => {
+                    return Err(Error::UnsupportedInstruction(self.state, inst.op));
+                }
+                Op::Bitcast => {
+                    inst.expect(4)?;
+                    self.parse_expr_cast(expressions, type_arena, false)?;
+                }
+                Op::SampledImage => {
+                    inst.expect(5)?;
+                    let _result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let image_id = self.next()?;
+                    let sampler_id = self.next()?;
+                    let image_lexp = self.lookup_expression.lookup(image_id)?;
+                    let sampler_lexp = self.lookup_expression.lookup(sampler_id)?;
+                    //TODO: compare the result type
+                    self.lookup_sampled_image.insert(
+                        result_id,
+                        LookupSampledImage {
+                            image: image_lexp.handle,
+                            sampler: sampler_lexp.handle,
+                        },
+                    );
+                }
+                Op::ImageSampleImplicitLod => {
+                    inst.expect_at_least(5)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let sampled_image_id = self.next()?;
+                    let coordinate_id = self.next()?;
+                    let si_lexp = self.lookup_sampled_image.lookup(sampled_image_id)?;
+                    let coord_lexp = self.lookup_expression.lookup(coordinate_id)?;
+                    let coord_type_handle = self.lookup_type.lookup(coord_lexp.type_id)?.handle;
+
+                    let sampler_type_handle =
+                        reach_global_type(si_lexp.sampler, &expressions, global_arena)
+                            .ok_or(Error::InvalidSamplerExpression(si_lexp.sampler))?;
+                    let image_type_handle =
+                        reach_global_type(si_lexp.image, &expressions, global_arena)
+                            .ok_or(Error::InvalidImageExpression(si_lexp.image))?;
+                    log::debug!(
+                        "\t\t\tImage {:?} with sampler {:?}",
+                        image_type_handle,
+                        sampler_type_handle
+                    );
+                    *self.handle_sampling.get_mut(&sampler_type_handle).unwrap() |=
+                        SamplingFlags::REGULAR;
+                    *self.handle_sampling.get_mut(&image_type_handle).unwrap() |=
+                        SamplingFlags::REGULAR;
+                    match type_arena[sampler_type_handle].inner {
+                        crate::TypeInner::Sampler { comparison: false } => (),
+                        _ => return Err(Error::InvalidSampleSampler(sampler_type_handle)),
+                    };
+                    match type_arena[image_type_handle].inner {
+                        //TODO: compare the result type
+                        crate::TypeInner::Image {
+                            base: _,
+                            dim,
+                            flags,
+                        } if flags
+                            & (crate::ImageFlags::MULTISAMPLED | crate::ImageFlags::SAMPLED)
+                            == crate::ImageFlags::SAMPLED =>
+                        {
+                            if !check_sample_coordinates(
+                                &type_arena[coord_type_handle],
+                                crate::ScalarKind::Float,
+                                dim,
+                                flags.contains(crate::ImageFlags::ARRAYED),
+                            ) {
+                                return Err(Error::InvalidSampleCoordinates(coord_type_handle));
+                            }
+                        }
+                        _ => return Err(Error::InvalidSampleImage(image_type_handle)),
+                    };
+
+                    let expr = crate::Expression::ImageSample {
+                        image: si_lexp.image,
+                        sampler: si_lexp.sampler,
+                        coordinate: coord_lexp.handle,
+                        depth_ref: None,
+                    };
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions.append(expr),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::ImageSampleDrefImplicitLod => {
+                    inst.expect_at_least(6)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let sampled_image_id = self.next()?;
+                    let coordinate_id = self.next()?;
+                    let dref_id = self.next()?;
+
+                    let si_lexp = self.lookup_sampled_image.lookup(sampled_image_id)?;
+                    let coord_lexp = self.lookup_expression.lookup(coordinate_id)?;
+                    let coord_type_handle = self.lookup_type.lookup(coord_lexp.type_id)?.handle;
+                    let sampler_type_handle =
+                        reach_global_type(si_lexp.sampler, &expressions, global_arena)
+                            .ok_or(Error::InvalidSamplerExpression(si_lexp.sampler))?;
+                    let image_type_handle =
+                        reach_global_type(si_lexp.image, &expressions, global_arena)
+                            .ok_or(Error::InvalidImageExpression(si_lexp.image))?;
+                    *self.handle_sampling.get_mut(&sampler_type_handle).unwrap() |=
+                        SamplingFlags::COMPARISON;
+                    *self.handle_sampling.get_mut(&image_type_handle).unwrap() |=
+                        SamplingFlags::COMPARISON;
+                    match type_arena[sampler_type_handle].inner {
+                        crate::TypeInner::Sampler { comparison: true } => (),
+                        _ => return Err(Error::InvalidSampleSampler(sampler_type_handle)),
+                    };
+                    match type_arena[image_type_handle].inner {
+                        //TODO: compare the result type
+                        crate::TypeInner::DepthImage { dim, arrayed } => {
+                            if !check_sample_coordinates(
+                                &type_arena[coord_type_handle],
+                                crate::ScalarKind::Float,
+                                dim,
+                                arrayed,
+                            ) {
+                                return Err(Error::InvalidSampleCoordinates(coord_type_handle));
+                            }
+                        }
+                        _ => return Err(Error::InvalidSampleImage(image_type_handle)),
+                    };
+
+                    let dref_lexp = self.lookup_expression.lookup(dref_id)?;
+                    let dref_type_handle = self.lookup_type.lookup(dref_lexp.type_id)?.handle;
+                    match type_arena[dref_type_handle].inner {
+                        crate::TypeInner::Scalar {
+                            kind: crate::ScalarKind::Float,
+                            width: _,
+                        } => (),
+                        _ => return Err(Error::InvalidDepthReference(dref_type_handle)),
+                    }
+
+                    let expr = crate::Expression::ImageSample {
+                        image: si_lexp.image,
+                        sampler: si_lexp.sampler,
+                        coordinate: coord_lexp.handle,
+                        depth_ref: Some(dref_lexp.handle),
+                    };
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions.append(expr),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::VectorShuffle => {
+                    inst.expect_at_least(5)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let v1_id = self.next()?;
+                    let v2_id = self.next()?;
+
+                    let v1_lexp = self.lookup_expression.lookup(v1_id)?;
+                    let v1_lty = self.lookup_type.lookup(v1_lexp.type_id)?;
+                    let n1 = match type_arena[v1_lty.handle].inner {
+                        crate::TypeInner::Vector { size, .. } => size as u8,
+                        _ => return Err(Error::InvalidInnerType(v1_lexp.type_id)),
+                    };
+                    let v1_handle = v1_lexp.handle;
+                    let v2_lexp = self.lookup_expression.lookup(v2_id)?;
+                    let v2_lty = self.lookup_type.lookup(v2_lexp.type_id)?;
+                    let n2 = match type_arena[v2_lty.handle].inner {
+                        crate::TypeInner::Vector { size, .. } => size as u8,
+                        _ => return Err(Error::InvalidInnerType(v2_lexp.type_id)),
+                    };
+                    let v2_handle = v2_lexp.handle;
+
+                    let mut components = Vec::with_capacity(inst.wc as usize - 5);
+                    for _ in 0..components.capacity() {
+                        let index = self.next()?;
+                        let expr = if index < n1 as u32 {
+                            crate::Expression::AccessIndex {
+                                base: v1_handle,
+                                index,
+                            }
+                        } else if index < n1 as u32 + n2 as u32 {
+                            crate::Expression::AccessIndex {
+                                base: v2_handle,
+                                index: index - n1 as u32,
+                            }
+                        } else {
+                            return Err(Error::InvalidAccessIndex(index));
+                        };
+                        components.push(expressions.append(expr));
+                    }
+                    let expr = crate::Expression::Compose {
+                        ty: self.lookup_type.lookup(result_type_id)?.handle,
+                        components,
+                    };
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions.append(expr),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::FunctionCall => {
+                    inst.expect_at_least(4)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let func_id = self.next()?;
+
+                    let mut arguments = Vec::with_capacity(inst.wc as usize - 4);
+                    for _ in 0..arguments.capacity() {
+                        let arg_id = self.next()?;
+                        arguments.push(self.lookup_expression.lookup(arg_id)?.handle);
+                    }
+                    let expr = crate::Expression::Call {
+                        // will be replaced by `Local()` after all the functions are parsed
+                        origin: crate::FunctionOrigin::External(String::new()),
+                        arguments,
+                    };
+                    let expr_handle = expressions.append(expr);
+                    local_function_calls.insert(expr_handle, func_id);
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expr_handle,
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                Op::ExtInst => {
+                    inst.expect_at_least(5)?;
+                    let result_type_id = self.next()?;
+                    let result_id = self.next()?;
+                    let set_id = self.next()?;
+                    if self.ignored_ext_sets.contains(&set_id) {
+                        for _ in 4..inst.wc {
+                            let _ = self.next()?;
+                        }
+                        continue;
+                    }
+                    if Some(set_id) != self.ext_glsl_id {
+                        return Err(Error::UnsupportedExtInstSet(set_id));
+                    }
+                    let inst_id = self.next()?;
+                    let name = match spirv::GLOp::from_u32(inst_id) {
+                        Some(spirv::GLOp::Floor) => {
+                            inst.expect(5 + 1)?;
+                            "floor"
+                        }
+                        Some(spirv::GLOp::Length) => {
+                            inst.expect(5 + 1)?;
+                            "length"
+                        }
+                        Some(spirv::GLOp::FMix) => {
+                            inst.expect(5 + 3)?;
+                            "mix"
+                        }
+                        _ => return Err(Error::UnsupportedExtInst(inst_id)),
+                    };
+
+                    let mut arguments = Vec::with_capacity(inst.wc as usize - 5);
+                    for _ in 0..arguments.capacity() {
+                        let arg_id = self.next()?;
+                        arguments.push(self.lookup_expression.lookup(arg_id)?.handle);
+                    }
+                    let expr = crate::Expression::Call {
+                        origin: crate::FunctionOrigin::External(name.to_string()),
+                        arguments,
+                    };
+                    self.lookup_expression.insert(
+                        result_id,
+                        LookupExpression {
+                            handle: expressions.append(expr),
+                            type_id: result_type_id,
+                        },
+                    );
+                }
+                // skipping an instruction in a function could lose a side effect,
+                // or a result used later: the non-semantic ones are already skipped
+                // by `next_inst`, or as instructions of an ignored set
+                _ => return Err(Error::UnsupportedInstruction(self.state, inst.op)),
+            }
+            if let Some(span) = self.current_span {
+                for (handle, _) in expressions.iter().skip(expression_count) {
+                    self.expression_spans.push((handle, span));
+                }
+            }
+        };
+        Ok(ControlFlowNode {
+            block,
+            merge,
+            terminator,
+        })
+    }
+
+    fn make_expression_storage(&mut self) -> Arena<crate::Expression> {
+        let mut expressions = Arena::new();
+        #[allow(clippy::panic)]
+        {
+            assert!(self.lookup_expression.is_empty());
+        }
+        // register global variables
+        for (&id, var) in self.lookup_variable.iter() {
+            self.lookup_expression.insert(
+                id,
+                LookupExpression {
+                    type_id: var.type_id,
+                    handle: expressions.append(crate::Expression::GlobalVariable(var.handle)),
+                },
+            );
+        }
+        // register constants
+        for (&id, con) in self.lookup_constant.iter() {
+            self.lookup_expression.insert(
+                id,
+                LookupExpression {
+                    type_id: con.type_id,
+                    handle: expressions.append(crate::Expression::Constant(con.handle)),
+                },
+            );
+        }
+        // done
+        expressions
+    }
+
+    fn switch(&mut self, state: ModuleState, op: spirv::Op) -> Result<(), Error> {
+        if state < self.state {
+            Err(Error::UnsupportedInstruction(self.state, op))
+        } else {
+            self.state = state;
+            Ok(())
+        }
+    }
+
+    pub fn parse(&mut self) -> Result<crate::Module, Error> {
+        let mut module = crate::Module::from_header({
+            if self.next()? != spirv::MAGIC_NUMBER {
+                return Err(Error::InvalidHeader);
+            }
+            let version_raw = self.next()?.to_le_bytes();
+            let generator = self.next()?;
+            let _bound = self.next()?;
+            let _schema = self.next()?;
+            crate::Header {
+                version: (version_raw[2], version_raw[1], version_raw[0]),
+                generator,
+            }
+        });
+        let mut entry_points = Vec::new();
+
+        while let Ok(inst) = self.next_inst() {
+            use spirv::Op;
+            log::debug!("\t{:?} [{}]", inst.op, inst.wc);
+            match inst.op {
+                Op::Capability => self.parse_capability(inst),
+                Op::Extension => self.parse_extension(inst),
+                Op::ExtInstImport => self.parse_ext_inst_import(inst),
+                Op::MemoryModel => self.parse_memory_model(inst),
+                Op::EntryPoint => self.parse_entry_point(inst, &mut entry_points),
+                Op::ExecutionMode => self.parse_execution_mode(inst),
+                Op::Source => self.parse_source(inst, &mut module),
+                Op::SourceContinued => self.parse_source_continued(inst, &mut module),
+                Op::String => self.parse_string(inst, &mut module),
+                Op::SourceExtension => self.parse_source_extension(inst),
+                Op::Name => self.parse_name(inst),
+                Op::MemberName => self.parse_member_name(inst),
+                Op::Decorate => self.parse_decorate(inst),
+                Op::MemberDecorate => self.parse_member_decorate(inst),
+                Op::DecorationGroup => self.parse_decoration_group(inst),
+                Op::GroupDecorate => self.parse_group_decorate(inst),
+                Op::GroupMemberDecorate => self.parse_group_member_decorate(inst),
+                Op::TypeVoid => self.parse_type_void(inst),
+                Op::TypeBool => self.parse_type_bool(inst, &mut module),
+                Op::TypeInt => self.parse_type_int(inst, &mut module),
+                Op::TypeFloat => self.parse_type_float(inst, &mut module),
+                Op::TypeVector => self.parse_type_vector(inst, &mut module),
+                Op::TypeMatrix => self.parse_type_matrix(inst, &mut module),
+                Op::TypeFunction => self.parse_type_function(inst),
+                Op::TypePointer => self.parse_type_pointer(inst, &mut module),
+                Op::TypeArray => self.parse_type_array(inst, &mut module),
+                Op::TypeRuntimeArray => self.parse_type_runtime_array(inst, &mut module),
+                Op::TypeStruct => self.parse_type_struct(inst, &mut module),
+                Op::TypeImage => self.parse_type_image(inst, &mut module),
+                Op::TypeSampledImage => self.parse_type_sampled_image(inst),
+                Op::TypeSampler => self.parse_type_sampler(inst, &mut module),
+                Op::Constant | Op::SpecConstant => self.parse_constant(inst, &mut module),
+                Op::ConstantComposite => self.parse_composite_constant(inst, &mut module),
+                Op::ConstantTrue
+                | Op::ConstantFalse
+                | Op::SpecConstantTrue
+                | Op::SpecConstantFalse => self.parse_bool_constant(inst, &mut module),
+                Op::Variable => self.parse_global_variable(inst, &mut module),
+                Op::Function => self.parse_function(inst, &mut module),
+                Op::ExtInst => self.parse_global_ext_inst(inst),
+                _ => self
+                    .check(Error::UnsupportedInstruction(self.state, inst.op))
+                    .and_then(|()| self.skip(inst)),
+            }?;
+        }
+
+        // Check all the images and samplers to have consistent comparison property.
+        for (handle, flags) in self.handle_sampling.drain() {
+            if !flags.contains(SamplingFlags::COMPARISON) {
+                continue;
+            }
+            if flags == SamplingFlags::all() {
+                return Err(Error::InconsistentComparisonSampling(handle));
+            }
+            let ty = module.types.get_mut(handle);
+            match ty.inner {
+                crate::TypeInner::Sampler { ref mut comparison } => {
+                    #[allow(clippy::panic)]
+                    {
+                        assert!(!*comparison)
+                    };
+                    *comparison = true;
+                }
+                crate::TypeInner::Image {
+                    base: _,
+                    dim,
+                    flags,
+                } => {
+                    ty.inner = crate::TypeInner::DepthImage {
+                        dim,
+                        arrayed: flags.contains(crate::ImageFlags::ARRAYED),
+                    };
+                }
+                _ => panic!("Unexpected comparison type {:?}", ty),
+            }
+        }
+
+        for dfc in self.deferred_function_calls.drain(..) {
+            let dst_handle = *self.lookup_function.lookup(dfc.dst_id)?;
+            match *module
+                .functions
+                .get_mut(dfc.source_handle)
+                .expressions
+                .get_mut(dfc.expr_handle)
+            {
+                crate::Expression::Call {
+                    ref mut origin,
+                    arguments: _,
+                } => *origin = crate::FunctionOrigin::Local(dst_handle),
+                _ => unreachable!(),
+            }
+        }
+
+        if !self.future_decor.is_empty() {
+            log::warn!("Unused item decorations: {:?}", self.future_decor);
+            self.future_decor.clear();
+        }
+        if !self.future_member_decor.is_empty() {
+            log::warn!("Unused member decorations: {:?}", self.future_member_decor);
+            self.future_member_decor.clear();
+        }
+
+        module.entry_points.reserve(entry_points.len());
+        for raw in entry_points {
+            module.entry_points.push(crate::EntryPoint {
+                stage: match raw.exec_model {
+                    spirv::ExecutionModel::Vertex => crate::ShaderStage::Vertex,
+                    spirv::ExecutionModel::Fragment => crate::ShaderStage::Fragment,
+                    spirv::ExecutionModel::GLCompute => crate::ShaderStage::Compute,
+                    other => return Err(Error::UnsupportedExecutionModel(other as u32)),
+                },
+                name: raw.name,
+                function: *self.lookup_function.lookup(raw.function_id)?,
+            });
+        }
+
+        Ok(module)
+    }
+
+    fn parse_capability(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Capability, inst.op)?;
+        inst.expect(2)?;
+        let capability = self.next()?;
+        match spirv::Capability::from_u32(capability) {
+            Some(cap) if SUPPORTED_CAPABILITIES.contains(&cap) => Ok(()),
+            Some(cap) => self.check(Error::UnsupportedCapability(cap)),
+            None => self.check(Error::UnknownCapability(capability)),
+        }
+    }
+
+    fn parse_extension(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Extension, inst.op)?;
+        inst.expect_at_least(2)?;
+        let (name, left) = self.next_string(inst.wc - 1)?;
+        if left != 0 {
+            return Err(Error::InvalidOperand);
+        }
+        if !SUPPORTED_EXTENSIONS.contains(&name.as_str()) {
+            self.check(Error::UnsupportedExtension(name))?;
+        }
+        Ok(())
+    }
+
+    fn parse_ext_inst_import(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Extension, inst.op)?;
+        inst.expect_at_least(3)?;
+        let result_id = self.next()?;
+        let (name, left) = self.next_string(inst.wc - 2)?;
+        if left != 0 {
+            return Err(Error::InvalidOperand);
+        }
+        if SUPPORTED_EXT_SETS.contains(&name.as_str()) {
+            self.ext_glsl_id = Some(result_id);
+        } else {
+            self.check(Error::UnsupportedExtSet(name))?;
+            self.ignored_ext_sets.insert(result_id);
+        }
+        Ok(())
+    }
+
+    fn parse_global_ext_inst(&mut self, inst: Instruction) -> Result<(), Error> {
+        inst.expect_at_least(5)?;
+        let _result_type_id = self.next()?;
+        let _result_id = self.next()?;
+        let set_id = self.next()?;
+        if !self.ignored_ext_sets.contains(&set_id) {
+            return Err(Error::UnsupportedInstruction(self.state, inst.op));
+        }
+        for _ in 4..inst.wc {
+            let _ = self.next()?;
+        }
+        Ok(())
+    }
+
+    fn parse_memory_model(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::MemoryModel, inst.op)?;
+        inst.expect(3)?;
+        let _addressing_model = self.next()?;
+        let _memory_model = self.next()?;
+        Ok(())
+    }
+
+    fn parse_entry_point(
+        &mut self,
+        inst: Instruction,
+        entry_points: &mut Vec<EntryPoint>,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::EntryPoint, inst.op)?;
+        inst.expect_at_least(4)?;
+        let exec_model = self.next()?;
+        let exec_model = spirv::ExecutionModel::from_u32(exec_model)
+            .ok_or(Error::UnsupportedExecutionModel(exec_model))?;
+        let function_id = self.next()?;
+        let (name, left) = self.next_string(inst.wc - 3)?;
+        let ep = EntryPoint {
+            exec_model,
+            name,
+            function_id,
+            variable_ids: self.data.by_ref().take(left as usize).collect(),
+        };
+        entry_points.push(ep);
+        Ok(())
+    }
+
+    fn parse_execution_mode(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::ExecutionMode, inst.op)?;
+        inst.expect_at_least(3)?;
+        let _ep_id = self.next()?;
+        let _mode = self.next()?;
+        for _ in 3..inst.wc {
+            let _ = self.next()?; //TODO
+        }
+        Ok(())
+    }
+
+    fn parse_source(&mut self, inst: Instruction, module: &mut crate::Module) -> Result<(), Error> {
+        self.switch(ModuleState::Source, inst.op)?;
+        inst.expect_at_least(3)?;
+        let _language = self.next()?;
+        let _version = self.next()?;
+        if inst.wc > 3 {
+            let file_id = self.next()?;
+            let file = *self.lookup_source_file.lookup(file_id)?;
+            if inst.wc > 4 {
+                let (text, left) = self.next_string(inst.wc - 4)?;
+                if left != 0 {
+                    return Err(Error::InvalidOperand);
+                }
+                module.spans.files.get_mut(file).text = Some(text);
+            }
+            self.last_source_file = Some(file);
+        }
+        Ok(())
+    }
+
+    fn parse_source_continued(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Source, inst.op)?;
+        inst.expect_at_least(2)?;
+        let (text, left) = self.next_string(inst.wc - 1)?;
+        if left != 0 {
+            return Err(Error::InvalidOperand);
+        }
+        let file = self.last_source_file.ok_or(Error::InvalidOperand)?;
+        module
+            .spans
+            .files
+            .get_mut(file)
+            .text
+            .get_or_insert_with(String::new)
+            .push_str(&text);
+        Ok(())
+    }
+
+    fn parse_string(&mut self, inst: Instruction, module: &mut crate::Module) -> Result<(), Error> {
+        self.switch(ModuleState::Source, inst.op)?;
+        inst.expect_at_least(3)?;
+        let id = self.next()?;
+        let (name, left) = self.next_string(inst.wc - 2)?;
+        if left != 0 {
+            return Err(Error::InvalidOperand);
+        }
+        let file = module.spans.files.append(crate::SourceFile {
+            name: Some(name),
+            text: None,
+        });
+        self.lookup_source_file.insert(id, file);
+        Ok(())
+    }
+
+    fn parse_source_extension(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Source, inst.op)?;
+        inst.expect_at_least(2)?;
+        let (_name, _) = self.next_string(inst.wc - 1)?;
+        Ok(())
+    }
+
+    fn parse_name(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Name, inst.op)?;
+        inst.expect_at_least(3)?;
+        let id = self.next()?;
+        let (name, left) = self.next_string(inst.wc - 2)?;
+        if left != 0 {
+            return Err(Error::InvalidOperand);
+        }
+        self.future_decor.entry(id).or_default().name = Some(name);
+        Ok(())
+    }
+
+    fn parse_member_name(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Name, inst.op)?;
+        inst.expect_at_least(4)?;
+        let id = self.next()?;
+        let member = self.next()?;
+        let (name, left) = self.next_string(inst.wc - 3)?;
+        if left != 0 {
+            return Err(Error::InvalidOperand);
+        }
+        self.future_member_decor
+            .entry((id, member))
+            .or_default()
+            .name = Some(name);
+        Ok(())
+    }
+
+    fn parse_decorate(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Annotation, inst.op)?;
+        inst.expect_at_least(3)?;
+        let id = self.next()?;
+        let mut dec = self.future_decor.remove(&id).unwrap_or_default();
+        self.next_decoration(inst, 2, &mut dec)?;
+        self.future_decor.insert(id, dec);
+        Ok(())
+    }
+
+    fn parse_member_decorate(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Annotation, inst.op)?;
+        inst.expect_at_least(4)?;
+        let id = self.next()?;
+        let member = self.next()?;
+        let mut dec = self
+            .future_member_decor
+            .remove(&(id, member))
+            .unwrap_or_default();
+        self.next_decoration(inst, 3, &mut dec)?;
+        self.future_member_decor.insert((id, member), dec);
+        Ok(())
+    }
+
+    fn parse_decoration_group(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Annotation, inst.op)?;
+        inst.expect(2)?;
+        let _group_id = self.next()?;
+        // The decorations are already collected under the group ID.
+        Ok(())
+    }
+
+    fn parse_group_decorate(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Annotation, inst.op)?;
+        inst.expect_at_least(2)?;
+        let group_id = self.next()?;
+        let group = self
+            .future_decor
+            .get(&group_id)
+            .cloned()
+            .ok_or(Error::InvalidId(group_id))?;
+        for _ in 2..inst.wc {
+            let id = self.next()?;
+            self.future_decor.entry(id).or_default().inherit(&group);
+        }
+        Ok(())
+    }
+
+    fn parse_group_member_decorate(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Annotation, inst.op)?;
+        inst.expect_at_least(2)?;
+        let group_id = self.next()?;
+        let group = self
+            .future_decor
+            .get(&group_id)
+            .cloned()
+            .ok_or(Error::InvalidId(group_id))?;
+        for _ in 0..(inst.wc - 2) / 2 {
+            let id = self.next()?;
+            let member = self.next()?;
+            self.future_member_decor
+                .entry((id, member))
+                .or_default()
+                .inherit(&group);
+        }
+        Ok(())
+    }
+
+    fn parse_type_void(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(2)?;
+        let id = self.next()?;
+        self.lookup_void_type.insert(id);
+        Ok(())
+    }
+
+    fn parse_type_bool(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(2)?;
+        let id = self.next()?;
+        let inner = crate::TypeInner::Scalar {
+            kind: crate::ScalarKind::Bool,
+            width: 1,
+        };
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: module.types.append(crate::Type {
+                    name: self.future_decor.remove(&id).and_then(|dec| dec.name),
+                    inner,
+                }),
+                base_id: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_int(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(4)?;
+        let id = self.next()?;
+        let width = self.next()?;
+        let sign = self.next()?;
+        let inner = crate::TypeInner::Scalar {
+            kind: match sign {
+                0 => crate::ScalarKind::Uint,
+                1 => crate::ScalarKind::Sint,
+                _ => return Err(Error::InvalidSign(sign)),
+            },
+            width: map_width(width)?,
+        };
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: module.types.append(crate::Type {
+                    name: self.future_decor.remove(&id).and_then(|dec| dec.name),
+                    inner,
+                }),
+                base_id: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_float(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(3)?;
+        let id = self.next()?;
+        let width = self.next()?;
+        let inner = crate::TypeInner::Scalar {
+            kind: crate::ScalarKind::Float,
+            width: map_width(width)?,
+        };
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: module.types.append(crate::Type {
+                    name: self.future_decor.remove(&id).and_then(|dec| dec.name),
+                    inner,
+                }),
+                base_id: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_vector(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(4)?;
+        let id = self.next()?;
+        let type_id = self.next()?;
+        let type_lookup = self.lookup_type.lookup(type_id)?;
+        let (kind, width) = match module.types[type_lookup.handle].inner {
+            crate::TypeInner::Scalar { kind, width } => (kind, width),
+            _ => return Err(Error::InvalidInnerType(type_id)),
+        };
+        let component_count = self.next()?;
+        let inner = crate::TypeInner::Vector {
+            size: map_vector_size(component_count)?,
+            kind,
+            width,
+        };
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: module.types.append(crate::Type {
+                    name: self.future_decor.remove(&id).and_then(|dec| dec.name),
+                    inner,
+                }),
+                base_id: Some(type_id),
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_matrix(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(4)?;
+        let id = self.next()?;
+        let vector_type_id = self.next()?;
+        let num_columns = self.next()?;
+        let vector_type_lookup = self.lookup_type.lookup(vector_type_id)?;
+        let inner = match module.types[vector_type_lookup.handle].inner {
+            crate::TypeInner::Vector { size, kind, width } => crate::TypeInner::Matrix {
+                columns: map_vector_size(num_columns)?,
+                rows: size,
+                kind,
+                width,
+            },
+            _ => return Err(Error::InvalidInnerType(vector_type_id)),
+        };
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: module.types.append(crate::Type {
+                    name: self.future_decor.remove(&id).and_then(|dec| dec.name),
+                    inner,
+                }),
+                base_id: Some(vector_type_id),
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_function(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect_at_least(3)?;
+        let id = self.next()?;
+        let return_type_id = self.next()?;
+        let parameter_type_ids = self.data.by_ref().take(inst.wc as usize - 3).collect();
+        self.lookup_function_type.insert(
+            id,
+            LookupFunctionType {
+                parameter_type_ids,
+                return_type_id,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_pointer(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(4)?;
+        let id = self.next()?;
+        let storage = self.next()?;
+        let type_id = self.next()?;
+        let inner = crate::TypeInner::Pointer {
+            base: self.lookup_type.lookup(type_id)?.handle,
+            class: map_storage_class(storage)?,
+        };
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: module.types.append(crate::Type {
+                    name: self.future_decor.remove(&id).and_then(|dec| dec.name),
+                    inner,
+                }),
+                base_id: Some(type_id),
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_array(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(4)?;
+        let id = self.next()?;
+        let type_id = self.next()?;
+        let length_id = self.next()?;
+
+        let length_handle = self.lookup_constant.lookup(length_id)?.handle;
+        let length = match module.constants[length_handle].inner {
+            crate::ConstantInner::Uint(value) if value > 0 => value as u32,
+            crate::ConstantInner::Sint(value) if value > 0 => value as u32,
+            _ => return Err(Error::InvalidArraySize(length_handle)),
+        };
+
+        let decor = self.future_decor.remove(&id);
+        let inner = crate::TypeInner::Array {
+            base: self.lookup_type.lookup(type_id)?.handle,
+            size: crate::ArraySize::Static(length),
+            stride: decor.as_ref().and_then(|dec| dec.array_stride),
+        };
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: module.types.append(crate::Type {
+                    name: decor.and_then(|dec| dec.name),
+                    inner,
+                }),
+                base_id: Some(type_id),
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_runtime_array(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(3)?;
+        let id = self.next()?;
+        let type_id = self.next()?;
+
+        let decor = self.future_decor.remove(&id);
+        let inner = crate::TypeInner::Array {
+            base: self.lookup_type.lookup(type_id)?.handle,
+            size: crate::ArraySize::Dynamic,
+            stride: decor.as_ref().and_then(|dec| dec.array_stride),
+        };
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: module.types.append(crate::Type {
+                    name: decor.and_then(|dec| dec.name),
+                    inner,
+                }),
+                base_id: Some(type_id),
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_struct(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect_at_least(2)?;
+        let id = self.next()?;
+        let decor = self.future_decor.remove(&id).unwrap_or_default();
+        let mut access = crate::StorageAccess::empty();
+        let mut members = Vec::with_capacity(inst.wc as usize - 2);
+        for i in 0..u32::from(inst.wc) - 2 {
+            let type_id = self.next()?;
+            let ty = self.lookup_type.lookup(type_id)?.handle;
+            self.lookup_member_type_id.insert((id, i), type_id);
+            let decor = self
+                .future_member_decor
+                .remove(&(id, i))
+                .unwrap_or_default();
+            let origin = decor.get_origin()?;
+            access |= decor.storage_access();
+            if let Some(stride) = decor.matrix_stride {
+                // the IR has no column stride, so it must be the one of a layout rule
+                let mut member_ty = ty;
+                while let crate::TypeInner::Array { base, .. } = module.types[member_ty].inner {
+                    member_ty = base;
+                }
+                let valid = match module.types[member_ty].inner {
+                    crate::TypeInner::Matrix {
+                        columns,
+                        rows,
+                        width,
+                        ..
+                    } => {
+                        // the stride of a row-major matrix separates its rows
+                        let size = if decor.row_major { columns } else { rows };
+                        [
+                            crate::proc::LayoutRule::Std140,
+                            crate::proc::LayoutRule::Std430,
+                        ]
+                        .iter()
+                        .any(|rule| rule.matrix_stride(size, width) == stride)
+                    }
+                    _ => false,
+                };
+                if !valid {
+                    return Err(Error::InvalidMatrixStride(stride));
+                }
+            }
+            members.push(crate::StructMember {
+                name: decor.name,
+                origin,
+                ty,
+            });
+        }
+        if members.is_empty() {
+            access = crate::StorageAccess::all();
+        }
+        let inner = crate::TypeInner::Struct { members };
+        let handle = module.types.append(crate::Type {
+            name: decor.name,
+            inner,
+        });
+        if let Some(block) = decor.block {
+            self.lookup_block.insert(
+                handle,
+                LookupBlock {
+                    buffer: block.buffer,
+                    access,
+                },
+            );
+        }
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle,
+                base_id: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_image(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect_at_least(9)?;
+
+        let id = self.next()?;
+        let sample_type_id = self.next()?;
+        let dim = self.next()?;
+        let mut flags = crate::ImageFlags::empty();
+        let _is_depth = self.next()?;
+        if self.next()? != 0 {
+            flags |= crate::ImageFlags::ARRAYED;
+        }
+        if self.next()? != 0 {
+            flags |= crate::ImageFlags::MULTISAMPLED;
+        }
+        let is_sampled = self.next()?;
+        if is_sampled != 0 {
+            flags |= crate::ImageFlags::SAMPLED;
+        }
+        let _format = self.next()?;
+        if inst.wc > 9 {
+            inst.expect(10)?;
+            let access = self.next()?;
+            if access == 0 || access == 2 {
+                flags |= crate::ImageFlags::CAN_LOAD;
+            }
+            if access == 1 || access == 2 {
+                flags |= crate::ImageFlags::CAN_STORE;
+            }
+        };
+
+        let decor = self.future_decor.remove(&id).unwrap_or_default();
+
+        let inner = crate::TypeInner::Image {
+            base: self.lookup_type.lookup(sample_type_id)?.handle,
+            dim: map_image_dim(dim)?,
+            flags,
+        };
+        let handle = module.types.append(crate::Type {
+            name: decor.name,
+            inner,
+        });
+        log::debug!("\t\ttracking {:?} for sampling properties", handle);
+        self.handle_sampling.insert(handle, SamplingFlags::empty());
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle,
+                base_id: Some(sample_type_id),
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_sampled_image(&mut self, inst: Instruction) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(3)?;
+        let id = self.next()?;
+        let image_id = self.next()?;
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle: self.lookup_type.lookup(image_id)?.handle,
+                base_id: Some(image_id),
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_type_sampler(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(2)?;
+        let id = self.next()?;
+        let decor = self.future_decor.remove(&id).unwrap_or_default();
+        // The comparison bit is temporary, will be overwritten based on the
+        // accumulated sampling flags at the end.
+        let inner = crate::TypeInner::Sampler { comparison: false };
+        let handle = module.types.append(crate::Type {
+            name: decor.name,
+            inner,
+        });
+        log::debug!("\t\ttracking {:?} for sampling properties", handle);
+        self.handle_sampling.insert(handle, SamplingFlags::empty());
+        self.lookup_type.insert(
+            id,
+            LookupType {
+                handle,
+                base_id: None,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_constant(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect_at_least(3)?;
+        let type_id = self.next()?;
+        let id = self.next()?;
+        let type_lookup = self.lookup_type.lookup(type_id)?;
+        let ty = type_lookup.handle;
+        let inner = match module.types[type_lookup.handle].inner {
+            crate::TypeInner::Scalar {
+                kind: crate::ScalarKind::Uint,
+                width,
+            } => {
+                let low = self.next()?;
+                let high = if width > 4 {
+                    inst.expect(4)?;
+                    self.next()?
+                } else {
+                    0
+                };
+                crate::ConstantInner::Uint((u64::from(high) << 32) | u64::from(low))
+            }
+            crate::TypeInner::Scalar {
+                kind: crate::ScalarKind::Sint,
+                width,
+            } => {
+                use std::cmp::Ordering;
+                let low = self.next()?;
+                let value = match width.cmp(&4) {
+                    Ordering::Less => return Err(Error::InvalidTypeWidth(u32::from(width))),
+                    Ordering::Greater => {
+                        inst.expect(4)?;
+                        let high = self.next()?;
+                        ((u64::from(high) << 32) | u64::from(low)) as i64
+                    }
+                    // narrower values are sign-extended
+                    Ordering::Equal => i64::from(low as i32),
+                };
+                crate::ConstantInner::Sint(value)
+            }
+            crate::TypeInner::Scalar {
+                kind: crate::ScalarKind::Float,
+                width,
+            } => {
+                let low = self.next()?;
+                let extended = match width {
+                    4 => f64::from(f32::from_bits(low)),
+                    8 => {
+                        inst.expect(4)?;
+                        let high = self.next()?;
+                        f64::from_bits((u64::from(high) << 32) | u64::from(low))
+                    }
+                    _ => return Err(Error::InvalidTypeWidth(u32::from(width))),
+                };
+                crate::ConstantInner::Float(extended)
+            }
+            _ => return Err(Error::UnsupportedType(type_lookup.handle)),
+        };
+        let dec = self.future_decor.remove(&id).unwrap_or_default();
+        self.lookup_constant.insert(
+            id,
+            LookupConstant {
+                handle: module.constants.append(crate::Constant {
+                    name: dec.name,
+                    specialization: dec.specialization,
+                    inner,
+                    ty,
+                }),
+                type_id,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_composite_constant(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect_at_least(3)?;
+        let type_id = self.next()?;
+        let type_lookup = self.lookup_type.lookup(type_id)?;
+        let ty = type_lookup.handle;
+
+        let id = self.next()?;
+
+        let constituents_count = inst.wc - 3;
+        let mut constituents = Vec::with_capacity(constituents_count as usize);
+        for _ in 0..constituents_count {
+            let constituent_id = self.next()?;
+            let constant = self.lookup_constant.lookup(constituent_id)?;
+            constituents.push(constant.handle);
+        }
+
+        self.lookup_constant.insert(
+            id,
+            LookupConstant {
+                handle: module.constants.append(crate::Constant {
+                    name: self.future_decor.remove(&id).and_then(|dec| dec.name),
+                    specialization: None,
+                    inner: crate::ConstantInner::Composite(constituents),
+                    ty,
+                }),
+                type_id,
+            },
+        );
+
+        Ok(())
+    }
+
+    fn parse_bool_constant(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect(3)?;
+        let type_id = self.next()?;
+        let id = self.next()?;
+        let type_lookup = self.lookup_type.lookup(type_id)?;
+        let ty = type_lookup.handle;
+        match module.types[ty].inner {
+            crate::TypeInner::Scalar {
+                kind: crate::ScalarKind::Bool,
+                ..
+            } => (),
+            _ => return Err(Error::UnsupportedType(ty)),
+        }
+
+        let dec = self.future_decor.remove(&id).unwrap_or_default();
+        let value = matches!(
+            inst.op,
+            spirv::Op::ConstantTrue | spirv::Op::SpecConstantTrue
+        );
+        self.lookup_constant.insert(
+            id,
+            LookupConstant {
+                handle: module.constants.append(crate::Constant {
+                    name: dec.name,
+                    specialization: dec.specialization,
+                    inner: crate::ConstantInner::Bool(value),
+                    ty,
+                }),
+                type_id,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_global_variable(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Type, inst.op)?;
+        inst.expect_at_least(4)?;
+        let type_id = self.next()?;
+        let id = self.next()?;
+        let storage = self.next()?;
+        if inst.wc != 4 {
+            inst.expect(5)?;
+            let _init = self.next()?; //TODO
+        }
+        let lookup_type = self.lookup_type.lookup(type_id)?;
+        let dec = self
+            .future_decor
+            .remove(&id)
+            .ok_or(Error::InvalidBinding(id))?;
+        let (ty, binding) = match module.types[lookup_type.handle].inner {
+            crate::TypeInner::Pointer { base, class } => {
+                let binding = match (class, &module.types[base].inner) {
+                    (crate::StorageClass::Input, &crate::TypeInner::Struct { .. })
+                    | (crate::StorageClass::Output, &crate::TypeInner::Struct { .. }) => None,
+                    _ => Some(dec.get_binding().ok_or(Error::InvalidBinding(id))?),
+                };
+                (base, binding)
+            }
+            _ => return Err(Error::UnsupportedType(lookup_type.handle)),
+        };
+        let block = self.lookup_block.get(&ty);
+        let class = match (map_storage_class(storage)?, block) {
+            // Storage buffers used to be declared as uniforms before SPIR-V 1.3
+            (crate::StorageClass::Uniform, Some(&LookupBlock { buffer: true, .. })) => {
+                crate::StorageClass::StorageBuffer
+            }
+            (class, _) => class,
+        };
+        let storage_access = match module.types[ty].inner {
+            crate::TypeInner::Struct { .. } if class == crate::StorageClass::StorageBuffer => {
+                block.map_or(crate::StorageAccess::all(), |block| block.access)
+                    & dec.storage_access()
+            }
+            crate::TypeInner::Image { flags, .. }
+                if flags.contains(crate::ImageFlags::CAN_STORE) =>
+            {
+                dec.storage_access()
+            }
+            _ => crate::StorageAccess::empty(),
+        };
+
+        let var = crate::GlobalVariable {
+            name: dec.name,
+            class,
+            binding,
+            ty,
+            interpolation: dec.interpolation.or_else(|| match class {
+                crate::StorageClass::Input | crate::StorageClass::Output => {
+                    Some(crate::Interpolation::Perspective)
+                }
+                _ => None,
+            }),
+            storage_access,
+        };
+        self.lookup_variable.insert(
+            id,
+            LookupVariable {
+                handle: module.global_variables.append(var),
+                type_id,
+            },
+        );
+        Ok(())
+    }
+
+    fn parse_function(
+        &mut self,
+        inst: Instruction,
+        module: &mut crate::Module,
+    ) -> Result<(), Error> {
+        self.switch(ModuleState::Function, inst.op)?;
+        inst.expect(5)?;
+        let result_type = self.next()?;
+        let fun_id = self.next()?;
+        let _fun_control = self.next()?;
+        let fun_type = self.next()?;
+        let mut span = self.current_span;
+        let mut fun = {
+            let ft = self.lookup_function_type.lookup(fun_type)?;
+            if ft.return_type_id != result_type {
+                return Err(Error::WrongFunctionResultType(result_type));
+            }
+            crate::Function {
+                name: self.future_decor.remove(&fun_id).and_then(|dec| dec.name),
+                parameter_types: Vec::with_capacity(ft.parameter_type_ids.len()),
+                parameter_names: Vec::with_capacity(ft.parameter_type_ids.len()),
+                return_type: if self.lookup_void_type.contains(&result_type) {
+                    None
+                } else {
+                    Some(self.lookup_type.lookup(result_type)?.handle)
+                },
+                global_usage: Vec::new(),
+                local_variables: Arena::new(),
+                expressions: self.make_expression_storage(),
+                body: Vec::new(),
+            }
+        };
+        // read parameters
+        for i in 0..fun.parameter_types.capacity() {
+            match self.next_inst()? {
+                Instruction {
+                    op: spirv::Op::FunctionParameter,
+                    wc: 3,
+                } => {
+                    let type_id = self.next()?;
+                    let id = self.next()?;
+                    //Note: we redo the lookup in order to work around `self` borrowing
+                    if type_id
+                        != self
+                            .lookup_function_type
+                            .lookup(fun_type)?
+                            .parameter_type_ids[i]
+                    {
+                        return Err(Error::WrongFunctionParameterType(type_id));
+                    }
+                    let ty = self.lookup_type.lookup(type_id)?.handle;
+                    fun.parameter_types.push(ty);
+                    fun.parameter_names
+                        .push(self.future_decor.remove(&id).and_then(|dec| dec.name));
+                    let handle = fun
+                        .expressions
+                        .append(crate::Expression::FunctionParameter(i as u32));
+                    self.lookup_expression
+                        .insert(id, LookupExpression { handle, type_id });
+                }
+                Instruction { op, .. } => return Err(Error::InvalidParameter(op)),
+            }
+        }
+        // read body
+        let mut local_function_calls = FastHashMap::default();
+        let mut flow_graph = FlowGraph::default();
+        let mut phis = Vec::new();
+        loop {
+            let fun_inst = self.next_inst()?;
+            log::debug!("\t\t{:?}", fun_inst.op);
+            match fun_inst.op {
+                spirv::Op::Label => {
+                    fun_inst.expect(2)?;
+                    let label_id = self.next()?;
+                    let node = self.next_block(
+                        &mut fun.expressions,
+                        &mut fun.local_variables,
+                        &module.types,
+                        &mut module.constants,
+                        &module.global_variables,
+                        &mut local_function_calls,
+                        &mut phis,
+                    )?;
+                    flow_graph.add_node(label_id, node);
+                    // the line information doesn't extend past the end of a block
+                    self.current_span = None;
+                }
+                spirv::Op::FunctionEnd => {
+                    fun_inst.expect(1)?;
+                    break;
+                }
+                _ => return Err(Error::UnsupportedInstruction(self.state, fun_inst.op)),
+            }
+        }
+        // phi values are assigned at the end of the predecessor blocks
+        for phi in phis {
+            for (value_id, parent_id) in phi.incoming {
+                let value = self.lookup_expression.lookup(value_id)?.handle;
+                flow_graph.append_to(
+                    parent_id,
+                    crate::Statement::Store {
+                        pointer: phi.pointer,
+                        value,
+                    },
+                )?;
+            }
+        }
+        fun.body = flow_graph.finish(flow::Arenas {
+            expressions: &mut fun.expressions,
+            local_variables: &mut fun.local_variables,
+            types: &mut module.types,
+            constants: &mut module.constants,
+        })?;
+        // done
+        fun.global_usage =
+            crate::GlobalUse::scan(&fun.expressions, &fun.body, &module.global_variables);
+        let handle = module.functions.append(fun);
+        if span.is_none() {
+            span = self.expression_spans.first().map(|&(_, span)| span);
+        }
+        if let Some(span) = span {
+            module.spans.functions.insert(handle, span);
+        }
+        for (expr_handle, span) in self.expression_spans.drain(..) {
+            module.spans.expressions.insert((handle, expr_handle), span);
+        }
+        for (expr_handle, dst_id) in local_function_calls {
+            self.deferred_function_calls.push(DeferredFunctionCall {
+                source_handle: handle,
+                expr_handle,
+                dst_id,
+            });
+        }
+
+        self.lookup_function.insert(fun_id, handle);
+        self.lookup_expression.clear();
+        self.lookup_sampled_image.clear();
+        Ok(())
+    }
+}
+
+pub fn parse_u8_slice(data: &[u8], options: &Options) -> Result<crate::Module, Error> {
+    if data.len() % 4 != 0 {
+        return Err(Error::IncompleteData);
+    }
+
+    let words = data
+        .chunks(4)
+        .map(|c| u32::from_le_bytes(c.try_into().unwrap()));
+    Parser::new(words, options).parse()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::arena::Handle;
+    use spirv::Op;
+
+    #[test]
+    fn parse() {
+        let bin = vec![
+            // Magic number.           Version number: 1.0.
+            0x03, 0x02, 0x23, 0x07, 0x00, 0x00, 0x01, 0x00,
+            // Generator number: 0.    Bound: 0.
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Reserved word: 0.
+            0x00, 0x00, 0x00, 0x00, // OpMemoryModel.          Logical.
+            0x0e, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, // GLSL450.
+            0x01, 0x00, 0x00, 0x00,
+        ];
+        let _ = super::parse_u8_slice(&bin, &super::Options::default()).unwrap();
+    }
+
+    const VOID: u32 = 1;
+    const BOOL: u32 = 2;
+    const FLOAT: u32 = 3;
+    const FUN_TYPE: u32 = 4;
+    const FLOAT_PTR: u32 = 5;
+    const TRUE: u32 = 6;
+    const ONE: u32 = 7;
+    const TWO: u32 = 8;
+    const INT: u32 = 9;
+    const INT_ONE: u32 = 10;
+    const FUN: u32 = 11;
+    const VAR: u32 = 12;
+    const VEC2: u32 = 13;
+    const DOUBLE: u32 = 14;
+
+    /// Builds a module out of the given instructions.
+    fn build_module<'a>(instructions: impl Iterator<Item = &'a (Op, &'a [u32])>) -> Vec<u32> {
+        let mut words = vec![spirv::MAGIC_NUMBER, 0x10000, 0, 100, 0];
+        for &(op, operands) in instructions {
+            words.push(((operands.len() as u32 + 1) << 16) | op as u32);
+            words.extend_from_slice(operands);
+        }
+        words
+    }
+
+    /// Builds a module with a few common declarations, and a function
+    /// with a local `float` variable, followed by the given instructions.
+    fn build_function(body: &[(Op, &[u32])]) -> Vec<u32> {
+        let header: &[(Op, &[u32])] = &[
+            (Op::Capability, &[spirv::Capability::Shader as u32]),
+            (Op::MemoryModel, &[0, 1]),
+            (Op::TypeVoid, &[VOID]),
+            (Op::TypeBool, &[BOOL]),
+            (Op::TypeFloat, &[FLOAT, 32]),
+            (Op::TypeFunction, &[FUN_TYPE, VOID]),
+            (Op::TypePointer, &[FLOAT_PTR, 7, FLOAT]),
+            (Op::ConstantTrue, &[BOOL, TRUE]),
+            (Op::Constant, &[FLOAT, ONE, 1f32.to_bits()]),
+            (Op::Constant, &[FLOAT, TWO, 2f32.to_bits()]),
+            (Op::TypeInt, &[INT, 32, 1]),
+            (Op::Constant, &[INT, INT_ONE, 1]),
+            (Op::TypeVector, &[VEC2, FLOAT, 2]),
+            (Op::TypeFloat, &[DOUBLE, 64]),
+            (Op::Function, &[VOID, FUN, 0, FUN_TYPE]),
+            (Op::Label, &[20]),
+            (Op::Variable, &[FLOAT_PTR, VAR, 7]),
+        ];
+        let footer: &[(Op, &[u32])] = &[(Op::FunctionEnd, &[])];
+        build_module(header.iter().chain(body).chain(footer))
+    }
+
+    fn parse_function_body(body: &[(Op, &[u32])]) -> crate::Module {
+        let words = build_function(body);
+        super::Parser::new(words.into_iter(), &super::Options::default())
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_selection() {
+        let module = parse_function_body(&[
+            (Op::SelectionMerge, &[23, 0]),
+            (Op::BranchConditional, &[TRUE, 21, 22]),
+            (Op::Label, &[21]),
+            (Op::Store, &[VAR, ONE]),
+            (Op::Branch, &[23]),
+            (Op::Label, &[22]),
+            (Op::Store, &[VAR, TWO]),
+            (Op::Branch, &[23]),
+            (Op::Label, &[23]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        match fun.body[..] {
+            [crate::Statement::If {
+                ref accept,
+                ref reject,
+                ..
+            }, crate::Statement::Return { value: None }] => {
+                assert_matches_store(accept);
+                assert_matches_store(reject);
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_loop() {
+        let module = parse_function_body(&[
+            (Op::Branch, &[21]),
+            (Op::Label, &[21]),
+            (Op::LoopMerge, &[24, 23, 0]),
+            (Op::BranchConditional, &[TRUE, 22, 24]),
+            (Op::Label, &[22]),
+            (Op::Store, &[VAR, ONE]),
+            (Op::Branch, &[23]),
+            (Op::Label, &[23]),
+            (Op::Store, &[VAR, TWO]),
+            (Op::Branch, &[21]),
+            (Op::Label, &[24]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        match fun.body[..] {
+            [crate::Statement::Loop {
+                ref body,
+                ref continuing,
+            }, crate::Statement::Return { value: None }] => {
+                match body[..] {
+                    [crate::Statement::If {
+                        ref accept,
+                        ref reject,
+                        ..
+                    }, crate::Statement::Store { .. }] => {
+                        assert!(accept.is_empty());
+                        match reject[..] {
+                            [crate::Statement::Break] => (),
+                            ref other => panic!("Unexpected exit {:?}", other),
+                        }
+                    }
+                    ref other => panic!("Unexpected loop body {:?}", other),
+                }
+                assert_matches_store(continuing);
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_phi() {
+        let module = parse_function_body(&[
+            (Op::SelectionMerge, &[23, 0]),
+            (Op::BranchConditional, &[TRUE, 21, 22]),
+            (Op::Label, &[21]),
+            (Op::Branch, &[23]),
+            (Op::Label, &[22]),
+            (Op::Branch, &[23]),
+            (Op::Label, &[23]),
+            (Op::Phi, &[FLOAT, 30, ONE, 21, TWO, 22]),
+            (Op::Store, &[VAR, 30]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        assert_eq!(fun.local_variables.len(), 2);
+        match fun.body[..] {
+            [crate::Statement::If {
+                ref accept,
+                ref reject,
+                ..
+            }, crate::Statement::Store { value, .. }, crate::Statement::Return { value: None }] => {
+                assert_matches_store(accept);
+                assert_matches_store(reject);
+                match fun.expressions[value] {
+                    crate::Expression::LocalVariable(_) => (),
+                    ref other => panic!("Unexpected phi value {:?}", other),
+                }
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_switch() {
+        let module = parse_function_body(&[
+            (Op::SelectionMerge, &[24, 0]),
+            (Op::Switch, &[INT_ONE, 23, 1, 21, 2, 22, 3, 22]),
+            (Op::Label, &[21]),
+            (Op::Kill, &[]),
+            (Op::Label, &[22]),
+            (Op::Store, &[VAR, ONE]),
+            (Op::Branch, &[24]),
+            (Op::Label, &[23]),
+            (Op::Branch, &[24]),
+            (Op::Label, &[24]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        match fun.body[..] {
+            [crate::Statement::Switch {
+                ref cases,
+                ref default,
+                ..
+            }, crate::Statement::Return { value: None }] => {
+                assert_eq!(cases.len(), 3);
+                match cases[&1].0[..] {
+                    [crate::Statement::Kill] => (),
+                    ref other => panic!("Unexpected case {:?}", other),
+                }
+                assert_matches_store(&cases[&2].0);
+                assert_matches_store(&cases[&3].0);
+                assert!(default.is_empty());
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_switch_breaking_loop() {
+        let module = parse_function_body(&[
+            (Op::Branch, &[21]),
+            (Op::Label, &[21]),
+            (Op::LoopMerge, &[26, 25, 0]),
+            (Op::Branch, &[22]),
+            (Op::Label, &[22]),
+            (Op::SelectionMerge, &[24, 0]),
+            (Op::Switch, &[INT_ONE, 24, 1, 23, 2, 26]),
+            (Op::Label, &[23]),
+            (Op::Store, &[VAR, ONE]),
+            (Op::Branch, &[24]),
+            (Op::Label, &[24]),
+            (Op::Branch, &[25]),
+            (Op::Label, &[25]),
+            (Op::Branch, &[21]),
+            (Op::Label, &[26]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        let body = match fun.body[..] {
+            [crate::Statement::Loop {
+                ref body,
+                ref continuing,
+            }, crate::Statement::Return { value: None }] => {
+                assert!(continuing.is_empty());
+                body
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        };
+        // the case sets a flag, which leaves the loop after the switch
+        let (cases, accept) = match body[..] {
+            [crate::Statement::Switch { ref cases, .. }, crate::Statement::If {
+                condition,
+                ref accept,
+                ref reject,
+            }] => {
+                assert!(reject.is_empty());
+                match fun.expressions[condition] {
+                    crate::Expression::Load { .. } => (),
+                    ref other => panic!("Unexpected condition {:?}", other),
+                }
+                (cases, accept)
+            }
+            ref other => panic!("Unexpected loop body {:?}", other),
+        };
+        assert_matches_store(&cases[&1].0);
+        let flag = match cases[&2].0[..] {
+            [crate::Statement::Store { pointer, .. }, crate::Statement::Break] => pointer,
+            ref other => panic!("Unexpected case {:?}", other),
+        };
+        match accept[..] {
+            [crate::Statement::Store { pointer, .. }, crate::Statement::Break] => {
+                assert_eq!(pointer, flag)
+            }
+            ref other => panic!("Unexpected exit {:?}", other),
+        }
+    }
+
+    /// Returns the value stored by the last statement of the function.
+    fn stored_value(fun: &crate::Function) -> Handle<crate::Expression> {
+        match fun.body[..] {
+            [.., crate::Statement::Store { value, .. }, crate::Statement::Return { value: None }] => {
+                value
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_arithmetic() {
+        let module = parse_function_body(&[
+            (Op::FAdd, &[FLOAT, 30, ONE, TWO]),
+            (Op::FNegate, &[FLOAT, 31, 30]),
+            (Op::FOrdLessThan, &[BOOL, 32, 31, ONE]),
+            (Op::Select, &[FLOAT, 33, 32, ONE, TWO]),
+            (Op::Store, &[VAR, 33]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        let condition = match fun.expressions[stored_value(fun)] {
+            crate::Expression::Select { condition, .. } => condition,
+            ref other => panic!("Unexpected select {:?}", other),
+        };
+        let negation = match fun.expressions[condition] {
+            crate::Expression::Binary {
+                op: crate::BinaryOperator::Less,
+                left,
+                ..
+            } => left,
+            ref other => panic!("Unexpected comparison {:?}", other),
+        };
+        let sum = match fun.expressions[negation] {
+            crate::Expression::Unary {
+                op: crate::UnaryOperator::Negate,
+                expr,
+            } => expr,
+            ref other => panic!("Unexpected negation {:?}", other),
+        };
+        match fun.expressions[sum] {
+            crate::Expression::Binary {
+                op: crate::BinaryOperator::Add,
+                ..
+            } => (),
+            ref other => panic!("Unexpected sum {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_cast() {
+        let module = parse_function_body(&[
+            (Op::ConvertFToS, &[INT, 30, ONE]),
+            (Op::Bitcast, &[FLOAT, 31, 30]),
+            (Op::Store, &[VAR, 31]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        let converted = match fun.expressions[stored_value(fun)] {
+            crate::Expression::As {
+                expr,
+                kind: crate::ScalarKind::Float,
+                convert: false,
+            } => expr,
+            ref other => panic!("Unexpected bitcast {:?}", other),
+        };
+        match fun.expressions[converted] {
+            crate::Expression::As {
+                kind: crate::ScalarKind::Sint,
+                convert: true,
+                ..
+            } => (),
+            ref other => panic!("Unexpected conversion {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reject_inexpressible_operations() {
+        let cases: &[(Op, &[u32])] = &[
+            (Op::FConvert, &[DOUBLE, 30, ONE]),
+            (Op::UConvert, &[INT, 30, INT_ONE]),
+        ];
+        for &(op, operands) in cases {
+            let words = build_function(&[(op, operands), (Op::Return, &[])]);
+            match super::Parser::new(words.into_iter(), &super::Options::default()).parse() {
+                Err(super::Error::UnsupportedInstruction(_, other)) if other == op => (),
+                other => panic!("Unexpected result for {:?}: {:?}", op, other),
+            }
+        }
+    }
+
+    #[test]
+    fn lower_mod_and_unordered_comparisons() {
+        let module = parse_function_body(&[
+            (Op::FMod, &[FLOAT, 30, ONE, TWO]),
+            (Op::Store, &[VAR, 30]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        match fun.expressions[stored_value(fun)] {
+            crate::Expression::Binary {
+                op: crate::BinaryOperator::Subtract,
+                right,
+                ..
+            } => match fun.expressions[right] {
+                crate::Expression::Binary {
+                    op: crate::BinaryOperator::Multiply,
+                    ..
+                } => (),
+                ref other => panic!("Unexpected product {:?}", other),
+            },
+            ref other => panic!("Unexpected modulo {:?}", other),
+        }
+
+        let module = parse_function_body(&[
+            (Op::SMod, &[INT, 30, INT_ONE, INT_ONE]),
+            (Op::ConvertSToF, &[FLOAT, 31, 30]),
+            (Op::Store, &[VAR, 31]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        match fun.expressions[stored_value(fun)] {
+            crate::Expression::As { expr, .. } => match fun.expressions[expr] {
+                crate::Expression::Select { reject, .. } => match fun.expressions[reject] {
+                    crate::Expression::Binary {
+                        op: crate::BinaryOperator::Modulo,
+                        ..
+                    } => (),
+                    ref other => panic!("Unexpected remainder {:?}", other),
+                },
+                ref other => panic!("Unexpected modulo {:?}", other),
+            },
+            ref other => panic!("Unexpected conversion {:?}", other),
+        }
+
+        let cases = [
+            (
+                Op::FUnordLessThan,
+                Some(crate::BinaryOperator::GreaterEqual),
+            ),
+            (
+                Op::FUnordGreaterThan,
+                Some(crate::BinaryOperator::LessEqual),
+            ),
+            (Op::FUnordEqual, Some(crate::BinaryOperator::LogicalOr)),
+            (Op::FOrdNotEqual, None),
+        ];
+        for &(op, negated) in cases.iter() {
+            let module = parse_function_body(&[
+                (op, &[BOOL, 30, ONE, TWO]),
+                (Op::Select, &[FLOAT, 31, 30, ONE, TWO]),
+                (Op::Store, &[VAR, 31]),
+                (Op::Return, &[]),
+            ]);
+            let fun = module.functions.iter().next().unwrap().1;
+            let condition = match fun.expressions[stored_value(fun)] {
+                crate::Expression::Select { condition, .. } => condition,
+                ref other => panic!("Unexpected selection {:?}", other),
+            };
+            let comparison = match (negated, &fun.expressions[condition]) {
+                (
+                    Some(expected),
+                    &crate::Expression::Unary {
+                        op: crate::UnaryOperator::Not,
+                        expr,
+                    },
+                ) => match fun.expressions[expr] {
+                    crate::Expression::Binary { op, .. } => op == expected,
+                    _ => false,
+                },
+                (
+                    None,
+                    &crate::Expression::Binary {
+                        op: crate::BinaryOperator::LogicalOr,
+                        ..
+                    },
+                ) => true,
+                _ => false,
+            };
+            assert!(comparison, "{:?}: {:?}", op, fun.expressions[condition]);
+        }
+    }
+
+    #[test]
+    fn parse_composite_insert() {
+        let module = parse_function_body(&[
+            (Op::CompositeConstruct, &[VEC2, 30, ONE, ONE]),
+            (Op::CompositeInsert, &[VEC2, 31, TWO, 30, 1]),
+            (Op::CompositeExtract, &[FLOAT, 32, 31, 1]),
+            (Op::Store, &[VAR, 32]),
+            (Op::Return, &[]),
+        ]);
+        let fun = module.functions.iter().next().unwrap().1;
+        let composite = match fun.expressions[stored_value(fun)] {
+            crate::Expression::AccessIndex { base, index: 1 } => base,
+            ref other => panic!("Unexpected extraction {:?}", other),
+        };
+        match fun.expressions[composite] {
+            crate::Expression::Compose { ref components, .. } => {
+                match fun.expressions[components[0]] {
+                    crate::Expression::AccessIndex { index: 0, .. } => (),
+                    ref other => panic!("Unexpected first component {:?}", other),
+                }
+                match fun.expressions[components[1]] {
+                    crate::Expression::Constant(_) => (),
+                    ref other => panic!("Unexpected second component {:?}", other),
+                }
+            }
+            ref other => panic!("Unexpected insertion {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_decorations() {
+        use spirv::Decoration as D;
+        let instructions: &[(Op, &[u32])] = &[
+            (Op::Capability, &[spirv::Capability::Shader as u32]),
+            (Op::MemoryModel, &[0, 1]),
+            (Op::Decorate, &[1, D::NonWritable as u32]),
+            (Op::DecorationGroup, &[1]),
+            (Op::Decorate, &[3, D::BufferBlock as u32]),
+            (Op::MemberDecorate, &[3, 0, D::Offset as u32, 0]),
+            (Op::GroupMemberDecorate, &[1, 3, 0]),
+            (Op::Decorate, &[5, D::DescriptorSet as u32, 0]),
+            (Op::Decorate, &[5, D::Binding as u32, 1]),
+            (Op::Decorate, &[7, D::SpecId as u32, 3]),
+            (Op::TypeFloat, &[2, 32]),
+            (Op::TypeStruct, &[3, 2]),
+            (
+                Op::TypePointer,
+                &[4, spirv::StorageClass::Uniform as u32, 3],
+            ),
+            (Op::Variable, &[4, 5, spirv::StorageClass::Uniform as u32]),
+            (Op::TypeInt, &[6, 32, 0]),
+            (Op::SpecConstant, &[6, 7, 42]),
+        ];
+        let words = build_module(instructions.iter());
+        let module = super::Parser::new(words.into_iter(), &super::Options::default())
+            .parse()
+            .unwrap();
+
+        let var = module.global_variables.iter().next().unwrap().1;
+        assert_eq!(var.class, crate::StorageClass::StorageBuffer);
+        assert_eq!(var.storage_access, crate::StorageAccess::LOAD);
+        let constant = module.constants.iter().next().unwrap().1;
+        assert_eq!(constant.specialization, Some(3));
+    }
+
+    #[test]
+    fn parse_matrix_layout() {
+        use spirv::Decoration as D;
+        const VEC3: u32 = 14;
+        // a matrix with two columns of the given type, as the member of a block
+        let parse = |strict: bool, column_type: u32, decorations: &[&[u32]]| {
+            let member_decorations: Vec<Vec<u32>> = decorations
+                .iter()
+                .map(|decoration| [&[3, 0][..], decoration].concat())
+                .collect();
+            let mut instructions: Vec<(Op, &[u32])> = vec![
+                (Op::Capability, &[spirv::Capability::Shader as u32]),
+                (Op::MemoryModel, &[0, 1]),
+                (Op::Decorate, &[3, D::Block as u32]),
+                (Op::MemberDecorate, &[3, 0, D::Offset as u32, 0]),
+            ];
+            for decoration in member_decorations.iter() {
+                instructions.push((Op::MemberDecorate, decoration));
+            }
+            let column = [2, column_type, 2];
+            instructions.extend_from_slice(&[
+                (Op::TypeFloat, &[FLOAT, 32]),
+                (Op::TypeVector, &[VEC2, FLOAT, 2]),
+                (Op::TypeVector, &[VEC3, FLOAT, 3]),
+                (Op::TypeMatrix, &column),
+                (Op::TypeStruct, &[3, 2]),
+            ]);
+            let words = build_module(instructions.iter());
+            let mut parser = super::Parser::new(words.into_iter(), &super::Options { strict });
+            parser.parse().map(|_| parser)
+        };
+        // both the std140 and std430 strides are valid
+        parse(true, VEC2, &[&[D::MatrixStride as u32, 16]]).unwrap();
+        parse(true, VEC2, &[&[D::MatrixStride as u32, 8]]).unwrap();
+        match parse(true, VEC2, &[&[D::MatrixStride as u32, 12]]).err() {
+            Some(super::Error::InvalidMatrixStride(12)) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        // row-major matrices are only reported in lenient mode,
+        // with the stride separating the rows of two components
+        let row_major: &[&[u32]] = &[&[D::RowMajor as u32], &[D::MatrixStride as u32, 8]];
+        match parse(true, VEC3, row_major).err() {
+            Some(super::Error::UnsupportedDecoration(D::RowMajor)) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match *parse(false, VEC3, row_major).unwrap().warnings() {
+            [super::Error::UnsupportedDecoration(D::RowMajor)] => (),
+            ref other => panic!("Unexpected warnings {:?}", other),
+        }
+        match parse(false, VEC3, &[&[D::MatrixStride as u32, 8]]).err() {
+            Some(super::Error::InvalidMatrixStride(8)) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    /// Encodes a nul-terminated string into words.
+    fn string_words(string: &str) -> Vec<u32> {
+        let mut bytes = string.as_bytes().to_vec();
+        bytes.resize(string.len() / 4 * 4 + 4, 0);
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn parse_spans() {
+        const FILE: u32 = 30;
+        let mut file_name = vec![FILE];
+        file_name.extend(string_words("shader.frag"));
+        let mut source = vec![spirv::SourceLanguage::GLSL as u32, 450, FILE];
+        source.extend(string_words("void main() {"));
+        let source_continued = string_words("}");
+        let instructions: &[(Op, &[u32])] = &[
+            (Op::Capability, &[spirv::Capability::Shader as u32]),
+            (Op::MemoryModel, &[0, 1]),
+            (Op::String, &file_name),
+            (Op::Source, &source),
+            (Op::SourceContinued, &source_continued),
+            (Op::TypeVoid, &[VOID]),
+            (Op::TypeFloat, &[FLOAT, 32]),
+            (Op::TypeFunction, &[FUN_TYPE, VOID]),
+            (Op::TypePointer, &[FLOAT_PTR, 7, FLOAT]),
+            (Op::Constant, &[FLOAT, ONE, 1f32.to_bits()]),
+            (Op::Line, &[FILE, 1, 1]),
+            (Op::Function, &[VOID, FUN, 0, FUN_TYPE]),
+            (Op::Label, &[20]),
+            (Op::Variable, &[FLOAT_PTR, VAR, 7]),
+            (Op::Line, &[FILE, 2, 5]),
+            (Op::FNegate, &[FLOAT, 31, ONE]),
+            (Op::NoLine, &[]),
+            (Op::FAdd, &[FLOAT, 32, 31, ONE]),
+            (Op::Store, &[VAR, 32]),
+            (Op::Return, &[]),
+            (Op::FunctionEnd, &[]),
+        ];
+        let words = build_module(instructions.iter());
+        let module = super::Parser::new(words.into_iter(), &super::Options::default())
+            .parse()
+            .unwrap();
+
+        let (file_handle, file) = module.spans.files.iter().next().unwrap();
+        assert_eq!(file.name.as_deref(), Some("shader.frag"));
+        assert_eq!(file.text.as_deref(), Some("void main() {}"));
+
+        let (fun_handle, fun) = module.functions.iter().next().unwrap();
+        assert_eq!(module.spans.functions[&fun_handle].line, 1);
+        let sum = stored_value(fun);
+        let negation = match fun.expressions[sum] {
+            crate::Expression::Binary { left, .. } => left,
+            ref other => panic!("Unexpected sum {:?}", other),
+        };
+        assert_eq!(
+            module.spans.expressions.get(&(fun_handle, negation)),
+            Some(&crate::Span {
+                file: file_handle,
+                line: 2,
+                column: 5,
+            })
+        );
+        assert_eq!(module.spans.expressions.get(&(fun_handle, sum)), None);
+    }
+
+    #[test]
+    fn parse_lenient() {
+        let extension = string_words("SPV_KHR_non_semantic_info");
+        let mut ext_set = vec![1];
+        ext_set.extend(string_words("NonSemantic.Shader.DebugInfo.100"));
+        let processed = string_words("opt");
+        let instructions: &[(Op, &[u32])] = &[
+            (Op::Capability, &[spirv::Capability::Shader as u32]),
+            (Op::Capability, &[spirv::Capability::Float64 as u32]),
+            (Op::Extension, &extension),
+            (Op::ExtInstImport, &ext_set),
+            (Op::MemoryModel, &[0, 1]),
+            (Op::ModuleProcessed, &processed),
+            (Op::TypeVoid, &[VOID]),
+            (Op::ExtInst, &[VOID, 3, 1, 1]),
+        ];
+        let words = build_module(instructions.iter());
+
+        let strict = super::Options::default();
+        match super::Parser::new(words.iter().cloned(), &strict).parse() {
+            Err(super::Error::UnsupportedCapability(spirv::Capability::Float64)) => (),
+            other => panic!("Unexpected strict result {:?}", other),
+        }
+
+        let lenient = super::Options { strict: false };
+        let mut parser = super::Parser::new(words.into_iter(), &lenient);
+        parser.parse().unwrap();
+        match *parser.warnings() {
+            [super::Error::UnsupportedCapability(spirv::Capability::Float64), super::Error::UnsupportedExtension(ref extension), super::Error::UnsupportedExtSet(ref set)] =>
+            {
+                assert_eq!(extension, "SPV_KHR_non_semantic_info");
+                assert_eq!(set, "NonSemantic.Shader.DebugInfo.100");
+            }
+            ref other => panic!("Unexpected warnings {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reject_unknown_function_instructions() {
+        let words = build_function(&[(Op::CopyMemory, &[VAR, VAR]), (Op::Return, &[])]);
+        let lenient = super::Options { strict: false };
+        match super::Parser::new(words.into_iter(), &lenient).parse() {
+            Err(super::Error::UnsupportedInstruction(_, Op::CopyMemory)) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        // opcodes the parser doesn't know are only skipped outside of functions
+        const UNKNOWN: u32 = (2 << 16) | 0xfff0;
+        let mut words = build_function(&[(Op::Return, &[])]);
+        words.splice(5..5, [UNKNOWN, 0].iter().cloned());
+        let mut parser = super::Parser::new(words.iter().cloned(), &lenient);
+        parser.parse().unwrap();
+        match *parser.warnings() {
+            [super::Error::UnknownInstruction(0xfff0)] => (),
+            ref other => panic!("Unexpected warnings {:?}", other),
+        }
+        let return_index = words.len() - 2;
+        words.splice(return_index..return_index, [UNKNOWN, VAR].iter().cloned());
+        match super::Parser::new(words.into_iter(), &lenient).parse() {
+            Err(super::Error::UnknownInstruction(0xfff0)) => (),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    fn assert_matches_store(block: &[crate::Statement]) {
+        match *block {
+            [crate::Statement::Store { .. }] => (),
+            ref other => panic!("Expected a single store, got {:?}", other),
+        }
+    }
+}
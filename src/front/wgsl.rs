@@ -297,6 +297,7 @@ struct StatementContext<'input, 'temp, 'out> {
     types: &'out mut Arena<crate::Type>,
     constants: &'out mut Arena<crate::Constant>,
     global_vars: &'out Arena<crate::GlobalVariable>,
+    parameter_types: &'out [Handle<crate::Type>],
 }
 
 impl<'a> StatementContext<'a, '_, '_> {
@@ -309,6 +310,7 @@ impl<'a> StatementContext<'a, '_, '_> {
             types: self.types,
             constants: self.constants,
             global_vars: self.global_vars,
+            parameter_types: self.parameter_types,
         }
     }
 
@@ -320,6 +322,7 @@ impl<'a> StatementContext<'a, '_, '_> {
             types: self.types,
             constants: self.constants,
             global_vars: self.global_vars,
+            parameter_types: self.parameter_types,
             local_vars: self.variables,
         }
     }
@@ -332,6 +335,7 @@ struct ExpressionContext<'input, 'temp, 'out> {
     types: &'out mut Arena<crate::Type>,
     constants: &'out mut Arena<crate::Constant>,
     global_vars: &'out Arena<crate::GlobalVariable>,
+    parameter_types: &'out [Handle<crate::Type>],
     local_vars: &'out Arena<crate::LocalVariable>,
 }
 
@@ -344,6 +348,7 @@ impl<'a> ExpressionContext<'a, '_, '_> {
             types: self.types,
             constants: self.constants,
             global_vars: self.global_vars,
+            parameter_types: self.parameter_types,
             local_vars: self.local_vars,
         }
     }
@@ -361,6 +366,7 @@ impl<'a> ExpressionContext<'a, '_, '_> {
                 self.global_vars,
                 self.local_vars,
                 &Arena::new(),
+                self.parameter_types,
             )
             .map_err(Error::InvalidResolve)
     }
@@ -1399,6 +1405,7 @@ impl Parser {
         }
         // read parameter list
         let mut parameter_types = Vec::new();
+        let mut parameter_names = Vec::new();
         lexer.expect(Token::Paren('('))?;
         while !lexer.skip(Token::Paren(')')) {
             if !parameter_types.is_empty() {
@@ -1411,6 +1418,7 @@ impl Parser {
                 expressions.append(crate::Expression::FunctionParameter(param_index));
             lookup_ident.insert(param_name, expression_token);
             parameter_types.push(param_type);
+            parameter_names.push(Some(param_name.to_owned()));
         }
         // read return type
         lexer.expect(Token::Arrow)?;
@@ -1432,6 +1440,7 @@ impl Parser {
                 types: &mut module.types,
                 constants: &mut module.constants,
                 global_vars: &module.global_variables,
+                parameter_types: &parameter_types,
             },
         )?;
         // done
@@ -1441,6 +1450,7 @@ impl Parser {
         let fun = crate::Function {
             name: Some(fun_name.to_owned()),
             parameter_types,
+            parameter_names,
             return_type,
             global_usage,
             local_variables,
@@ -1569,6 +1579,11 @@ impl Parser {
                         }
                         _ => None,
                     },
+                    storage_access: if class == crate::StorageClass::StorageBuffer {
+                        crate::StorageAccess::all()
+                    } else {
+                        crate::StorageAccess::empty()
+                    },
                 });
                 lookup_global_expression
                     .insert(name, crate::Expression::GlobalVariable(var_handle));
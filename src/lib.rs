@@ -46,7 +46,7 @@ pub struct Header {
 }
 
 /// Stage of the programmable pipeline.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 #[allow(missing_docs)] // The names are self evident
@@ -147,6 +147,8 @@ pub enum ArraySize {
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub enum MemberOrigin {
+    /// Placed by the back ends, according to the layout rules of the target.
+    Empty,
     /// Built-in shader variable.
     BuiltIn(BuiltIn),
     /// Offset within the struct.
@@ -222,7 +224,7 @@ bitflags::bitflags! {
 }
 
 /// A data type declared in the module.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub struct Type {
@@ -327,6 +329,18 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    /// Indicates how a storage buffer or a storage image may be accessed.
+    #[cfg_attr(feature = "serialize", derive(Serialize))]
+    #[cfg_attr(feature = "deserialize", derive(Deserialize))]
+    pub struct StorageAccess: u8 {
+        /// Data can be read from the variable.
+        const LOAD = 0x1;
+        /// Data can be written to the variable.
+        const STORE = 0x2;
+    }
+}
+
 /// Variable defined at module level.
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
@@ -344,6 +358,9 @@ pub struct GlobalVariable {
     /// Required for global outputs in vertex shaders
     /// and global inputs in fragment shaders.
     pub interpolation: Option<Interpolation>,
+    /// Access allowed to a storage buffer or a storage image.
+    /// Empty for the other variables.
+    pub storage_access: StorageAccess,
 }
 
 /// Variable defined at function level.
@@ -360,7 +377,7 @@ pub struct LocalVariable {
 }
 
 /// Operation that can be applied on a single value.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub enum UnaryOperator {
@@ -369,7 +386,7 @@ pub enum UnaryOperator {
 }
 
 /// Operation that can be applied on two values.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub enum BinaryOperator {
@@ -377,7 +394,9 @@ pub enum BinaryOperator {
     Subtract,
     Multiply,
     Divide,
+    /// Remainder of the division, with the sign of the dividend.
     Modulo,
+    /// Comparisons are false if an operand is NaN, except for `NotEqual`, which is true.
     Equal,
     NotEqual,
     Less,
@@ -395,7 +414,7 @@ pub enum BinaryOperator {
 }
 
 /// Built-in shader function.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub enum IntrinsicFunction {
@@ -408,7 +427,7 @@ pub enum IntrinsicFunction {
 }
 
 /// Axis on which to compute a derivative.
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub enum DerivativeAxis {
@@ -418,7 +437,7 @@ pub enum DerivativeAxis {
 }
 
 /// Origin of a function to call.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub enum FunctionOrigin {
@@ -427,7 +446,7 @@ pub enum FunctionOrigin {
 }
 
 /// An expression that can be evaluated to obtain a value.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub enum Expression {
@@ -489,6 +508,24 @@ pub enum Expression {
         //modifier,
         expr: Handle<Expression>,
     },
+    /// Select between two values based on a condition.
+    ///
+    /// The condition is either a boolean scalar, or a boolean vector
+    /// of the same size as the values, selecting each component separately.
+    Select {
+        condition: Handle<Expression>,
+        accept: Handle<Expression>,
+        reject: Handle<Expression>,
+    },
+    /// Cast a scalar or a vector to another kind, keeping the width.
+    ///
+    /// If `convert` is true, the value is converted numerically,
+    /// otherwise its bits are reinterpreted.
+    As {
+        expr: Handle<Expression>,
+        kind: ScalarKind,
+        convert: bool,
+    },
     /// Call another function.
     Call {
         origin: FunctionOrigin,
@@ -556,6 +593,10 @@ pub struct Function {
     //pub control: spirv::FunctionControl,
     /// The types of the parameters of this function.
     pub parameter_types: Vec<Handle<Type>>,
+    /// Names of the parameters, in the same order as their types.
+    ///
+    /// May be shorter than `parameter_types` if the names aren't known.
+    pub parameter_names: Vec<Option<String>>,
     /// The return type of this function, if any.
     pub return_type: Option<Handle<Type>>,
     /// Vector of global variable usages.
@@ -583,6 +624,46 @@ pub struct EntryPoint {
     pub function: Handle<Function>,
 }
 
+/// Source file, which the module was translated from.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
+pub struct SourceFile {
+    /// Name of the file, if known.
+    pub name: Option<String>,
+    /// Contents of the file, if embedded into the shader.
+    pub text: Option<String>,
+}
+
+/// Location in a source file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
+pub struct Span {
+    /// The file containing this location.
+    pub file: Handle<SourceFile>,
+    /// Line number, starting from 1.
+    pub line: u32,
+    /// Column number, starting from 1. Zero if unknown.
+    pub column: u32,
+}
+
+/// Locations of the module contents in the original source files.
+///
+/// Statements don't have their own locations: they are reported
+/// by the locations of the expressions they refer to.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize))]
+#[cfg_attr(feature = "deserialize", derive(Deserialize))]
+pub struct SpanTable {
+    /// Source files referenced by the spans.
+    pub files: Arena<SourceFile>,
+    /// Locations of the functions.
+    pub functions: FastHashMap<Handle<Function>, Span>,
+    /// Locations of the expressions, per function.
+    pub expressions: FastHashMap<(Handle<Function>, Handle<Expression>), Span>,
+}
+
 /// Shader module.
 ///
 /// A module is a set of constants, global variables and functions, as well as
@@ -610,4 +691,6 @@ pub struct Module {
     pub functions: Arena<Function>,
     /// Vector of exported entry points.
     pub entry_points: Vec<EntryPoint>,
+    /// Locations of the module contents in the original sources, if known.
+    pub spans: SpanTable,
 }
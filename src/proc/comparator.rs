@@ -0,0 +1,654 @@
+use crate::{arena::Handle, FastHashMap, FastHashSet};
+
+use thiserror::Error;
+
+/// The first difference found between two modules.
+///
+/// Handles of the left module are followed by the ones of the right module.
+#[derive(Clone, Debug, Error)]
+pub enum ComparisonError {
+    #[error("The entry points are different")]
+    EntryPoints,
+    #[error("Type {0:?} doesn't match {1:?}")]
+    Type(Handle<crate::Type>, Handle<crate::Type>),
+    #[error("Constant {0:?} doesn't match {1:?}")]
+    Constant(Handle<crate::Constant>, Handle<crate::Constant>),
+    #[error("Global variable {0:?} doesn't match {1:?}")]
+    GlobalVariable(Handle<crate::GlobalVariable>, Handle<crate::GlobalVariable>),
+    #[error("Function {0:?} doesn't match {1:?}")]
+    Function(Handle<crate::Function>, Handle<crate::Function>),
+    #[error("Local variable {1:?} doesn't match {2:?} in function {0:?}")]
+    LocalVariable(
+        Handle<crate::Function>,
+        Handle<crate::LocalVariable>,
+        Handle<crate::LocalVariable>,
+    ),
+    #[error("Expression {1:?} doesn't match {2:?} in function {0:?}")]
+    Expression(
+        Handle<crate::Function>,
+        Handle<crate::Expression>,
+        Handle<crate::Expression>,
+    ),
+    #[error("The statements of function {0:?} are different")]
+    Statement(Handle<crate::Function>),
+}
+
+/// Pairs of handles already known to match.
+struct HandleMap<T>(FastHashMap<Handle<T>, Handle<T>>);
+
+impl<T> HandleMap<T> {
+    fn new() -> Self {
+        HandleMap(FastHashMap::default())
+    }
+
+    /// Returns `None` if the left handle hasn't been compared yet,
+    /// and otherwise whether it was matched with the right one.
+    fn check(&mut self, left: Handle<T>, right: Handle<T>) -> Option<bool> {
+        match self.0.get(&left) {
+            Some(&known) => Some(known == right),
+            None => {
+                self.0.insert(left, right);
+                None
+            }
+        }
+    }
+}
+
+struct FunctionPair<'a> {
+    handles: (Handle<crate::Function>, Handle<crate::Function>),
+    left: &'a crate::Function,
+    right: &'a crate::Function,
+    local_variables: HandleMap<crate::LocalVariable>,
+    /// Pairs of expressions known to match. Expressions are evaluated where they are used,
+    /// so one of them may be written out again for each use, and match several others.
+    expressions: FastHashSet<(Handle<crate::Expression>, Handle<crate::Expression>)>,
+}
+
+struct Comparator<'a> {
+    left: &'a crate::Module,
+    right: &'a crate::Module,
+    types: HandleMap<crate::Type>,
+    constants: HandleMap<crate::Constant>,
+    global_variables: HandleMap<crate::GlobalVariable>,
+    functions: HandleMap<crate::Function>,
+}
+
+impl<'a> Comparator<'a> {
+    fn types(
+        &mut self,
+        left: Handle<crate::Type>,
+        right: Handle<crate::Type>,
+    ) -> Result<(), ComparisonError> {
+        use crate::TypeInner as Ti;
+
+        let error = ComparisonError::Type(left, right);
+        match self.types.check(left, right) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(error),
+            None => {}
+        }
+        let (left_ty, right_ty) = (&self.left.types[left], &self.right.types[right]);
+        if left_ty.name != right_ty.name {
+            return Err(error);
+        }
+        match (&left_ty.inner, &right_ty.inner) {
+            (
+                &Ti::Image {
+                    base: left_base,
+                    dim: left_dim,
+                    flags: left_flags,
+                },
+                &Ti::Image {
+                    base: right_base,
+                    dim: right_dim,
+                    flags: right_flags,
+                },
+            ) if left_dim == right_dim && left_flags == right_flags => {
+                self.types(left_base, right_base)
+            }
+            (
+                &Ti::Pointer {
+                    base: left_base,
+                    class: left_class,
+                },
+                &Ti::Pointer {
+                    base: right_base,
+                    class: right_class,
+                },
+            ) if left_class == right_class => self.types(left_base, right_base),
+            (
+                &Ti::Array {
+                    base: left_base,
+                    size: ref left_size,
+                    stride: left_stride,
+                },
+                &Ti::Array {
+                    base: right_base,
+                    size: ref right_size,
+                    stride: right_stride,
+                },
+            ) if left_size == right_size && left_stride == right_stride => {
+                self.types(left_base, right_base)
+            }
+            (
+                Ti::Struct {
+                    members: left_members,
+                },
+                Ti::Struct {
+                    members: right_members,
+                },
+            ) if left_members.len() == right_members.len() => {
+                for (left_member, right_member) in left_members.iter().zip(right_members) {
+                    if left_member.name != right_member.name
+                        || left_member.origin != right_member.origin
+                    {
+                        return Err(error);
+                    }
+                    self.types(left_member.ty, right_member.ty)?;
+                }
+                Ok(())
+            }
+            (&Ti::Image { .. }, _)
+            | (&Ti::Pointer { .. }, _)
+            | (&Ti::Array { .. }, _)
+            | (&Ti::Struct { .. }, _) => Err(error),
+            // the other types don't refer to any handles
+            (left_inner, right_inner) if left_inner == right_inner => Ok(()),
+            _ => Err(error),
+        }
+    }
+
+    fn constants(
+        &mut self,
+        left: Handle<crate::Constant>,
+        right: Handle<crate::Constant>,
+    ) -> Result<(), ComparisonError> {
+        let error = ComparisonError::Constant(left, right);
+        match self.constants.check(left, right) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(error),
+            None => {}
+        }
+        let (left_const, right_const) = (&self.left.constants[left], &self.right.constants[right]);
+        if left_const.name != right_const.name
+            || left_const.specialization != right_const.specialization
+        {
+            return Err(error);
+        }
+        self.types(left_const.ty, right_const.ty)?;
+        match (&left_const.inner, &right_const.inner) {
+            (
+                crate::ConstantInner::Composite(left_components),
+                crate::ConstantInner::Composite(right_components),
+            ) if left_components.len() == right_components.len() => {
+                for (&l, &r) in left_components.iter().zip(right_components) {
+                    self.constants(l, r)?;
+                }
+                Ok(())
+            }
+            (&crate::ConstantInner::Composite(_), _) => Err(error),
+            (left_inner, right_inner) if left_inner == right_inner => Ok(()),
+            _ => Err(error),
+        }
+    }
+
+    fn global_variables(
+        &mut self,
+        left: Handle<crate::GlobalVariable>,
+        right: Handle<crate::GlobalVariable>,
+    ) -> Result<(), ComparisonError> {
+        let error = ComparisonError::GlobalVariable(left, right);
+        match self.global_variables.check(left, right) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(error),
+            None => {}
+        }
+        let (left_var, right_var) = (
+            &self.left.global_variables[left],
+            &self.right.global_variables[right],
+        );
+        if left_var.name != right_var.name
+            || left_var.class != right_var.class
+            || left_var.binding != right_var.binding
+            || left_var.interpolation != right_var.interpolation
+            || left_var.storage_access != right_var.storage_access
+        {
+            return Err(error);
+        }
+        self.types(left_var.ty, right_var.ty)
+    }
+
+    fn functions(
+        &mut self,
+        left: Handle<crate::Function>,
+        right: Handle<crate::Function>,
+    ) -> Result<(), ComparisonError> {
+        let error = ComparisonError::Function(left, right);
+        match self.functions.check(left, right) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(error),
+            None => {}
+        }
+        let mut fun = FunctionPair {
+            handles: (left, right),
+            left: &self.left.functions[left],
+            right: &self.right.functions[right],
+            local_variables: HandleMap::new(),
+            expressions: FastHashSet::default(),
+        };
+        if fun.left.name != fun.right.name
+            || fun.left.parameter_names != fun.right.parameter_names
+            || fun.left.parameter_types.len() != fun.right.parameter_types.len()
+        {
+            return Err(error);
+        }
+        for (&l, &r) in fun
+            .left
+            .parameter_types
+            .iter()
+            .zip(fun.right.parameter_types.iter())
+        {
+            self.types(l, r)?;
+        }
+        match (fun.left.return_type, fun.right.return_type) {
+            (Some(l), Some(r)) => self.types(l, r)?,
+            (None, None) => {}
+            _ => return Err(error),
+        }
+        let (left_body, right_body) = (&fun.left.body, &fun.right.body);
+        self.blocks(&mut fun, left_body, right_body)
+    }
+
+    fn local_variables(
+        &mut self,
+        fun: &mut FunctionPair<'a>,
+        left: Handle<crate::LocalVariable>,
+        right: Handle<crate::LocalVariable>,
+    ) -> Result<(), ComparisonError> {
+        let error = ComparisonError::LocalVariable(fun.handles.0, left, right);
+        match fun.local_variables.check(left, right) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(error),
+            None => {}
+        }
+        let (left_var, right_var) = (
+            &fun.left.local_variables[left],
+            &fun.right.local_variables[right],
+        );
+        if left_var.name != right_var.name {
+            return Err(error);
+        }
+        self.types(left_var.ty, right_var.ty)?;
+        match (left_var.init, right_var.init) {
+            (Some(l), Some(r)) => self.expressions(fun, l, r),
+            (None, None) => Ok(()),
+            _ => Err(error),
+        }
+    }
+
+    fn expressions(
+        &mut self,
+        fun: &mut FunctionPair<'a>,
+        left: Handle<crate::Expression>,
+        right: Handle<crate::Expression>,
+    ) -> Result<(), ComparisonError> {
+        use crate::Expression as E;
+
+        let error = ComparisonError::Expression(fun.handles.0, left, right);
+        if !fun.expressions.insert((left, right)) {
+            return Ok(());
+        }
+        match (&fun.left.expressions[left], &fun.right.expressions[right]) {
+            (
+                &E::Access {
+                    base: left_base,
+                    index: left_index,
+                },
+                &E::Access {
+                    base: right_base,
+                    index: right_index,
+                },
+            ) => {
+                self.expressions(fun, left_base, right_base)?;
+                self.expressions(fun, left_index, right_index)
+            }
+            (
+                &E::AccessIndex {
+                    base: left_base,
+                    index: left_index,
+                },
+                &E::AccessIndex {
+                    base: right_base,
+                    index: right_index,
+                },
+            ) if left_index == right_index => self.expressions(fun, left_base, right_base),
+            (&E::Constant(l), &E::Constant(r)) => self.constants(l, r),
+            (
+                &E::Compose {
+                    ty: left_ty,
+                    components: ref left_components,
+                },
+                &E::Compose {
+                    ty: right_ty,
+                    components: ref right_components,
+                },
+            ) if left_components.len() == right_components.len() => {
+                self.types(left_ty, right_ty)?;
+                for (&l, &r) in left_components.iter().zip(right_components) {
+                    self.expressions(fun, l, r)?;
+                }
+                Ok(())
+            }
+            (&E::FunctionParameter(l), &E::FunctionParameter(r)) if l == r => Ok(()),
+            (&E::GlobalVariable(l), &E::GlobalVariable(r)) => self.global_variables(l, r),
+            (&E::LocalVariable(l), &E::LocalVariable(r)) => self.local_variables(fun, l, r),
+            (&E::Load { pointer: l }, &E::Load { pointer: r }) => self.expressions(fun, l, r),
+            (
+                &E::ImageSample {
+                    image: left_image,
+                    sampler: left_sampler,
+                    coordinate: left_coordinate,
+                    depth_ref: left_depth_ref,
+                },
+                &E::ImageSample {
+                    image: right_image,
+                    sampler: right_sampler,
+                    coordinate: right_coordinate,
+                    depth_ref: right_depth_ref,
+                },
+            ) => {
+                self.expressions(fun, left_image, right_image)?;
+                self.expressions(fun, left_sampler, right_sampler)?;
+                self.expressions(fun, left_coordinate, right_coordinate)?;
+                match (left_depth_ref, right_depth_ref) {
+                    (Some(l), Some(r)) => self.expressions(fun, l, r),
+                    (None, None) => Ok(()),
+                    _ => Err(error),
+                }
+            }
+            (
+                &E::Unary {
+                    op: left_op,
+                    expr: l,
+                },
+                &E::Unary {
+                    op: right_op,
+                    expr: r,
+                },
+            ) if left_op == right_op => self.expressions(fun, l, r),
+            (
+                &E::Binary {
+                    op: left_op,
+                    left: left_left,
+                    right: left_right,
+                },
+                &E::Binary {
+                    op: right_op,
+                    left: right_left,
+                    right: right_right,
+                },
+            ) if left_op == right_op => {
+                self.expressions(fun, left_left, right_left)?;
+                self.expressions(fun, left_right, right_right)
+            }
+            (
+                &E::Intrinsic {
+                    fun: left_fun,
+                    argument: l,
+                },
+                &E::Intrinsic {
+                    fun: right_fun,
+                    argument: r,
+                },
+            ) if left_fun == right_fun => self.expressions(fun, l, r),
+            (&E::DotProduct(left_a, left_b), &E::DotProduct(right_a, right_b))
+            | (&E::CrossProduct(left_a, left_b), &E::CrossProduct(right_a, right_b)) => {
+                self.expressions(fun, left_a, right_a)?;
+                self.expressions(fun, left_b, right_b)
+            }
+            (
+                &E::Derivative {
+                    axis: left_axis,
+                    expr: l,
+                },
+                &E::Derivative {
+                    axis: right_axis,
+                    expr: r,
+                },
+            ) if left_axis == right_axis => self.expressions(fun, l, r),
+            (
+                &E::Select {
+                    condition: left_condition,
+                    accept: left_accept,
+                    reject: left_reject,
+                },
+                &E::Select {
+                    condition: right_condition,
+                    accept: right_accept,
+                    reject: right_reject,
+                },
+            ) => {
+                self.expressions(fun, left_condition, right_condition)?;
+                self.expressions(fun, left_accept, right_accept)?;
+                self.expressions(fun, left_reject, right_reject)
+            }
+            (
+                &E::As {
+                    expr: l,
+                    kind: left_kind,
+                    convert: left_convert,
+                },
+                &E::As {
+                    expr: r,
+                    kind: right_kind,
+                    convert: right_convert,
+                },
+            ) if left_kind == right_kind && left_convert == right_convert => {
+                self.expressions(fun, l, r)
+            }
+            (
+                E::Call {
+                    origin: left_origin,
+                    arguments: left_arguments,
+                },
+                E::Call {
+                    origin: right_origin,
+                    arguments: right_arguments,
+                },
+            ) if left_arguments.len() == right_arguments.len() => {
+                match (left_origin, right_origin) {
+                    (&crate::FunctionOrigin::Local(l), &crate::FunctionOrigin::Local(r)) => {
+                        self.functions(l, r)?
+                    }
+                    (crate::FunctionOrigin::External(l), crate::FunctionOrigin::External(r))
+                        if l == r => {}
+                    _ => return Err(error),
+                }
+                for (&l, &r) in left_arguments.iter().zip(right_arguments) {
+                    self.expressions(fun, l, r)?;
+                }
+                Ok(())
+            }
+            _ => Err(error),
+        }
+    }
+
+    fn blocks(
+        &mut self,
+        fun: &mut FunctionPair<'a>,
+        left: &'a [crate::Statement],
+        right: &'a [crate::Statement],
+    ) -> Result<(), ComparisonError> {
+        use crate::Statement as S;
+
+        let error = ComparisonError::Statement(fun.handles.0);
+        if left.len() != right.len() {
+            return Err(error);
+        }
+        for (left_statement, right_statement) in left.iter().zip(right) {
+            match (left_statement, right_statement) {
+                (&S::Empty, &S::Empty)
+                | (&S::Break, &S::Break)
+                | (&S::Continue, &S::Continue)
+                | (&S::Kill, &S::Kill) => {}
+                (S::Block(l), S::Block(r)) => self.blocks(fun, l, r)?,
+                (
+                    &S::If {
+                        condition: left_condition,
+                        accept: ref left_accept,
+                        reject: ref left_reject,
+                    },
+                    &S::If {
+                        condition: right_condition,
+                        accept: ref right_accept,
+                        reject: ref right_reject,
+                    },
+                ) => {
+                    self.expressions(fun, left_condition, right_condition)?;
+                    self.blocks(fun, left_accept, right_accept)?;
+                    self.blocks(fun, left_reject, right_reject)?;
+                }
+                (
+                    &S::Switch {
+                        selector: left_selector,
+                        cases: ref left_cases,
+                        default: ref left_default,
+                    },
+                    &S::Switch {
+                        selector: right_selector,
+                        cases: ref right_cases,
+                        default: ref right_default,
+                    },
+                ) if left_cases.len() == right_cases.len() => {
+                    self.expressions(fun, left_selector, right_selector)?;
+                    for (value, (left_body, left_fall_through)) in left_cases.iter() {
+                        match right_cases.get(value) {
+                            Some((right_body, right_fall_through))
+                                if left_fall_through.is_some() == right_fall_through.is_some() =>
+                            {
+                                self.blocks(fun, left_body, right_body)?
+                            }
+                            _ => return Err(error),
+                        }
+                    }
+                    self.blocks(fun, left_default, right_default)?;
+                }
+                (
+                    S::Loop {
+                        body: left_body,
+                        continuing: left_continuing,
+                    },
+                    S::Loop {
+                        body: right_body,
+                        continuing: right_continuing,
+                    },
+                ) => {
+                    self.blocks(fun, left_body, right_body)?;
+                    self.blocks(fun, left_continuing, right_continuing)?;
+                }
+                (&S::Return { value: Some(l) }, &S::Return { value: Some(r) }) => {
+                    self.expressions(fun, l, r)?
+                }
+                (&S::Return { value: None }, &S::Return { value: None }) => {}
+                (
+                    &S::Store {
+                        pointer: left_pointer,
+                        value: left_value,
+                    },
+                    &S::Store {
+                        pointer: right_pointer,
+                        value: right_value,
+                    },
+                ) => {
+                    self.expressions(fun, left_pointer, right_pointer)?;
+                    self.expressions(fun, left_value, right_value)?;
+                }
+                _ => return Err(error),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the global usage of the matched functions,
+    /// once all the matching global variables are known.
+    fn global_usage(&self) -> Result<(), ComparisonError> {
+        for (&left_fun, &right_fun) in self.functions.0.iter() {
+            let left_usage = &self.left.functions[left_fun].global_usage;
+            let right_usage = &self.right.functions[right_fun].global_usage;
+            for (&left_var, &right_var) in self.global_variables.0.iter() {
+                if left_usage.get(left_var.index()) != right_usage.get(right_var.index()) {
+                    return Err(ComparisonError::Function(left_fun, right_fun));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares the contents of two modules, ignoring the order of the arenas.
+///
+/// Only the parts reachable from the entry points are compared,
+/// and the module headers and spans are ignored.
+pub fn compare_modules(left: &crate::Module, right: &crate::Module) -> Result<(), ComparisonError> {
+    if left.entry_points.len() != right.entry_points.len() {
+        return Err(ComparisonError::EntryPoints);
+    }
+    let mut comparator = Comparator {
+        left,
+        right,
+        types: HandleMap::new(),
+        constants: HandleMap::new(),
+        global_variables: HandleMap::new(),
+        functions: HandleMap::new(),
+    };
+    for (left_ep, right_ep) in left.entry_points.iter().zip(right.entry_points.iter()) {
+        if left_ep.stage != right_ep.stage || left_ep.name != right_ep.name {
+            return Err(ComparisonError::EntryPoints);
+        }
+        comparator.functions(left_ep.function, right_ep.function)?;
+    }
+    comparator.global_usage()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_modules, ComparisonError};
+
+    #[test]
+    fn reordered_arenas() {
+        let source = "
+            [[location 0]] var<in> a_value : f32;
+            [[location 0]] var<out> o_color : vec4<f32>;
+            fn main() -> void {
+                o_color = vec4<f32>(a_value, 0.0, 0.0, 1.0);
+                return;
+            }
+            entry_point fragment as \"main\" = main;
+        ";
+        let reordered = "
+            [[location 0]] var<out> o_color : vec4<f32>;
+            [[location 0]] var<in> a_value : f32;
+            fn main() -> void {
+                o_color = vec4<f32>(a_value, 0.0, 0.0, 1.0);
+                return;
+            }
+            entry_point fragment as \"main\" = main;
+        ";
+        let changed = "
+            [[location 0]] var<in> a_value : f32;
+            [[location 0]] var<out> o_color : vec4<f32>;
+            fn main() -> void {
+                o_color = vec4<f32>(0.0, a_value, 0.0, 1.0);
+                return;
+            }
+            entry_point fragment as \"main\" = main;
+        ";
+        let module = crate::front::wgsl::parse_str(source).unwrap();
+        compare_modules(&module, &module).unwrap();
+        compare_modules(&module, &crate::front::wgsl::parse_str(reordered).unwrap()).unwrap();
+        match compare_modules(&module, &crate::front::wgsl::parse_str(changed).unwrap()) {
+            Err(ComparisonError::Expression(..)) | Err(ComparisonError::Constant(..)) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
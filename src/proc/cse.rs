@@ -0,0 +1,305 @@
+use super::rewrite;
+use crate::{arena::Handle, FastHashMap};
+
+/// Memory that may be accessed through a pointer.
+#[derive(Clone, Copy, PartialEq)]
+enum Root {
+    Global(Handle<crate::GlobalVariable>),
+    Local(Handle<crate::LocalVariable>),
+    Unknown,
+}
+
+impl Root {
+    fn aliases(self, other: Root) -> bool {
+        self == Root::Unknown || other == Root::Unknown || self == other
+    }
+}
+
+/// Range of statement positions where an expression is evaluated,
+/// from the first use to the last one inclusive.
+#[derive(Clone, Copy)]
+struct Range {
+    start: usize,
+    end: usize,
+}
+
+impl Range {
+    fn union(self, other: Range) -> Range {
+        Range {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+/// Positions of the statements, numbered in the order they are written.
+struct Positions<'a> {
+    expressions: &'a crate::Arena<crate::Expression>,
+    next: usize,
+    ranges: Vec<Option<Range>>,
+    calls: Vec<Option<bool>>,
+    /// Places where memory may be written, including calls.
+    stores: Vec<(usize, Root)>,
+    /// First and last positions of each loop.
+    loops: Vec<Range>,
+}
+
+impl<'a> Positions<'a> {
+    fn new(function: &'a crate::Function) -> Self {
+        let mut positions = Positions {
+            expressions: &function.expressions,
+            next: 1,
+            ranges: vec![None; function.expressions.len()],
+            calls: vec![None; function.expressions.len()],
+            stores: Vec::new(),
+            loops: Vec::new(),
+        };
+        // initializers are evaluated before the body
+        for (_, var) in function.local_variables.iter() {
+            if let Some(init) = var.init {
+                positions.use_expression(init, 0);
+            }
+        }
+        positions.block(&function.body);
+        positions
+    }
+
+    fn root(&self, pointer: Handle<crate::Expression>) -> Root {
+        match self.expressions[pointer] {
+            crate::Expression::Access { base, .. }
+            | crate::Expression::AccessIndex { base, .. } => self.root(base),
+            crate::Expression::GlobalVariable(handle) => Root::Global(handle),
+            crate::Expression::LocalVariable(handle) => Root::Local(handle),
+            _ => Root::Unknown,
+        }
+    }
+
+    /// Checks if evaluating the expression involves a call.
+    fn has_call(&mut self, handle: Handle<crate::Expression>) -> bool {
+        if let Some(result) = self.calls[handle.index()] {
+            return result;
+        }
+        let expressions = self.expressions;
+        let mut result = matches!(expressions[handle], crate::Expression::Call { .. });
+        rewrite::for_each_operand(&expressions[handle], |operand| {
+            result |= self.has_call(operand);
+        });
+        self.calls[handle.index()] = Some(result);
+        result
+    }
+
+    fn use_expression(&mut self, handle: Handle<crate::Expression>, position: usize) {
+        let expressions = self.expressions;
+        let has_call = self.has_call(handle);
+        let range = match self.ranges[handle.index()] {
+            // the operands already cover this position,
+            // but calls are evaluated again at each use
+            Some(range) if range.start <= position && position <= range.end && !has_call => return,
+            Some(range) => range.union(Range {
+                start: position,
+                end: position,
+            }),
+            None => Range {
+                start: position,
+                end: position,
+            },
+        };
+        self.ranges[handle.index()] = Some(range);
+        if let crate::Expression::Call { .. } = expressions[handle] {
+            self.stores.push((position, Root::Unknown));
+        }
+        rewrite::for_each_operand(&expressions[handle], |operand| {
+            self.use_expression(operand, position)
+        });
+    }
+
+    fn block(&mut self, block: &[crate::Statement]) {
+        use crate::Statement as S;
+        for statement in block {
+            let position = self.next;
+            self.next += 1;
+            match *statement {
+                S::Empty | S::Break | S::Continue | S::Kill => {}
+                S::Block(ref b) => self.block(b),
+                S::If {
+                    condition,
+                    ref accept,
+                    ref reject,
+                } => {
+                    self.use_expression(condition, position);
+                    self.block(accept);
+                    self.block(reject);
+                }
+                S::Switch {
+                    selector,
+                    ref cases,
+                    ref default,
+                } => {
+                    self.use_expression(selector, position);
+                    for (case, _) in cases.values() {
+                        self.block(case);
+                    }
+                    self.block(default);
+                }
+                S::Loop {
+                    ref body,
+                    ref continuing,
+                } => {
+                    self.block(body);
+                    self.block(continuing);
+                    self.loops.push(Range {
+                        start: position,
+                        end: self.next - 1,
+                    });
+                }
+                S::Return { value } => {
+                    if let Some(value) = value {
+                        self.use_expression(value, position);
+                    }
+                }
+                S::Store { pointer, value } => {
+                    self.use_expression(pointer, position);
+                    self.use_expression(value, position);
+                    let root = self.root(pointer);
+                    self.stores.push((position, root));
+                }
+            }
+        }
+    }
+
+    /// Checks if the memory may be written between the evaluations in the range.
+    fn is_clobbered(&self, root: Root, mut range: Range) -> bool {
+        // a range reaching into a loop has to hold over all of its iterations,
+        // including the stores at the end of the last statement
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for lp in self.loops.iter() {
+                let overlaps = range.start <= lp.end && lp.start <= range.end;
+                let inside = lp.start < range.start && range.end <= lp.end;
+                let covers = range.start <= lp.start && lp.end < range.end;
+                if overlaps && !inside && !covers {
+                    range = range.union(Range {
+                        start: lp.start,
+                        end: lp.end + 1,
+                    });
+                    changed = true;
+                }
+            }
+        }
+        self.stores.iter().any(|&(position, store_root)| {
+            range.start <= position && position < range.end && store_root.aliases(root)
+        })
+    }
+}
+
+/// Merges the identical expressions of each function.
+///
+/// Expressions reading memory are only merged when nothing may write
+/// to that memory between their evaluations, and calls are never merged.
+pub fn eliminate_common_subexpressions(module: &mut crate::Module) {
+    let function_handles: Vec<_> = module.functions.iter().map(|(handle, _)| handle).collect();
+    for fun_handle in function_handles {
+        let function = module.functions.get_mut(fun_handle);
+        let mut positions = Positions::new(function);
+
+        let mut replacements = FastHashMap::default();
+        let mut seen = FastHashMap::<crate::Expression, Vec<Handle<crate::Expression>>>::default();
+        for (handle, expr) in function.expressions.iter() {
+            let range = match positions.ranges[handle.index()] {
+                Some(range) => range,
+                None => continue,
+            };
+            let root = match *expr {
+                crate::Expression::Call { .. } => continue,
+                crate::Expression::Load { pointer } => Some(positions.root(pointer)),
+                crate::Expression::GlobalVariable(_) | crate::Expression::LocalVariable(_) => {
+                    // variables may be read directly as values
+                    Some(positions.root(handle))
+                }
+                _ => None,
+            };
+            let mut key = expr.clone();
+            rewrite::map_operands(&mut key, |operand| {
+                replacements.get(&operand).cloned().unwrap_or(operand)
+            });
+
+            let same = seen.entry(key).or_default();
+            let found = same.iter().cloned().find(|&other| {
+                let other_range = positions.ranges[other.index()].unwrap();
+                match root {
+                    Some(root) => !positions.is_clobbered(root, range.union(other_range)),
+                    None => true,
+                }
+            });
+            match found {
+                Some(other) => {
+                    let other_range = positions.ranges[other.index()].unwrap();
+                    positions.ranges[other.index()] = Some(range.union(other_range));
+                    replacements.insert(handle, other);
+                }
+                None => same.push(handle),
+            }
+        }
+        if replacements.is_empty() {
+            continue;
+        }
+
+        let expression_handles: Vec<_> = function.expressions.iter().map(|(h, _)| h).collect();
+        for handle in expression_handles {
+            rewrite::map_operands(function.expressions.get_mut(handle), |operand| {
+                replacements.get(&operand).cloned().unwrap_or(operand)
+            });
+        }
+        rewrite::map_block(&mut function.body, &mut |handle| {
+            replacements.get(&handle).cloned().unwrap_or(handle)
+        });
+        let local_handles: Vec<_> = function.local_variables.iter().map(|(h, _)| h).collect();
+        for handle in local_handles {
+            let var = function.local_variables.get_mut(handle);
+            var.init = var
+                .init
+                .map(|init| replacements.get(&init).cloned().unwrap_or(init));
+        }
+
+        let new_handles = rewrite::compact_expressions(function);
+        rewrite::remap_spans(&mut module.spans, fun_handle, &new_handles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::eliminate_common_subexpressions;
+
+    #[test]
+    fn pure_expressions() {
+        let source = "
+            [[location 0]] var<in> a_value : f32;
+            [[location 0]] var<out> o_color : vec4<f32>;
+            fn main() -> void {
+                o_color = vec4<f32>(a_value * 2.0, a_value * 2.0, 0.0, 1.0);
+                return;
+            }
+            entry_point fragment as \"main\" = main;
+        ";
+        let mut module = crate::front::wgsl::parse_str(source).unwrap();
+        eliminate_common_subexpressions(&mut module);
+        crate::proc::Validator::new().validate(&module).unwrap();
+        let (_, function) = module.functions.iter().next().unwrap();
+        let products = function
+            .expressions
+            .iter()
+            .filter(|&(_, expr)| matches!(*expr, crate::Expression::Binary { .. }))
+            .count();
+        assert_eq!(products, 1);
+        match function.body[0] {
+            crate::Statement::Store { value, .. } => match function.expressions[value] {
+                crate::Expression::Compose { ref components, .. } => {
+                    assert_eq!(components[0], components[1]);
+                }
+                ref other => panic!("Unexpected expression {:?}", other),
+            },
+            ref other => panic!("Unexpected statement {:?}", other),
+        }
+    }
+}
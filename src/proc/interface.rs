@@ -64,6 +64,18 @@ impl<'a> Interface<'a> {
             E::Derivative { expr, .. } => {
                 self.add_inputs(expr);
             }
+            E::Select {
+                condition,
+                accept,
+                reject,
+            } => {
+                self.add_inputs(condition);
+                self.add_inputs(accept);
+                self.add_inputs(reject);
+            }
+            E::As { expr, .. } => {
+                self.add_inputs(expr);
+            }
             E::Call { ref arguments, .. } => {
                 for &argument in arguments {
                     self.add_inputs(argument);
@@ -0,0 +1,211 @@
+use crate::arena::{Arena, Handle};
+
+/// Rules for placing data in buffers, following the GLSL specification.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LayoutRule {
+    /// Layout of uniform buffers, aligning arrays and structures to 16 bytes.
+    Std140,
+    /// Layout of storage buffers.
+    Std430,
+}
+
+/// Size and alignment of a type, in bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TypeLayout {
+    pub size: u32,
+    pub alignment: u32,
+}
+
+fn round_up(value: u32, alignment: u32) -> u32 {
+    // alignments are always powers of two
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+impl LayoutRule {
+    /// Alignment of an array element or a structure.
+    fn aggregate_alignment(self, alignment: u32) -> u32 {
+        match self {
+            LayoutRule::Std140 => round_up(alignment, 16),
+            LayoutRule::Std430 => alignment,
+        }
+    }
+
+    pub fn layout(self, types: &Arena<crate::Type>, handle: Handle<crate::Type>) -> TypeLayout {
+        match types[handle].inner {
+            crate::TypeInner::Scalar { width, .. } => TypeLayout {
+                size: width as u32,
+                alignment: width as u32,
+            },
+            crate::TypeInner::Vector { size, width, .. } => TypeLayout {
+                size: size as u32 * width as u32,
+                alignment: match size {
+                    crate::VectorSize::Bi => 2 * width as u32,
+                    crate::VectorSize::Tri | crate::VectorSize::Quad => 4 * width as u32,
+                },
+            },
+            crate::TypeInner::Matrix {
+                columns,
+                rows,
+                width,
+                ..
+            } => {
+                let stride = self.matrix_stride(rows, width);
+                TypeLayout {
+                    size: columns as u32 * stride,
+                    alignment: stride,
+                }
+            }
+            crate::TypeInner::Array { base, size, stride } => {
+                let alignment = self.aggregate_alignment(self.layout(types, base).alignment);
+                let stride = stride.map_or_else(|| self.array_stride(types, base), |s| s.get());
+                TypeLayout {
+                    size: match size {
+                        crate::ArraySize::Static(count) => count * stride,
+                        crate::ArraySize::Dynamic => stride,
+                    },
+                    alignment,
+                }
+            }
+            crate::TypeInner::Struct { ref members } => {
+                let offsets = self.member_offsets(types, members);
+                let mut alignment = 1;
+                let mut end = 0;
+                for (member, &offset) in members.iter().zip(offsets.iter()) {
+                    let layout = self.layout(types, member.ty);
+                    alignment = alignment.max(layout.alignment);
+                    end = end.max(offset + layout.size);
+                }
+                let alignment = self.aggregate_alignment(alignment);
+                TypeLayout {
+                    size: round_up(end, alignment),
+                    alignment,
+                }
+            }
+            // opaque types don't take space in buffers
+            crate::TypeInner::Pointer { .. }
+            | crate::TypeInner::Image { .. }
+            | crate::TypeInner::DepthImage { .. }
+            | crate::TypeInner::Sampler { .. } => TypeLayout {
+                size: 0,
+                alignment: 1,
+            },
+        }
+    }
+
+    /// Distance between the elements of an array without an explicit stride.
+    pub fn array_stride(self, types: &Arena<crate::Type>, base: Handle<crate::Type>) -> u32 {
+        let layout = self.layout(types, base);
+        round_up(layout.size, self.aggregate_alignment(layout.alignment))
+    }
+
+    /// Distance between the columns of a matrix.
+    pub fn matrix_stride(self, rows: crate::VectorSize, width: crate::Bytes) -> u32 {
+        let alignment = match rows {
+            crate::VectorSize::Bi => 2 * width as u32,
+            crate::VectorSize::Tri | crate::VectorSize::Quad => 4 * width as u32,
+        };
+        self.aggregate_alignment(alignment)
+    }
+
+    /// Offsets of the structure members, keeping the explicit ones
+    /// and placing the others right after the previous member.
+    pub fn member_offsets(
+        self,
+        types: &Arena<crate::Type>,
+        members: &[crate::StructMember],
+    ) -> Vec<u32> {
+        let mut offsets = Vec::with_capacity(members.len());
+        let mut end = 0;
+        for member in members {
+            let layout = self.layout(types, member.ty);
+            let offset = match member.origin {
+                crate::MemberOrigin::Offset(offset) => offset,
+                crate::MemberOrigin::Empty | crate::MemberOrigin::BuiltIn(_) => {
+                    round_up(end, layout.alignment)
+                }
+            };
+            offsets.push(offset);
+            end = offset + layout.size;
+        }
+        offsets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LayoutRule, TypeLayout};
+    use crate::{Arena, MemberOrigin, ScalarKind, StructMember, Type, TypeInner, VectorSize};
+
+    #[test]
+    fn std140_and_std430() {
+        let mut types = Arena::new();
+        let float = types.append(Type {
+            name: None,
+            inner: TypeInner::Scalar {
+                kind: ScalarKind::Float,
+                width: 4,
+            },
+        });
+        let vec3 = types.append(Type {
+            name: None,
+            inner: TypeInner::Vector {
+                size: VectorSize::Tri,
+                kind: ScalarKind::Float,
+                width: 4,
+            },
+        });
+        let mat2 = types.append(Type {
+            name: None,
+            inner: TypeInner::Matrix {
+                columns: VectorSize::Bi,
+                rows: VectorSize::Bi,
+                kind: ScalarKind::Float,
+                width: 4,
+            },
+        });
+        let floats = types.append(Type {
+            name: None,
+            inner: TypeInner::Array {
+                base: float,
+                size: crate::ArraySize::Static(3),
+                stride: None,
+            },
+        });
+        let member = |ty| StructMember {
+            name: None,
+            origin: MemberOrigin::Empty,
+            ty,
+        };
+        let members = vec![member(vec3), member(float), member(floats), member(mat2)];
+        let data = types.append(Type {
+            name: None,
+            inner: TypeInner::Struct {
+                members: members.clone(),
+            },
+        });
+
+        let rule = LayoutRule::Std140;
+        assert_eq!(rule.member_offsets(&types, &members), vec![0, 12, 16, 64]);
+        assert_eq!(rule.array_stride(&types, float), 16);
+        assert_eq!(rule.matrix_stride(VectorSize::Bi, 4), 16);
+        assert_eq!(
+            rule.layout(&types, data),
+            TypeLayout {
+                size: 96,
+                alignment: 16,
+            }
+        );
+
+        let rule = LayoutRule::Std430;
+        assert_eq!(rule.member_offsets(&types, &members), vec![0, 12, 16, 32]);
+        assert_eq!(rule.array_stride(&types, float), 4);
+        assert_eq!(rule.matrix_stride(VectorSize::Bi, 4), 8);
+        assert_eq!(
+            rule.layout(&types, data),
+            TypeLayout {
+                size: 48,
+                alignment: 16,
+            }
+        );
+    }
+}
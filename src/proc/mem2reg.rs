@@ -0,0 +1,639 @@
+use super::rewrite;
+use crate::{
+    arena::{Arena, Handle},
+    FastHashMap, FastHashSet,
+};
+
+/// Value last stored into a local variable.
+#[derive(Clone, Copy)]
+struct Value {
+    expr: Handle<crate::Expression>,
+    /// The value depends on the control flow, e.g. with implicit derivatives,
+    /// so it can't be moved into a nested block.
+    sensitive: bool,
+}
+
+type Known = FastHashMap<Handle<crate::LocalVariable>, Value>;
+
+struct Promoter<'a> {
+    function: &'a crate::Function,
+    global_variables: &'a Arena<crate::GlobalVariable>,
+    /// The function may modify global variables through calls.
+    has_calls: bool,
+    candidates: &'a FastHashSet<Handle<crate::LocalVariable>>,
+    /// Value of each load of a candidate, or `None` if it has to stay a load.
+    decisions: FastHashMap<Handle<crate::Expression>, Option<Handle<crate::Expression>>>,
+    /// Candidates with a load that sees different values at different places.
+    conflicts: FastHashSet<Handle<crate::LocalVariable>>,
+}
+
+impl<'a> Promoter<'a> {
+    fn visit(&mut self, handle: Handle<crate::Expression>, known: &Known) {
+        let expressions = &self.function.expressions;
+        if let crate::Expression::Load { pointer } = expressions[handle] {
+            if let crate::Expression::LocalVariable(var) = expressions[pointer] {
+                if self.candidates.contains(&var) {
+                    // the value must already be computed where the load is
+                    let value = known
+                        .get(&var)
+                        .map(|value| value.expr)
+                        .filter(|&expr| expr < handle);
+                    match self.decisions.insert(handle, value) {
+                        Some(previous) if previous != value => {
+                            self.conflicts.insert(var);
+                        }
+                        _ => {}
+                    }
+                }
+                return;
+            }
+        }
+        rewrite::for_each_operand(&expressions[handle], |operand| self.visit(operand, known));
+    }
+
+    fn is_read_only(&self, pointer: Handle<crate::Expression>) -> bool {
+        match self.function.expressions[pointer] {
+            crate::Expression::Access { base, index } => {
+                self.is_read_only(base) && self.forwardable(index).is_some()
+            }
+            crate::Expression::AccessIndex { base, .. } => self.is_read_only(base),
+            crate::Expression::GlobalVariable(handle) => {
+                match self.global_variables[handle].class {
+                    crate::StorageClass::Constant
+                    | crate::StorageClass::Input
+                    | crate::StorageClass::Uniform => true,
+                    _ if self.has_calls => false,
+                    _ => match self.function.global_usage.get(handle.index()) {
+                        Some(usage) => !usage.contains(crate::GlobalUse::STORE),
+                        None => false,
+                    },
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks if the expression evaluates to the same value anywhere in the function,
+    /// returning whether it depends on the control flow.
+    fn forwardable(&self, handle: Handle<crate::Expression>) -> Option<bool> {
+        use crate::Expression as E;
+        let expr = &self.function.expressions[handle];
+        let sensitive = match *expr {
+            E::Load { pointer } => {
+                return match self.function.expressions[pointer] {
+                    E::LocalVariable(_) => match self.decisions.get(&handle) {
+                        Some(&Some(value)) => self.forwardable(value),
+                        _ => None,
+                    },
+                    _ if self.is_read_only(pointer) => Some(false),
+                    _ => None,
+                };
+            }
+            E::LocalVariable(_)
+            | E::Call {
+                origin: crate::FunctionOrigin::Local(_),
+                ..
+            } => return None,
+            E::ImageSample { .. } | E::Derivative { .. } => true,
+            _ => false,
+        };
+        let mut result = Some(sensitive);
+        rewrite::for_each_operand(expr, |operand| {
+            result = match (result, self.forwardable(operand)) {
+                (Some(a), Some(b)) => Some(a || b),
+                _ => None,
+            };
+        });
+        result
+    }
+
+    fn block(&mut self, block: &[crate::Statement], known: &mut Known) {
+        use crate::Statement as S;
+        for statement in block {
+            match *statement {
+                S::Empty | S::Break | S::Continue | S::Kill => {}
+                S::Block(ref b) => self.block(b, known),
+                S::If {
+                    condition,
+                    ref accept,
+                    ref reject,
+                } => {
+                    self.visit(condition, known);
+                    self.branch(accept, known);
+                    self.branch(reject, known);
+                    forget_stored(&self.function.expressions, accept, known);
+                    forget_stored(&self.function.expressions, reject, known);
+                }
+                S::Switch {
+                    selector,
+                    ref cases,
+                    ref default,
+                } => {
+                    self.visit(selector, known);
+                    for (case, _) in cases.values() {
+                        self.branch(case, known);
+                    }
+                    self.branch(default, known);
+                    for (case, _) in cases.values() {
+                        forget_stored(&self.function.expressions, case, known);
+                    }
+                    forget_stored(&self.function.expressions, default, known);
+                }
+                S::Loop {
+                    ref body,
+                    ref continuing,
+                } => {
+                    // values stored in the loop are unknown from the second iteration on
+                    forget_stored(&self.function.expressions, body, known);
+                    forget_stored(&self.function.expressions, continuing, known);
+                    self.branch(body, known);
+                    self.branch(continuing, known);
+                }
+                S::Return { value } => {
+                    if let Some(value) = value {
+                        self.visit(value, known);
+                    }
+                }
+                S::Store { pointer, value } => {
+                    self.visit(value, known);
+                    match self.function.expressions[pointer] {
+                        crate::Expression::LocalVariable(var) if self.candidates.contains(&var) => {
+                            match self.forwardable(value) {
+                                Some(sensitive) => {
+                                    known.insert(
+                                        var,
+                                        Value {
+                                            expr: value,
+                                            sensitive,
+                                        },
+                                    );
+                                }
+                                None => {
+                                    known.remove(&var);
+                                }
+                            }
+                        }
+                        _ => self.visit(pointer, known),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Processes a block with different control flow.
+    fn branch(&mut self, block: &[crate::Statement], known: &Known) {
+        let mut inner = known
+            .iter()
+            .filter(|&(_, value)| !value.sensitive)
+            .map(|(&var, &value)| (var, value))
+            .collect();
+        self.block(block, &mut inner);
+    }
+}
+
+fn forget_stored(
+    expressions: &Arena<crate::Expression>,
+    block: &[crate::Statement],
+    known: &mut Known,
+) {
+    use crate::Statement as S;
+    for statement in block {
+        match *statement {
+            S::Block(ref b) => forget_stored(expressions, b, known),
+            S::If {
+                ref accept,
+                ref reject,
+                ..
+            } => {
+                forget_stored(expressions, accept, known);
+                forget_stored(expressions, reject, known);
+            }
+            S::Switch {
+                ref cases,
+                ref default,
+                ..
+            } => {
+                for (case, _) in cases.values() {
+                    forget_stored(expressions, case, known);
+                }
+                forget_stored(expressions, default, known);
+            }
+            S::Loop {
+                ref body,
+                ref continuing,
+            } => {
+                forget_stored(expressions, body, known);
+                forget_stored(expressions, continuing, known);
+            }
+            S::Store { pointer, .. } => {
+                if let crate::Expression::LocalVariable(var) = expressions[pointer] {
+                    known.remove(&var);
+                }
+            }
+            S::Empty | S::Break | S::Continue | S::Kill | S::Return { .. } => {}
+        }
+    }
+}
+
+/// Calls `f` on the expressions used by the statements, except for the store pointers.
+fn for_each_value(block: &[crate::Statement], f: &mut impl FnMut(Handle<crate::Expression>)) {
+    use crate::Statement as S;
+    for statement in block {
+        match *statement {
+            S::Empty | S::Break | S::Continue | S::Kill => {}
+            S::Block(ref b) => for_each_value(b, f),
+            S::If {
+                condition,
+                ref accept,
+                ref reject,
+            } => {
+                f(condition);
+                for_each_value(accept, f);
+                for_each_value(reject, f);
+            }
+            S::Switch {
+                selector,
+                ref cases,
+                ref default,
+            } => {
+                f(selector);
+                for (case, _) in cases.values() {
+                    for_each_value(case, f);
+                }
+                for_each_value(default, f);
+            }
+            S::Loop {
+                ref body,
+                ref continuing,
+            } => {
+                for_each_value(body, f);
+                for_each_value(continuing, f);
+            }
+            S::Return { value } => {
+                if let Some(value) = value {
+                    f(value);
+                }
+            }
+            S::Store { value, .. } => f(value),
+        }
+    }
+}
+
+/// Collects the local variables whose address isn't taken,
+/// i.e. which are only loaded and stored as a whole.
+fn collect_candidates(function: &crate::Function) -> FastHashSet<Handle<crate::LocalVariable>> {
+    let mut candidates: FastHashSet<_> = function
+        .local_variables
+        .iter()
+        .map(|(handle, _)| handle)
+        .collect();
+    let expressions = &function.expressions;
+    let mut address_taken = |operand| {
+        if let crate::Expression::LocalVariable(var) = expressions[operand] {
+            candidates.remove(&var);
+        }
+    };
+    for (_, expr) in expressions.iter() {
+        if let crate::Expression::Load { .. } = *expr {
+            continue;
+        }
+        rewrite::for_each_operand(expr, &mut address_taken);
+    }
+    for_each_value(&function.body, &mut address_taken);
+    for (_, var) in function.local_variables.iter() {
+        if let Some(init) = var.init {
+            address_taken(init);
+        }
+    }
+    candidates
+}
+
+/// Removes the stores into the given variables.
+fn remove_stores(
+    expressions: &Arena<crate::Expression>,
+    block: &mut crate::Block,
+    vars: &FastHashSet<Handle<crate::LocalVariable>>,
+) {
+    use crate::Statement as S;
+    block.retain(|statement| match *statement {
+        S::Store { pointer, .. } => match expressions[pointer] {
+            crate::Expression::LocalVariable(var) => !vars.contains(&var),
+            _ => true,
+        },
+        _ => true,
+    });
+    for statement in block.iter_mut() {
+        match *statement {
+            S::Block(ref mut b) => remove_stores(expressions, b, vars),
+            S::If {
+                ref mut accept,
+                ref mut reject,
+                ..
+            } => {
+                remove_stores(expressions, accept, vars);
+                remove_stores(expressions, reject, vars);
+            }
+            S::Switch {
+                ref mut cases,
+                ref mut default,
+                ..
+            } => {
+                for (case, _) in cases.values_mut() {
+                    remove_stores(expressions, case, vars);
+                }
+                remove_stores(expressions, default, vars);
+            }
+            S::Loop {
+                ref mut body,
+                ref mut continuing,
+            } => {
+                remove_stores(expressions, body, vars);
+                remove_stores(expressions, continuing, vars);
+            }
+            S::Empty | S::Break | S::Continue | S::Kill | S::Return { .. } | S::Store { .. } => {}
+        }
+    }
+}
+
+/// Finds the values of the loads that can be replaced.
+fn find_replacements(
+    function: &crate::Function,
+    global_variables: &Arena<crate::GlobalVariable>,
+    candidates: &mut FastHashSet<Handle<crate::LocalVariable>>,
+) -> FastHashMap<Handle<crate::Expression>, Handle<crate::Expression>> {
+    let has_calls = function.expressions.iter().any(|(_, expr)| {
+        matches!(
+            *expr,
+            crate::Expression::Call {
+                origin: crate::FunctionOrigin::Local(_),
+                ..
+            }
+        )
+    });
+    loop {
+        let mut promoter = Promoter {
+            function,
+            global_variables,
+            has_calls,
+            candidates,
+            decisions: FastHashMap::default(),
+            conflicts: FastHashSet::default(),
+        };
+        let mut known = Known::default();
+        for (handle, var) in function.local_variables.iter() {
+            if let Some(init) = var.init {
+                promoter.visit(init, &known);
+                if let (true, Some(sensitive)) =
+                    (candidates.contains(&handle), promoter.forwardable(init))
+                {
+                    known.insert(
+                        handle,
+                        Value {
+                            expr: init,
+                            sensitive,
+                        },
+                    );
+                }
+            }
+        }
+        promoter.block(&function.body, &mut known);
+
+        if promoter.conflicts.is_empty() {
+            return promoter
+                .decisions
+                .into_iter()
+                .filter_map(|(load, value)| value.map(|value| (load, value)))
+                .collect();
+        }
+        // try again, keeping these variables in memory
+        for var in promoter.conflicts {
+            candidates.remove(&var);
+        }
+    }
+}
+
+/// Promotes the local variables which aren't address-taken into expressions.
+///
+/// Loads of these variables are replaced by the values last stored into them,
+/// when these values are known at the place of the load and don't depend on
+/// anything that could have changed in between. The variables that don't have
+/// any loads left are removed, together with their stores.
+pub fn promote_local_variables(module: &mut crate::Module) {
+    let function_handles: Vec<_> = module.functions.iter().map(|(handle, _)| handle).collect();
+    for fun_handle in function_handles {
+        let function = module.functions.get_mut(fun_handle);
+        let mut candidates = collect_candidates(function);
+        let replacements = find_replacements(function, &module.global_variables, &mut candidates);
+        if replacements.is_empty() {
+            continue;
+        }
+
+        let resolve = |mut handle| {
+            while let Some(&value) = replacements.get(&handle) {
+                handle = value;
+            }
+            handle
+        };
+        let expression_handles: Vec<_> = function.expressions.iter().map(|(h, _)| h).collect();
+        for &handle in expression_handles.iter() {
+            rewrite::map_operands(function.expressions.get_mut(handle), resolve);
+        }
+        rewrite::map_block(&mut function.body, &mut |handle| resolve(handle));
+
+        // variables with all the loads replaced aren't needed anymore
+        for (handle, expr) in function.expressions.iter() {
+            if let crate::Expression::Load { pointer } = *expr {
+                if let crate::Expression::LocalVariable(var) = function.expressions[pointer] {
+                    if !replacements.contains_key(&handle) {
+                        candidates.remove(&var);
+                    }
+                }
+            }
+        }
+        remove_stores(&function.expressions, &mut function.body, &candidates);
+        let mut local_variables = Arena::new();
+        let mut local_handles = FastHashMap::default();
+        for (handle, var) in function.local_variables.iter() {
+            if !candidates.contains(&handle) {
+                local_handles.insert(handle, local_variables.append(var.clone()));
+            }
+        }
+        function.local_variables = local_variables;
+
+        let new_handles = rewrite::compact_expressions(function);
+        let expression_handles: Vec<_> = function.expressions.iter().map(|(h, _)| h).collect();
+        for handle in expression_handles {
+            if let crate::Expression::LocalVariable(ref mut var) =
+                *function.expressions.get_mut(handle)
+            {
+                *var = local_handles[var];
+            }
+        }
+
+        rewrite::remap_spans(&mut module.spans, fun_handle, &new_handles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::promote_local_variables;
+    use crate::{
+        arena::{Arena, Handle},
+        Expression as E, Statement as S,
+    };
+
+    /// Makes a module with a function returning `f32`, with a local variable `x`
+    /// and the constants `0.0` and `1.0`.
+    fn make_module() -> (crate::Module, crate::Function, [Handle<crate::Constant>; 2]) {
+        let mut module = crate::Module {
+            header: crate::Header {
+                version: (1, 0, 0),
+                generator: 0,
+            },
+            types: Arena::new(),
+            constants: Arena::new(),
+            global_variables: Arena::new(),
+            functions: Arena::new(),
+            entry_points: Vec::new(),
+            spans: crate::SpanTable::default(),
+        };
+        let ty = module.types.append(crate::Type {
+            name: None,
+            inner: crate::TypeInner::Scalar {
+                kind: crate::ScalarKind::Float,
+                width: 4,
+            },
+        });
+        let mut constant = |value| {
+            module.constants.append(crate::Constant {
+                name: None,
+                specialization: None,
+                inner: crate::ConstantInner::Float(value),
+                ty,
+            })
+        };
+        let constants = [constant(0.0), constant(1.0)];
+        let mut function = crate::Function {
+            name: Some("main".to_string()),
+            parameter_types: Vec::new(),
+            parameter_names: Vec::new(),
+            return_type: Some(ty),
+            global_usage: Vec::new(),
+            local_variables: Arena::new(),
+            expressions: Arena::new(),
+            body: Vec::new(),
+        };
+        function.local_variables.append(crate::LocalVariable {
+            name: Some("x".to_string()),
+            ty,
+            init: None,
+        });
+        (module, function, constants)
+    }
+
+    fn is_local_load(function: &crate::Function, handle: Handle<crate::Expression>) -> bool {
+        match function.expressions[handle] {
+            E::Load { pointer } => matches!(function.expressions[pointer], E::LocalVariable(_)),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn replaced_load() {
+        let (mut module, mut function, [zero, _]) = make_module();
+        let (var, _) = function.local_variables.iter().next().unwrap();
+        let pointer = function.expressions.append(E::LocalVariable(var));
+        let value = function.expressions.append(E::Constant(zero));
+        let load = function.expressions.append(E::Load { pointer });
+        function.body = vec![S::Store { pointer, value }, S::Return { value: Some(load) }];
+        module.functions.append(function);
+
+        promote_local_variables(&mut module);
+        let (_, function) = module.functions.iter().next().unwrap();
+        assert_eq!(function.local_variables.iter().count(), 0);
+        match function.body[..] {
+            [S::Return { value: Some(value) }] => {
+                assert_eq!(function.expressions[value], E::Constant(zero));
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn store_in_loop() {
+        // the load in the loop sees `0.0` on the first iteration only,
+        // and the value stored in the loop after that
+        let (mut module, mut function, [zero, one]) = make_module();
+        let (var, _) = function.local_variables.iter().next().unwrap();
+        let pointer = function.expressions.append(E::LocalVariable(var));
+        let initial = function.expressions.append(E::Constant(zero));
+        let step = function.expressions.append(E::Constant(one));
+        let load = function.expressions.append(E::Load { pointer });
+        let sum = function.expressions.append(E::Binary {
+            op: crate::BinaryOperator::Add,
+            left: load,
+            right: step,
+        });
+        let result = function.expressions.append(E::Load { pointer });
+        function.body = vec![
+            S::Store {
+                pointer,
+                value: initial,
+            },
+            S::Loop {
+                body: vec![
+                    S::Store {
+                        pointer,
+                        value: sum,
+                    },
+                    S::Break,
+                ],
+                continuing: Vec::new(),
+            },
+            S::Return {
+                value: Some(result),
+            },
+        ];
+        module.functions.append(function);
+
+        promote_local_variables(&mut module);
+        let (_, function) = module.functions.iter().next().unwrap();
+        assert_eq!(function.local_variables.iter().count(), 1);
+        match function.body[..] {
+            [S::Store { .. }, S::Loop { ref body, .. }, S::Return {
+                value: Some(result),
+            }] => {
+                match body[..] {
+                    [S::Store { value, .. }, S::Break] => match function.expressions[value] {
+                        E::Binary { left, .. } => assert!(is_local_load(function, left)),
+                        ref other => panic!("Unexpected expression {:?}", other),
+                    },
+                    ref other => panic!("Unexpected loop body {:?}", other),
+                }
+                assert!(is_local_load(function, result));
+            }
+            ref other => panic!("Unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn implicit_reads() {
+        // variables used directly as values are read where the expression is evaluated,
+        // which can't be told apart from the stores to them, so they are left alone
+        let source = "
+            [[location 0]] var<in> a_value : f32;
+            [[location 0]] var<out> o_color : vec4<f32>;
+            fn main() -> void {
+                var x : f32 = a_value;
+                x = x * 2.0;
+                o_color = vec4<f32>(x, 0.0, 0.0, 1.0);
+                return;
+            }
+            entry_point fragment as \"main\" = main;
+        ";
+        let module = crate::front::wgsl::parse_str(source).unwrap();
+        let mut promoted = crate::front::wgsl::parse_str(source).unwrap();
+        promote_local_variables(&mut promoted);
+        crate::proc::compare_modules(&module, &promoted).unwrap();
+    }
+}
@@ -1,8 +1,17 @@
 //! Module processing functionality.
 
+mod comparator;
+mod cse;
 mod interface;
+mod layouter;
+mod mem2reg;
+mod rewrite;
 mod typifier;
 mod validator;
 
+pub use comparator::{compare_modules, ComparisonError};
+pub use cse::eliminate_common_subexpressions;
+pub use layouter::{LayoutRule, TypeLayout};
+pub use mem2reg::promote_local_variables;
 pub use typifier::{check_constant_types, ResolveError, Typifier, UnexpectedConstantTypeError};
 pub use validator::{ValidationError, Validator};
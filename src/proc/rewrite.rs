@@ -0,0 +1,258 @@
+//! Helpers for the passes rewriting function bodies.
+
+use crate::{
+    arena::{Arena, Handle},
+    FastHashMap,
+};
+
+/// Calls `f` on every expression the given one refers to.
+pub(super) fn for_each_operand(
+    expression: &crate::Expression,
+    mut f: impl FnMut(Handle<crate::Expression>),
+) {
+    use crate::Expression as E;
+    match *expression {
+        E::Access { base, index } => {
+            f(base);
+            f(index);
+        }
+        E::AccessIndex { base, .. } => f(base),
+        E::Constant(_) | E::FunctionParameter(_) | E::GlobalVariable(_) | E::LocalVariable(_) => {}
+        E::Compose { ref components, .. } => {
+            for &component in components {
+                f(component);
+            }
+        }
+        E::Load { pointer } => f(pointer),
+        E::ImageSample {
+            image,
+            sampler,
+            coordinate,
+            depth_ref,
+        } => {
+            f(image);
+            f(sampler);
+            f(coordinate);
+            if let Some(depth_ref) = depth_ref {
+                f(depth_ref);
+            }
+        }
+        E::Unary { expr, .. }
+        | E::Intrinsic { argument: expr, .. }
+        | E::Derivative { expr, .. }
+        | E::As { expr, .. } => f(expr),
+        E::Binary { left, right, .. }
+        | E::DotProduct(left, right)
+        | E::CrossProduct(left, right) => {
+            f(left);
+            f(right);
+        }
+        E::Select {
+            condition,
+            accept,
+            reject,
+        } => {
+            f(condition);
+            f(accept);
+            f(reject);
+        }
+        E::Call { ref arguments, .. } => {
+            for &argument in arguments {
+                f(argument);
+            }
+        }
+    }
+}
+
+/// Replaces every expression the given one refers to by the result of `f`.
+pub(super) fn map_operands(
+    expression: &mut crate::Expression,
+    mut f: impl FnMut(Handle<crate::Expression>) -> Handle<crate::Expression>,
+) {
+    use crate::Expression as E;
+    match *expression {
+        E::Access {
+            ref mut base,
+            ref mut index,
+        } => {
+            *base = f(*base);
+            *index = f(*index);
+        }
+        E::AccessIndex { ref mut base, .. } => *base = f(*base),
+        E::Constant(_) | E::FunctionParameter(_) | E::GlobalVariable(_) | E::LocalVariable(_) => {}
+        E::Compose {
+            ref mut components, ..
+        } => {
+            for component in components.iter_mut() {
+                *component = f(*component);
+            }
+        }
+        E::Load { ref mut pointer } => *pointer = f(*pointer),
+        E::ImageSample {
+            ref mut image,
+            ref mut sampler,
+            ref mut coordinate,
+            ref mut depth_ref,
+        } => {
+            *image = f(*image);
+            *sampler = f(*sampler);
+            *coordinate = f(*coordinate);
+            if let Some(ref mut depth_ref) = *depth_ref {
+                *depth_ref = f(*depth_ref);
+            }
+        }
+        E::Unary { ref mut expr, .. }
+        | E::Intrinsic {
+            argument: ref mut expr,
+            ..
+        }
+        | E::Derivative { ref mut expr, .. }
+        | E::As { ref mut expr, .. } => *expr = f(*expr),
+        E::Binary {
+            ref mut left,
+            ref mut right,
+            ..
+        }
+        | E::DotProduct(ref mut left, ref mut right)
+        | E::CrossProduct(ref mut left, ref mut right) => {
+            *left = f(*left);
+            *right = f(*right);
+        }
+        E::Select {
+            ref mut condition,
+            ref mut accept,
+            ref mut reject,
+        } => {
+            *condition = f(*condition);
+            *accept = f(*accept);
+            *reject = f(*reject);
+        }
+        E::Call {
+            ref mut arguments, ..
+        } => {
+            for argument in arguments.iter_mut() {
+                *argument = f(*argument);
+            }
+        }
+    }
+}
+
+/// Replaces every expression referred to by the statements of `block` by the result of `f`.
+pub(super) fn map_block(
+    block: &mut [crate::Statement],
+    f: &mut impl FnMut(Handle<crate::Expression>) -> Handle<crate::Expression>,
+) {
+    use crate::Statement as S;
+    for statement in block.iter_mut() {
+        match *statement {
+            S::Empty | S::Break | S::Continue | S::Kill => {}
+            S::Block(ref mut b) => map_block(b, f),
+            S::If {
+                ref mut condition,
+                ref mut accept,
+                ref mut reject,
+            } => {
+                *condition = f(*condition);
+                map_block(accept, f);
+                map_block(reject, f);
+            }
+            S::Switch {
+                ref mut selector,
+                ref mut cases,
+                ref mut default,
+            } => {
+                *selector = f(*selector);
+                for (case, _) in cases.values_mut() {
+                    map_block(case, f);
+                }
+                map_block(default, f);
+            }
+            S::Loop {
+                ref mut body,
+                ref mut continuing,
+            } => {
+                map_block(body, f);
+                map_block(continuing, f);
+            }
+            S::Return { ref mut value } => {
+                if let Some(ref mut value) = *value {
+                    *value = f(*value);
+                }
+            }
+            S::Store {
+                ref mut pointer,
+                ref mut value,
+            } => {
+                *pointer = f(*pointer);
+                *value = f(*value);
+            }
+        }
+    }
+}
+
+/// Removes the expressions that aren't used by the function body
+/// or by the initializers of its local variables, keeping the order of the others.
+///
+/// Returns the new handles of the remaining expressions.
+pub(super) fn compact_expressions(
+    function: &mut crate::Function,
+) -> FastHashMap<Handle<crate::Expression>, Handle<crate::Expression>> {
+    let mut used = vec![false; function.expressions.len()];
+    let mut stack = Vec::new();
+    map_block(&mut function.body, &mut |handle| {
+        stack.push(handle);
+        handle
+    });
+    stack.extend(
+        function
+            .local_variables
+            .iter()
+            .filter_map(|(_, var)| var.init),
+    );
+    while let Some(handle) = stack.pop() {
+        if !used[handle.index()] {
+            used[handle.index()] = true;
+            for_each_operand(&function.expressions[handle], |operand| stack.push(operand));
+        }
+    }
+
+    let mut new_handles = FastHashMap::default();
+    let mut expressions = Arena::new();
+    for (handle, expression) in function.expressions.iter() {
+        if used[handle.index()] {
+            let mut expression = expression.clone();
+            map_operands(&mut expression, |operand| new_handles[&operand]);
+            new_handles.insert(handle, expressions.append(expression));
+        }
+    }
+    function.expressions = expressions;
+
+    map_block(&mut function.body, &mut |handle| new_handles[&handle]);
+    let local_handles: Vec<_> = function.local_variables.iter().map(|(h, _)| h).collect();
+    for handle in local_handles {
+        let var = function.local_variables.get_mut(handle);
+        var.init = var.init.map(|init| new_handles[&init]);
+    }
+    new_handles
+}
+
+/// Moves the expression spans of a function to the new handles of its expressions,
+/// dropping the spans of the removed ones.
+pub(super) fn remap_spans(
+    spans: &mut crate::SpanTable,
+    function: Handle<crate::Function>,
+    new_handles: &FastHashMap<Handle<crate::Expression>, Handle<crate::Expression>>,
+) {
+    let old_spans: Vec<_> = spans
+        .expressions
+        .iter()
+        .filter(|&(&(fun, _), _)| fun == function)
+        .map(|(&(_, expr), &span)| (expr, span))
+        .collect();
+    for (expr, span) in old_spans {
+        spans.expressions.remove(&(function, expr));
+        if let Some(&new_expr) = new_handles.get(&expr) {
+            spans.expressions.insert((function, new_expr), span);
+        }
+    }
+}
@@ -17,6 +17,10 @@ pub enum ResolveError {
     FunctionNotDefined { name: String },
     #[error("Function without return type")]
     FunctionReturnsVoid,
+    #[error("Function parameter {0} is out of range")]
+    InvalidParameter(u32),
+    #[error("Incompatible operand {operand} for {op}")]
+    IncompatibleOperand { op: String, operand: String },
 }
 
 impl Typifier {
@@ -34,6 +38,7 @@ impl Typifier {
         global_vars: &Arena<crate::GlobalVariable>,
         local_vars: &Arena<crate::LocalVariable>,
         functions: &Arena<crate::Function>,
+        parameter_types: &[Handle<crate::Type>],
     ) -> Result<Handle<crate::Type>, ResolveError> {
         if self.types.len() <= expr_handle.index() {
             for (eh, expr) in expressions.iter().skip(self.types.len()) {
@@ -41,6 +46,20 @@ impl Typifier {
                     crate::Expression::Access { base, .. } => {
                         match types[self.types[base.index()]].inner {
                             crate::TypeInner::Array { base, .. } => base,
+                            crate::TypeInner::Vector { kind, width, .. } => {
+                                let inner = crate::TypeInner::Scalar { kind, width };
+                                Self::deduce_type_handle(inner, types)
+                            }
+                            crate::TypeInner::Matrix {
+                                rows, kind, width, ..
+                            } => {
+                                let inner = crate::TypeInner::Vector {
+                                    size: rows,
+                                    kind,
+                                    width,
+                                };
+                                Self::deduce_type_handle(inner, types)
+                            }
                             ref other => panic!("Can't access into {:?}", other),
                         }
                     }
@@ -81,10 +100,19 @@ impl Typifier {
                     }
                     crate::Expression::Constant(h) => constants[h].ty,
                     crate::Expression::Compose { ty, .. } => ty,
-                    crate::Expression::FunctionParameter(_) => unimplemented!(),
+                    crate::Expression::FunctionParameter(index) => parameter_types
+                        .get(index as usize)
+                        .cloned()
+                        .ok_or(ResolveError::InvalidParameter(index))?,
                     crate::Expression::GlobalVariable(h) => global_vars[h].ty,
                     crate::Expression::LocalVariable(h) => local_vars[h].ty,
-                    crate::Expression::Load { .. } => unimplemented!(),
+                    crate::Expression::Load { pointer } => {
+                        let ty_handle = self.types[pointer.index()];
+                        match types[ty_handle].inner {
+                            crate::TypeInner::Pointer { base, .. } => base,
+                            _ => ty_handle,
+                        }
+                    }
                     crate::Expression::ImageSample { image, .. } => {
                         let image = self.resolve(
                             image,
@@ -94,31 +122,39 @@ impl Typifier {
                             global_vars,
                             local_vars,
                             functions,
+                            parameter_types,
                         )?;
 
-                        let (kind, width) = match types[image].inner {
+                        let inner = match types[image].inner {
                             TypeInner::Image { base, .. } => match types[base].inner {
-                                TypeInner::Scalar { kind, width } => (kind, width),
+                                TypeInner::Scalar { kind, width } => TypeInner::Vector {
+                                    kind,
+                                    width,
+                                    size: VectorSize::Quad,
+                                },
                                 _ => unimplemented!(),
                             },
+                            TypeInner::DepthImage { .. } => TypeInner::Scalar {
+                                kind: crate::ScalarKind::Float,
+                                width: 4,
+                            },
                             _ => unreachable!(),
                         };
 
-                        types.fetch_or_append(Type {
-                            name: None,
-                            inner: TypeInner::Vector {
-                                kind,
-                                width,
-                                size: VectorSize::Quad,
-                            },
-                        })
+                        types.fetch_or_append(Type { name: None, inner })
                     }
                     crate::Expression::Unary { expr, .. } => self.types[expr.index()],
                     crate::Expression::Binary { op, left, right } => match op {
                         crate::BinaryOperator::Add
                         | crate::BinaryOperator::Subtract
                         | crate::BinaryOperator::Divide
-                        | crate::BinaryOperator::Modulo => self.types[left.index()],
+                        | crate::BinaryOperator::Modulo => {
+                            let ty_left = self.types[left.index()];
+                            match types[ty_left].inner {
+                                crate::TypeInner::Scalar { .. } => self.types[right.index()],
+                                _ => ty_left,
+                            }
+                        }
                         crate::BinaryOperator::Multiply => {
                             let ty_left = self.types[left.index()];
                             let ty_right = self.types[right.index()];
@@ -128,24 +164,45 @@ impl Typifier {
                                 ty_left
                             } else if let crate::TypeInner::Scalar { .. } = types[ty_left].inner {
                                 ty_right
-                            } else if let crate::TypeInner::Matrix {
-                                columns,
-                                kind,
-                                width,
-                                ..
-                            } = types[ty_left].inner
-                            {
-                                let inner = crate::TypeInner::Vector {
-                                    size: columns,
-                                    kind,
-                                    width,
+                            } else {
+                                let inner = match (&types[ty_left].inner, &types[ty_right].inner) {
+                                    (
+                                        &crate::TypeInner::Matrix {
+                                            rows, kind, width, ..
+                                        },
+                                        &crate::TypeInner::Vector { .. },
+                                    ) => crate::TypeInner::Vector {
+                                        size: rows,
+                                        kind,
+                                        width,
+                                    },
+                                    (
+                                        &crate::TypeInner::Vector { kind, width, .. },
+                                        &crate::TypeInner::Matrix { columns, .. },
+                                    ) => crate::TypeInner::Vector {
+                                        size: columns,
+                                        kind,
+                                        width,
+                                    },
+                                    (
+                                        &crate::TypeInner::Matrix {
+                                            rows, kind, width, ..
+                                        },
+                                        &crate::TypeInner::Matrix { columns, .. },
+                                    ) => crate::TypeInner::Matrix {
+                                        columns,
+                                        rows,
+                                        kind,
+                                        width,
+                                    },
+                                    (left, right) => {
+                                        return Err(ResolveError::IncompatibleOperand {
+                                            op: "multiply".to_string(),
+                                            operand: format!("{:?} x {:?}", left, right),
+                                        })
+                                    }
                                 };
                                 Self::deduce_type_handle(inner, types)
-                            } else {
-                                panic!(
-                                    "Incompatible arguments {:?} x {:?}",
-                                    types[ty_left], types[ty_right]
-                                );
                             }
                         }
                         crate::BinaryOperator::Equal
@@ -153,9 +210,13 @@ impl Typifier {
                         | crate::BinaryOperator::Less
                         | crate::BinaryOperator::LessEqual
                         | crate::BinaryOperator::Greater
-                        | crate::BinaryOperator::GreaterEqual
-                        | crate::BinaryOperator::LogicalAnd
-                        | crate::BinaryOperator::LogicalOr => self.types[left.index()],
+                        | crate::BinaryOperator::GreaterEqual => {
+                            let ty_handle = self.types[left.index()];
+                            Self::deduce_bool_type_handle(ty_handle, types)
+                        }
+                        crate::BinaryOperator::LogicalAnd | crate::BinaryOperator::LogicalOr => {
+                            self.types[left.index()]
+                        }
                         crate::BinaryOperator::And
                         | crate::BinaryOperator::ExclusiveOr
                         | crate::BinaryOperator::InclusiveOr
@@ -163,10 +224,58 @@ impl Typifier {
                         | crate::BinaryOperator::ShiftRightLogical
                         | crate::BinaryOperator::ShiftRightArithmetic => self.types[left.index()],
                     },
-                    crate::Expression::Intrinsic { .. } => unimplemented!(),
-                    crate::Expression::DotProduct(_, _) => unimplemented!(),
-                    crate::Expression::CrossProduct(_, _) => unimplemented!(),
-                    crate::Expression::Derivative { .. } => unimplemented!(),
+                    crate::Expression::Intrinsic { fun, argument } => match fun {
+                        crate::IntrinsicFunction::Any | crate::IntrinsicFunction::All => {
+                            let inner = crate::TypeInner::Scalar {
+                                kind: crate::ScalarKind::Bool,
+                                width: 1,
+                            };
+                            Self::deduce_type_handle(inner, types)
+                        }
+                        crate::IntrinsicFunction::IsNan
+                        | crate::IntrinsicFunction::IsInf
+                        | crate::IntrinsicFunction::IsFinite
+                        | crate::IntrinsicFunction::IsNormal => {
+                            let ty_handle = self.types[argument.index()];
+                            Self::deduce_bool_type_handle(ty_handle, types)
+                        }
+                    },
+                    crate::Expression::DotProduct(left, _) => {
+                        let ty_handle = self.types[left.index()];
+                        let inner = match types[ty_handle].inner {
+                            crate::TypeInner::Vector { kind, width, .. } => {
+                                crate::TypeInner::Scalar { kind, width }
+                            }
+                            ref other => {
+                                return Err(ResolveError::IncompatibleOperand {
+                                    op: "dot".to_string(),
+                                    operand: format!("{:?}", other),
+                                })
+                            }
+                        };
+                        Self::deduce_type_handle(inner, types)
+                    }
+                    crate::Expression::CrossProduct(left, _) => self.types[left.index()],
+                    crate::Expression::Derivative { expr, .. } => self.types[expr.index()],
+                    crate::Expression::Select { accept, .. } => self.types[accept.index()],
+                    crate::Expression::As { expr, kind, .. } => {
+                        let ty_handle = self.types[expr.index()];
+                        let inner = match types[ty_handle].inner {
+                            crate::TypeInner::Scalar { width, .. } => {
+                                crate::TypeInner::Scalar { kind, width }
+                            }
+                            crate::TypeInner::Vector { size, width, .. } => {
+                                crate::TypeInner::Vector { size, kind, width }
+                            }
+                            ref other => {
+                                return Err(ResolveError::IncompatibleOperand {
+                                    op: "as".to_string(),
+                                    operand: format!("{:?}", other),
+                                })
+                            }
+                        };
+                        Self::deduce_type_handle(inner, types)
+                    }
                     crate::Expression::Call {
                         origin: crate::FunctionOrigin::External(ref name),
                         ref arguments,
@@ -181,8 +290,40 @@ impl Typifier {
                             };
                             Self::deduce_type_handle(inner, types)
                         }
-                        "normalize" | "fclamp" | "max" | "reflect" | "pow" | "clamp" | "mix" => {
-                            self.types[arguments[0].index()]
+                        "arrayLength" => Self::deduce_type_handle(
+                            crate::TypeInner::Scalar {
+                                kind: crate::ScalarKind::Uint,
+                                width: 4,
+                            },
+                            types,
+                        ),
+                        "normalize" | "fclamp" | "max" | "min" | "reflect" | "pow" | "clamp"
+                        | "mix" | "abs" | "sign" | "floor" | "ceil" | "fract" | "sqrt"
+                        | "inversesqrt" | "exp" | "exp2" | "log" | "log2" | "sin" | "cos"
+                        | "tan" | "asin" | "acos" | "atan" | "atan2" | "step" | "smoothstep"
+                        | "cross" | "faceforward" => self.types[arguments[0].index()],
+                        "transpose" => {
+                            let ty_handle = self.types[arguments[0].index()];
+                            let inner = match types[ty_handle].inner {
+                                crate::TypeInner::Matrix {
+                                    columns,
+                                    rows,
+                                    kind,
+                                    width,
+                                } => crate::TypeInner::Matrix {
+                                    columns: rows,
+                                    rows: columns,
+                                    kind,
+                                    width,
+                                },
+                                ref other => {
+                                    return Err(ResolveError::IncompatibleOperand {
+                                        op: name.clone(),
+                                        operand: format!("{:?}", other),
+                                    })
+                                }
+                            };
+                            Self::deduce_type_handle(inner, types)
                         }
                         _ => return Err(ResolveError::FunctionNotDefined { name: name.clone() }),
                     },
@@ -200,6 +341,29 @@ impl Typifier {
         Ok(self.types[expr_handle.index()])
     }
 
+    /// Get the type of an expression that was already resolved.
+    pub fn get(&self, expr_handle: Handle<crate::Expression>) -> Handle<crate::Type> {
+        self.types[expr_handle.index()]
+    }
+
+    fn deduce_bool_type_handle(
+        ty_handle: Handle<crate::Type>,
+        arena: &mut Arena<crate::Type>,
+    ) -> Handle<crate::Type> {
+        let inner = match arena[ty_handle].inner {
+            crate::TypeInner::Vector { size, .. } => crate::TypeInner::Vector {
+                size,
+                kind: crate::ScalarKind::Bool,
+                width: 1,
+            },
+            _ => crate::TypeInner::Scalar {
+                kind: crate::ScalarKind::Bool,
+                width: 1,
+            },
+        };
+        Self::deduce_type_handle(inner, arena)
+    }
+
     pub fn deduce_type_handle(
         inner: crate::TypeInner,
         arena: &mut Arena<crate::Type>,
@@ -11,6 +11,8 @@ pub enum ValidationError {
     UnresolvedType(Handle<crate::Type>),
     #[error("There are instructions after `return`/`break`/`continue`")]
     InvalidControlFlowExitTail,
+    #[error("The global variable {0:?} is used beyond its storage access")]
+    InvalidStorageAccess(Handle<crate::GlobalVariable>),
 }
 
 impl Validator {
@@ -58,6 +60,17 @@ impl Validator {
             }
         }
 
+        for (_, fun) in module.functions.iter() {
+            for ((handle, var), &usage) in module.global_variables.iter().zip(&fun.global_usage) {
+                if var.class == crate::StorageClass::StorageBuffer
+                    && usage.contains(crate::GlobalUse::STORE)
+                    && !var.storage_access.contains(crate::StorageAccess::STORE)
+                {
+                    return Err(ValidationError::InvalidStorageAccess(handle));
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -12,7 +12,25 @@ fn load_wgsl(name: &str) -> naga::Module {
 fn load_spv(name: &str) -> naga::Module {
     let path = format!("{}/test-data/spv/{}", env!("CARGO_MANIFEST_DIR"), name);
     let input = std::fs::read(path).unwrap();
-    naga::front::spv::parse_u8_slice(&input).unwrap()
+    naga::front::spv::parse_u8_slice(&input, &Default::default()).unwrap()
+}
+
+#[cfg(feature = "spirv")]
+fn load_spvasm(name: &str) -> naga::Module {
+    let input = load_test_data(&format!("spv/{}", name));
+    let words = naga::back::spv::assemble(&input).unwrap();
+    naga::front::spv::Parser::new(words.into_iter(), &Default::default())
+        .parse()
+        .unwrap()
+}
+
+#[cfg(feature = "spirv")]
+fn debug_options() -> naga::back::spv::Options {
+    naga::back::spv::Options {
+        target_env: naga::back::spv::TargetEnvironment::Universal(1, 0),
+        flags: naga::back::spv::WriterFlags::DEBUG,
+        ..Default::default()
+    }
 }
 
 #[cfg(feature = "glsl")]
@@ -35,6 +53,7 @@ fn convert_quad() {
                 texture: Some(1),
                 sampler: None,
                 mutable: false,
+                inline_sampler: None,
             },
         );
         binding_map.insert(
@@ -44,13 +63,23 @@ fn convert_quad() {
                 texture: None,
                 sampler: Some(1),
                 mutable: false,
+                inline_sampler: None,
             },
         );
-        let options = msl::Options {
-            binding_map: &binding_map,
-        };
+        let options = msl::Options::new(&binding_map);
         msl::write_string(&module, options).unwrap();
     }
+    #[cfg(feature = "spirv")]
+    {
+        use naga::back::spv;
+        let words = spv::Writer::new(&module.header, &debug_options())
+            .write(&module)
+            .unwrap();
+        let reparsed = naga::front::spv::Parser::new(words.into_iter(), &Default::default())
+            .parse()
+            .unwrap();
+        naga::proc::Validator::new().validate(&reparsed).unwrap();
+    }
 }
 
 #[test]
@@ -67,6 +96,7 @@ fn convert_boids() {
                 texture: None,
                 sampler: None,
                 mutable: false,
+                inline_sampler: None,
             },
         );
         binding_map.insert(
@@ -76,6 +106,7 @@ fn convert_boids() {
                 texture: None,
                 sampler: Some(1),
                 mutable: false,
+                inline_sampler: None,
             },
         );
         binding_map.insert(
@@ -85,13 +116,307 @@ fn convert_boids() {
                 texture: None,
                 sampler: Some(1),
                 mutable: false,
+                inline_sampler: None,
             },
         );
-        let options = msl::Options {
-            binding_map: &binding_map,
-        };
+        let options = msl::Options::new(&binding_map);
         msl::write_string(&module, options).unwrap();
     }
+    #[cfg(feature = "spirv")]
+    {
+        use naga::back::spv;
+        let words = spv::Writer::new(&module.header, &debug_options())
+            .write(&module)
+            .unwrap();
+        let text = spv::disassemble(&words).unwrap();
+        assert_eq!(spv::assemble(&text).unwrap(), words);
+    }
+}
+
+#[test]
+fn msl_language_version() {
+    use naga::back::msl;
+    let module = load_wgsl("simple.wgsl");
+    let binding_map = msl::BindingMap::default();
+    let mut options = msl::Options {
+        platform: msl::Platform::Ios,
+        ..msl::Options::new(&binding_map)
+    };
+    msl::write_string(&module, options).unwrap();
+    assert!(!options.supports(msl::Feature::ArgumentBuffers));
+
+    options.lang_version = (2, 1);
+    assert!(options.supports(msl::Feature::ArgumentBuffers));
+    assert!(!options.supports(msl::Feature::SimdGroupFunctions));
+    options.platform = msl::Platform::MacOs;
+    assert!(options.supports(msl::Feature::SimdGroupFunctions));
+
+    options.lang_version = (1, 1);
+    match msl::write_string(&module, options) {
+        Err(msl::Error::UnsupportedLanguageVersion(1, 1)) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn msl_argument_buffers() {
+    use naga::back::msl;
+    let module = load_wgsl("boids.wgsl");
+    let mut binding_map = msl::BindingMap::default();
+    for binding in 0..3 {
+        binding_map.insert(
+            msl::BindSource { set: 0, binding },
+            msl::BindTarget {
+                buffer: Some(binding as u8),
+                texture: None,
+                sampler: None,
+                mutable: false,
+                inline_sampler: None,
+            },
+        );
+    }
+    let mut options = msl::Options {
+        argument_buffers: Some(msl::ArgumentBufferTier::Tier1),
+        ..msl::Options::new(&binding_map)
+    };
+    match msl::write_string(&module, options) {
+        Err(msl::Error::UnsupportedFeature(msl::Feature::ArgumentBuffers)) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+
+    options.lang_version = (2, 0);
+    let (output, info) = msl::write_string(&module, options).unwrap();
+    assert!(output.contains("struct ArgumentBuffer0 {"));
+    assert!(output.contains("[[buffer(0)]]"));
+    assert_eq!(info.argument_buffers.len(), 1);
+    let layout = &info.argument_buffers[0];
+    assert_eq!((layout.set, layout.buffer), (0, 0));
+    let ids: Vec<_> = layout.entries.iter().map(|entry| entry.id).collect();
+    assert_eq!(ids, [0, 1, 2]);
+    let mutable: Vec<_> = layout.entries.iter().map(|entry| entry.mutable).collect();
+    assert_eq!(mutable, [false, false, true]);
+}
+
+#[test]
+fn msl_allocate_bindings() {
+    use naga::back::msl;
+    let module = load_wgsl("boids.wgsl");
+    let mut binding_map = msl::BindingMap::default();
+    binding_map.insert(
+        msl::BindSource { set: 0, binding: 1 },
+        msl::BindTarget {
+            buffer: Some(3),
+            texture: None,
+            sampler: None,
+            mutable: false,
+            inline_sampler: None,
+        },
+    );
+    let mut options = msl::Options::new(&binding_map);
+    match msl::write_string(&module, options) {
+        Err(msl::Error::MissingBindTarget(msl::BindSource { set: 0, .. })) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+
+    options.allocate_bindings = true;
+    let (output, info) = msl::write_string(&module, options).unwrap();
+    assert!(output.contains("& particlesA [[buffer(3)]]"));
+    assert!(!info.binding_maps.contains_key(&naga::ShaderStage::Vertex));
+    let compute = &info.binding_maps[&naga::ShaderStage::Compute];
+    let mut buffers: Vec<_> = compute
+        .iter()
+        .map(|(source, target)| (source.binding, target.buffer, target.mutable))
+        .collect();
+    buffers.sort();
+    assert_eq!(
+        buffers,
+        [(0, Some(4), false), (1, Some(3), false), (2, Some(5), true)]
+    );
+
+    let empty_map = msl::BindingMap::default();
+    options.binding_map = &empty_map;
+    options.lang_version = (2, 0);
+    options.argument_buffers = Some(msl::ArgumentBufferTier::Tier2);
+    let (_, info) = msl::write_string(&module, options).unwrap();
+    assert!(info.binding_maps.is_empty());
+    let ids: Vec<_> = info.argument_buffers[0]
+        .entries
+        .iter()
+        .map(|entry| (entry.binding, entry.id))
+        .collect();
+    assert_eq!(ids, [(0, 0), (1, 1), (2, 2)]);
+}
+
+#[test]
+fn msl_vertex_pulling() {
+    use naga::back::msl;
+    let module = load_wgsl("boids.wgsl");
+    let mut binding_map = msl::BindingMap::default();
+    for binding in 0..3 {
+        binding_map.insert(
+            msl::BindSource { set: 0, binding },
+            msl::BindTarget {
+                buffer: Some(binding as u8),
+                texture: None,
+                sampler: None,
+                mutable: false,
+                inline_sampler: None,
+            },
+        );
+    }
+    let vertex_buffers = vec![
+        msl::VertexBufferLayout {
+            buffer: 4,
+            stride: 16,
+            step_mode: msl::VertexStepMode::Instance,
+            attributes: vec![
+                msl::VertexAttribute {
+                    location: 0,
+                    format: msl::VertexFormat::Float32x2,
+                    offset: 0,
+                },
+                msl::VertexAttribute {
+                    location: 1,
+                    format: msl::VertexFormat::Float32x2,
+                    offset: 8,
+                },
+            ],
+        },
+        msl::VertexBufferLayout {
+            buffer: 5,
+            stride: 4,
+            step_mode: msl::VertexStepMode::Vertex,
+            attributes: vec![msl::VertexAttribute {
+                location: 2,
+                format: msl::VertexFormat::Unorm8x4,
+                offset: 0,
+            }],
+        },
+    ];
+    let options = msl::Options {
+        vertex_buffers: &vertex_buffers,
+        ..msl::Options::new(&binding_map)
+    };
+    let (output, _) = msl::write_string(&module, options).unwrap();
+    assert!(!output.contains("[[attribute("));
+    assert!(!output.contains("InputMain_vertex"));
+    assert!(output.contains("const device uchar* vertex_buffer4 [[buffer(4)]]"));
+    assert!(output.contains("uint vertex_id [[vertex_id]]"));
+    assert!(output.contains("uint instance_id [[instance_id]]"));
+    assert!(output.contains(
+        "Type0 a_particleVel = Type0(float2(float2(*(const device packed_float2*)\
+         (vertex_buffer4 + instance_id * 16 + 8))));"
+    ));
+    assert!(output.contains(
+        "Type0 a_pos = Type0(float4(unpack_unorm4x8_to_float(*(const device uint*)\
+         (vertex_buffer5 + vertex_id * 4 + 0))).xy);"
+    ));
+
+    let options = msl::Options {
+        vertex_buffers: &vertex_buffers[..1],
+        ..options
+    };
+    match msl::write_string(&module, options) {
+        Err(msl::Error::InvalidVertexAttribute(2)) => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn msl_runtime_array_length() {
+    use naga::back::msl;
+    let source = "
+        import \"GLSL.std.450\" as std;
+        type Data = struct {
+            [[offset 0]] count : u32;
+            [[offset 16]] values : [[stride 16]] array<vec4<f32> >;
+        };
+        [[binding 0, set 0]] var<storage_buffer> data : Data;
+        [[builtin global_invocation_id]] var gl_GlobalInvocationID : vec3<u32>;
+        fn main() -> void {
+            data.count = std::arrayLength(data.values);
+            data.values[gl_GlobalInvocationID.x] = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+            return;
+        }
+        entry_point compute as \"main\" = main;
+    ";
+    let module = naga::front::wgsl::parse_str(source).unwrap();
+    naga::proc::Validator::new().validate(&module).unwrap();
+    let binding_map = msl::BindingMap::default();
+    let mut options = msl::Options {
+        allocate_bindings: true,
+        ..msl::Options::new(&binding_map)
+    };
+    match msl::write_string(&module, options) {
+        Err(msl::Error::UnsupportedCall(ref name)) if name == "arrayLength" => {}
+        other => panic!("Unexpected result {:?}", other),
+    }
+
+    options.sizes_buffer = Some(0);
+    let (output, info) = msl::write_string(&module, options).unwrap();
+    assert_eq!(info.sized_buffers.len(), 1);
+    // a buffer smaller than the offset of the array has no element,
+    // and the clamped index doesn't wrap around
+    assert!(output.contains("data.count = ((max(buffer_sizes[0], 16u) - 16u) / 16);"));
+    assert!(output.contains(
+        "data.values[min(uint(gl_GlobalInvocationID.x), \
+         max(((max(buffer_sizes[0], 16u) - 16u) / 16), 1u) - 1)]"
+    ));
+    assert!(output.contains("constant uint* buffer_sizes [[buffer(0)]]"));
+    assert!(output.contains("device Type3& data [[buffer(1)]]"));
+}
+
+#[test]
+fn msl_runtime_array_empty() {
+    use naga::back::msl;
+    let source = "
+        type Data = struct {
+            [[offset 0]] values : [[stride 16]] array<vec4<f32> >;
+        };
+        [[binding 0, set 0]] var<storage_buffer> data : Data;
+        [[builtin global_invocation_id]] var gl_GlobalInvocationID : vec3<u32>;
+        fn main() -> void {
+            data.values[gl_GlobalInvocationID.x] = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+            return;
+        }
+        entry_point compute as \"main\" = main;
+    ";
+    let module = naga::front::wgsl::parse_str(source).unwrap();
+    let binding_map = msl::BindingMap::default();
+    let options = msl::Options {
+        allocate_bindings: true,
+        sizes_buffer: Some(0),
+        ..msl::Options::new(&binding_map)
+    };
+    let (output, _) = msl::write_string(&module, options).unwrap();
+    // an empty buffer clamps the index to 0 instead of 0xFFFFFFFF
+    assert!(output.contains(
+        "data.values[min(uint(gl_GlobalInvocationID.x), max((buffer_sizes[0] / 16), 1u) - 1)]"
+    ));
+}
+
+#[test]
+fn msl_shared_entry_point() {
+    use naga::back::msl;
+    let source = "
+        [[location 0]] var<out> half : vec4<f32>;
+        fn main() -> void {
+            half = vec4<f32>(1.0, 0.0, 0.0, 1.0);
+            return;
+        }
+        entry_point vertex as \"main\" = main;
+        entry_point fragment as \"main\" = main;
+    ";
+    let module = naga::front::wgsl::parse_str(source).unwrap();
+    let binding_map = msl::BindingMap::default();
+    let options = msl::Options::new(&binding_map);
+    let (output, info) = msl::write_string(&module, options).unwrap();
+    assert_eq!(info.entry_point_names, vec!["main_vertex", "main_fragment"]);
+    assert_eq!(output.matches("void main_(").count(), 1);
+    assert!(output.contains("vertex OutputMain_vertex main_vertex("));
+    assert!(output.contains("Type0 half_ [[user(loc0)]];"));
+    assert!(output.contains("fragment OutputMain_fragment main_fragment("));
+    assert!(output.contains("Type0 half_ [[color(0)]];"));
 }
 
 #[cfg(feature = "spirv")]
@@ -102,6 +427,220 @@ fn convert_cube() {
     validator.validate(&vs).unwrap();
     let fs = load_spv("cube.frag.spv");
     validator.validate(&fs).unwrap();
+
+    use naga::back::msl;
+    let binding_map = msl::BindingMap::default();
+    let options = msl::Options {
+        allocate_bindings: true,
+        ..msl::Options::new(&binding_map)
+    };
+    msl::write_string(&vs, options).unwrap();
+    msl::write_string(&fs, options).unwrap();
+}
+
+#[cfg(feature = "spirv")]
+#[test]
+fn round_trip_spv() {
+    let dir = format!("{}/test-data/spv", env!("CARGO_MANIFEST_DIR"));
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let words = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("spv") => {
+                let input = std::fs::read(&path).unwrap();
+                input
+                    .chunks(4)
+                    .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                    .collect::<Vec<_>>()
+            }
+            Some("spvasm") => {
+                let input = std::fs::read_to_string(&path).unwrap();
+                naga::back::spv::assemble(&input)
+                    .unwrap_or_else(|e| panic!("{}: {:?}", path.display(), e))
+            }
+            _ => continue,
+        };
+        let module = naga::front::spv::Parser::new(words.into_iter(), &Default::default())
+            .parse()
+            .unwrap_or_else(|e| panic!("{}: {:?}", path.display(), e));
+        let words = naga::back::spv::Writer::new(&module.header, &debug_options())
+            .write(&module)
+            .unwrap();
+        let reparsed = naga::front::spv::Parser::new(words.into_iter(), &Default::default())
+            .parse()
+            .unwrap_or_else(|e| panic!("{}: {:?}", path.display(), e));
+        naga::proc::compare_modules(&module, &reparsed)
+            .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    }
+}
+
+#[cfg(feature = "spirv")]
+#[test]
+fn convert_mix() {
+    let module = load_spvasm("mix.spvasm");
+    naga::proc::Validator::new().validate(&module).unwrap();
+}
+
+#[cfg(feature = "spirv")]
+#[test]
+fn msl_inline_sampler() {
+    use naga::back::msl;
+    let module = load_spv("cube.frag.spv");
+    let (_, sampler) = module
+        .global_variables
+        .iter()
+        .find(|&(_, var)| {
+            module.types[var.ty].inner == naga::TypeInner::Sampler { comparison: false }
+        })
+        .unwrap();
+    let (set, binding) = match sampler.binding {
+        Some(naga::Binding::Descriptor { set, binding }) => (set, binding),
+        ref other => panic!("Unexpected binding {:?}", other),
+    };
+    let mut binding_map = msl::BindingMap::default();
+    binding_map.insert(
+        msl::BindSource { set, binding },
+        msl::BindTarget {
+            inline_sampler: Some(msl::InlineSampler {
+                mag_filter: msl::SamplerFilter::Linear,
+                min_filter: msl::SamplerFilter::Linear,
+                mip_filter: Some(msl::SamplerFilter::Nearest),
+                address: [
+                    msl::SamplerAddress::Repeat,
+                    msl::SamplerAddress::ClampToEdge,
+                    msl::SamplerAddress::ClampToBorder,
+                ],
+                border_color: msl::SamplerBorderColor::OpaqueWhite,
+                compare_func: Some(msl::SamplerCompareFunction::Less),
+                lod_clamp: Some(0.0..8.0),
+            }),
+            ..msl::BindTarget::default()
+        },
+    );
+    let mut options = msl::Options {
+        allocate_bindings: true,
+        ..msl::Options::new(&binding_map)
+    };
+    let (output, _) = msl::write_string(&module, options).unwrap();
+    assert!(output.contains(
+        "constexpr sampler s_Color(mag_filter::linear, min_filter::linear, \
+         mip_filter::nearest, s_address::repeat, t_address::clamp_to_edge, \
+         r_address::clamp_to_border, border_color::opaque_white, compare_func::less, \
+         lod_clamp(0.0, 8.0));"
+    ));
+    assert!(!output.contains("[[sampler("));
+
+    options.platform = msl::Platform::Ios;
+    match msl::write_string(&module, options) {
+        Err(msl::Error::UnsupportedFeature(msl::Feature::SamplerBorderColor)) => {}
+        other => panic!("Unexpected result {:?}", other.map(|(output, _)| output)),
+    }
+    options.platform = msl::Platform::MacOs;
+
+    options.lang_version = (2, 0);
+    options.argument_buffers = Some(msl::ArgumentBufferTier::Tier1);
+    let (_, info) = msl::write_string(&module, options).unwrap();
+    assert_eq!(info.argument_buffers[0].entries.len(), 1);
+}
+
+#[cfg(feature = "spirv")]
+#[test]
+fn msl_control_flow() {
+    use naga::back::msl;
+    let module = load_spvasm("control_flow.spvasm");
+    naga::proc::Validator::new().validate(&module).unwrap();
+    let binding_map = msl::BindingMap::default();
+    let options = msl::Options::new(&binding_map);
+    let (output, _) = msl::write_string(&module, options).unwrap();
+    for expected in &[
+        "discard_fragment();",
+        "isnan(",
+        "dfdx(",
+        "scale(dfdx(",
+        "as_type<int>(",
+        "select(",
+        "bool loop_init0 = true;",
+        "switch(i) {",
+        "case 1: {",
+        "default: {",
+        "float(as_type<int>(as_type<uint>(i) >> 1))",
+    ] {
+        assert!(output.contains(expected), "missing {:?}", expected);
+    }
+}
+
+#[cfg(feature = "spirv")]
+#[test]
+fn promote_locals() {
+    let mut module = load_spvasm("locals.spvasm");
+    naga::proc::promote_local_variables(&mut module);
+    naga::proc::Validator::new().validate(&module).unwrap();
+    let (_, function) = module.functions.iter().next().unwrap();
+    // `x` is promoted, `y` depends on the branch taken
+    let names: Vec<_> = function
+        .local_variables
+        .iter()
+        .map(|(_, var)| var.name.as_deref())
+        .collect();
+    assert_eq!(names, [Some("y")]);
+    let loads = function
+        .expressions
+        .iter()
+        .filter(|&(_, expr)| match *expr {
+            naga::Expression::Load { pointer } => match function.expressions[pointer] {
+                naga::Expression::LocalVariable(_) => true,
+                _ => false,
+            },
+            _ => false,
+        })
+        .count();
+    assert_eq!(loads, 1);
+    naga::back::spv::Writer::new(&module.header, &debug_options())
+        .write(&module)
+        .unwrap();
+}
+
+#[cfg(feature = "spirv")]
+#[test]
+fn eliminate_common_subexpressions() {
+    let mut module = load_spvasm("duplicates.spvasm");
+    naga::proc::eliminate_common_subexpressions(&mut module);
+    naga::proc::Validator::new().validate(&module).unwrap();
+    let (_, function) = module.functions.iter().next().unwrap();
+    let (mut global_loads, mut local_loads, mut products) = (0, 0, 0);
+    for (_, expr) in function.expressions.iter() {
+        match *expr {
+            naga::Expression::Load { pointer } => match function.expressions[pointer] {
+                naga::Expression::GlobalVariable(_) => global_loads += 1,
+                naga::Expression::LocalVariable(_) => local_loads += 1,
+                _ => {}
+            },
+            naga::Expression::Binary { .. } => products += 1,
+            _ => {}
+        }
+    }
+    assert_eq!(global_loads, 1);
+    assert_eq!(products, 1);
+    // `x` is stored between the first and the other loads
+    assert_eq!(local_loads, 2);
+    naga::back::spv::Writer::new(&module.header, &debug_options())
+        .write(&module)
+        .unwrap();
+}
+
+#[cfg(feature = "spirv")]
+#[test]
+fn disassemble_cube() {
+    use naga::back::spv;
+    for name in &["cube.vert.spv", "cube.frag.spv"] {
+        let path = format!("{}/test-data/spv/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let words: Vec<u32> = std::fs::read(path)
+            .unwrap()
+            .chunks(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        let text = spv::disassemble(&words).unwrap();
+        assert_eq!(spv::assemble(&text).unwrap(), words);
+    }
 }
 
 #[cfg(feature = "glsl")]
@@ -119,9 +658,9 @@ fn convert_phong_lighting() {
         version: (1, 0, 0),
         generator: 1234,
     };
-    let writer_flags = naga::back::spv::WriterFlags::empty();
-    let mut w = naga::back::spv::Writer::new(&header, writer_flags);
-    w.write(&module);
+    let options = naga::back::spv::Options::default();
+    let mut w = naga::back::spv::Writer::new(&header, &options);
+    w.write(&module).unwrap();
 }
 
 #[cfg(feature = "glsl")]
@@ -134,3 +673,177 @@ fn constant_expressions() {
     );
     naga::proc::Validator::new().validate(&module).unwrap();
 }
+
+#[cfg(feature = "spirv")]
+#[test]
+fn msl_image_kinds() {
+    use naga::back::msl;
+    let mut module = load_spvasm("images.spvasm");
+    naga::proc::Validator::new().validate(&module).unwrap();
+    let binding_map = msl::BindingMap::default();
+    let mut options = msl::Options {
+        allocate_bindings: true,
+        ..msl::Options::new(&binding_map)
+    };
+    let (output, _) = msl::write_string(&module, options).unwrap();
+    for expected in &[
+        "texture2d_array<Type0, access::sample>",
+        "texturecube_array<Type0, access::sample>",
+        "layers.sample(sampler_, float3(coord.x, coord.y, coord.z).xy, \
+         uint(rint(float3(coord.x, coord.y, coord.z).z)))",
+        "faces.sample(sampler_, coord.xyz, uint(rint(coord.w)))",
+    ] {
+        assert!(output.contains(expected), "missing {:?}", expected);
+    }
+
+    options.platform = msl::Platform::Ios;
+    match msl::write_string(&module, options) {
+        Err(msl::Error::UnsupportedFeature(msl::Feature::CubeArrayTextures)) => {}
+        other => panic!("Unexpected result {:?}", other.map(|(output, _)| output)),
+    }
+    options.platform = msl::Platform::MacOs;
+
+    // turn the cube array into a depth texture
+    let (layers, _) = module
+        .types
+        .iter()
+        .find(|&(_, ty)| match ty.inner {
+            naga::TypeInner::Image { dim, .. } => dim == naga::ImageDimension::D2,
+            _ => false,
+        })
+        .unwrap();
+    let (faces, _) = module
+        .types
+        .iter()
+        .find(|&(_, ty)| match ty.inner {
+            naga::TypeInner::Image { dim, .. } => dim == naga::ImageDimension::Cube,
+            _ => false,
+        })
+        .unwrap();
+    module.types.get_mut(faces).inner = naga::TypeInner::DepthImage {
+        dim: naga::ImageDimension::Cube,
+        arrayed: true,
+    };
+    let function = module.functions.get_mut(module.entry_points[0].function);
+    let (layer, _) = function
+        .expressions
+        .iter()
+        .find(|&(_, expr)| match *expr {
+            naga::Expression::AccessIndex { index, .. } => index == 3,
+            _ => false,
+        })
+        .unwrap();
+    let mut samples = function
+        .expressions
+        .iter()
+        .filter(|&(_, expr)| match *expr {
+            naga::Expression::ImageSample { .. } => true,
+            _ => false,
+        })
+        .map(|(handle, _)| handle)
+        .collect::<Vec<_>>();
+    let compared = samples.pop().unwrap();
+    if let naga::Expression::ImageSample {
+        ref mut depth_ref, ..
+    } = *function.expressions.get_mut(compared)
+    {
+        *depth_ref = Some(layer);
+    }
+    let (output, _) = msl::write_string(&module, options).unwrap();
+    for expected in &[
+        "depthcube_array<float, access::sample>",
+        "faces.sample_compare(sampler_, coord.xyz, uint(rint(coord.w)), coord.w)",
+    ] {
+        assert!(output.contains(expected), "missing {:?}", expected);
+    }
+
+    // multisampled textures can't be sampled
+    if let naga::TypeInner::Image { ref mut flags, .. } = module.types.get_mut(layers).inner {
+        *flags |= naga::ImageFlags::MULTISAMPLED;
+    }
+    match msl::write_string(&module, options) {
+        Err(msl::Error::InvalidImageFlags(_)) => {}
+        other => panic!("Unexpected result {:?}", other.map(|(output, _)| output)),
+    }
+}
+
+#[cfg(feature = "spirv")]
+#[test]
+fn msl_source_map() {
+    use naga::back::msl;
+    let mut module = load_spvasm("control_flow.spvasm");
+    let file = module.spans.files.append(naga::SourceFile {
+        name: Some("control_flow.frag".to_string()),
+        text: None,
+    });
+    let main = module.entry_points[0].function;
+    module.spans.functions.insert(
+        main,
+        naga::Span {
+            file,
+            line: 1,
+            column: 1,
+        },
+    );
+    let selector = module.functions[main]
+        .body
+        .iter()
+        .find_map(|statement| match *statement {
+            naga::Statement::Switch { selector, .. } => Some(selector),
+            _ => None,
+        })
+        .unwrap();
+    module.spans.expressions.insert(
+        (main, selector),
+        naga::Span {
+            file,
+            line: 12,
+            column: 5,
+        },
+    );
+    let binding_map = msl::BindingMap::default();
+    let mut options = msl::Options {
+        source_map: true,
+        ..msl::Options::new(&binding_map)
+    };
+    let (output, info) = msl::write_string(&module, options).unwrap();
+    let lines = output.lines().collect::<Vec<_>>();
+    let line = |number: u32| lines[number as usize - 1];
+
+    // the helper, the entry point function, and its wrapper
+    let functions = info
+        .source_map
+        .iter()
+        .filter(|mapping| mapping.expression.is_none())
+        .collect::<Vec<_>>();
+    assert_eq!(functions.len(), 3);
+    for mapping in functions {
+        assert!(line(mapping.lines.start).ends_with('('));
+        assert_eq!(line(mapping.lines.end - 1), "}");
+    }
+    let switch = info
+        .source_map
+        .iter()
+        .find(|mapping| mapping.expression == Some(selector))
+        .unwrap();
+    assert_eq!(switch.function, main);
+    assert_eq!(switch.span.map(|span| span.line), Some(12));
+    assert!(line(switch.lines.start).trim_start().starts_with("switch("));
+    assert!(info
+        .source_map
+        .windows(2)
+        .all(|pair| pair[0].lines.start <= pair[1].lines.start));
+
+    options.source_map = false;
+    options.annotate = true;
+    let (output, info) = msl::write_string(&module, options).unwrap();
+    assert!(info.source_map.is_empty());
+    assert!(output.contains(&format!(
+        "// function {} (control_flow.frag:1:1)",
+        main.index()
+    )));
+    assert!(output.contains(&format!(
+        "\t// expression {} (control_flow.frag:12:5)\n\tswitch(",
+        selector.index()
+    )));
+}